@@ -0,0 +1,54 @@
+//! Exposing endpoints that allow an administrator to reset a client's rate limit.
+//!
+//! In a real application the admin scope must be protected by authentication.
+//!
+//! ```sh
+//! cargo run --example admin_endpoints --features admin
+//! curl -v -X DELETE http://127.0.0.1:8080/admin/rate-limit/keys/127.0.0.1
+//! ```
+//!
+//! Or using Docker:
+//!
+//! ```sh
+//! docker compose -f examples/docker-compose.yml up admin_endpoints
+//! curl -v -X DELETE http://127.0.0.1:8083/admin/rate-limit/keys/127.0.0.1
+//! ```
+
+use actix_extensible_rate_limit::admin::rate_limit_admin;
+use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+use actix_extensible_rate_limit::backend::{
+    SimpleInputFunctionBuilder, SimpleInputFuture, SimpleOutput,
+};
+use actix_extensible_rate_limit::RateLimiter;
+use actix_web::dev::ServiceRequest;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use std::time::Duration;
+
+pub fn rate_limiter(
+    backend: InMemoryBackend,
+) -> RateLimiter<InMemoryBackend, SimpleOutput, impl Fn(&ServiceRequest) -> SimpleInputFuture> {
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+        .peer_ip_key()
+        .build();
+    RateLimiter::builder(backend, input).add_headers().build()
+}
+
+pub fn routes(backend: InMemoryBackend) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        cfg.service(web::scope("/admin").service(rate_limit_admin(backend.clone())))
+            .service(
+                web::scope("/api")
+                    .wrap(rate_limiter(backend))
+                    .route("", web::get().to(|| async { HttpResponse::Ok().finish() })),
+            );
+    }
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let backend = InMemoryBackend::builder().build();
+    HttpServer::new(move || App::new().configure(routes(backend.clone())))
+        .bind(("0.0.0.0", 8080))?
+        .run()
+        .await
+}
@@ -0,0 +1,49 @@
+//! Rate limiting an application that is deployed behind a reverse proxy.
+//!
+//! The proxy is responsible for setting the `X-Forwarded-For` header, so each client is limited
+//! by their real IP address instead of the address of the proxy.
+//!
+//! ```sh
+//! docker compose -f examples/docker-compose.yml up behind_proxy
+//! curl -v http://127.0.0.1:8080
+//! ```
+
+use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+use actix_extensible_rate_limit::backend::{
+    SimpleInputFunctionBuilder, SimpleInputFuture, SimpleOutput,
+};
+use actix_extensible_rate_limit::RateLimiter;
+use actix_web::dev::ServiceRequest;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use std::time::Duration;
+
+pub fn rate_limiter(
+    backend: InMemoryBackend,
+) -> RateLimiter<InMemoryBackend, SimpleOutput, impl Fn(&ServiceRequest) -> SimpleInputFuture> {
+    // Only use `real_ip_key()` if the application can only be reached through a proxy that you
+    // control, otherwise clients can spoof the `X-Forwarded-For` header.
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+        .real_ip_key()
+        .build();
+    RateLimiter::builder(backend, input).add_headers().build()
+}
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route(
+        "/",
+        web::get().to(|| async { HttpResponse::Ok().body("Hello world!") }),
+    );
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let backend = InMemoryBackend::builder().build();
+    HttpServer::new(move || {
+        App::new()
+            .wrap(rate_limiter(backend.clone()))
+            .configure(routes)
+    })
+    .bind(("0.0.0.0", 8081))?
+    .run()
+    .await
+}
@@ -0,0 +1,71 @@
+//! Protecting a login endpoint against password guessing.
+//!
+//! Only failed login attempts are counted against the client, so that a user who logs in
+//! successfully is never locked out by their own traffic.
+//!
+//! ```sh
+//! cargo run --example login_protection
+//! curl -v -X POST -d 'wrong' http://127.0.0.1:8080/login
+//! ```
+//!
+//! Or using Docker:
+//!
+//! ```sh
+//! docker compose -f examples/docker-compose.yml up login_protection
+//! curl -v -X POST -d 'wrong' http://127.0.0.1:8084/login
+//! ```
+
+use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+use actix_extensible_rate_limit::backend::{
+    SimpleInputFunctionBuilder, SimpleInputFuture, SimpleOutput,
+};
+use actix_extensible_rate_limit::RateLimiter;
+use actix_web::dev::ServiceRequest;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use std::time::Duration;
+
+pub const MAX_FAILED_ATTEMPTS: u64 = 5;
+
+pub fn rate_limiter(
+    backend: InMemoryBackend,
+) -> RateLimiter<InMemoryBackend, SimpleOutput, impl Fn(&ServiceRequest) -> SimpleInputFuture> {
+    // Clients connect directly to this example, so the peer address can be trusted
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(15 * 60), MAX_FAILED_ATTEMPTS)
+        .peer_ip_key()
+        .custom_key("login")
+        .build();
+    RateLimiter::builder(backend, input)
+        .add_headers()
+        // Successful logins should not count towards the limit
+        .rollback_condition(Some(|status: actix_web::http::StatusCode| {
+            status.is_success()
+        }))
+        .build()
+}
+
+async fn login(password: String) -> HttpResponse {
+    if password == "hunter2" {
+        HttpResponse::Ok().body("Welcome!")
+    } else {
+        HttpResponse::Unauthorized().body("Incorrect password")
+    }
+}
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/login", web::post().to(login));
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let backend = InMemoryBackend::builder().build();
+    HttpServer::new(move || {
+        App::new().service(
+            web::scope("")
+                .wrap(rate_limiter(backend.clone()))
+                .configure(routes),
+        )
+    })
+    .bind(("0.0.0.0", 8080))?
+    .run()
+    .await
+}
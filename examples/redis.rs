@@ -0,0 +1,48 @@
+//! Sharing rate limits between several instances of an application using Redis.
+//!
+//! ```sh
+//! docker compose -f examples/docker-compose.yml up redis_example
+//! curl -v http://127.0.0.1:8082
+//! ```
+//!
+//! This uses a single Redis node. Redis Cluster is out of scope: the [RedisBackend] is built on
+//! a [ConnectionManager], which only connects to a single node and doesn't follow the cluster's
+//! `MOVED` redirects. For high availability, use a single primary with replicas, failing over
+//! with Redis Sentinel.
+
+use actix_extensible_rate_limit::backend::redis::RedisBackend;
+use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+use actix_extensible_rate_limit::RateLimiter;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use redis::aio::ConnectionManager;
+use std::time::Duration;
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_owned());
+    let client = redis::Client::open(url).expect("Invalid Redis URL");
+    let manager = ConnectionManager::new(client)
+        .await
+        .expect("Unable to connect to Redis");
+    // The prefix keeps the rate limit keys separate from any other data in Redis
+    let backend = RedisBackend::builder(manager)
+        .key_prefix(Some("rate-limit:"))
+        .build();
+    HttpServer::new(move || {
+        let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+            .peer_ip_key()
+            .build();
+        let middleware = RateLimiter::builder(backend.clone(), input)
+            .add_headers()
+            // Allow requests through if Redis is unavailable
+            .fail_open(true)
+            .build();
+        App::new().wrap(middleware).route(
+            "/",
+            web::get().to(|| async { HttpResponse::Ok().body("Hello world!") }),
+        )
+    })
+    .bind(("0.0.0.0", 8082))?
+    .run()
+    .await
+}
@@ -0,0 +1,72 @@
+//! Applying different limits to each API key, depending on the plan that the key belongs to.
+//!
+//! ```sh
+//! cargo run --example tiered_api_keys
+//! curl -v -H 'x-api-key: pro_abc' http://127.0.0.1:8080
+//! ```
+//!
+//! Or using Docker:
+//!
+//! ```sh
+//! docker compose -f examples/docker-compose.yml up tiered_api_keys
+//! curl -v -H 'x-api-key: pro_abc' http://127.0.0.1:8085
+//! ```
+
+use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+use actix_extensible_rate_limit::backend::{SimpleInput, SimpleInputFuture, SimpleOutput};
+use actix_extensible_rate_limit::RateLimiter;
+use actix_web::dev::ServiceRequest;
+use actix_web::error::ErrorUnauthorized;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use std::future::ready;
+use std::time::Duration;
+
+pub const FREE_LIMIT: u64 = 10;
+pub const PRO_LIMIT: u64 = 100;
+
+fn input(req: &ServiceRequest) -> Result<SimpleInput, actix_web::Error> {
+    let api_key = req
+        .headers()
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| ErrorUnauthorized("Missing API key"))?;
+    // A real application would look the plan up in a database
+    let max_requests = if api_key.starts_with("pro_") {
+        PRO_LIMIT
+    } else {
+        FREE_LIMIT
+    };
+    Ok(SimpleInput {
+        interval: Duration::from_secs(60),
+        max_requests,
+        key: format!("api-key-{api_key}").into(),
+    })
+}
+
+pub fn rate_limiter(
+    backend: InMemoryBackend,
+) -> RateLimiter<InMemoryBackend, SimpleOutput, impl Fn(&ServiceRequest) -> SimpleInputFuture> {
+    RateLimiter::builder(backend, |req: &ServiceRequest| ready(input(req)))
+        .add_headers()
+        .build()
+}
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route(
+        "/",
+        web::get().to(|| async { HttpResponse::Ok().body("Hello world!") }),
+    );
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let backend = InMemoryBackend::builder().build();
+    HttpServer::new(move || {
+        App::new()
+            .wrap(rate_limiter(backend.clone()))
+            .configure(routes)
+    })
+    .bind(("0.0.0.0", 8080))?
+    .run()
+    .await
+}
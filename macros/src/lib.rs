@@ -0,0 +1,200 @@
+//! Attribute macros for [actix-extensible-rate-limit](https://docs.rs/actix-extensible-rate-limit).
+//!
+//! These are re-exported by the main crate when the `macros` feature is enabled, and should be
+//! used from there.
+
+use proc_macro::TokenStream;
+use quote::{quote, ToTokens};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Error, Expr, ExprLit, ItemFn, Lit, LitStr, Meta, Token};
+
+/// The actix-web attribute macros that accept a `wrap` argument.
+const ROUTE_MACROS: &[&str] = &[
+    "route", "get", "post", "put", "delete", "head", "connect", "options", "trace", "patch",
+];
+
+/// Rate limit a single handler.
+///
+/// Must be placed above the actix-web route macro, e.g. `#[get("/")]`. See the main crate for
+/// documentation.
+#[proc_macro_attribute]
+pub fn rate_limit(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args with Punctuated::<Expr, Token![,]>::parse_terminated);
+    let mut item = parse_macro_input!(item as ItemFn);
+    match expand(args, &mut item) {
+        Ok(()) => item.into_token_stream().into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(args: Punctuated<Expr, Token![,]>, item: &mut ItemFn) -> Result<(), Error> {
+    let mut args = args.into_iter();
+    let policy = match args.next() {
+        Some(Expr::Lit(ExprLit {
+            lit: Lit::Str(policy),
+            ..
+        })) => policy,
+        other => {
+            return Err(Error::new(
+                other.map_or(item.sig.ident.span(), |o| o.span()),
+                "Expected a policy string, e.g. \"10/minute\"",
+            ))
+        }
+    };
+    let (max_requests, interval_millis) =
+        parse_policy(&policy.value()).map_err(|e| Error::new(policy.span(), e))?;
+
+    let mut key = Key::PeerIp;
+    for arg in args {
+        match &arg {
+            Expr::Assign(assign) if is_ident(&assign.left, "key") => {
+                let value = string_value(&assign.right)?;
+                key = Key::parse(&value.value()).map_err(|e| Error::new(value.span(), e))?;
+            }
+            _ => {
+                return Err(Error::new(
+                    arg.span(),
+                    "Unknown argument, expected `key = \"..\"`",
+                ))
+            }
+        }
+    }
+
+    let route = item
+        .attrs
+        .iter_mut()
+        .find(|attr| {
+            attr.path()
+                .segments
+                .last()
+                .is_some_and(|s| ROUTE_MACROS.contains(&s.ident.to_string().as_str()))
+        })
+        .ok_or_else(|| {
+            Error::new(
+                item.sig.ident.span(),
+                "#[rate_limit] must be placed above a route macro such as #[get(\"/\")]",
+            )
+        })?;
+    let Meta::List(list) = &mut route.meta else {
+        return Err(Error::new(
+            route.span(),
+            "Expected a route macro with arguments",
+        ));
+    };
+
+    let handler = item.sig.ident.to_string();
+    let key = key.variant();
+    let wrap = format!(
+        "::actix_extensible_rate_limit::__private::handler_limiter(\
+        concat!(module_path!(), \"::{handler}\"), \
+        {interval_millis}, {max_requests}, \
+        ::actix_extensible_rate_limit::__private::Key::{key})"
+    );
+    let wrap = LitStr::new(&wrap, policy.span());
+    let tokens = &list.tokens;
+    list.tokens = quote!(#tokens, wrap = #wrap);
+    Ok(())
+}
+
+fn is_ident(expr: &Expr, ident: &str) -> bool {
+    matches!(expr, Expr::Path(path) if path.path.is_ident(ident))
+}
+
+fn string_value(expr: &Expr) -> Result<LitStr, Error> {
+    match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(value),
+            ..
+        }) => Ok(value.clone()),
+        _ => Err(Error::new(expr.span(), "Expected a string literal")),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    RealIp,
+    PeerIp,
+    Global,
+}
+
+impl Key {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "real_ip" => Ok(Self::RealIp),
+            "peer_ip" => Ok(Self::PeerIp),
+            "global" => Ok(Self::Global),
+            _ => Err(format!(
+                "Unknown key `{value}`, expected one of `real_ip`, `peer_ip` or `global`"
+            )),
+        }
+    }
+
+    fn variant(&self) -> &'static str {
+        match self {
+            Key::RealIp => "RealIp",
+            Key::PeerIp => "PeerIp",
+            Key::Global => "Global",
+        }
+    }
+}
+
+/// Parses a policy such as `10/minute` or `100/5m` into the max requests and the interval in
+/// milliseconds.
+fn parse_policy(policy: &str) -> Result<(u64, u64), String> {
+    let (max_requests, interval) = policy
+        .split_once('/')
+        .ok_or_else(|| format!("Invalid policy `{policy}`, expected e.g. \"10/minute\""))?;
+    let max_requests = max_requests
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("Invalid max requests `{max_requests}`"))?;
+    let interval = interval.trim();
+    let unit_start = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("Missing interval unit in `{interval}`"))?;
+    let count = match &interval[..unit_start] {
+        "" => 1,
+        count => count
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid interval `{interval}`"))?,
+    };
+    let unit_millis = match interval[unit_start..].trim() {
+        "ms" | "millisecond" | "milliseconds" => 1,
+        "s" | "sec" | "second" | "seconds" => 1000,
+        "m" | "min" | "minute" | "minutes" => 60 * 1000,
+        "h" | "hour" | "hours" => 60 * 60 * 1000,
+        "d" | "day" | "days" => 24 * 60 * 60 * 1000,
+        unit => return Err(format!("Unknown interval unit `{unit}`")),
+    };
+    if count == 0 {
+        return Err("Interval must be non-zero".to_string());
+    }
+    let interval_millis = count
+        .checked_mul(unit_millis)
+        .ok_or_else(|| format!("Interval `{interval}` is too large"))?;
+    Ok((max_requests, interval_millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(parse_policy("10/minute").unwrap(), (10, 60_000));
+        assert_eq!(parse_policy("100/5m").unwrap(), (100, 300_000));
+        assert_eq!(parse_policy("1 / 2 hours").unwrap(), (1, 7_200_000));
+        assert_eq!(parse_policy("5/250ms").unwrap(), (5, 250));
+        assert!(parse_policy("10").is_err());
+        assert!(parse_policy("ten/minute").is_err());
+        assert!(parse_policy("10/0s").is_err());
+        assert!(parse_policy("10/fortnight").is_err());
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(Key::parse("real_ip").unwrap(), Key::RealIp);
+        assert!(Key::parse("api_key").is_err());
+    }
+}
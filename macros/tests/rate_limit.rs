@@ -0,0 +1,38 @@
+use actix_extensible_rate_limit::rate_limit;
+use actix_web::http::StatusCode;
+use actix_web::test::{call_service, init_service, TestRequest};
+use actix_web::{get, App, HttpResponse, Responder};
+
+#[rate_limit("2/minute", key = "peer_ip")]
+#[get("/login")]
+async fn login() -> impl Responder {
+    HttpResponse::Ok()
+}
+
+#[rate_limit("5/1m")]
+#[get("/search")]
+async fn search() -> impl Responder {
+    HttpResponse::Ok()
+}
+
+#[actix_web::test]
+async fn test_rate_limit() {
+    let app = init_service(App::new().service(login).service(search)).await;
+    let request = |uri: &str| {
+        TestRequest::get()
+            .uri(uri)
+            .peer_addr("127.0.0.1:12345".parse().unwrap())
+            .to_request()
+    };
+    for _ in 0..2 {
+        let response = call_service(&app, request("/login")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = call_service(&app, request("/login")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Each handler is limited separately
+    let response = call_service(&app, request("/search")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("x-ratelimit-limit").unwrap(), "5");
+}
@@ -0,0 +1,490 @@
+//! JSON endpoints for inspecting and managing rate limit keys.
+//!
+//! # Security
+//!
+//! The [rate_limit_admin] endpoints allow anyone who can reach them to reset or ban any key, so
+//! they must be protected, e.g. by wrapping the scope with an authentication middleware, or only
+//! serving it on an internal port. The [quota_status_handler] only reports the caller's own
+//! status, so can be public.
+
+#[cfg(feature = "stats")]
+use crate::backend::stats::{KeyStats, RateLimitStats, StatsBackend};
+use crate::backend::{Backend, Decision, KeyStatus, SimpleBackend};
+use crate::HeaderCompatibleOutput;
+use actix_web::dev::ServiceRequest;
+#[cfg(feature = "stats")]
+use actix_web::Resource;
+use actix_web::{web, HttpRequest, HttpResponse, Route, Scope};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_TOP_LIMIT: usize = 10;
+/// The longest ban that can be set, 10 years.
+pub const MAX_BAN_SECONDS: u64 = 10 * 365 * 24 * 60 * 60;
+
+/// Create a [Scope] mounted at `/rate-limit`, with the following endpoints:
+///
+/// | Endpoint                     | Description                                                  |
+/// |------------------------------|--------------------------------------------------------------|
+/// | `GET /keys?prefix=`          | List the status of every key starting with the prefix.       |
+/// | `GET /keys/{key}`            | Get the status of a key, or 404 if it doesn't exist.         |
+/// | `DELETE /keys/{key}`         | Reset a key.                                                 |
+/// | `POST /bans`                 | Ban a key, with a body of `{"key": "...", "seconds": 900}`.  |
+/// | `GET /top?limit=`            | List the keys with the highest counts (defaults to 10).      |
+///
+/// A key status is returned as `{"key": "...", "count": 5, "ttl_seconds": 42}`.
+///
+/// A ban sets the count of the key to its maximum for the given duration, so every request is
+/// denied regardless of the limit, up to [MAX_BAN_SECONDS]. It can be lifted early by resetting
+/// the key.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::admin::rate_limit_admin;
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_web::App;
+/// # #[actix_web::main]
+/// # async fn main() {
+/// let backend = InMemoryBackend::builder().build();
+/// // Remember to protect these endpoints!
+/// let app = App::new().service(rate_limit_admin(backend.clone()));
+/// # }
+/// ```
+pub fn rate_limit_admin<B>(backend: B) -> Scope
+where
+    B: SimpleBackend + 'static,
+    B::Error: Into<actix_web::Error>,
+{
+    web::scope("/rate-limit")
+        .app_data(web::Data::new(backend))
+        .route("/keys", web::get().to(list::<B>))
+        .route("/keys/{key:.*}", web::get().to(get::<B>))
+        .route("/keys/{key:.*}", web::delete().to(reset::<B>))
+        .route("/bans", web::post().to(ban::<B>))
+        .route("/top", web::get().to(top::<B>))
+}
+
+/// Create a [Resource] at `/stats`, which returns the [RateLimitStats] of a [StatsBackend] as
+/// `{"top_requests": [...], "top_denied": [...]}`, where each key is
+/// `{"key": "...", "requests": 10, "denied": 2}`.
+///
+/// This is intended to be added to the [rate_limit_admin] scope.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::admin::{rate_limit_admin, rate_limit_stats};
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_extensible_rate_limit::backend::stats::StatsBackend;
+/// # use actix_web::App;
+/// # #[actix_web::main]
+/// # async fn main() {
+/// let backend = StatsBackend::builder(InMemoryBackend::builder().build()).build();
+/// let admin = rate_limit_admin(backend.clone()).service(rate_limit_stats(backend.clone()));
+/// let app = App::new().service(admin);
+/// # }
+/// ```
+#[cfg(feature = "stats")]
+#[cfg_attr(docsrs, doc(cfg(feature = "stats")))]
+pub fn rate_limit_stats<B>(backend: StatsBackend<B>) -> Resource
+where
+    B: Clone + 'static,
+{
+    web::resource("/stats").route(web::get().to(move || {
+        let stats = backend.stats();
+        async move { HttpResponse::Ok().json(Stats::from(stats)) }
+    }))
+}
+
+/// Create a `GET` [Route] that returns the caller's current rate limit status, like GitHub's
+/// `/rate_limit` endpoint, without counting the request:
+///
+/// ```json
+/// {"limit": 100, "remaining": 42, "used": 58, "reset": 1700000000, "reset_after_seconds": 30}
+/// ```
+///
+/// Where `reset` is the Unix timestamp at which the limit resets.
+///
+/// The status is read using [Backend::peek], with the input from the `input_fn`, which should be
+/// the same input function as the [RateLimiter](crate::RateLimiter) being reported on, so that
+/// the caller has the same key and policy.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::admin::quota_status_handler;
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+/// # use actix_extensible_rate_limit::RateLimiter;
+/// # use actix_web::{web, App};
+/// # use std::time::Duration;
+/// # #[actix_web::main]
+/// # async fn main() {
+/// let backend = InMemoryBackend::builder().build();
+/// let input = || SimpleInputFunctionBuilder::new(Duration::from_secs(60), 100)
+///     .real_ip_key()
+///     .build();
+/// let app = App::new().service(
+///     web::scope("/v1")
+///         // Not rate limited itself
+///         .route("/rate_limit", quota_status_handler(backend.clone(), input()))
+///         .service(
+///             web::scope("")
+///                 .wrap(RateLimiter::builder(backend.clone(), input()).build()),
+///         ),
+/// );
+/// # }
+/// ```
+pub fn quota_status_handler<B, BI, F, O>(backend: B, input_fn: F) -> Route
+where
+    B: Backend<BI> + 'static,
+    B::Output: HeaderCompatibleOutput,
+    B::Error: Into<actix_web::Error>,
+    BI: 'static,
+    F: Fn(&ServiceRequest) -> O + 'static,
+    O: Future<Output = Result<BI, actix_web::Error>> + 'static,
+{
+    let input_fn = Arc::new(input_fn);
+    web::get().to(move |req: HttpRequest| {
+        let backend = backend.clone();
+        let input = input_fn(&ServiceRequest::from_request(req));
+        async move {
+            let (decision, output) = backend.peek(input.await?).await.map_err(Into::into)?;
+            Ok::<_, actix_web::Error>(HttpResponse::Ok().json(QuotaStatus::new(decision, &output)))
+        }
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct QuotaStatus {
+    limit: u64,
+    remaining: u64,
+    used: u64,
+    reset: u64,
+    reset_after_seconds: u64,
+}
+
+impl QuotaStatus {
+    fn new<BO: HeaderCompatibleOutput>(decision: Decision, output: &BO) -> Self {
+        // The peeked output is as if this request were counted, which it isn't
+        let remaining = match decision {
+            Decision::Allowed => output.remaining().saturating_add(1).min(output.limit()),
+            Decision::Denied => 0,
+        };
+        let reset_after_seconds = output.seconds_until_reset();
+        let reset_at = output
+            .reset_at()
+            .unwrap_or_else(|| SystemTime::now() + Duration::from_secs(reset_after_seconds));
+        let reset = reset_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self {
+            limit: output.limit(),
+            remaining,
+            used: output.limit().saturating_sub(remaining),
+            reset: reset.as_secs() + u64::from(reset.subsec_nanos() > 0),
+            reset_after_seconds,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    key: String,
+    count: u64,
+    ttl_seconds: u64,
+}
+
+impl From<KeyStatus> for Status {
+    fn from(status: KeyStatus) -> Self {
+        Self {
+            key: status.key,
+            count: status.count,
+            ttl_seconds: status.ttl.as_secs(),
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+#[derive(Debug, Serialize)]
+struct Stats {
+    top_requests: Vec<KeyStatsResponse>,
+    top_denied: Vec<KeyStatsResponse>,
+}
+
+#[cfg(feature = "stats")]
+#[derive(Debug, Serialize)]
+struct KeyStatsResponse {
+    key: String,
+    requests: u64,
+    denied: u64,
+}
+
+#[cfg(feature = "stats")]
+impl From<RateLimitStats> for Stats {
+    fn from(stats: RateLimitStats) -> Self {
+        let convert = |keys: Vec<KeyStats>| {
+            keys.into_iter()
+                .map(|k| KeyStatsResponse {
+                    key: k.key,
+                    requests: k.requests,
+                    denied: k.denied,
+                })
+                .collect()
+        };
+        Self {
+            top_requests: convert(stats.top_requests),
+            top_denied: convert(stats.top_denied),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    #[serde(default)]
+    prefix: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TopQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BanRequest {
+    key: String,
+    seconds: u64,
+}
+
+async fn list<B>(
+    backend: web::Data<B>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    B: SimpleBackend,
+    B::Error: Into<actix_web::Error>,
+{
+    let statuses = backend.list(&query.prefix).await.map_err(Into::into)?;
+    Ok(HttpResponse::Ok().json(statuses.into_iter().map(Status::from).collect::<Vec<_>>()))
+}
+
+async fn get<B>(
+    backend: web::Data<B>,
+    key: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    B: SimpleBackend,
+    B::Error: Into<actix_web::Error>,
+{
+    match backend.get(&key).await.map_err(Into::into)? {
+        Some(status) => Ok(HttpResponse::Ok().json(Status::from(status))),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+async fn reset<B>(
+    backend: web::Data<B>,
+    key: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    B: SimpleBackend,
+    B::Error: Into<actix_web::Error>,
+{
+    backend.remove_key(&key).await.map_err(Into::into)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn ban<B>(
+    backend: web::Data<B>,
+    body: web::Json<BanRequest>,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    B: SimpleBackend,
+    B::Error: Into<actix_web::Error>,
+{
+    if body.seconds == 0 {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Ban duration must be non-zero",
+        ));
+    }
+    if body.seconds > MAX_BAN_SECONDS {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "Ban duration must be at most {MAX_BAN_SECONDS} seconds"
+        )));
+    }
+    backend
+        .set_count(&body.key, u64::MAX, Duration::from_secs(body.seconds))
+        .await
+        .map_err(Into::into)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn top<B>(
+    backend: web::Data<B>,
+    query: web::Query<TopQuery>,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    B: SimpleBackend,
+    B::Error: Into<actix_web::Error>,
+{
+    let mut statuses = backend.list("").await.map_err(Into::into)?;
+    statuses.sort_by_key(|s| std::cmp::Reverse(s.count));
+    statuses.truncate(query.limit.unwrap_or(DEFAULT_TOP_LIMIT));
+    Ok(HttpResponse::Ok().json(statuses.into_iter().map(Status::from).collect::<Vec<_>>()))
+}
+
+#[cfg(all(test, feature = "dashmap"))]
+mod tests {
+    use super::*;
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::{SimpleInput, SimpleInputFunctionBuilder};
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_and_read_body_json, call_service, init_service, TestRequest};
+    use actix_web::App;
+    use serde_json::{json, Value};
+
+    #[actix_web::test]
+    async fn test_admin() {
+        let backend = InMemoryBackend::builder().build();
+        for (key, count) in [("1.2.3.4-/login", 3), ("5.6.7.8-/login", 1)] {
+            let input = SimpleInput {
+                interval: Duration::from_secs(60),
+                max_requests: 5,
+                key: key.into(),
+            };
+            for _ in 0..count {
+                backend.request(input.clone()).await.unwrap();
+            }
+        }
+        let app = init_service(App::new().service(rate_limit_admin(backend.clone()))).await;
+
+        let req = TestRequest::get()
+            .uri("/rate-limit/keys/1.2.3.4-/login")
+            .to_request();
+        let body: Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(body["key"], "1.2.3.4-/login");
+        assert_eq!(body["count"], 3);
+
+        let req = TestRequest::get()
+            .uri("/rate-limit/top?limit=1")
+            .to_request();
+        let body: Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["key"], "1.2.3.4-/login");
+
+        let req = TestRequest::get()
+            .uri("/rate-limit/keys?prefix=5.6")
+            .to_request();
+        let body: Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["key"], "5.6.7.8-/login");
+
+        let req = TestRequest::post()
+            .uri("/rate-limit/bans")
+            .set_json(json!({ "key": "5.6.7.8-/login", "seconds": 900 }))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        let status = backend.get("5.6.7.8-/login").await.unwrap().unwrap();
+        assert_eq!(status.count, u64::MAX);
+        let req = TestRequest::post()
+            .uri("/rate-limit/bans")
+            .set_json(json!({ "key": "5.6.7.8-/login", "seconds": u64::MAX }))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let req = TestRequest::delete()
+            .uri("/rate-limit/keys/5.6.7.8-/login")
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        let req = TestRequest::get()
+            .uri("/rate-limit/keys/5.6.7.8-/login")
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_quota_status() {
+        let backend = InMemoryBackend::builder().build();
+        let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 2)
+            .custom_key("KEY1")
+            .build();
+        let app = init_service(
+            App::new().route("/rate_limit", quota_status_handler(backend.clone(), input)),
+        )
+        .await;
+        let status = || async {
+            let req = TestRequest::get().uri("/rate_limit").to_request();
+            let body: Value = call_and_read_body_json(&app, req).await;
+            body
+        };
+        let input = SimpleInput {
+            interval: Duration::from_secs(60),
+            max_requests: 2,
+            key: "KEY1".into(),
+        };
+
+        let body = status().await;
+        assert_eq!(body["limit"], 2);
+        assert_eq!(body["remaining"], 2);
+        assert_eq!(body["used"], 0);
+        // Checking the status doesn't count
+        let body = status().await;
+        assert_eq!(body["remaining"], 2);
+
+        backend.request(input.clone()).await.unwrap();
+        let body = status().await;
+        assert_eq!(body["remaining"], 1);
+        assert_eq!(body["used"], 1);
+        let reset_after = body["reset_after_seconds"].as_u64().unwrap();
+        assert!(reset_after > 0 && reset_after <= 60);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let reset = body["reset"].as_u64().unwrap();
+        assert!(reset > now && reset <= now + 61);
+
+        backend.request(input.clone()).await.unwrap();
+        backend.request(input).await.unwrap();
+        let body = status().await;
+        assert_eq!(body["remaining"], 0);
+        assert_eq!(body["used"], 2);
+    }
+
+    #[cfg(feature = "stats")]
+    #[actix_web::test]
+    async fn test_stats() {
+        use crate::backend::stats::StatsBackend;
+
+        let backend = StatsBackend::builder(InMemoryBackend::builder().build()).build();
+        let input = SimpleInput {
+            interval: Duration::from_secs(60),
+            max_requests: 1,
+            key: "KEY1".into(),
+        };
+        backend.request(input.clone()).await.unwrap();
+        backend.request(input).await.unwrap();
+        let admin = rate_limit_admin(backend.clone()).service(rate_limit_stats(backend.clone()));
+        let app = init_service(App::new().service(admin)).await;
+
+        let req = TestRequest::get().uri("/rate-limit/stats").to_request();
+        let body: Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body["top_denied"],
+            json!([{ "key": "KEY1", "requests": 2, "denied": 1 }])
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use crate::backend::{Backend, Decision, SimpleInput, SimpleOutput};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: i64 = 60 * 60 * 24;
+
+/// The calendar period of a [CalendarQuotaBackend].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CalendarPeriod {
+    /// Resets every day at midnight.
+    Day,
+    /// Resets at midnight on the first day of every month.
+    Month,
+    /// Resets at midnight on the 1st of January.
+    Year,
+}
+
+/// A [Backend] decorator for quotas that reset at calendar boundaries, e.g. "10,000 requests per
+/// month" resetting on the 1st, since fixed intervals of 30 days drift away from the calendar.
+///
+/// Each period is counted by the inner backend under the rate limit key suffixed with the period,
+/// e.g. `-2024-02` for a monthly quota, and an interval lasting until the end of the period, so
+/// the quota is persisted and shared wherever the inner backend is, e.g. Redis. The
+/// [SimpleInput::interval] of the input is ignored.
+///
+/// The outputs have the exact [SimpleOutput::reset_at] of the period, which can be sent to
+/// clients using [ResetFormat::UnixTimestamp](crate::ResetFormat::UnixTimestamp).
+///
+/// The inner backend should start each key's window at its first request (the default for the
+/// [InMemoryBackend](crate::backend::memory::InMemoryBackend) and the
+/// [RedisBackend](crate::backend::redis::RedisBackend)), rather than being aligned itself.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::calendar::{CalendarPeriod, CalendarQuotaBackend};
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+/// # use actix_extensible_rate_limit::{RateLimiter, ResetFormat};
+/// # use std::time::Duration;
+/// # actix_web::rt::System::new().block_on(async {
+/// // 10,000 requests per month, resetting at midnight in UTC+1
+/// let backend = CalendarQuotaBackend::builder(InMemoryBackend::builder().build(), CalendarPeriod::Month)
+///     .utc_offset(60 * 60)
+///     .build();
+/// // The interval is ignored
+/// let input = SimpleInputFunctionBuilder::new(Duration::ZERO, 10_000)
+///     .real_ip_key()
+///     .build();
+/// let middleware = RateLimiter::builder(backend, input)
+///     .add_headers_with_reset_format(ResetFormat::UnixTimestamp)
+///     .build();
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct CalendarQuotaBackend<B> {
+    backend: B,
+    period: CalendarPeriod,
+    offset_seconds: i32,
+}
+
+/// The period containing a point in time.
+#[derive(Debug, Eq, PartialEq)]
+struct Period {
+    /// The key suffix, e.g. `2024-02`.
+    label: String,
+    reset_at: SystemTime,
+}
+
+impl<B> CalendarQuotaBackend<B> {
+    pub fn builder(backend: B, period: CalendarPeriod) -> Builder<B> {
+        Builder {
+            backend,
+            period,
+            offset_seconds: 0,
+        }
+    }
+
+    fn period(&self, now: SystemTime) -> Period {
+        let offset = i64::from(self.offset_seconds);
+        let unix = now
+            .duration_since(UNIX_EPOCH)
+            .expect("System clock is before the Unix epoch")
+            .as_secs() as i64;
+        let day = (unix + offset).div_euclid(SECONDS_PER_DAY);
+        let (year, month, date) = civil_from_days(day);
+        let (label, end_day) = match self.period {
+            CalendarPeriod::Day => (format!("{year:04}-{month:02}-{date:02}"), day + 1),
+            CalendarPeriod::Month => {
+                let end_day = match month {
+                    12 => days_from_civil(year + 1, 1, 1),
+                    _ => days_from_civil(year, month + 1, 1),
+                };
+                (format!("{year:04}-{month:02}"), end_day)
+            }
+            CalendarPeriod::Year => (format!("{year:04}"), days_from_civil(year + 1, 1, 1)),
+        };
+        let reset = end_day * SECONDS_PER_DAY - offset;
+        Period {
+            label,
+            reset_at: UNIX_EPOCH + Duration::from_secs(reset as u64),
+        }
+    }
+
+    /// Counts the input against the current period instead of its own interval.
+    fn period_input(&self, mut input: SimpleInput) -> (SimpleInput, SystemTime) {
+        let now = SystemTime::now();
+        let period = self.period(now);
+        input.key = format!("{}-{}", input.key, period.label).into();
+        input.interval = period
+            .reset_at
+            .duration_since(now)
+            .unwrap_or_default()
+            .max(Duration::from_millis(1));
+        (input, period.reset_at)
+    }
+}
+
+pub struct Builder<B> {
+    backend: B,
+    period: CalendarPeriod,
+    offset_seconds: i32,
+}
+
+impl<B> Builder<B> {
+    /// Override the time zone, as an offset in seconds east of UTC, e.g. `-5 * 3600` for the
+    /// periods to start at midnight in UTC-5.
+    ///
+    /// Daylight saving time isn't taken into account, so a zone's standard offset should be
+    /// used.
+    ///
+    /// Defaults to 0 (UTC).
+    pub fn utc_offset(mut self, offset_seconds: i32) -> Self {
+        assert!(
+            i64::from(offset_seconds).abs() < SECONDS_PER_DAY,
+            "UTC offset must be less than a day"
+        );
+        self.offset_seconds = offset_seconds;
+        self
+    }
+
+    pub fn build(self) -> CalendarQuotaBackend<B> {
+        CalendarQuotaBackend {
+            backend: self.backend,
+            period: self.period,
+            offset_seconds: self.offset_seconds,
+        }
+    }
+}
+
+impl<B> Backend<SimpleInput> for CalendarQuotaBackend<B>
+where
+    B: Backend<SimpleInput, Output = SimpleOutput>,
+{
+    type Output = SimpleOutput;
+    type RollbackToken = B::RollbackToken;
+    type Error = B::Error;
+
+    async fn request(
+        &self,
+        input: SimpleInput,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        let (input, reset_at) = self.period_input(input);
+        let (decision, mut output, token) = self.backend.request(input).await?;
+        output.reset_at = Some(reset_at);
+        Ok((decision, output, token))
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        self.backend.rollback(token).await
+    }
+
+    async fn peek(&self, input: SimpleInput) -> Result<(Decision, Self::Output), Self::Error> {
+        let (input, reset_at) = self.period_input(input);
+        let (decision, mut output) = self.backend.peek(input).await?;
+        output.reset_at = Some(reset_at);
+        Ok((decision, output))
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        self.backend.health_check().await
+    }
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date in the proleptic Gregorian
+/// calendar, see <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) date to days since the Unix epoch, the inverse of
+/// [civil_from_days].
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(unix: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(unix)
+    }
+
+    fn period(period: CalendarPeriod, offset_seconds: i32, unix: u64) -> (String, u64) {
+        let backend = CalendarQuotaBackend::builder((), period)
+            .utc_offset(offset_seconds)
+            .build();
+        let period = backend.period(at(unix));
+        let reset = period.reset_at.duration_since(UNIX_EPOCH).unwrap();
+        (period.label, reset.as_secs())
+    }
+
+    #[test]
+    fn test_civil_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        // 2024-02-29
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(days_from_civil(2024, 3, 1), 19783);
+        for days in [-1000, 0, 11016, 19782, 100000] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_period() {
+        // 2024-02-29T12:00:00Z
+        let leap_day = 1709208000;
+        assert_eq!(
+            period(CalendarPeriod::Day, 0, leap_day),
+            ("2024-02-29".to_owned(), 1709251200)
+        );
+        // Resets on 2024-03-01T00:00:00Z
+        assert_eq!(
+            period(CalendarPeriod::Month, 0, leap_day),
+            ("2024-02".to_owned(), 1709251200)
+        );
+        assert_eq!(
+            period(CalendarPeriod::Year, 0, leap_day),
+            ("2024".to_owned(), 1735689600)
+        );
+        // 2024-03-01T02:00:00Z is still February in UTC-5, which resets at 05:00 UTC
+        assert_eq!(
+            period(CalendarPeriod::Month, -5 * 3600, 1709258400),
+            ("2024-02".to_owned(), 1709269200)
+        );
+        // 2023-12-31T23:00:00Z is already January in UTC+2
+        assert_eq!(
+            period(CalendarPeriod::Month, 2 * 3600, 1704063600),
+            ("2024-01".to_owned(), 1706738400)
+        );
+    }
+
+    #[cfg(feature = "dashmap")]
+    #[actix_web::test]
+    async fn test_calendar_quota() {
+        use crate::backend::memory::InMemoryBackend;
+        use crate::backend::SimpleBackend;
+
+        let inner = InMemoryBackend::builder().build();
+        let backend = CalendarQuotaBackend::builder(inner.clone(), CalendarPeriod::Day).build();
+        let input = SimpleInput {
+            interval: Duration::ZERO,
+            max_requests: 1,
+            key: "KEY1".into(),
+        };
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        let reset_at = output.reset_at.unwrap().duration_since(UNIX_EPOCH).unwrap();
+        assert_eq!(reset_at.as_secs() % SECONDS_PER_DAY as u64, 0);
+        let (decision, _) = backend.peek(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+
+        let label = backend.period(SystemTime::now()).label;
+        let status = inner.get(&format!("KEY1-{label}")).await.unwrap().unwrap();
+        assert_eq!(status.count, 1);
+    }
+}
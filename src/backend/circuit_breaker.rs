@@ -0,0 +1,325 @@
+use crate::backend::{Backend, BackendError, ClassifyError, Decision, KeyStatus, SimpleBackend};
+#[cfg(feature = "actix")]
+use actix_web::http::StatusCode;
+#[cfg(feature = "actix")]
+use actix_web::{HttpResponse, ResponseError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::Instant;
+
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+pub const DEFAULT_COOL_DOWN_SECONDS: u64 = 30;
+
+#[derive(Debug, Error)]
+pub enum Error<E> {
+    #[error("Circuit breaker is open")]
+    Open,
+    #[error("{0}")]
+    Backend(E),
+}
+
+impl<E: ClassifyError> ClassifyError for Error<E> {
+    fn class(&self) -> BackendError {
+        match self {
+            Error::Open => BackendError::Transient,
+            Error::Backend(e) => e.class(),
+        }
+    }
+}
+
+#[cfg(feature = "actix")]
+impl<E: ResponseError> ResponseError for Error<E> {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Open => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Backend(e) => e.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Error::Open => HttpResponse::ServiceUnavailable().finish(),
+            Error::Backend(e) => e.error_response(),
+        }
+    }
+}
+
+/// A [Backend] decorator that stops calling the inner backend after a number of consecutive
+/// failures, instead of adding load to a store that is already struggling.
+///
+/// While the circuit is open every request immediately fails with [Error::Open]. Whether those
+/// requests are then allowed or denied is controlled by
+/// [RateLimiterBuilder::fail_open](crate::RateLimiterBuilder::fail_open).
+///
+/// After the cool-down period the circuit is half-open: requests are passed to the inner backend
+/// again, the first success closes the circuit, and the first failure opens it again.
+///
+/// Only [Transient](BackendError::Transient) errors count as failures; the other classes mean the
+/// store did respond, so they are returned without affecting the circuit.
+pub struct CircuitBreakerBackend<B> {
+    backend: B,
+    failure_threshold: u32,
+    cool_down: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl<B: Clone> Clone for CircuitBreakerBackend<B> {
+    fn clone(&self) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            failure_threshold: self.failure_threshold,
+            cool_down: self.cool_down,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<B> CircuitBreakerBackend<B> {
+    pub fn builder(backend: B) -> Builder<B> {
+        Builder {
+            backend,
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            cool_down: Duration::from_secs(DEFAULT_COOL_DOWN_SECONDS),
+        }
+    }
+
+    /// Returns true if the circuit is currently open, i.e. the inner backend is not being called.
+    pub fn is_open(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .open_until
+            .is_some_and(|open_until| open_until > Instant::now())
+    }
+
+    fn record<T, E: ClassifyError>(&self, result: Result<T, E>) -> Result<T, Error<E>> {
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(value) => {
+                state.consecutive_failures = 0;
+                state.open_until = None;
+                Ok(value)
+            }
+            Err(e) if !e.class().is_transient() => Err(Error::Backend(e)),
+            Err(e) => {
+                state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+                // Open (or re-open when half-open) the circuit
+                if state.consecutive_failures >= self.failure_threshold
+                    || state.open_until.is_some()
+                {
+                    log::warn!("Rate limiter circuit breaker opened");
+                    state.open_until = Some(Instant::now() + self.cool_down);
+                }
+                Err(Error::Backend(e))
+            }
+        }
+    }
+}
+
+pub struct Builder<B> {
+    backend: B,
+    failure_threshold: u32,
+    cool_down: Duration,
+}
+
+impl<B> Builder<B> {
+    /// Override the number of consecutive failures after which the circuit is opened.
+    ///
+    /// Defaults to 5.
+    pub fn failure_threshold(mut self, failure_threshold: u32) -> Self {
+        assert!(failure_threshold > 0, "Failure threshold must be non-zero");
+        self.failure_threshold = failure_threshold;
+        self
+    }
+
+    /// Override how long the circuit stays open before calling the inner backend again.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn cool_down(mut self, cool_down: Duration) -> Self {
+        self.cool_down = cool_down;
+        self
+    }
+
+    pub fn build(self) -> CircuitBreakerBackend<B> {
+        CircuitBreakerBackend {
+            backend: self.backend,
+            failure_threshold: self.failure_threshold,
+            cool_down: self.cool_down,
+            state: Default::default(),
+        }
+    }
+}
+
+impl<B, I> Backend<I> for CircuitBreakerBackend<B>
+where
+    B: Backend<I>,
+    B::Error: ClassifyError,
+    I: 'static,
+{
+    type Output = B::Output;
+    type RollbackToken = B::RollbackToken;
+    type Error = Error<B::Error>;
+
+    async fn request(
+        &self,
+        input: I,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        if self.is_open() {
+            return Err(Error::Open);
+        }
+        let result = self.backend.request(input).await;
+        self.record(result)
+    }
+
+    async fn request_batch(
+        &self,
+        inputs: Vec<I>,
+    ) -> Result<Vec<(Decision, Self::Output, Self::RollbackToken)>, Self::Error> {
+        if self.is_open() {
+            return Err(Error::Open);
+        }
+        let result = self.backend.request_batch(inputs).await;
+        self.record(result)
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        if self.is_open() {
+            return Err(Error::Open);
+        }
+        let result = self.backend.rollback(token).await;
+        self.record(result)
+    }
+
+    async fn peek(&self, input: I) -> Result<(Decision, Self::Output), Self::Error> {
+        if self.is_open() {
+            return Err(Error::Open);
+        }
+        let result = self.backend.peek(input).await;
+        self.record(result)
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        // Report the health of the backend itself, regardless of the circuit state
+        self.backend.health_check().await.map_err(Error::Backend)
+    }
+}
+
+impl<B> SimpleBackend for CircuitBreakerBackend<B>
+where
+    B: SimpleBackend,
+    B::Error: ClassifyError,
+{
+    async fn remove_key(&self, key: &str) -> Result<(), Self::Error> {
+        self.backend.remove_key(key).await.map_err(Error::Backend)
+    }
+
+    async fn clear(&self, prefix: &str) -> Result<(), Self::Error> {
+        self.backend.clear(prefix).await.map_err(Error::Backend)
+    }
+
+    async fn set_count(&self, key: &str, count: u64, ttl: Duration) -> Result<(), Self::Error> {
+        self.backend
+            .set_count(key, count, ttl)
+            .await
+            .map_err(Error::Backend)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<KeyStatus>, Self::Error> {
+        self.backend.get(key).await.map_err(Error::Backend)
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<KeyStatus>, Self::Error> {
+        self.backend.list(prefix).await.map_err(Error::Backend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    #[derive(Clone, Default)]
+    struct MockBackend {
+        calls: Arc<AtomicU32>,
+        failing: Arc<AtomicBool>,
+        rejecting: Arc<AtomicBool>,
+    }
+
+    impl Backend<()> for MockBackend {
+        type Output = ();
+        type RollbackToken = ();
+        type Error = BackendError;
+
+        async fn request(
+            &self,
+            _: (),
+        ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            if self.failing.load(Ordering::Relaxed) {
+                Err(BackendError::Transient)
+            } else if self.rejecting.load(Ordering::Relaxed) {
+                Err(BackendError::KeyRejected)
+            } else {
+                Ok((Decision::Allowed, (), ()))
+            }
+        }
+
+        async fn rollback(&self, _: Self::RollbackToken) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_circuit_breaker() {
+        tokio::time::pause();
+        let inner = MockBackend::default();
+        let backend = CircuitBreakerBackend::builder(inner.clone())
+            .failure_threshold(2)
+            .cool_down(Duration::from_secs(10))
+            .build();
+        inner.failing.store(true, Ordering::Relaxed);
+        assert!(matches!(backend.request(()).await, Err(Error::Backend(_))));
+        assert!(!backend.is_open());
+        assert!(matches!(backend.request(()).await, Err(Error::Backend(_))));
+        assert!(backend.is_open());
+        // The inner backend should no longer be called
+        assert!(matches!(backend.request(()).await, Err(Error::Open)));
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 2);
+
+        // After the cool-down a single failure re-opens the circuit
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(matches!(backend.request(()).await, Err(Error::Backend(_))));
+        assert!(backend.is_open());
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 3);
+
+        // After the next cool-down a success closes the circuit
+        tokio::time::advance(Duration::from_secs(10)).await;
+        inner.failing.store(false, Ordering::Relaxed);
+        assert!(backend.request(()).await.is_ok());
+        assert!(!backend.is_open());
+        inner.failing.store(true, Ordering::Relaxed);
+        assert!(matches!(backend.request(()).await, Err(Error::Backend(_))));
+        assert!(!backend.is_open());
+    }
+
+    #[actix_web::test]
+    async fn test_circuit_breaker_ignores_rejected_keys() {
+        let inner = MockBackend::default();
+        let backend = CircuitBreakerBackend::builder(inner.clone())
+            .failure_threshold(1)
+            .build();
+        inner.rejecting.store(true, Ordering::Relaxed);
+        let result = backend.request(()).await;
+        assert!(matches!(
+            result,
+            Err(Error::Backend(BackendError::KeyRejected))
+        ));
+        assert!(!backend.is_open());
+    }
+}
@@ -0,0 +1,153 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{HeaderName, FORWARDED};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+#[allow(clippy::declare_interior_mutable_const)]
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// The address of a client, as forwarded by a proxy.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum ClientAddr {
+    Ip(IpAddr),
+    /// An obfuscated identifier from the `Forwarded` header, e.g. `_hidden`, see
+    /// [RFC 7239](https://www.rfc-editor.org/rfc/rfc7239#section-6.3).
+    Obfuscated(String),
+}
+
+impl ClientAddr {
+    /// Parses a forwarded address, which may be an IP address (optionally with a port) or an
+    /// obfuscated identifier.
+    pub(crate) fn parse(addr: &str) -> Option<Self> {
+        if addr.starts_with('_') {
+            return Some(Self::Obfuscated(addr.to_owned()));
+        }
+        parse_ip(addr).map(Self::Ip)
+    }
+}
+
+/// Resolves the client address of a request that may have been forwarded by trusted proxies.
+///
+/// Starting from the connection peer, the forwarded chain is walked from the right (i.e. the
+/// most recent hop) for as long as the address is a trusted proxy. The first untrusted address
+/// is the client, in the same way as nginx's `set_real_ip_from` with `real_ip_recursive`.
+///
+/// The chain is read from the `Forwarded` header if present, otherwise `X-Forwarded-For`.
+///
+/// Returns None if the peer address is unknown.
+pub(crate) fn client_addr(req: &ServiceRequest, trusted_proxies: &[IpNet]) -> Option<ClientAddr> {
+    let trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let mut client = ClientAddr::Ip(req.peer_addr()?.ip());
+    for hop in forwarded_chain(req).into_iter().rev() {
+        match &client {
+            ClientAddr::Ip(ip) if trusted(ip) => {}
+            _ => break,
+        }
+        match hop {
+            Some(addr) => client = addr,
+            // A trusted proxy forwarded an unknown address, so it can't be walked further
+            None => break,
+        }
+    }
+    Some(client)
+}
+
+/// Returns the forwarded addresses, from the client to the most recent proxy.
+fn forwarded_chain(req: &ServiceRequest) -> Vec<Option<ClientAddr>> {
+    let header_values = |name| {
+        req.headers()
+            .get_all(name)
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(','))
+            .map(str::trim)
+    };
+    if req.headers().contains_key(FORWARDED) {
+        // e.g. `for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"`
+        header_values(FORWARDED)
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| ClientAddr::parse(value.trim_matches('"')))
+            })
+            .collect()
+    } else {
+        header_values(X_FORWARDED_FOR)
+            .map(ClientAddr::parse)
+            .collect()
+    }
+}
+
+/// Parses an IP address, that may include a port, or be an IPv6 address in square brackets.
+pub(crate) fn parse_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<IpAddr>()
+        .ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|s| s.ip()))
+        .or_else(|| {
+            addr.strip_prefix('[')
+                .and_then(|addr| addr.strip_suffix(']'))
+                .and_then(|addr| addr.parse().ok())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_parse_ip() {
+        assert_eq!(parse_ip("10.0.0.1"), Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(parse_ip("10.0.0.1:8080"), Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(parse_ip("[::1]:8080"), Some("::1".parse().unwrap()));
+        assert_eq!(parse_ip("[::1]"), Some("::1".parse().unwrap()));
+        assert_eq!(parse_ip("unknown"), None);
+    }
+
+    fn client_ip(peer: &str, header: (HeaderName, &str)) -> String {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let req = TestRequest::default()
+            .peer_addr(format!("{peer}:1234").parse().unwrap())
+            .insert_header(header)
+            .to_srv_request();
+        match client_addr(&req, &trusted).unwrap() {
+            ClientAddr::Ip(ip) => ip.to_string(),
+            ClientAddr::Obfuscated(obfuscated) => obfuscated,
+        }
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let client_ip = |peer, forwarded_for| client_ip(peer, (X_FORWARDED_FOR, forwarded_for));
+        // Untrusted peers can't spoof their address
+        assert_eq!(client_ip("203.0.113.1", "198.51.100.1"), "203.0.113.1");
+        // The chain is walked until the first untrusted address
+        assert_eq!(client_ip("10.0.0.1", "198.51.100.1"), "198.51.100.1");
+        assert_eq!(
+            client_ip("10.0.0.1", "192.0.2.1, 198.51.100.1, 10.0.0.2"),
+            "198.51.100.1"
+        );
+        // If every hop is trusted, the leftmost is used
+        assert_eq!(client_ip("10.0.0.1", "10.0.0.3, 10.0.0.2"), "10.0.0.3");
+        assert_eq!(client_ip("10.0.0.1", "garbage"), "10.0.0.1");
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let client_ip = |peer, forwarded| client_ip(peer, (FORWARDED, forwarded));
+        assert_eq!(client_ip("203.0.113.1", "for=198.51.100.1"), "203.0.113.1");
+        assert_eq!(
+            client_ip(
+                "10.0.0.1",
+                "for=192.0.2.1;proto=http, For=\"[2001:db8::1]:4711\";by=10.0.0.1"
+            ),
+            "2001:db8::1"
+        );
+        assert_eq!(
+            client_ip("10.0.0.1", "for=_hidden, for=10.0.0.2"),
+            "_hidden"
+        );
+        assert_eq!(client_ip("10.0.0.1", "for=unknown"), "10.0.0.1");
+    }
+}
@@ -0,0 +1,126 @@
+use crate::backend::{Decision, SimpleBackend, SimpleInput, SimpleOutput};
+use std::time::Duration;
+
+/// A handle for drawing from rate limit budgets outside of the HTTP middleware.
+///
+/// This allows background jobs (e.g. sending emails or dispatching webhooks) to share the same
+/// per-tenant budgets as HTTP traffic, by using a clone of the same backend, and generating the
+/// same keys as the middleware input function.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::Consumer;
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use std::time::Duration;
+/// # actix_web::rt::System::new().block_on(async {
+/// let backend = InMemoryBackend::builder().build();
+/// // The same limit as the middleware input function
+/// let consumer = Consumer::simple(backend.clone(), Duration::from_secs(60), 100);
+///
+/// // Sending a batch of 10 emails costs 10 requests
+/// let (decision, _) = consumer.consume("tenant-1", 10).await.unwrap();
+/// if decision.is_allowed() {
+///     // Send the emails
+/// }
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct Consumer<B, F> {
+    backend: B,
+    input_fn: F,
+}
+
+impl<B, F> Consumer<B, F> {
+    /// # Arguments
+    ///
+    /// * `backend`: The backend to draw from, normally a clone of the middleware backend.
+    /// * `input_fn`: Produces input to the backend for a given key.
+    pub fn new(backend: B, input_fn: F) -> Self
+    where
+        B: SimpleBackend,
+        F: Fn(&str) -> SimpleInput,
+    {
+        Self { backend, input_fn }
+    }
+}
+
+impl<B> Consumer<B, ()> {
+    /// Create a consumer with a fixed interval and max requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend`: The backend to draw from, normally a clone of the middleware backend.
+    /// * `interval`: Should match the interval of the middleware input function.
+    /// * `max_requests`: Should match the max requests of the middleware input function.
+    pub fn simple(
+        backend: B,
+        interval: Duration,
+        max_requests: u64,
+    ) -> Consumer<B, impl Fn(&str) -> SimpleInput + Clone>
+    where
+        B: SimpleBackend,
+    {
+        Consumer::new(backend, move |key: &str| SimpleInput {
+            interval,
+            max_requests,
+            key: key.into(),
+        })
+    }
+}
+
+impl<B, F> Consumer<B, F>
+where
+    B: SimpleBackend,
+    F: Fn(&str) -> SimpleInput,
+{
+    /// Draw `cost` requests from the budget for the given key, see [SimpleBackend::consume].
+    ///
+    /// The cost is charged with a single atomic increment. Like requests made by the middleware,
+    /// the cost is charged even if the decision is denied, which is whether the count is still
+    /// within the limit after charging. A cost of zero is charged as one request.
+    pub async fn consume(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<(Decision, SimpleOutput), B::Error> {
+        self.backend.consume((self.input_fn)(key), cost).await
+    }
+}
+
+#[cfg(all(test, feature = "dashmap"))]
+mod tests {
+    use super::*;
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::Backend;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[actix_web::test]
+    async fn test_consume() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder().build();
+        let consumer = Consumer::simple(backend.clone(), MINUTE, 10);
+        let (decision, output) = consumer.consume("KEY1", 4).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 6);
+
+        // The budget is shared with other users of the backend
+        let (_, output, _) = backend
+            .request(SimpleInput {
+                interval: MINUTE,
+                max_requests: 10,
+                key: "KEY1".into(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(output.remaining, 5);
+
+        // Exceeding the budget is denied, but still charged
+        let (decision, output) = consumer.consume("KEY1", 6).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.remaining, 0);
+        let status = backend.get("KEY1").await.unwrap().unwrap();
+        assert_eq!(status.count, 11);
+    }
+}
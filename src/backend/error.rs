@@ -0,0 +1,72 @@
+use std::convert::Infallible;
+use std::fmt::{Display, Formatter};
+
+/// The class of a [Backend](crate::backend::Backend) error, so that the
+/// [RateLimiter](crate::RateLimiter) and decorators can react differently to each.
+///
+/// | Class                       | Fails open with [fail_open_transient] | Retried by [RetryTransient] | Trips the circuit breaker |
+/// |-----------------------------|---------------------------------------|-----------------------------|---------------------------|
+/// | `Transient`                 | Yes                                   | Yes                         | Yes                       |
+/// | `PermanentMisconfiguration` | No                                    | No                          | No                        |
+/// | `KeyRejected`               | No                                    | No                          | No                        |
+///
+/// Every class fails open with [fail_open].
+///
+/// [fail_open_transient]: crate::RateLimiterBuilder::fail_open_transient
+/// [fail_open]: crate::RateLimiterBuilder::fail_open
+/// [RetryTransient]: crate::backend::retry::RetryTransient
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BackendError {
+    /// The store is temporarily unavailable, e.g. a timeout or dropped connection, and the same
+    /// call may succeed later.
+    Transient,
+    /// The backend is misconfigured, e.g. invalid credentials, and will keep failing until it is
+    /// fixed; failing open would silently disable rate limiting.
+    PermanentMisconfiguration,
+    /// The store refused this particular key, e.g. it holds data of an unexpected type; other
+    /// keys are unaffected.
+    KeyRejected,
+}
+
+impl BackendError {
+    /// The class as a `snake_case` string, used in logs and metrics.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Transient => "transient",
+            Self::PermanentMisconfiguration => "permanent_misconfiguration",
+            Self::KeyRejected => "key_rejected",
+        }
+    }
+
+    pub fn is_transient(self) -> bool {
+        matches!(self, Self::Transient)
+    }
+}
+
+impl Display for BackendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Maps a [Backend::Error](crate::backend::Backend::Error) into its [BackendError] class.
+///
+/// Errors are [Transient](BackendError::Transient) unless the implementation says otherwise, so
+/// an error type that can't tell can simply use `impl ClassifyError for MyError {}`.
+pub trait ClassifyError {
+    fn class(&self) -> BackendError {
+        BackendError::Transient
+    }
+}
+
+impl ClassifyError for BackendError {
+    fn class(&self) -> BackendError {
+        *self
+    }
+}
+
+impl ClassifyError for Infallible {
+    fn class(&self) -> BackendError {
+        match *self {}
+    }
+}
@@ -0,0 +1,207 @@
+use crate::backend::{Backend, BackendError, ClassifyError, Decision, SimpleInput, SimpleOutput};
+use ::governor::clock::{Clock, DefaultClock};
+use ::governor::middleware::StateInformationMiddleware;
+use ::governor::state::keyed::{DefaultKeyedStateStore, KeyedStateStore};
+use ::governor::{Quota, RateLimiter};
+#[cfg(feature = "actix")]
+use actix_web::ResponseError;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::time::Instant;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Governor can't check a key without consuming its quota, see [GovernorBackend].
+    #[error("The governor backend doesn't support peeking")]
+    PeekUnsupported,
+}
+
+impl ClassifyError for Error {
+    fn class(&self) -> BackendError {
+        BackendError::PermanentMisconfiguration
+    }
+}
+
+#[cfg(feature = "actix")]
+impl ResponseError for Error {}
+
+/// The governor rate limiter used by a [GovernorBackend].
+pub type Limiter<S> = RateLimiter<Arc<str>, S, DefaultClock, StateInformationMiddleware>;
+
+/// A [Backend] that uses a [governor] keyed rate limiter, to ease migrating from
+/// `actix-governor` without rewriting the existing quotas first.
+///
+/// The rate limit key is taken from the [SimpleInput], but its `interval` and `max_requests` are
+/// ignored, since the limit is defined by the governor [Quota] instead. Rollbacks are not
+/// supported by governor, so they have no effect.
+///
+/// Governor also can't check a key without consuming its quota, so [Backend::peek] fails with
+/// [Error::PeekUnsupported], rather than charging the client. This backend therefore can't be
+/// used with `RateLimiterBuilder::peek_only`, the `RateLimitGuard` or the
+/// `admin::quota_status_handler`.
+///
+/// The output reports the burst size as the limit, and the remaining burst capacity. When allowed,
+/// the reset is when the burst capacity will be fully replenished; when denied, it is the earliest
+/// time that the next request will be allowed.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::governor::GovernorBackend;
+/// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+/// # use actix_extensible_rate_limit::RateLimiter;
+/// # use governor::Quota;
+/// # use std::num::NonZeroU32;
+/// # use std::time::Duration;
+/// // Equivalent to GovernorConfigBuilder::default().per_second(2).burst_size(5)
+/// let quota = Quota::with_period(Duration::from_secs(2))
+///     .unwrap()
+///     .allow_burst(NonZeroU32::new(5).unwrap());
+/// let backend = GovernorBackend::new(quota);
+/// // The policy of the input function is ignored, only the key is used
+/// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(1), 1)
+///     .peer_ip_key()
+///     .build();
+/// let middleware = RateLimiter::builder(backend, input).add_headers().build();
+/// ```
+pub struct GovernorBackend<S: KeyedStateStore<Arc<str>> = DefaultKeyedStateStore<Arc<str>>> {
+    limiter: Arc<Limiter<S>>,
+}
+
+impl<S: KeyedStateStore<Arc<str>>> Clone for GovernorBackend<S> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+impl GovernorBackend {
+    /// Creates a backend using governor's default keyed state store.
+    pub fn new(quota: Quota) -> Self {
+        Self::from_limiter(RateLimiter::keyed(quota))
+    }
+}
+
+impl<S: KeyedStateStore<Arc<str>>> GovernorBackend<S> {
+    /// Creates a backend from an existing keyed rate limiter, e.g. one using a different state
+    /// store.
+    pub fn from_limiter<MW>(limiter: RateLimiter<Arc<str>, S, DefaultClock, MW>) -> Self
+    where
+        MW: ::governor::middleware::RateLimitingMiddleware<<DefaultClock as Clock>::Instant>,
+    {
+        Self {
+            limiter: Arc::new(limiter.with_middleware()),
+        }
+    }
+
+    /// The underlying rate limiter, e.g. to call
+    /// [retain_recent](::governor::RateLimiter::retain_recent) periodically.
+    pub fn limiter(&self) -> &Limiter<S> {
+        &self.limiter
+    }
+}
+
+impl<S> Backend<SimpleInput> for GovernorBackend<S>
+where
+    S: KeyedStateStore<Arc<str>>,
+{
+    type Output = SimpleOutput;
+    type RollbackToken = ();
+    type Error = Error;
+
+    async fn request(
+        &self,
+        input: SimpleInput,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        let now = Instant::now();
+        let (decision, output) = match self.limiter.check_key(&input.key) {
+            Ok(snapshot) => {
+                let quota = snapshot.quota();
+                let limit = quota.burst_size().get();
+                let remaining = snapshot.remaining_burst_capacity();
+                let output = SimpleOutput {
+                    limit: u64::from(limit),
+                    remaining: u64::from(remaining),
+                    reset: now + quota.replenish_interval() * (limit - remaining),
+                    reset_at: None,
+                };
+                (Decision::Allowed, output)
+            }
+            Err(not_until) => {
+                let wait = not_until.wait_time_from(DefaultClock::default().now());
+                let output = SimpleOutput {
+                    limit: u64::from(not_until.quota().burst_size().get()),
+                    remaining: 0,
+                    reset: now + wait,
+                    reset_at: None,
+                };
+                (Decision::Denied, output)
+            }
+        };
+        Ok((decision, output, ()))
+    }
+
+    async fn rollback(&self, _: Self::RollbackToken) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn peek(&self, _: SimpleInput) -> Result<(Decision, Self::Output), Self::Error> {
+        Err(Error::PeekUnsupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    #[actix_web::test]
+    async fn test_governor() {
+        let quota = Quota::per_minute(NonZeroU32::new(2).unwrap());
+        let backend = GovernorBackend::new(quota);
+        let input = SimpleInput {
+            interval: Duration::from_secs(1),
+            max_requests: 100,
+            key: "KEY1".into(),
+        };
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.limit, 2);
+        assert_eq!(output.remaining, 1);
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 0);
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        // One request is replenished every 30 seconds
+        let wait = output.reset - Instant::now();
+        assert!(wait > Duration::from_secs(25) && wait <= Duration::from_secs(30));
+
+        // Keys are limited independently
+        let input = SimpleInput {
+            key: "KEY2".into(),
+            ..input
+        };
+        let (decision, _, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(backend.limiter().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_peek_unsupported() {
+        let quota = Quota::per_minute(NonZeroU32::new(1).unwrap());
+        let backend = GovernorBackend::new(quota);
+        let input = SimpleInput {
+            interval: Duration::from_secs(1),
+            max_requests: 100,
+            key: "KEY1".into(),
+        };
+        let err = backend.peek(input.clone()).await.unwrap_err();
+        assert!(matches!(err, Error::PeekUnsupported));
+        // The peek didn't consume the quota
+        let (decision, _, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+    }
+}
@@ -1,15 +1,128 @@
-use crate::backend::SimpleInput;
+use crate::backend::client_ip::{client_addr, ClientAddr};
+use crate::backend::key::KeyInterner;
+#[cfg(feature = "sha2")]
+use crate::backend::key::{hash_key, hex};
+use crate::backend::weighted::WeightedInput;
+use crate::backend::{
+    BoxedInputFuture, PolicySet, QuotaProvider, RateLimitKey, RateLimitPolicy, SimpleInput,
+};
 use actix_web::dev::ServiceRequest;
-use actix_web::ResponseError;
-use std::future::{ready, Ready};
+use actix_web::http::header::{COOKIE, USER_AGENT};
+use actix_web::http::StatusCode;
+use actix_web::{HttpMessage, ResponseError};
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
 use std::net::{AddrParseError, IpAddr, Ipv6Addr};
+use std::sync::Arc;
 use std::time::Duration;
 use thiserror::Error;
 
-type CustomFn = Box<dyn Fn(&ServiceRequest) -> Result<String, actix_web::Error>>;
+/// The key used for all requests that aren't limited, see [UnknownIp::Unlimited].
+const UNLIMITED_KEY: &str = "unlimited";
+
+/// The match pattern key component for requests that don't match a route.
+const UNMATCHED_PATTERN: &str = "unmatched";
+
+type CustomFn = Box<dyn Fn(&ServiceRequest) -> Result<String, actix_web::Error> + Send + Sync>;
+type ChargedKeyFn = Box<dyn Fn(&ServiceRequest) -> Option<String> + Send + Sync>;
+type UserAgentClassifier = Box<dyn Fn(Option<&str>) -> String + Send + Sync>;
+type CustomAsyncFn = Box<
+    dyn Fn(&ServiceRequest) -> LocalBoxFuture<'static, Result<String, actix_web::Error>>
+        + Send
+        + Sync,
+>;
 
 pub type SimpleInputFuture = Ready<Result<SimpleInput, actix_web::Error>>;
 
+pub type MultiInputFuture = Ready<Result<Vec<SimpleInput>, actix_web::Error>>;
+
+/// What to do when a key component is missing from the request, e.g. see
+/// [SimpleInputFunctionBuilder::header_key].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MissingKey {
+    /// Fail the request with a 400 Bad Request response.
+    Reject,
+    /// Use the client's real IP instead, see [SimpleInputFunctionBuilder::real_ip_key].
+    RealIp,
+    /// Use the connection peer IP instead, see [SimpleInputFunctionBuilder::peer_ip_key].
+    PeerIp,
+    /// Leave the component out of the key, so that all such requests share the same key.
+    Omit,
+}
+
+/// Identifies the client's TLS certificate, for mutual TLS deployments, see
+/// [SimpleInputFunctionBuilder::client_cert_key].
+///
+/// Actix doesn't expose the peer certificate directly, so this must be inserted into the
+/// connection data using
+/// [HttpServer::on_connect](https://docs.rs/actix-web/4/actix_web/struct.HttpServer.html#method.on_connect).
+/// Alternatively, if TLS is terminated by a proxy that forwards the certificate in a header,
+/// it can be inserted into the request extensions by a middleware that runs before the
+/// [RateLimiter](crate::RateLimiter).
+///
+/// # Examples
+///
+/// ```no_run
+/// # use actix_extensible_rate_limit::backend::ClientCertificate;
+/// # use actix_web::{App, HttpServer};
+/// # fn peer_certificate_subject(conn: &dyn std::any::Any) -> Option<String> { None }
+/// # async fn run() -> std::io::Result<()> {
+/// HttpServer::new(|| App::new())
+///     .on_connect(|conn, data| {
+///         // e.g. using the peer certificates of a rustls or openssl stream
+///         if let Some(subject) = peer_certificate_subject(conn) {
+///             data.insert(ClientCertificate::new(subject));
+///         }
+///     })
+///     .bind(("127.0.0.1", 8443))?
+///     .run()
+///     .await
+/// # }
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ClientCertificate {
+    identity: String,
+}
+
+impl ClientCertificate {
+    /// Identify the certificate by any string, e.g. its subject or common name.
+    pub fn new(identity: impl Into<String>) -> Self {
+        Self {
+            identity: identity.into(),
+        }
+    }
+
+    /// Identify the certificate by the SHA-256 fingerprint of its DER encoding.
+    #[cfg(feature = "sha2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sha2")))]
+    pub fn from_der(der: &[u8]) -> Self {
+        use sha2::{Digest, Sha256};
+        Self::new(hex(&Sha256::digest(der)))
+    }
+
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+}
+
+/// What to do when the client IP can't be determined, e.g. when listening on a Unix socket, or when
+/// a proxy sends a malformed header, see [SimpleInputFunctionBuilder::unknown_ip].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum UnknownIp {
+    /// Fail the request with a 400 Bad Request response.
+    Reject,
+    /// Use this constant instead, so that all such requests share the same key.
+    Key(String),
+    /// Don't limit the request.
+    ///
+    /// The request is still passed to the backend, using a shared key and the maximum allowed
+    /// requests of [u64::MAX].
+    Unlimited,
+}
+
 /// Utility to create a input function that produces a [SimpleInput].
 ///
 /// You should take care to ensure that you are producing unique keys per backend.
@@ -20,10 +133,31 @@ pub struct SimpleInputFunctionBuilder {
     interval: Duration,
     max_requests: u64,
     real_ip_key: bool,
+    trusted_proxies: Option<Vec<IpNet>>,
+    unknown_ip: UnknownIp,
     peer_ip_key: bool,
+    host_key: bool,
     path_key: bool,
+    match_pattern_key: bool,
+    method_key: bool,
+    scope_key: bool,
+    path_within_scope_key: bool,
+    header_key: Option<(String, MissingKey)>,
+    cookie_key: Option<(String, MissingKey)>,
+    client_cert_key: Option<MissingKey>,
+    user_agent_class_key: Option<UserAgentClassifier>,
+    #[cfg(feature = "session")]
+    session_key: Option<(String, MissingKey)>,
+    #[cfg(feature = "identity")]
+    identity_key: Option<MissingKey>,
     custom_key: Option<String>,
     custom_fn: Option<CustomFn>,
+    custom_async_fn: Option<CustomAsyncFn>,
+    additional_policies: Vec<(Duration, u64)>,
+    charged_keys: Vec<(ChargedKeyFn, Duration, u64)>,
+    route_policies: HashMap<String, RateLimitPolicy>,
+    policy_set: Option<PolicySet>,
+    key_encoder: KeyEncoder,
 }
 
 impl SimpleInputFunctionBuilder {
@@ -32,13 +166,64 @@ impl SimpleInputFunctionBuilder {
             interval,
             max_requests,
             real_ip_key: false,
+            trusted_proxies: None,
+            unknown_ip: UnknownIp::Reject,
             peer_ip_key: false,
+            host_key: false,
             path_key: false,
+            match_pattern_key: false,
+            method_key: false,
+            scope_key: false,
+            path_within_scope_key: false,
+            header_key: None,
+            cookie_key: None,
+            client_cert_key: None,
+            user_agent_class_key: None,
+            #[cfg(feature = "session")]
+            session_key: None,
+            #[cfg(feature = "identity")]
+            identity_key: None,
             custom_key: None,
             custom_fn: None,
+            custom_async_fn: None,
+            additional_policies: Vec::new(),
+            charged_keys: Vec::new(),
+            route_policies: HashMap::new(),
+            policy_set: None,
+            key_encoder: KeyEncoder::default(),
         }
     }
 
+    /// Create a builder using a [RateLimitPolicy], e.g. one parsed from configuration.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// let policy = std::env::var("RATE_LIMIT").unwrap_or("100/1m".to_string());
+    /// let input = SimpleInputFunctionBuilder::from_policy(policy.parse().unwrap())
+    ///     .real_ip_key()
+    ///     .build();
+    /// ```
+    pub fn from_policy(policy: RateLimitPolicy) -> Self {
+        Self::new(policy.interval(), policy.allowed_requests())
+    }
+
+    /// Create a builder that uses the policies from a [PolicySet], e.g. one loaded from a config
+    /// file.
+    ///
+    /// Requests matching a rule are counted separately for each rule, the rule's path and methods
+    /// are appended to the key. Policies registered with
+    /// [SimpleInputFunctionBuilder::route_policy] take precedence over the rules.
+    ///
+    /// The rules are only used by [SimpleInputFunctionBuilder::build] and
+    /// [SimpleInputFunctionBuilder::build_async].
+    pub fn from_policy_set(policy_set: PolicySet) -> Self {
+        let mut builder = Self::from_policy(policy_set.default_policy());
+        builder.policy_set = Some(policy_set);
+        builder
+    }
+
     /// Adds the client's real IP to the rate limiting key.
     ///
     /// # Security
@@ -46,7 +231,12 @@ impl SimpleInputFunctionBuilder {
     /// This calls
     /// [ConnectionInfo::realip_remote_addr()](actix_web::dev::ConnectionInfo::realip_remote_addr)
     /// internally which is only suitable for Actix applications deployed behind a proxy that you
-    /// control.
+    /// control. Use [SimpleInputFunctionBuilder::trusted_proxies] to only trust the headers set
+    /// by your proxies.
+    ///
+    /// Both the standard `Forwarded` header and the legacy `X-Forwarded-For` header are
+    /// supported. Obfuscated identifiers in the `Forwarded` header (e.g. `for=_hidden`) are used
+    /// as the key as is.
     ///
     /// # IPv6
     ///
@@ -56,6 +246,32 @@ impl SimpleInputFunctionBuilder {
         self
     }
 
+    /// Only trust the `Forwarded` or `X-Forwarded-For` header when determining the client's real
+    /// IP, when it was set by one of these proxy networks.
+    ///
+    /// The forwarded chain is walked from the right, starting from the connection peer,
+    /// stopping at the first address that isn't a trusted proxy, in the same way as nginx's
+    /// `set_real_ip_from`. This means clients can't spoof their address by sending their own
+    /// header.
+    ///
+    /// This applies to [SimpleInputFunctionBuilder::real_ip_key] and [MissingKey::RealIp].
+    /// If the `Forwarded` header is present, it is used instead of `X-Forwarded-For`.
+    pub fn trusted_proxies(mut self, networks: impl IntoIterator<Item = IpNet>) -> Self {
+        self.trusted_proxies = Some(networks.into_iter().collect());
+        self
+    }
+
+    /// What to do when the client IP is unknown or can't be parsed.
+    ///
+    /// This applies to [SimpleInputFunctionBuilder::real_ip_key],
+    /// [SimpleInputFunctionBuilder::peer_ip_key], [MissingKey::RealIp] and [MissingKey::PeerIp].
+    ///
+    /// Defaults to [UnknownIp::Reject].
+    pub fn unknown_ip(mut self, unknown_ip: UnknownIp) -> Self {
+        self.unknown_ip = unknown_ip;
+        self
+    }
+
     /// Adds the connection peer IP to the rate limiting key.
     ///
     /// This is suitable when clients connect directly to the Actix application.
@@ -68,12 +284,216 @@ impl SimpleInputFunctionBuilder {
         self
     }
 
+    /// Add the requested host, e.g. `tenant.example.com`, to the rate limiting key, so that each
+    /// domain served by the application is counted separately.
+    ///
+    /// This uses [ConnectionInfo::host()](actix_web::dev::ConnectionInfo::host), which is taken
+    /// from the `Forwarded` / `X-Forwarded-Host` headers, the `Host` header or the request URI.
+    /// The host is converted to lowercase, and includes the port if one was given.
+    pub fn host_key(mut self) -> Self {
+        self.host_key = true;
+        self
+    }
+
     /// Add the request path to the rate limiting key
+    ///
+    /// This is always the full path, even when the [RateLimiter](crate::RateLimiter) wraps a
+    /// scope, see [SimpleInputFunctionBuilder::path_within_scope_key].
+    ///
+    /// Each concrete path gets its own key, for paths containing IDs consider
+    /// [SimpleInputFunctionBuilder::match_pattern_key] instead.
     pub fn path_key(mut self) -> Self {
         self.path_key = true;
         self
     }
 
+    /// Add the matched route pattern, e.g. `/users/{id}`, to the rate limiting key.
+    ///
+    /// Unlike [SimpleInputFunctionBuilder::path_key], requests to `/users/1` and `/users/2`
+    /// share the same key. Requests that don't match any route (e.g. 404s) all share the key
+    /// component `unmatched`.
+    pub fn match_pattern_key(mut self) -> Self {
+        self.match_pattern_key = true;
+        self
+    }
+
+    /// Add the request method to the rate limiting key, so that e.g. `GET` and `POST` requests
+    /// are counted separately.
+    pub fn method_key(mut self) -> Self {
+        self.method_key = true;
+        self
+    }
+
+    /// Add the path prefix of the scope that the [RateLimiter](crate::RateLimiter) wraps, e.g.
+    /// `/api/v1`, to the rate limiting key.
+    ///
+    /// This allows the same limiter to be used to wrap multiple scopes, while counting each scope
+    /// separately.
+    pub fn scope_key(mut self) -> Self {
+        self.scope_key = true;
+        self
+    }
+
+    /// Add the request path, relative to the scope that the [RateLimiter](crate::RateLimiter)
+    /// wraps, to the rate limiting key. E.g. for a scope `/api/v1`, a request to `/api/v1/users`
+    /// uses the path `/users`.
+    ///
+    /// Unlike [SimpleInputFunctionBuilder::path_key], if the same limiter wraps multiple scopes
+    /// then the same path in each scope shares a key. Combine with
+    /// [SimpleInputFunctionBuilder::scope_key] to count them separately.
+    ///
+    /// When wrapping the whole `App` this is the same as the full path.
+    pub fn path_within_scope_key(mut self) -> Self {
+        self.path_within_scope_key = true;
+        self
+    }
+
+    /// Add the value of a request header, e.g. `x-api-key`, to the rate limiting key.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The header name.
+    /// * `missing`: What to do if the header is missing (or isn't valid ASCII).
+    ///
+    /// # Security
+    ///
+    /// Clients can choose any header value, so this should only be used with a value that is
+    /// validated by your application (e.g. an API key), otherwise clients can trivially
+    /// bypass the limit by changing the value on each request.
+    pub fn header_key(mut self, name: &str, missing: MissingKey) -> Self {
+        self.header_key = Some((name.to_owned(), missing));
+        self
+    }
+
+    /// Add the value of a cookie, e.g. a session ID, to the rate limiting key.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The cookie name.
+    /// * `missing`: What to do if the cookie is missing, e.g. fallback to the
+    ///   [MissingKey::RealIp] for clients without a session.
+    ///
+    /// # Security
+    ///
+    /// Clients can choose any cookie value, so this should only be used with a value that is
+    /// validated by your application, see [SimpleInputFunctionBuilder::header_key].
+    pub fn cookie_key(mut self, name: &str, missing: MissingKey) -> Self {
+        self.cookie_key = Some((name.to_owned(), missing));
+        self
+    }
+
+    /// Add the client's TLS certificate identity to the rate limiting key, so that machine clients
+    /// using mutual TLS are limited per certificate rather than per IP.
+    ///
+    /// The certificate must be inserted into the connection data, or request extensions, as a
+    /// [ClientCertificate].
+    ///
+    /// # Arguments
+    ///
+    /// * `missing`: What to do if the client didn't present a certificate.
+    pub fn client_cert_key(mut self, missing: MissingKey) -> Self {
+        self.client_cert_key = Some(missing);
+        self
+    }
+
+    /// Add the class of the client's `User-Agent` to the rate limiting key, e.g. so that bots and
+    /// browsers are counted separately.
+    ///
+    /// # Arguments
+    ///
+    /// * `classifier`: Returns the class of a `User-Agent` header value (or None if it is missing
+    ///   or isn't valid ASCII), e.g. using [UserAgentClass::classify](crate::backend::UserAgentClass::classify).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::backend::{SimpleInputFunctionBuilder, UserAgentClass};
+    /// # use std::time::Duration;
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 100)
+    ///     .real_ip_key()
+    ///     .user_agent_class_key(|ua| UserAgentClass::classify(ua).as_str())
+    ///     .build();
+    /// ```
+    pub fn user_agent_class_key<F, S>(mut self, classifier: F) -> Self
+    where
+        F: Fn(Option<&str>) -> S + Send + Sync + 'static,
+        S: Into<String>,
+    {
+        self.user_agent_class_key = Some(Box::new(move |ua| classifier(ua).into()));
+        self
+    }
+
+    /// Add a value from the [actix_session::Session], e.g. a user ID, to the rate limiting key.
+    ///
+    /// The value must be a string or a number.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The session key.
+    /// * `missing`: What to do if the value is missing, e.g. fallback to the [MissingKey::RealIp]
+    ///   for anonymous users.
+    ///
+    /// The [actix_session::SessionMiddleware] must be registered after (i.e. wrap) the
+    /// [RateLimiter](crate::RateLimiter), so that the session is loaded first.
+    #[cfg(feature = "session")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "session")))]
+    pub fn session_key(mut self, name: &str, missing: MissingKey) -> Self {
+        self.session_key = Some((name.to_owned(), missing));
+        self
+    }
+
+    /// Add the logged-in user's [actix_identity::Identity] to the rate limiting key, so that each
+    /// account is limited separately.
+    ///
+    /// # Arguments
+    ///
+    /// * `missing`: What to do for anonymous users, e.g. fallback to the [MissingKey::RealIp].
+    ///
+    /// The [actix_identity::IdentityMiddleware] and [actix_session::SessionMiddleware] must be
+    /// registered after (i.e. wrap) the [RateLimiter](crate::RateLimiter), so that the identity is
+    /// loaded first.
+    #[cfg(feature = "identity")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "identity")))]
+    pub fn identity_key(mut self, missing: MissingKey) -> Self {
+        self.identity_key = Some(missing);
+        self
+    }
+
+    /// Hash the rate limiting key using SHA-256 before it is passed to the backend, so that IP
+    /// addresses, API keys etc. are not stored in clear text, and the key length is bounded.
+    ///
+    /// Keys are hex encoded. The key is hashed after any other components have been added, e.g.
+    /// the route pattern for [SimpleInputFunctionBuilder::route_policy]. A
+    /// [QuotaProvider] is passed the key before it is hashed.
+    #[cfg(feature = "sha2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sha2")))]
+    pub fn hash_key(self) -> Self {
+        self.hash_key_salted(b"")
+    }
+
+    /// Hash the rate limiting key with a salt, see [SimpleInputFunctionBuilder::hash_key].
+    ///
+    /// A secret salt prevents the original keys from being recovered by hashing every IP
+    /// address. Changing the salt resets all rate limits.
+    #[cfg(feature = "sha2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sha2")))]
+    pub fn hash_key_salted(mut self, salt: impl AsRef<[u8]>) -> Self {
+        self.key_encoder.salt = Some(salt.as_ref().into());
+        self
+    }
+
+    /// Share the allocation of the most recently used keys, about `capacity` of them, between
+    /// requests.
+    ///
+    /// This reduces allocator pressure when the key space is small (e.g. keys per route or per
+    /// tenant), and the memory used by backends that store the keys. The interned keys are
+    /// shared by every worker.
+    pub fn intern_keys(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "Capacity must be non-zero");
+        self.key_encoder.interner = Some(Arc::new(KeyInterner::new(capacity)));
+        self
+    }
+
     /// Add a custom component to the rate limiting key
     pub fn custom_key(mut self, key: &str) -> Self {
         self.custom_key = Some(key.to_owned());
@@ -83,66 +503,584 @@ impl SimpleInputFunctionBuilder {
     /// Dynamically add a custom component to the rate limiting key
     pub fn custom_fn<F>(mut self, f: F) -> Self
     where
-        F: Fn(&ServiceRequest) -> Result<String, actix_web::Error> + 'static,
+        F: Fn(&ServiceRequest) -> Result<String, actix_web::Error> + Send + Sync + 'static,
     {
         self.custom_fn = Some(Box::new(f));
         self
     }
 
-    pub fn build(self) -> impl Fn(&ServiceRequest) -> SimpleInputFuture + 'static {
+    /// Dynamically add a custom component to the rate limiting key, that is looked up
+    /// asynchronously, e.g. the user from a session store or an authentication service.
+    ///
+    /// This requires an asynchronous input function, so can only be used with
+    /// [SimpleInputFunctionBuilder::build_async] or
+    /// [SimpleInputFunctionBuilder::build_with_quota].
+    pub fn custom_async_fn<F, O>(mut self, f: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> O + Send + Sync + 'static,
+        O: Future<Output = Result<String, actix_web::Error>> + 'static,
+    {
+        self.custom_async_fn = Some(Box::new(move |req| f(req).boxed_local()));
+        self
+    }
+
+    /// Add another interval and max requests policy, to be enforced in addition to the one
+    /// passed to [SimpleInputFunctionBuilder::new].
+    ///
+    /// This is only used by [SimpleInputFunctionBuilder::build_multi].
+    pub fn add_policy(mut self, interval: Duration, max_requests: u64) -> Self {
+        self.additional_policies.push((interval, max_requests));
+        self
+    }
+
+    /// Also charge each request to another key with its own policy, e.g. the organization of the
+    /// user, so that the request is only allowed if neither limit is exceeded.
+    ///
+    /// The function returns the key to charge, or [None] to not charge the request to another
+    /// key, e.g. for users that don't belong to an organization. The key is used as is (other than
+    /// being hashed or interned), so it should be distinct from the keys produced by this
+    /// builder, e.g. by using a prefix.
+    ///
+    /// This is only used by [SimpleInputFunctionBuilder::build_multi].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::multi::MultiPolicyBackend;
+    /// # use actix_extensible_rate_limit::backend::{MissingKey, SimpleInputFunctionBuilder};
+    /// # use actix_extensible_rate_limit::RateLimiter;
+    /// # use std::time::Duration;
+    /// # actix_web::rt::System::new().block_on(async {
+    /// // Each user can make 100 requests per minute, and their organization 1000 in total
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 100)
+    ///     .header_key("x-user-id", MissingKey::Reject)
+    ///     .charge_key(
+    ///         |req| {
+    ///             let org = req.headers().get("x-org-id")?.to_str().ok()?;
+    ///             Some(format!("org-{org}"))
+    ///         },
+    ///         Duration::from_secs(60),
+    ///         1000,
+    ///     )
+    ///     .build_multi();
+    /// let backend = MultiPolicyBackend::new(InMemoryBackend::builder().build());
+    /// let middleware = RateLimiter::builder(backend, input).add_headers().build();
+    /// # });
+    /// ```
+    pub fn charge_key<F>(mut self, f: F, interval: Duration, max_requests: u64) -> Self
+    where
+        F: Fn(&ServiceRequest) -> Option<String> + Send + Sync + 'static,
+    {
+        self.charged_keys
+            .push((Box::new(f), interval, max_requests));
+        self
+    }
+
+    /// Use a different policy for requests matching a route pattern, e.g. `/users/{id}`, as
+    /// returned by [HttpRequest::match_pattern](actix_web::HttpRequest::match_pattern).
+    ///
+    /// A [RateLimitPolicy] can alternatively be attached to a scope or resource using
+    /// `.app_data()`, however this is only visible to a [RateLimiter](crate::RateLimiter) that
+    /// wraps the same (or a nested) scope or resource. A policy registered here takes precedence.
+    ///
+    /// Requests using an overridden policy are counted separately for each route, the route
+    /// pattern is appended to the key.
+    ///
+    /// This is only used by [SimpleInputFunctionBuilder::build] and
+    /// [SimpleInputFunctionBuilder::build_async].
+    pub fn route_policy(mut self, pattern: &str, policy: RateLimitPolicy) -> Self {
+        self.route_policies.insert(pattern.to_owned(), policy);
+        self
+    }
+
+    /// # Panics
+    ///
+    /// If a [SimpleInputFunctionBuilder::custom_async_fn] is set, use
+    /// [SimpleInputFunctionBuilder::build_async] instead.
+    pub fn build(self) -> impl Fn(&ServiceRequest) -> SimpleInputFuture + Send + Sync + 'static {
+        self.assert_sync();
+        move |req| {
+            let policy = (self.interval, self.max_requests);
+            ready(
+                self.key(req).map(|key| {
+                    simple_input(policy, key, self.policy_override(req), &self.key_encoder)
+                }),
+            )
+        }
+    }
+
+    /// Build an asynchronous input function, this is required to use
+    /// [SimpleInputFunctionBuilder::custom_async_fn].
+    pub fn build_async(
+        self,
+    ) -> impl Fn(&ServiceRequest) -> BoxedInputFuture<SimpleInput> + Send + Sync + 'static {
+        move |req| {
+            let policy = (self.interval, self.max_requests);
+            let key = self.async_key(req);
+            let route = self.policy_override(req);
+            let key_encoder = self.key_encoder.clone();
+            async move { Ok(simple_input(policy, key.await?, route, &key_encoder)) }.boxed_local()
+        }
+    }
+
+    /// Build an asynchronous input function that also produces the cost of each request, for use
+    /// with a [WeightedBackend](crate::backend::weighted::WeightedBackend), e.g. based on the
+    /// size of the request body using
+    /// [content_length_cost](crate::backend::weighted::content_length_cost).
+    ///
+    /// The cost function is called after the key has been produced.
+    pub fn build_weighted<C, CO>(
+        self,
+        cost_fn: C,
+    ) -> impl Fn(&ServiceRequest) -> BoxedInputFuture<WeightedInput> + Send + Sync + 'static
+    where
+        C: Fn(&ServiceRequest) -> CO + Send + Sync + 'static,
+        CO: Future<Output = Result<u64, actix_web::Error>> + 'static,
+    {
         move |req| {
-            ready((|| {
-                let mut components = Vec::new();
+            let policy = (self.interval, self.max_requests);
+            let key = self.async_key(req);
+            let route = self.policy_override(req);
+            let key_encoder = self.key_encoder.clone();
+            let cost = cost_fn(req);
+            async move {
+                let input = simple_input(policy, key.await?, route, &key_encoder);
+                Ok(WeightedInput {
+                    input,
+                    cost: cost.await?,
+                })
+            }
+            .boxed_local()
+        }
+    }
+
+    /// Build an input function that looks up the policy for each key from a [QuotaProvider], e.g.
+    /// to apply different limits per API key or subscription tier.
+    ///
+    /// The provider is passed the rate limit key produced by this builder, so you will usually
+    /// want to use [SimpleInputFunctionBuilder::custom_fn] to extract the API key or user. The
+    /// default policy is used for keys without a quota.
+    ///
+    /// Route policies and policy sets are not used.
+    pub fn build_with_quota<P>(
+        self,
+        provider: P,
+    ) -> impl Fn(&ServiceRequest) -> BoxedInputFuture<SimpleInput> + Send + Sync + 'static
+    where
+        P: QuotaProvider + Send + Sync + 'static,
+    {
+        let provider = Arc::new(provider);
+        move |req| {
+            let key = self.async_key(req);
+            let provider = provider.clone();
+            let (interval, max_requests) = (self.interval, self.max_requests);
+            let key_encoder = self.key_encoder.clone();
+            async move {
+                let Some(key) = key.await? else {
+                    return Ok(unlimited_input(interval));
+                };
+                let key = key.to_string();
+                let input = match provider.quota(&key).await? {
+                    Some(policy) => SimpleInput {
+                        interval: policy.interval(),
+                        max_requests: policy.allowed_requests(),
+                        key: key_encoder.encode(key),
+                    },
+                    None => SimpleInput {
+                        interval,
+                        max_requests,
+                        key: key_encoder.encode(key),
+                    },
+                };
+                Ok(input)
+            }
+            .boxed_local()
+        }
+    }
+
+    /// Build an input function that produces a [SimpleInput] for each policy, for use with a
+    /// [MultiPolicyBackend](crate::backend::multi::MultiPolicyBackend).
+    ///
+    /// The interval (in milliseconds) is appended to the key of each policy so that they are
+    /// counted separately.
+    ///
+    /// The keys added with [SimpleInputFunctionBuilder::charge_key] are charged in addition.
+    ///
+    /// # Panics
+    ///
+    /// If a [SimpleInputFunctionBuilder::custom_async_fn] is set.
+    pub fn build_multi(
+        self,
+    ) -> impl Fn(&ServiceRequest) -> MultiInputFuture + Send + Sync + 'static {
+        self.assert_sync();
+        let mut policies = vec![(self.interval, self.max_requests)];
+        policies.extend(self.additional_policies.iter().copied());
+        move |req| {
+            ready(self.key(req).map(|key| {
+                let Some(key) = key else {
+                    return vec![unlimited_input(self.interval)];
+                };
+                let mut inputs: Vec<SimpleInput> = policies
+                    .iter()
+                    .map(|(interval, max_requests)| {
+                        let key = key.clone().with(interval.as_millis().to_string());
+                        SimpleInput {
+                            interval: *interval,
+                            max_requests: *max_requests,
+                            key: self.key_encoder.encode(key.to_string()),
+                        }
+                    })
+                    .collect();
+                inputs.extend(self.charged_keys.iter().filter_map(
+                    |(f, interval, max_requests)| {
+                        f(req).map(|key| SimpleInput {
+                            interval: *interval,
+                            max_requests: *max_requests,
+                            key: self.key_encoder.encode(key),
+                        })
+                    },
+                ));
+                inputs
+            }))
+        }
+    }
+
+    /// Returns the rate limiting key, or None if the request shouldn't be limited, see
+    /// [UnknownIp::Unlimited].
+    pub(crate) fn key(
+        &self,
+        req: &ServiceRequest,
+    ) -> Result<Option<RateLimitKey>, actix_web::Error> {
+        match self.components(req) {
+            Ok(key) => Ok(Some(key)),
+            Err(e) if matches!(e.as_error::<Error>(), Some(Error::Unlimited)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the rate limiting key, including the component from the
+    /// [SimpleInputFunctionBuilder::custom_async_fn] if set.
+    fn async_key(
+        &self,
+        req: &ServiceRequest,
+    ) -> LocalBoxFuture<'static, Result<Option<RateLimitKey>, actix_web::Error>> {
+        let key = self.key(req);
+        let custom = match (&key, &self.custom_async_fn) {
+            (Ok(Some(_)), Some(f)) => Some(f(req)),
+            _ => None,
+        };
+        async move {
+            match (key?, custom) {
+                (Some(key), Some(custom)) => Ok(Some(key.with(custom.await?))),
+                (key, _) => Ok(key),
+            }
+        }
+        .boxed_local()
+    }
+
+    fn assert_sync(&self) {
+        assert!(
+            self.custom_async_fn.is_none(),
+            "custom_async_fn requires an asynchronous input function, use build_async()"
+        );
+    }
+
+    fn components(&self, req: &ServiceRequest) -> Result<RateLimitKey, actix_web::Error> {
+        let mut components = RateLimitKey::new();
+        if let Some(custom) = &self.custom_key {
+            components.push(custom.clone());
+        }
+        if self.real_ip_key {
+            components.push(self.real_ip(req)?)
+        }
+        if self.peer_ip_key {
+            components.push(self.peer_ip(req)?)
+        }
+        if self.host_key {
+            components.push(req.connection_info().host().to_ascii_lowercase());
+        }
+        if self.path_key {
+            components.push(req.path().to_owned());
+        }
+        if self.match_pattern_key {
+            components.push(
+                req.match_pattern()
+                    .unwrap_or_else(|| UNMATCHED_PATTERN.to_owned()),
+            );
+        }
+        if self.method_key {
+            components.push(req.method().as_str().to_owned());
+        }
+        if self.scope_key || self.path_within_scope_key {
+            // The path that remains after the scope prefix has been matched
+            let path = req.match_info().as_str();
+            let unprocessed = req.match_info().unprocessed();
+            if self.scope_key {
+                components.push(path[..path.len() - unprocessed.len()].to_owned());
+            }
+            if self.path_within_scope_key {
+                components.push(unprocessed.to_owned());
+            }
+        }
+        if let Some((name, missing)) = &self.header_key {
+            let value = req.headers().get(name).and_then(|v| v.to_str().ok());
+            if let Some(component) = self.component(req, value, *missing, name)? {
+                components.push(component);
+            }
+        }
+        if let Some((name, missing)) = &self.cookie_key {
+            let value = cookie(req, name);
+            if let Some(component) = self.component(req, value, *missing, name)? {
+                components.push(component);
+            }
+        }
+        if let Some(missing) = &self.client_cert_key {
+            let value = match req.conn_data::<ClientCertificate>() {
+                Some(certificate) => Some(certificate.identity.clone()),
+                None => req
+                    .extensions()
+                    .get::<ClientCertificate>()
+                    .map(|certificate| certificate.identity.clone()),
+            };
+            if let Some(component) =
+                self.component(req, value.as_deref(), *missing, "certificate")?
+            {
+                components.push(component);
+            }
+        }
+        if let Some(classifier) = &self.user_agent_class_key {
+            let user_agent = req.headers().get(USER_AGENT).and_then(|v| v.to_str().ok());
+            components.push(classifier(user_agent));
+        }
+        #[cfg(feature = "identity")]
+        if let Some(missing) = &self.identity_key {
+            use actix_identity::IdentityExt;
+            let value = req.get_identity().and_then(|identity| identity.id()).ok();
+            if let Some(component) = self.component(req, value.as_deref(), *missing, "identity")? {
+                components.push(component);
+            }
+        }
+        #[cfg(feature = "session")]
+        if let Some((name, missing)) = &self.session_key {
+            let value = session_value(req, name);
+            if let Some(component) = self.component(req, value.as_deref(), *missing, name)? {
+                components.push(component);
+            }
+        }
+        if let Some(f) = &self.custom_fn {
+            components.push(f(req)?)
+        }
+        Ok(components)
+    }
+
+    /// Returns the key component, or the fallback if it is missing.
+    fn component(
+        &self,
+        req: &ServiceRequest,
+        value: Option<&str>,
+        missing: MissingKey,
+        name: &str,
+    ) -> Result<Option<String>, actix_web::Error> {
+        if let Some(value) = value {
+            return Ok(Some(value.to_owned()));
+        }
+        Ok(match missing {
+            MissingKey::Reject => return Err(Error::MissingKeyComponent(name.to_owned()).into()),
+            MissingKey::RealIp => Some(self.real_ip(req)?),
+            MissingKey::PeerIp => Some(self.peer_ip(req)?),
+            MissingKey::Omit => None,
+        })
+    }
+
+    fn real_ip(&self, req: &ServiceRequest) -> Result<String, Error> {
+        let addr = match &self.trusted_proxies {
+            Some(trusted_proxies) => client_addr(req, trusted_proxies),
+            None => {
                 let info = req.connection_info();
-                if let Some(custom) = &self.custom_key {
-                    components.push(custom.clone());
+                match info.realip_remote_addr() {
+                    Some(addr) => match ClientAddr::parse(addr) {
+                        Some(addr) => Some(addr),
+                        None => return self.ip_key(addr),
+                    },
+                    None => None,
                 }
-                if self.real_ip_key {
-                    components.push(ip_key(info.realip_remote_addr().unwrap())?)
-                }
-                if self.peer_ip_key {
-                    components.push(ip_key(info.peer_addr().unwrap())?)
-                }
-                if self.path_key {
-                    components.push(req.path().to_owned());
-                }
-                if let Some(f) = &self.custom_fn {
-                    components.push(f(req)?)
-                }
-                let key = components.join("-");
+            }
+        };
+        match addr {
+            Some(ClientAddr::Ip(ip)) => Ok(ip_addr_key(ip)),
+            Some(ClientAddr::Obfuscated(obfuscated)) => Ok(obfuscated),
+            None => self.ip_fallback(Error::UnknownClientIp),
+        }
+    }
 
-                Ok(SimpleInput {
-                    interval: self.interval,
-                    max_requests: self.max_requests,
-                    key,
-                })
-            })())
+    fn peer_ip(&self, req: &ServiceRequest) -> Result<String, Error> {
+        match req.connection_info().peer_addr() {
+            Some(addr) => self.ip_key(addr),
+            None => self.ip_fallback(Error::UnknownClientIp),
+        }
+    }
+
+    fn ip_key(&self, addr: &str) -> Result<String, Error> {
+        ip_key(addr).or_else(|e| self.ip_fallback(e))
+    }
+
+    /// Applies the [UnknownIp] strategy.
+    fn ip_fallback(&self, error: Error) -> Result<String, Error> {
+        match &self.unknown_ip {
+            UnknownIp::Reject => Err(error),
+            UnknownIp::Key(key) => Ok(key.clone()),
+            UnknownIp::Unlimited => Err(Error::Unlimited),
+        }
+    }
+
+    fn policy_override(&self, req: &ServiceRequest) -> Option<(String, RateLimitPolicy)> {
+        let pattern = req.match_pattern();
+        if let Some(policy) = pattern
+            .as_ref()
+            .and_then(|pattern| self.route_policies.get(pattern))
+        {
+            return pattern.map(|pattern| (pattern, *policy));
+        }
+        if let Some(rule) = self.policy_set.as_ref().and_then(|set| set.find(req)) {
+            return Some((rule.name(), rule.policy()));
         }
+        let policy = req.app_data::<RateLimitPolicy>()?;
+        let route = pattern.unwrap_or_else(|| req.path().to_owned());
+        Some((route, *policy))
     }
 }
 
 #[derive(Debug, Error)]
-enum Error {
+pub(crate) enum Error {
     #[error("Unable to parse remote IP address: {0}")]
-    InvalidIpError(
+    InvalidIp(
         #[source]
         #[from]
         AddrParseError,
     ),
+    #[error("Unknown rate limit tier: {0}")]
+    UnknownTier(String),
+    #[error("Missing rate limit key: {0}")]
+    MissingKeyComponent(String),
+    #[error("Unable to determine the client IP address")]
+    UnknownClientIp,
+    /// Not returned to the client, see [UnknownIp::Unlimited].
+    #[error("Request is not rate limited")]
+    Unlimited,
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::InvalidIp(_) | Error::MissingKeyComponent(_) | Error::UnknownClientIp => {
+                StatusCode::BAD_REQUEST
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
 
-impl ResponseError for Error {}
+/// Converts the rate limiting key to its final form, see [SimpleInputFunctionBuilder::hash_key]
+/// and [SimpleInputFunctionBuilder::intern_keys].
+#[derive(Clone, Default)]
+struct KeyEncoder {
+    #[cfg(feature = "sha2")]
+    salt: Option<Arc<[u8]>>,
+    interner: Option<Arc<KeyInterner>>,
+}
+
+impl KeyEncoder {
+    fn encode(&self, key: String) -> Arc<str> {
+        let key = self.hash(key);
+        match &self.interner {
+            Some(interner) => interner.intern(key),
+            None => key.into(),
+        }
+    }
+
+    #[cfg(feature = "sha2")]
+    fn hash(&self, key: String) -> String {
+        match &self.salt {
+            Some(salt) => hash_key(&key, salt),
+            None => key,
+        }
+    }
+
+    #[cfg(not(feature = "sha2"))]
+    fn hash(&self, key: String) -> String {
+        key
+    }
+}
+
+/// Returns an input for a request that shouldn't be limited.
+pub(crate) fn unlimited_input(interval: Duration) -> SimpleInput {
+    SimpleInput {
+        interval,
+        max_requests: u64::MAX,
+        key: UNLIMITED_KEY.into(),
+    }
+}
+
+/// Returns the input using the route's policy if it has one, otherwise the default policy.
+fn simple_input(
+    (interval, max_requests): (Duration, u64),
+    key: Option<RateLimitKey>,
+    route: Option<(String, RateLimitPolicy)>,
+    key_encoder: &KeyEncoder,
+) -> SimpleInput {
+    match (key, route) {
+        (None, _) => unlimited_input(interval),
+        (Some(key), Some((route, policy))) => SimpleInput {
+            interval: policy.interval(),
+            max_requests: policy.allowed_requests(),
+            key: key_encoder.encode(key.with(route).to_string()),
+        },
+        (Some(key), None) => SimpleInput {
+            interval,
+            max_requests,
+            key: key_encoder.encode(key.to_string()),
+        },
+    }
+}
+
+/// Returns a string or number value from the session.
+#[cfg(feature = "session")]
+fn session_value(req: &ServiceRequest, name: &str) -> Option<String> {
+    use actix_session::SessionExt;
+    let session = req.get_session();
+    match session.get::<String>(name) {
+        Ok(value) => value,
+        // Not a string, the values are stored as JSON so e.g. a number can be used as is
+        Err(_) => session.entries().get(name).cloned(),
+    }
+}
+
+/// Returns the value of a cookie, this avoids requiring the actix-web `cookies` feature.
+fn cookie<'a>(req: &'a ServiceRequest, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get_all(COOKIE)
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value.trim_matches('"'))
+        .filter(|value| !value.is_empty())
+}
 
 // Groups IPv6 addresses together, see:
 // https://adam-p.ca/blog/2022/02/ipv6-rate-limiting/
 // https://support.cloudflare.com/hc/en-us/articles/115001635128-Configuring-Cloudflare-Rate-Limiting
-fn ip_key(ip_str: &str) -> Result<String, Error> {
-    let ip = ip_str.parse::<IpAddr>()?;
-    Ok(match ip {
+pub(crate) fn ip_key(ip_str: &str) -> Result<String, Error> {
+    Ok(ip_addr_key(ip_str.parse::<IpAddr>()?))
+}
+
+pub(crate) fn ip_addr_key(ip: IpAddr) -> String {
+    match ip {
         IpAddr::V4(v4) => v4.to_string(),
         IpAddr::V6(v6) => {
             if let Some(v4) = v6.to_ipv4() {
-                return Ok(v4.to_string());
+                return v4.to_string();
             }
             let zeroes = [0u16; 4];
             let concat = [&v6.segments()[0..4], &zeroes].concat();
@@ -150,7 +1088,7 @@ fn ip_key(ip_str: &str) -> Result<String, Error> {
             let subnet = Ipv6Addr::from(concat);
             format!("{}/64", subnet)
         }
-    })
+    }
 }
 
 #[cfg(test)]
@@ -169,4 +1107,684 @@ mod tests {
             "2a00:1450:4009:81f::/64"
         );
     }
+
+    #[actix_web::test]
+    async fn test_method_key() {
+        use actix_web::test::TestRequest;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .path_key()
+            .method_key()
+            .build();
+        let input = input_fn(&TestRequest::post().uri("/login").to_srv_request())
+            .await
+            .unwrap();
+        assert_eq!(&*input.key, "/login-POST");
+    }
+
+    #[actix_web::test]
+    async fn test_charge_key() {
+        use actix_web::test::TestRequest;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 10)
+            .path_key()
+            .add_policy(Duration::from_secs(1), 2)
+            .charge_key(
+                |req| {
+                    let org = req.headers().get("org")?.to_str().ok()?;
+                    Some(format!("org-{org}"))
+                },
+                Duration::from_secs(3600),
+                100,
+            )
+            .build_multi();
+        let req = TestRequest::get()
+            .uri("/users")
+            .insert_header(("org", "acme"))
+            .to_srv_request();
+        let inputs = input_fn(&req).await.unwrap();
+        let keys = inputs.iter().map(|i| &*i.key).collect::<Vec<_>>();
+        assert_eq!(keys, ["/users-60000", "/users-1000", "org-acme"]);
+        assert_eq!(inputs[2].max_requests, 100);
+        assert_eq!(inputs[2].interval, Duration::from_secs(3600));
+
+        // Not charged to an organization
+        let req = TestRequest::get().uri("/users").to_srv_request();
+        assert_eq!(input_fn(&req).await.unwrap().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_build_weighted() {
+        use crate::backend::weighted::content_length_cost;
+        use actix_web::test::TestRequest;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 100)
+            .path_key()
+            .build_weighted(content_length_cost(1000, 50));
+        let req = TestRequest::post()
+            .uri("/upload")
+            .insert_header(("content-length", "2500"))
+            .to_srv_request();
+        let input = input_fn(&req).await.unwrap();
+        assert_eq!(&*input.input.key, "/upload");
+        assert_eq!(input.cost, 3);
+        // Chunked
+        let req = TestRequest::post().uri("/upload").to_srv_request();
+        assert_eq!(input_fn(&req).await.unwrap().cost, 50);
+    }
+
+    #[actix_web::test]
+    async fn test_intern_keys() {
+        use actix_web::test::TestRequest;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .path_key()
+            .intern_keys(10)
+            .build();
+        let input_fn = &input_fn;
+        let key = |uri| {
+            let req = TestRequest::get().uri(uri).to_srv_request();
+            async move { input_fn(&req).await.unwrap().key }
+        };
+        let first = key("/a").await;
+        assert!(Arc::ptr_eq(&first, &key("/a").await));
+        assert!(!Arc::ptr_eq(&first, &key("/b").await));
+    }
+
+    #[actix_web::test]
+    async fn test_header_key() {
+        use actix_web::test::TestRequest;
+
+        let input_fn = |missing| {
+            SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+                .custom_key("api")
+                .header_key("x-api-key", missing)
+                .build()
+        };
+        let req = TestRequest::default()
+            .insert_header(("x-api-key", "abc"))
+            .to_srv_request();
+        let input = input_fn(MissingKey::Reject)(&req).await.unwrap();
+        assert_eq!(&*input.key, "api-abc");
+
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .to_srv_request();
+        let err = input_fn(MissingKey::Reject)(&req).await.unwrap_err();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        let input = input_fn(MissingKey::PeerIp)(&req).await.unwrap();
+        assert_eq!(&*input.key, "api-10.0.0.1");
+        let input = input_fn(MissingKey::Omit)(&req).await.unwrap();
+        assert_eq!(&*input.key, "api");
+    }
+
+    #[actix_web::test]
+    async fn test_trusted_proxies() {
+        use actix_web::test::TestRequest;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .real_ip_key()
+            .trusted_proxies(["10.0.0.0/8".parse().unwrap()])
+            .build();
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .insert_header(("x-forwarded-for", "192.0.2.1, 198.51.100.1"))
+            .to_srv_request();
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "198.51.100.1");
+        let req = TestRequest::default()
+            .peer_addr("203.0.113.1:1234".parse().unwrap())
+            .insert_header(("x-forwarded-for", "198.51.100.1"))
+            .to_srv_request();
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "203.0.113.1");
+    }
+
+    #[actix_web::test]
+    async fn test_forwarded() {
+        use actix_web::http::header::FORWARDED;
+        use actix_web::test::TestRequest;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .real_ip_key()
+            .build();
+        let req = TestRequest::default()
+            .insert_header((FORWARDED, "for=\"[2001:db8::1]:4711\";proto=https"))
+            .to_srv_request();
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "2001:db8::/64");
+        let req = TestRequest::default()
+            .insert_header((FORWARDED, "for=_hidden"))
+            .to_srv_request();
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "_hidden");
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_ip() {
+        use actix_web::test::TestRequest;
+
+        let input_fn = |unknown_ip| {
+            SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+                .real_ip_key()
+                .unknown_ip(unknown_ip)
+                .build()
+        };
+        // e.g. a Unix socket listener
+        let req = TestRequest::default().to_srv_request();
+        let err = input_fn(UnknownIp::Reject)(&req).await.unwrap_err();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        let input = input_fn(UnknownIp::Key("unknown".to_owned()))(&req)
+            .await
+            .unwrap();
+        assert_eq!(&*input.key, "unknown");
+        assert_eq!(input.max_requests, 1);
+        let input = input_fn(UnknownIp::Unlimited)(&req).await.unwrap();
+        assert_eq!(input.max_requests, u64::MAX);
+
+        let req = TestRequest::default()
+            .insert_header(("x-forwarded-for", "garbage"))
+            .to_srv_request();
+        let err = input_fn(UnknownIp::Reject)(&req).await.unwrap_err();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        let input = input_fn(UnknownIp::Unlimited)(&req).await.unwrap();
+        assert_eq!(input.max_requests, u64::MAX);
+    }
+
+    #[cfg(feature = "identity")]
+    #[actix_web::test]
+    async fn test_identity_key() {
+        use actix_identity::{Identity, IdentityMiddleware};
+        use actix_session::storage::CookieSessionStore;
+        use actix_session::{SessionExt, SessionMiddleware};
+        use actix_web::cookie::Key;
+        use actix_web::dev::Service;
+        use actix_web::test::{call_service, init_service, TestRequest};
+        use actix_web::{web, App, HttpMessage, HttpRequest};
+
+        let input_fn = Arc::new(
+            SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+                .identity_key(MissingKey::RealIp)
+                .session_key("team", MissingKey::Omit)
+                .build(),
+        );
+        let app = init_service(
+            App::new()
+                // Returns the rate limit key in a header
+                .wrap_fn(move |req, srv| {
+                    let key = input_fn(&req).into_inner().unwrap().key;
+                    let res = srv.call(req);
+                    async move {
+                        let mut res = res.await?;
+                        res.headers_mut()
+                            .insert("x-key".parse().unwrap(), key.parse().unwrap());
+                        Ok(res)
+                    }
+                })
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::from(&[0; 64]),
+                ))
+                .route(
+                    "/login",
+                    web::post().to(|req: HttpRequest| async move {
+                        Identity::login(&req.extensions(), "alice".to_owned()).unwrap();
+                        req.get_session().insert("team", 42).unwrap();
+                        "Logged in"
+                    }),
+                ),
+        )
+        .await;
+
+        let peer = "203.0.113.1:1234".parse().unwrap();
+        let req = TestRequest::post().uri("/login").peer_addr(peer);
+        let res = call_service(&app, req.to_request()).await;
+        assert_eq!(res.headers().get("x-key").unwrap(), "203.0.113.1");
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+
+        let req = TestRequest::post()
+            .uri("/login")
+            .peer_addr(peer)
+            .cookie(cookie);
+        let res = call_service(&app, req.to_request()).await;
+        assert_eq!(res.headers().get("x-key").unwrap(), "alice-42");
+    }
+
+    #[cfg(feature = "session")]
+    #[actix_web::test]
+    async fn test_session_key() {
+        use actix_session::storage::CookieSessionStore;
+        use actix_session::{SessionExt, SessionMiddleware};
+        use actix_web::cookie::Key;
+        use actix_web::dev::Service;
+        use actix_web::test::{call_service, init_service, TestRequest};
+        use actix_web::{web, App, HttpRequest};
+
+        let input_fn = Arc::new(
+            SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+                .session_key("team", MissingKey::RealIp)
+                .build(),
+        );
+        let app = init_service(
+            App::new()
+                // Returns the rate limit key in a header
+                .wrap_fn(move |req, srv| {
+                    let key = input_fn(&req).into_inner().unwrap().key;
+                    let res = srv.call(req);
+                    async move {
+                        let mut res = res.await?;
+                        res.headers_mut()
+                            .insert("x-key".parse().unwrap(), key.parse().unwrap());
+                        Ok(res)
+                    }
+                })
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::from(&[0; 64]),
+                ))
+                .route(
+                    "/join",
+                    web::post().to(|req: HttpRequest| async move {
+                        req.get_session().insert("team", "red").unwrap();
+                        "Joined"
+                    }),
+                ),
+        )
+        .await;
+
+        let peer = "203.0.113.1:1234".parse().unwrap();
+        let req = TestRequest::post().uri("/join").peer_addr(peer);
+        let res = call_service(&app, req.to_request()).await;
+        assert_eq!(res.headers().get("x-key").unwrap(), "203.0.113.1");
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+
+        let req = TestRequest::post()
+            .uri("/join")
+            .peer_addr(peer)
+            .cookie(cookie);
+        let res = call_service(&app, req.to_request()).await;
+        assert_eq!(res.headers().get("x-key").unwrap(), "red");
+    }
+
+    #[actix_web::test]
+    async fn test_host_key() {
+        use actix_web::http::header::HOST;
+        use actix_web::test::TestRequest;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .host_key()
+            .path_key()
+            .build();
+        let req = TestRequest::get()
+            .uri("/users")
+            .insert_header((HOST, "Tenant.example.com"))
+            .to_srv_request();
+        assert_eq!(
+            &*input_fn(&req).await.unwrap().key,
+            "tenant.example.com-/users"
+        );
+        let req = TestRequest::get()
+            .uri("/users")
+            .insert_header((HOST, "other.example.com:8080"))
+            .to_srv_request();
+        assert_eq!(
+            &*input_fn(&req).await.unwrap().key,
+            "other.example.com:8080-/users"
+        );
+    }
+
+    #[cfg(feature = "sha2")]
+    #[actix_web::test]
+    async fn test_hash_key() {
+        use actix_web::test::TestRequest;
+
+        let req = TestRequest::default().to_srv_request();
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .custom_key("abc")
+            .hash_key()
+            .build();
+        assert_eq!(
+            &*input_fn(&req).await.unwrap().key,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .custom_key("c")
+            .hash_key_salted("ab")
+            .build();
+        assert_eq!(
+            &*input_fn(&req).await.unwrap().key,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // The same as the standalone function, e.g. used by the CLI
+        assert_eq!(&*input_fn(&req).await.unwrap().key, hash_key("c", b"ab"));
+    }
+
+    #[actix_web::test]
+    async fn test_client_cert_key() {
+        use actix_web::test::TestRequest;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .client_cert_key(MissingKey::Reject)
+            .build();
+        let req = TestRequest::default().to_srv_request();
+        req.extensions_mut()
+            .insert(ClientCertificate::new("CN=service-a"));
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "CN=service\\-a");
+        let req = TestRequest::default().to_srv_request();
+        let err = input_fn(&req).await.unwrap_err();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn test_client_cert_fingerprint() {
+        assert_eq!(
+            ClientCertificate::from_der(b"abc").identity(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_user_agent_class_key() {
+        use crate::backend::UserAgentClass;
+        use actix_web::test::TestRequest;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .custom_key("client")
+            .user_agent_class_key(|ua| UserAgentClass::classify(ua).as_str())
+            .build();
+        let req = TestRequest::default()
+            .insert_header((USER_AGENT, "curl/8.4.0"))
+            .to_srv_request();
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "client-bot");
+        let req = TestRequest::default().to_srv_request();
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "client-unknown");
+    }
+
+    #[actix_web::test]
+    async fn test_cookie_key() {
+        use actix_web::test::TestRequest;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .cookie_key("session", MissingKey::RealIp)
+            .build();
+        let req = TestRequest::default()
+            .insert_header((COOKIE, "theme=dark; session=abc123"))
+            .to_srv_request();
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "abc123");
+
+        let req = TestRequest::default()
+            .insert_header((COOKIE, "theme=dark"))
+            .insert_header(("x-forwarded-for", "10.0.0.1"))
+            .to_srv_request();
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "10.0.0.1");
+    }
+
+    #[actix_web::test]
+    async fn test_build_with_quota() {
+        use actix_web::test::TestRequest;
+
+        struct Tiers;
+
+        impl QuotaProvider for Tiers {
+            async fn quota(&self, key: &str) -> Result<Option<RateLimitPolicy>, actix_web::Error> {
+                Ok((key == "premium").then(|| RateLimitPolicy::new(Duration::from_secs(1), 50)))
+            }
+        }
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 10)
+            .custom_fn(|req| {
+                Ok(req
+                    .headers()
+                    .get("x-api-key")
+                    .and_then(|key| key.to_str().ok())
+                    .unwrap_or_default()
+                    .to_owned())
+            })
+            .build_with_quota(Tiers);
+
+        let req = TestRequest::default()
+            .insert_header(("x-api-key", "premium"))
+            .to_srv_request();
+        let input = input_fn(&req).await.unwrap();
+        assert_eq!(input.interval, Duration::from_secs(1));
+        assert_eq!(input.max_requests, 50);
+
+        let req = TestRequest::default()
+            .insert_header(("x-api-key", "free"))
+            .to_srv_request();
+        let input = input_fn(&req).await.unwrap();
+        assert_eq!(input.interval, Duration::from_secs(60));
+        assert_eq!(input.max_requests, 10);
+    }
+
+    #[actix_web::test]
+    async fn test_custom_async_fn() {
+        use actix_web::test::TestRequest;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 10)
+            .custom_key("api")
+            .custom_async_fn(|req| {
+                let session = cookie(req, "session").map(str::to_owned);
+                async move {
+                    // e.g. look up the user from a session store
+                    tokio::task::yield_now().await;
+                    match session.as_deref() {
+                        Some("abc123") => Ok("alice".to_owned()),
+                        _ => Err(actix_web::error::ErrorUnauthorized("Not logged in")),
+                    }
+                }
+            })
+            .build_async();
+
+        let req = TestRequest::default()
+            .insert_header((COOKIE, "session=abc123"))
+            .to_srv_request();
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "api-alice");
+        let req = TestRequest::default().to_srv_request();
+        let err = input_fn(&req).await.unwrap_err();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "build_async")]
+    fn test_custom_async_fn_sync_build() {
+        let _ = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 10)
+            .custom_async_fn(|_| async { Ok(String::new()) })
+            .build();
+    }
+
+    #[actix_web::test]
+    async fn test_policy_set() {
+        use crate::backend::PolicyRule;
+        use actix_web::test::TestRequest;
+
+        const MINUTE: Duration = Duration::from_secs(60);
+        let set = PolicySet::new(RateLimitPolicy::new(MINUTE, 100))
+            .rule(PolicyRule::new("/login", RateLimitPolicy::new(MINUTE, 5)).methods(["POST"]));
+        let input_fn = SimpleInputFunctionBuilder::from_policy_set(set)
+            .custom_key("client")
+            .build();
+
+        let input = input_fn(&TestRequest::post().uri("/login").to_srv_request())
+            .await
+            .unwrap();
+        assert_eq!(input.max_requests, 5);
+        assert_eq!(&*input.key, "client-POST:/login");
+
+        let input = input_fn(&TestRequest::get().uri("/login").to_srv_request())
+            .await
+            .unwrap();
+        assert_eq!(input.max_requests, 100);
+        assert_eq!(&*input.key, "client");
+    }
+
+    #[cfg(feature = "dashmap")]
+    #[actix_web::test]
+    async fn test_match_pattern_key() {
+        use crate::backend::memory::InMemoryBackend;
+        use crate::RateLimiter;
+        use actix_web::test::{call_service, init_service, TestRequest};
+        use actix_web::{web, App, HttpResponse};
+
+        let limiter = RateLimiter::builder(
+            InMemoryBackend::builder().build(),
+            SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+                .match_pattern_key()
+                .build(),
+        )
+        .build();
+        let app = init_service(
+            App::new()
+                .route("/users/{id}", web::get().to(HttpResponse::Ok))
+                .route("/posts/{id}", web::get().to(HttpResponse::Ok))
+                .wrap(limiter),
+        )
+        .await;
+        let status = |uri: &'static str| {
+            let app = &app;
+            async move {
+                call_service(app, TestRequest::get().uri(uri).to_request())
+                    .await
+                    .status()
+            }
+        };
+        assert!(status("/users/1").await.is_success());
+        assert!(status("/posts/1").await.is_success());
+        // Different IDs share the same key
+        assert_eq!(status("/users/2").await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(status("/missing").await, StatusCode::NOT_FOUND);
+        assert_eq!(status("/other").await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn test_scope_keys() {
+        use crate::backend::memory::InMemoryBackend;
+        use crate::RateLimiter;
+        use actix_web::test::{call_service, init_service, TestRequest};
+        use actix_web::{web, App, HttpResponse};
+
+        use std::sync::Mutex;
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .scope_key()
+            .path_within_scope_key()
+            .build();
+        let keys = Arc::new(Mutex::new(Vec::new()));
+        let input = {
+            let keys = keys.clone();
+            move |req: &ServiceRequest| {
+                let input = input_fn(req).into_inner().unwrap();
+                keys.lock().unwrap().push(input.key.to_string());
+                ready(Ok(input))
+            }
+        };
+        let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input).build();
+        let app = init_service(
+            App::new()
+                .service(
+                    web::scope("/a")
+                        .route("/x", web::get().to(HttpResponse::Ok))
+                        .wrap(limiter.clone()),
+                )
+                .service(
+                    web::scope("/b")
+                        .route("/x", web::get().to(HttpResponse::Ok))
+                        .wrap(limiter),
+                ),
+        )
+        .await;
+        let status = |uri: &'static str| {
+            let app = &app;
+            async move {
+                call_service(app, TestRequest::get().uri(uri).to_request())
+                    .await
+                    .status()
+            }
+        };
+        assert!(status("/a/x").await.is_success());
+        assert!(status("/b/x").await.is_success());
+        assert_eq!(status("/a/x").await, 429);
+        assert_eq!(status("/b/x").await, 429);
+        assert_eq!(*keys.lock().unwrap(), ["/a-/x", "/b-/x", "/a-/x", "/b-/x"]);
+
+        let input_fn = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+            .scope_key()
+            .path_within_scope_key()
+            .build();
+        let input = input_fn(&TestRequest::get().uri("/a/x").to_srv_request())
+            .await
+            .unwrap();
+        // Not within a scope
+        assert_eq!(&*input.key, "-/a/x");
+    }
+
+    #[cfg(feature = "dashmap")]
+    #[actix_web::test]
+    async fn test_route_policy() {
+        use crate::backend::memory::InMemoryBackend;
+        use crate::RateLimiter;
+        use actix_web::test::{call_service, init_service, TestRequest};
+        use actix_web::{web, App, HttpResponse};
+
+        const MINUTE: Duration = Duration::from_secs(60);
+        let input = SimpleInputFunctionBuilder::new(MINUTE, 100)
+            .route_policy("/login", RateLimitPolicy::new(MINUTE, 5))
+            .build();
+        let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+            .add_headers()
+            .build();
+        let ok = || async { HttpResponse::Ok().finish() };
+        let app = init_service(
+            App::new()
+                .route("/login", web::get().to(ok))
+                .route("/search", web::get().to(ok))
+                .service(
+                    web::scope("/export")
+                        .app_data(RateLimitPolicy::new(MINUTE, 10).with_cost(5))
+                        .route("", web::get().to(ok)),
+                )
+                .wrap(limiter),
+        )
+        .await;
+        let limit = |uri: &'static str| {
+            let app = &app;
+            async move {
+                let response = call_service(app, TestRequest::get().uri(uri).to_request()).await;
+                response.headers().get("x-ratelimit-limit").unwrap().clone()
+            }
+        };
+        assert_eq!(limit("/login").await, "5");
+        assert_eq!(limit("/search").await, "100");
+        // Scope data isn't visible to a limiter that wraps the whole app
+        assert_eq!(limit("/export").await, "100");
+
+        let input = SimpleInputFunctionBuilder::new(MINUTE, 100).build();
+        let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+            .add_headers()
+            .build();
+        let app = init_service(
+            App::new().service(
+                web::scope("/export")
+                    .app_data(RateLimitPolicy::new(MINUTE, 10).with_cost(5))
+                    .route("", web::get().to(ok))
+                    .wrap(limiter),
+            ),
+        )
+        .await;
+        let response = call_service(&app, TestRequest::get().uri("/export").to_request()).await;
+        assert_eq!(response.headers().get("x-ratelimit-limit").unwrap(), "2");
+    }
 }
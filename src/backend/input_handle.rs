@@ -0,0 +1,117 @@
+use actix_web::dev::ServiceRequest;
+use arc_swap::ArcSwap;
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use std::future::Future;
+use std::sync::Arc;
+
+pub type BoxedInputFuture<I> = LocalBoxFuture<'static, Result<I, actix_web::Error>>;
+
+type BoxedInputFn<I> = Box<dyn Fn(&ServiceRequest) -> BoxedInputFuture<I> + Send + Sync>;
+
+/// A handle to an input function that can be replaced at runtime.
+///
+/// This allows the key strategy to be changed without restarting the server, e.g. switching from
+/// per IP address to per API key limits during an attack. The middleware picks up the new input
+/// function on the next request.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::{InputFunctionHandle, SimpleInputFunctionBuilder};
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_extensible_rate_limit::RateLimiter;
+/// # use std::time::Duration;
+/// let handle = InputFunctionHandle::new(
+///     SimpleInputFunctionBuilder::new(Duration::from_secs(60), 100)
+///         .peer_ip_key()
+///         .build(),
+/// );
+/// # actix_web::rt::System::new().block_on(async {
+/// let backend = InMemoryBackend::builder().build();
+/// let middleware = RateLimiter::builder(backend, handle.input_fn()).build();
+/// # });
+///
+/// // Later on, e.g. from an admin endpoint:
+/// handle.swap(
+///     SimpleInputFunctionBuilder::new(Duration::from_secs(60), 100)
+///         .custom_fn(|req| Ok(req.path().to_owned()))
+///         .build(),
+/// );
+/// ```
+pub struct InputFunctionHandle<I> {
+    current: Arc<ArcSwap<BoxedInputFn<I>>>,
+}
+
+impl<I> Clone for InputFunctionHandle<I> {
+    fn clone(&self) -> Self {
+        Self {
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<I: 'static> InputFunctionHandle<I> {
+    pub fn new<F, O>(input_fn: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> O + Send + Sync + 'static,
+        O: Future<Output = Result<I, actix_web::Error>> + 'static,
+    {
+        Self {
+            current: Arc::new(ArcSwap::from_pointee(Self::boxed(input_fn))),
+        }
+    }
+
+    /// Replace the input function, taking effect from the next request.
+    pub fn swap<F, O>(&self, input_fn: F)
+    where
+        F: Fn(&ServiceRequest) -> O + Send + Sync + 'static,
+        O: Future<Output = Result<I, actix_web::Error>> + 'static,
+    {
+        self.current.store(Arc::new(Self::boxed(input_fn)));
+    }
+
+    /// Returns an input function, to be passed to the [RateLimiter](crate::RateLimiter), that
+    /// delegates to the current input function of this handle.
+    pub fn input_fn(
+        &self,
+    ) -> impl Fn(&ServiceRequest) -> BoxedInputFuture<I> + Send + Sync + 'static {
+        let current = self.current.clone();
+        move |req| (current.load())(req)
+    }
+
+    fn boxed<F, O>(input_fn: F) -> BoxedInputFn<I>
+    where
+        F: Fn(&ServiceRequest) -> O + Send + Sync + 'static,
+        O: Future<Output = Result<I, actix_web::Error>> + 'static,
+    {
+        Box::new(move |req| input_fn(req).boxed_local())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use actix_web::test::TestRequest;
+    use std::time::Duration;
+
+    #[actix_web::test]
+    async fn test_swap() {
+        let handle = InputFunctionHandle::new(
+            SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+                .custom_key("first")
+                .build(),
+        );
+        let input_fn = handle.input_fn();
+        let req = TestRequest::default().to_srv_request();
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "first");
+        handle.swap(
+            SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+                .custom_key("second")
+                .build(),
+        );
+        // The existing input function should pick up the change
+        assert_eq!(&*input_fn(&req).await.unwrap().key, "second");
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// A rate limit key made up of multiple components, e.g. the client IP and the request path.
+///
+/// When converted to a string the components are joined with `-`. Any `-` or `\` within a
+/// component is escaped with a `\`, so that different components can never produce the same key,
+/// e.g. `["a-b", "c"]` and `["a", "b-c"]`.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::RateLimitKey;
+/// let key = RateLimitKey::new().with("192.0.2.1").with("/login").with("user-1");
+/// assert_eq!(key.to_string(), r"192.0.2.1-/login-user\-1");
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct RateLimitKey {
+    components: Vec<String>,
+}
+
+impl RateLimitKey {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a component to the key.
+    pub fn push(&mut self, component: impl Into<String>) {
+        self.components.push(component.into());
+    }
+
+    /// Append a component to the key, see [RateLimitKey::push].
+    pub fn with(mut self, component: impl Into<String>) -> Self {
+        self.push(component);
+        self
+    }
+
+    pub fn components(&self) -> &[String] {
+        &self.components
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+}
+
+impl Display for RateLimitKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, component) in self.components.iter().enumerate() {
+            if i > 0 {
+                f.write_str("-")?;
+            }
+            for c in component.chars() {
+                if c == '-' || c == '\\' {
+                    f.write_str("\\")?;
+                }
+                write!(f, "{c}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<RateLimitKey> for String {
+    fn from(key: RateLimitKey) -> Self {
+        key.to_string()
+    }
+}
+
+impl From<RateLimitKey> for Arc<str> {
+    fn from(key: RateLimitKey) -> Self {
+        key.to_string().into()
+    }
+}
+
+impl<S: Into<String>> FromIterator<S> for RateLimitKey {
+    fn from_iter<T: IntoIterator<Item = S>>(iter: T) -> Self {
+        Self {
+            components: iter.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// Interns keys so that repeated keys share an allocation, see
+/// [SimpleInputFunctionBuilder::intern_keys](crate::backend::SimpleInputFunctionBuilder::intern_keys).
+///
+/// Approximates an LRU cache using two generations; when the current generation is full it
+/// replaces the previous one, and keys used from the previous generation are moved to the
+/// current one.
+#[cfg_attr(not(feature = "actix"), allow(dead_code))]
+pub(crate) struct KeyInterner {
+    capacity: usize,
+    generations: Mutex<Generations>,
+}
+
+#[cfg_attr(not(feature = "actix"), allow(dead_code))]
+#[derive(Default)]
+struct Generations {
+    current: HashSet<Arc<str>>,
+    previous: HashSet<Arc<str>>,
+}
+
+#[cfg_attr(not(feature = "actix"), allow(dead_code))]
+impl KeyInterner {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            generations: Default::default(),
+        }
+    }
+
+    pub(crate) fn intern(&self, key: String) -> Arc<str> {
+        let mut generations = self.generations.lock().unwrap();
+        if let Some(interned) = generations.current.get(key.as_str()) {
+            return interned.clone();
+        }
+        let interned = generations
+            .previous
+            .take(key.as_str())
+            .unwrap_or_else(|| key.into());
+        if generations.current.len() >= self.capacity {
+            generations.previous = std::mem::take(&mut generations.current);
+        }
+        generations.current.insert(interned.clone());
+        interned
+    }
+}
+
+/// Hashes a rate limit key the same way as
+/// [SimpleInputFunctionBuilder::hash_key_salted](crate::backend::SimpleInputFunctionBuilder::hash_key_salted)
+/// (with an empty salt for `hash_key`), e.g. to find the key stored in the backend for a client.
+#[cfg(feature = "sha2")]
+#[cfg_attr(docsrs, doc(cfg(feature = "sha2")))]
+pub fn hash_key(key: &str, salt: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::new()
+        .chain_update(salt)
+        .chain_update(key.as_bytes())
+        .finalize();
+    hex(&digest)
+}
+
+/// Hex encodes a hash.
+#[cfg(feature = "sha2")]
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escaping() {
+        let key = |components: &[&str]| components.iter().copied().collect::<RateLimitKey>();
+        assert_eq!(key(&[]).to_string(), "");
+        assert_eq!(key(&["a", "b"]).to_string(), "a-b");
+        assert_ne!(
+            key(&["a-b", "c"]).to_string(),
+            key(&["a", "b-c"]).to_string()
+        );
+        assert_ne!(key(&["a\\", "b"]).to_string(), key(&["a\\-b"]).to_string());
+        assert_eq!(key(&["a\\-b"]).to_string(), r"a\\\-b");
+    }
+
+    #[test]
+    fn test_interner() {
+        let interner = KeyInterner::new(2);
+        let a = interner.intern("a".to_string());
+        assert!(Arc::ptr_eq(&a, &interner.intern("a".to_string())));
+        interner.intern("b".to_string());
+        // "a" and "b" move to the previous generation
+        interner.intern("c".to_string());
+        assert!(Arc::ptr_eq(&a, &interner.intern("a".to_string())));
+        interner.intern("d".to_string());
+        interner.intern("e".to_string());
+        assert!(Arc::ptr_eq(&a, &interner.intern("a".to_string())));
+        // Keys that aren't used for two generations are evicted
+        let b = interner.intern("b".to_string());
+        interner.intern("f".to_string());
+        interner.intern("g".to_string());
+        interner.intern("h".to_string());
+        assert!(!Arc::ptr_eq(&b, &interner.intern("b".to_string())));
+    }
+}
@@ -0,0 +1,40 @@
+/// A custom limit for an individual rate limit key, stored in the backend so that e.g. a
+/// customer's limit can be boosted without a deploy.
+///
+/// Overrides are applied by the backend to the [SimpleInput::max_requests] of every request for
+/// the key, so the reported limit and remaining requests reflect the override.
+///
+/// [SimpleInput::max_requests]: crate::backend::SimpleInput::max_requests
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitOverride {
+    /// Replaces the limit, regardless of the input.
+    Limit(u64),
+    /// Multiplies the limit of the input, e.g. `Multiplier(10.0)` to give a key 10x the usual
+    /// limit. The result is rounded to the nearest integer.
+    Multiplier(f64),
+}
+
+impl LimitOverride {
+    /// Returns the overridden limit.
+    pub fn apply(self, max_requests: u64) -> u64 {
+        match self {
+            Self::Limit(limit) => limit,
+            // Float to integer casts saturate
+            Self::Multiplier(multiplier) => (max_requests as f64 * multiplier).round() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(LimitOverride::Limit(500).apply(100), 500);
+        assert_eq!(LimitOverride::Multiplier(10.0).apply(100), 1000);
+        assert_eq!(LimitOverride::Multiplier(0.5).apply(5), 3);
+        assert_eq!(LimitOverride::Multiplier(2.0).apply(u64::MAX), u64::MAX);
+        assert_eq!(LimitOverride::Multiplier(-1.0).apply(100), 0);
+    }
+}
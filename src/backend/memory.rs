@@ -1,50 +1,442 @@
-use crate::backend::{Backend, Decision, SimpleBackend, SimpleInput, SimpleOutput};
-use actix_web::rt::task::JoinHandle;
-use actix_web::rt::time::Instant;
+use crate::backend::window::{Timeline, WindowAlignment};
+use crate::backend::{
+    Backend, Decision, KeyStatus, LimitOverride, SimpleBackend, SimpleInput, SimpleOutput, MAX_TTL,
+};
+use dashmap::mapref::entry::Entry;
+use dashmap::mapref::multiple::RefMulti;
+use dashmap::mapref::one::Ref;
 use dashmap::DashMap;
+use std::collections::hash_map::RandomState;
 use std::convert::Infallible;
-use std::sync::Arc;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Weak};
 use std::time::Duration;
+use tokio::time::Instant;
 
 pub const DEFAULT_GC_INTERVAL_SECONDS: u64 = 60 * 10;
+pub const DEFAULT_GC_SEGMENTS: usize = 16;
+
+/// The name of the counter that is incremented each time a request for a new key is made while
+/// the backend holds the [maximum number of keys](Builder::with_max_keys).
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+pub const MAX_KEYS_COUNTER: &str = "rate_limit_max_keys_reached_total";
+
+/// The name of the counter of keys scanned by the garbage collector.
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+pub const GC_SCANNED_COUNTER: &str = "rate_limit_gc_keys_scanned_total";
+
+/// The name of the counter of expired keys removed by the garbage collector.
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+pub const GC_REMOVED_COUNTER: &str = "rate_limit_gc_keys_removed_total";
+
+/// The name of the histogram of garbage collector sweep durations, in seconds.
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+pub const GC_DURATION_HISTOGRAM: &str = "rate_limit_gc_duration_seconds";
 
 /// A Fixed Window rate limiter [Backend] that uses [Dashmap](dashmap::DashMap) to store keys
 /// in memory.
+///
+/// Clones of the backend share the same counts, so to enforce the same limit across all the
+/// workers of a `HttpServer`, the backend must be created once outside of the `HttpServer::new`
+/// factory closure, and cloned inside it. If it is created inside the closure instead, each worker
+/// silently gets its own counts; if that is intended, the
+/// [PerWorkerInMemoryBackend](crate::backend::per_worker::PerWorkerInMemoryBackend) avoids the
+/// cost of locking.
+///
+/// By default each key's window starts at its first request, see [Builder::with_alignment] to
+/// align the windows to the wall clock instead.
+///
+/// Individual keys can be given custom limits with [InMemoryBackend::set_limit_override].
+///
+/// Unused requests can carry over into the next window, see [Builder::with_carry_over].
+///
+/// The number of keys is unbounded by default, see [Builder::with_max_keys] to guard against
+/// floods of unique keys exhausting memory.
 #[derive(Clone)]
 pub struct InMemoryBackend {
-    map: Arc<DashMap<String, Value>>,
-    gc_handle: Option<Arc<JoinHandle<()>>>,
+    map: Arc<Store>,
+    overrides: Arc<DashMap<Arc<str>, LimitOverride>>,
+    timeline: Option<Timeline>,
+    carry_over: Option<CarryOver>,
+    key_limit: Option<Arc<KeyLimit>>,
+}
+
+/// What an [InMemoryBackend] does with a request for a new key once it holds the
+/// [maximum number of keys](Builder::with_max_keys).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EvictionPolicy {
+    /// Evict the least recently used keys to make room for the new key.
+    LeastRecentlyUsed,
+    /// Deny the request, so existing keys are unaffected but new clients are denied until keys
+    /// are garbage collected.
+    RejectNewKeys,
+}
+
+impl EvictionPolicy {
+    #[cfg(feature = "metrics")]
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::LeastRecentlyUsed => "least_recently_used",
+            Self::RejectNewKeys => "reject_new_keys",
+        }
+    }
+}
+
+type MaxKeysCallback = dyn Fn(&str) + Send + Sync;
+
+struct KeyLimit {
+    max_keys: usize,
+    policy: EvictionPolicy,
+    callback: Option<Box<MaxKeysCallback>>,
+}
+
+/// Statistics of a garbage collection sweep, see [Builder::on_gc].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct GcStats {
+    /// The number of keys checked.
+    pub scanned: usize,
+    /// The number of expired keys removed.
+    pub removed: usize,
+    /// How long the sweep took.
+    pub duration: Duration,
+}
+
+impl GcStats {
+    fn add(&mut self, other: GcStats) {
+        self.scanned += other.scanned;
+        self.removed += other.removed;
+        self.duration += other.duration;
+    }
+}
+
+type GcCallback = dyn Fn(GcStats) + Send + Sync;
+
+/// The buckets, split into segments that are swept one at a time, so that the garbage collector
+/// never scans every key at once.
+struct Store {
+    hasher: RandomState,
+    segments: Box<[DashMap<Arc<str>, Value>]>,
+}
+
+impl Store {
+    fn new(segments: usize) -> Self {
+        Self {
+            hasher: RandomState::new(),
+            segments: (0..segments).map(|_| DashMap::new()).collect(),
+        }
+    }
+
+    fn segment(&self, key: &str) -> &DashMap<Arc<str>, Value> {
+        let index = self.hasher.hash_one(key) as usize % self.segments.len();
+        &self.segments[index]
+    }
+
+    fn entry(&self, key: Arc<str>) -> Entry<'_, Arc<str>, Value> {
+        self.segment(&key).entry(key)
+    }
+
+    fn get(&self, key: &str) -> Option<Ref<'_, Arc<str>, Value>> {
+        self.segment(key).get(key)
+    }
+
+    fn insert(&self, key: Arc<str>, value: Value) {
+        self.segment(&key).insert(key, value);
+    }
+
+    fn remove(&self, key: &str) {
+        self.segment(key).remove(key);
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.segment(key).contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        self.segments.iter().map(DashMap::len).sum()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = RefMulti<'_, Arc<str>, Value>> {
+        self.segments.iter().flat_map(DashMap::iter)
+    }
+
+    fn retain(&self, mut f: impl FnMut(&Arc<str>, &mut Value) -> bool) {
+        for segment in self.segments.iter() {
+            segment.retain(&mut f);
+        }
+    }
+
+    /// Removes the expired buckets from a segment.
+    fn sweep(&self, index: usize, now: Instant) -> GcStats {
+        let start = std::time::Instant::now();
+        let mut stats = GcStats::default();
+        self.segments[index].retain(|_k, v| {
+            stats.scanned += 1;
+            let keep = v.retain_until > now;
+            if !keep {
+                stats.removed += 1;
+            }
+            keep
+        });
+        stats.duration = start.elapsed();
+        stats
+    }
 }
 
+/// The [InMemoryBackend], named for when it is intentionally shared by all workers, as opposed to
+/// the [PerWorkerInMemoryBackend](crate::backend::per_worker::PerWorkerInMemoryBackend).
+pub type SharedInMemoryBackend = InMemoryBackend;
+
 struct Value {
     ttl: Instant,
     count: u64,
+    /// Requests carried over from the previous window, in addition to the limit.
+    credit: u64,
+    /// When the value may be garbage collected, after the window it could carry over into.
+    retain_until: Instant,
+    last_used: Instant,
+}
+
+#[derive(Clone, Copy)]
+struct CarryOver {
+    fraction: f64,
+    cap: f64,
+}
+
+impl CarryOver {
+    /// The credit for the next window, given the count and credit of the previous window.
+    fn credit(self, max_requests: u64, credit: u64, count: u64) -> u64 {
+        let unused = max_requests.saturating_add(credit).saturating_sub(count);
+        let carried = (unused as f64 * self.fraction) as u64;
+        carried.min((max_requests as f64 * self.cap) as u64)
+    }
 }
 
 impl InMemoryBackend {
     pub fn builder() -> Builder {
         Builder {
             gc_interval: Some(Duration::from_secs(DEFAULT_GC_INTERVAL_SECONDS)),
+            gc_segments: DEFAULT_GC_SEGMENTS,
+            on_gc: None,
+            alignment: None,
+            carry_over: None,
+            max_keys: None,
+            on_max_keys: None,
+        }
+    }
+
+    /// Override the limit of a rate limit key, e.g. to give a customer 10x the usual limit,
+    /// replacing any existing override for the key.
+    ///
+    /// The override applies to every request for the key until it is removed, and is unaffected
+    /// by the key's window resetting, [SimpleBackend::remove_key] or [SimpleBackend::clear].
+    pub fn set_limit_override(&self, key: &str, limit: LimitOverride) {
+        self.overrides.insert(key.into(), limit);
+    }
+
+    /// Remove the limit override of a rate limit key, if it has one.
+    pub fn remove_limit_override(&self, key: &str) {
+        self.overrides.remove(key);
+    }
+
+    /// Returns the limit override of a rate limit key, if it has one.
+    pub fn limit_override(&self, key: &str) -> Option<LimitOverride> {
+        self.overrides.get(key).map(|limit| *limit)
+    }
+
+    /// The limit of the input, after applying any override for its key.
+    fn max_requests(&self, input: &SimpleInput) -> u64 {
+        match self.overrides.get(&input.key) {
+            Some(limit) => limit.apply(input.max_requests),
+            None => input.max_requests,
+        }
+    }
+
+    /// The time at which a window starting at `now` resets.
+    fn window_end(&self, now: Instant, interval: Duration) -> Instant {
+        match &self.timeline {
+            Some(timeline) => timeline.next_reset(now, interval),
+            None => now
+                .checked_add(interval)
+                .expect("Interval unexpectedly large"),
+        }
+    }
+
+    /// When a window ending at `expiry` may be garbage collected.
+    fn retain_until(&self, expiry: Instant, interval: Duration) -> Instant {
+        match self.carry_over {
+            // Keep the count until the end of the next window, so it can carry over
+            Some(_) => expiry.checked_add(interval).unwrap_or(expiry),
+            None => expiry,
+        }
+    }
+
+    /// The credit carried over into a new window starting at `now`, from an expired bucket.
+    fn carried_credit(&self, value: &Value, now: Instant, max_requests: u64) -> u64 {
+        match self.carry_over {
+            // Only carry over into the window immediately following
+            Some(carry_over) if value.retain_until > now => {
+                carry_over.credit(max_requests, value.credit, value.count)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Makes room for a new key if the backend holds the maximum number of keys, returning false
+    /// if the request should be rejected instead.
+    fn admit(&self, key: &str) -> bool {
+        let Some(key_limit) = &self.key_limit else {
+            return true;
+        };
+        if self.map.len() < key_limit.max_keys || self.map.contains_key(key) {
+            return true;
+        }
+        if let Some(callback) = &key_limit.callback {
+            callback(key);
+        }
+        #[cfg(feature = "metrics")]
+        metrics::counter!(MAX_KEYS_COUNTER, "policy" => key_limit.policy.as_str()).increment(1);
+        match key_limit.policy {
+            EvictionPolicy::LeastRecentlyUsed => {
+                self.evict(key_limit.max_keys);
+                true
+            }
+            EvictionPolicy::RejectNewKeys => false,
         }
     }
 
-    fn garbage_collector(map: Arc<DashMap<String, Value>>, interval: Duration) -> JoinHandle<()> {
+    /// Evicts the least recently used tenth of the keys, so that the cost of finding them is
+    /// amortized over the following new keys.
+    fn evict(&self, max_keys: usize) {
+        let mut entries: Vec<(Instant, Arc<str>)> = self
+            .map
+            .iter()
+            .map(|entry| (entry.last_used, entry.key().clone()))
+            .collect();
+        let count = (max_keys / 10).max(1);
+        if count < entries.len() {
+            entries.select_nth_unstable_by_key(count, |(last_used, _)| *last_used);
+            entries.truncate(count);
+        }
+        for (_, key) in entries {
+            self.map.remove(&key);
+        }
+    }
+
+    /// Removes every expired bucket now, rather than waiting for the garbage collector, e.g. after
+    /// a burst of requests with unique keys, or when garbage collection is disabled.
+    ///
+    /// Unlike the garbage collector this sweeps every segment at once.
+    pub fn gc_now(&self) -> GcStats {
+        let now = Instant::now();
+        let mut stats = GcStats::default();
+        for index in 0..self.map.segments.len() {
+            stats.add(self.map.sweep(index, now));
+        }
+        stats
+    }
+
+    // The garbage collector sweeps one segment per tick, so that every key is checked once per
+    // interval. It only holds a weak reference to the map, so that it stops once the last clone
+    // of the backend is dropped.
+    fn garbage_collector(map: Weak<Store>, interval: Duration, callback: Option<Box<GcCallback>>) {
         assert!(
             interval.as_secs_f64() > 0f64,
             "GC interval must be non-zero"
         );
-        actix_web::rt::spawn(async move {
+        tokio::spawn(async move {
+            let mut index = 0;
             loop {
                 let now = Instant::now();
-                map.retain(|_k, v| v.ttl > now);
-                actix_web::rt::time::sleep_until(now + interval).await;
+                let Some(map) = map.upgrade() else {
+                    return;
+                };
+                let segments = map.segments.len();
+                let stats = map.sweep(index, now);
+                drop(map);
+                #[cfg(feature = "metrics")]
+                {
+                    metrics::counter!(GC_SCANNED_COUNTER).increment(stats.scanned as u64);
+                    metrics::counter!(GC_REMOVED_COUNTER).increment(stats.removed as u64);
+                    metrics::histogram!(GC_DURATION_HISTOGRAM).record(stats.duration);
+                }
+                if let Some(callback) = &callback {
+                    callback(stats);
+                }
+                index = (index + 1) % segments;
+                tokio::time::sleep_until(now + interval / segments as u32).await;
             }
-        })
+        });
+    }
+
+    /// Increment the bucket for the input by `amount`, creating it if it doesn't exist.
+    fn increment(&self, input: &SimpleInput, amount: u64) -> (Decision, SimpleOutput) {
+        let now = Instant::now();
+        let max_requests = self.max_requests(input);
+        let mut count = amount;
+        let mut credit = 0;
+        let mut expiry = self.window_end(now, input.interval);
+        let retain_until = self.retain_until(expiry, input.interval);
+        if !self.admit(&input.key) {
+            let output = SimpleOutput {
+                limit: max_requests,
+                remaining: 0,
+                reset: expiry,
+                reset_at: None,
+            };
+            return (Decision::Denied, output);
+        }
+        self.map
+            .entry(input.key.clone())
+            .and_modify(|v| {
+                // If this bucket hasn't yet expired, increment and extract the count/expiry
+                v.last_used = now;
+                if v.ttl > now {
+                    v.count = v.count.saturating_add(amount);
+                    count = v.count;
+                    credit = v.credit;
+                    expiry = v.ttl;
+                } else {
+                    // If this bucket has expired we will reset the count and set a new TTL,
+                    // carrying over any unused requests.
+                    credit = self.carried_credit(v, now, max_requests);
+                    v.ttl = expiry;
+                    v.count = count;
+                    v.credit = credit;
+                    v.retain_until = retain_until;
+                }
+            })
+            .or_insert_with(|| Value {
+                // If the bucket doesn't exist, create it with the initial count, and set the TTL.
+                ttl: expiry,
+                count,
+                credit,
+                retain_until,
+                last_used: now,
+            });
+        let limit = max_requests.saturating_add(credit);
+        let allow = count <= limit;
+        let output = SimpleOutput {
+            limit,
+            remaining: limit.saturating_sub(count),
+            reset: expiry,
+            reset_at: None,
+        };
+        (Decision::from_allowed(allow), output)
     }
 }
 
 pub struct Builder {
     gc_interval: Option<Duration>,
+    gc_segments: usize,
+    on_gc: Option<Box<GcCallback>>,
+    alignment: Option<WindowAlignment>,
+    carry_over: Option<CarryOver>,
+    max_keys: Option<(usize, EvictionPolicy)>,
+    on_max_keys: Option<Box<MaxKeysCallback>>,
 }
 
 impl Builder {
@@ -52,61 +444,136 @@ impl Builder {
     ///
     /// Set to None to disable garbage collection.
     ///
-    /// The garbage collector periodically scans the internal map, removing expired buckets.
+    /// The garbage collector periodically scans the internal map, removing expired buckets. Every
+    /// bucket is checked once per interval.
     pub fn with_gc_interval(mut self, interval: Option<Duration>) -> Self {
         self.gc_interval = interval;
         self
     }
 
+    /// Override the number of segments the internal map is split into.
+    ///
+    /// The garbage collector sweeps one segment at a time, spread evenly over the GC interval,
+    /// so more segments means fewer keys are locked and scanned at once, avoiding latency spikes
+    /// when there are millions of keys.
+    ///
+    /// Defaults to [DEFAULT_GC_SEGMENTS].
+    pub fn with_gc_segments(mut self, segments: usize) -> Self {
+        assert!(segments > 0, "Number of GC segments must be non-zero");
+        self.gc_segments = segments;
+        self
+    }
+
+    /// Call a function with the statistics of each segment swept by the garbage collector, e.g.
+    /// to monitor how long sweeps take.
+    ///
+    /// When the `metrics` feature is enabled the statistics are also recorded to the
+    /// `rate_limit_gc_keys_scanned_total` and `rate_limit_gc_keys_removed_total` counters, and the
+    /// `rate_limit_gc_duration_seconds` histogram.
+    pub fn on_gc<C>(mut self, callback: C) -> Self
+    where
+        C: Fn(GcStats) + Send + Sync + 'static,
+    {
+        self.on_gc = Some(Box::new(callback));
+        self
+    }
+
+    /// Align the windows of every key to fixed boundaries, e.g. with [WindowAlignment::utc] a
+    /// daily limit always resets at midnight UTC, as required by "X per day" quotas, instead of
+    /// 24 hours after the key's first request.
+    ///
+    /// By default each key's window starts at its first request.
+    pub fn with_alignment(mut self, alignment: WindowAlignment) -> Self {
+        self.alignment = Some(alignment);
+        self
+    }
+
+    /// Carry a `fraction` (0 to 1) of each key's unused requests over into its next window, up to
+    /// `cap` times the limit, so that clients that are bursty but well-behaved on average aren't
+    /// denied for the occasional burst.
+    ///
+    /// For example with a limit of 100 requests per minute and `with_carry_over(0.5, 0.5)`, a key
+    /// that makes 20 requests in a minute may make up to 140 requests in the following minute,
+    /// and never more than 150. Credit is included in the reported limit, and is only carried
+    /// into the window immediately following, so it is lost if a key makes no requests for a
+    /// whole window.
+    ///
+    /// By default unused requests are discarded when the window resets.
+    ///
+    /// # Panics
+    ///
+    /// If the fraction isn't between 0 and 1, or the cap is negative.
+    pub fn with_carry_over(mut self, fraction: f64, cap: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "Carry over fraction must be between 0 and 1"
+        );
+        assert!(cap >= 0.0, "Carry over cap must be non-negative");
+        self.carry_over = Some(CarryOver { fraction, cap });
+        self
+    }
+
+    /// Limit the number of keys held in memory, so that a flood of requests with unique keys, e.g.
+    /// from spoofed IP addresses, degrades gracefully instead of exhausting memory.
+    ///
+    /// Once the limit is reached, requests for new keys are handled according to the `policy`.
+    /// Expired keys count towards the limit until they are garbage collected (or evicted).
+    ///
+    /// By default the number of keys is unbounded.
+    ///
+    /// # Panics
+    ///
+    /// If `max_keys` is zero.
+    pub fn with_max_keys(mut self, max_keys: usize, policy: EvictionPolicy) -> Self {
+        assert!(max_keys > 0, "Max keys must be non-zero");
+        self.max_keys = Some((max_keys, policy));
+        self
+    }
+
+    /// Call a function with the new key whenever a request for it is made while the backend holds
+    /// the [maximum number of keys](Builder::with_max_keys), e.g. to alert on a possible attack.
+    ///
+    /// The function is called synchronously while handling the request, so should be cheap.
+    pub fn on_max_keys<C>(mut self, callback: C) -> Self
+    where
+        C: Fn(&str) + Send + Sync + 'static,
+    {
+        self.on_max_keys = Some(Box::new(callback));
+        self
+    }
+
     pub fn build(self) -> InMemoryBackend {
-        let map = Arc::new(DashMap::<String, Value>::new());
-        let gc_handle = self.gc_interval.map(|gc_interval| {
-            Arc::new(InMemoryBackend::garbage_collector(map.clone(), gc_interval))
-        });
-        InMemoryBackend { map, gc_handle }
+        let map = Arc::new(Store::new(self.gc_segments));
+        if let Some(gc_interval) = self.gc_interval {
+            InMemoryBackend::garbage_collector(Arc::downgrade(&map), gc_interval, self.on_gc);
+        }
+        InMemoryBackend {
+            map,
+            overrides: Default::default(),
+            timeline: self.alignment.map(Timeline::new),
+            carry_over: self.carry_over,
+            key_limit: self.max_keys.map(|(max_keys, policy)| {
+                Arc::new(KeyLimit {
+                    max_keys,
+                    policy,
+                    callback: self.on_max_keys,
+                })
+            }),
+        }
     }
 }
 
 impl Backend<SimpleInput> for InMemoryBackend {
     type Output = SimpleOutput;
-    type RollbackToken = String;
+    type RollbackToken = Arc<str>;
     type Error = Infallible;
 
     async fn request(
         &self,
         input: SimpleInput,
     ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
-        let now = Instant::now();
-        let mut count = 1;
-        let mut expiry = now
-            .checked_add(input.interval)
-            .expect("Interval unexpectedly large");
-        self.map
-            .entry(input.key.clone())
-            .and_modify(|v| {
-                // If this bucket hasn't yet expired, increment and extract the count/expiry
-                if v.ttl > now {
-                    v.count += 1;
-                    count = v.count;
-                    expiry = v.ttl;
-                } else {
-                    // If this bucket has expired we will reset the count to 1 and set a new TTL.
-                    v.ttl = expiry;
-                    v.count = count;
-                }
-            })
-            .or_insert_with(|| Value {
-                // If the bucket doesn't exist, create it with a count of 1, and set the TTL.
-                ttl: expiry,
-                count,
-            });
-        let allow = count <= input.max_requests;
-        let output = SimpleOutput {
-            limit: input.max_requests,
-            remaining: input.max_requests.saturating_sub(count),
-            reset: expiry,
-        };
-        Ok((Decision::from_allowed(allow), output, input.key))
+        let (decision, output) = self.increment(&input, 1);
+        Ok((decision, output, input.key))
     }
 
     async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
@@ -115,6 +582,29 @@ impl Backend<SimpleInput> for InMemoryBackend {
         });
         Ok(())
     }
+
+    async fn peek(&self, input: SimpleInput) -> Result<(Decision, Self::Output), Self::Error> {
+        let now = Instant::now();
+        let max_requests = self.max_requests(&input);
+        let (count, credit, expiry) = match self.map.get(&input.key) {
+            Some(v) if v.ttl > now => (v.count + 1, v.credit, v.ttl),
+            Some(v) => (
+                1,
+                self.carried_credit(&v, now, max_requests),
+                self.window_end(now, input.interval),
+            ),
+            None => (1, 0, self.window_end(now, input.interval)),
+        };
+        let limit = max_requests.saturating_add(credit);
+        let allow = count <= limit;
+        let output = SimpleOutput {
+            limit,
+            remaining: limit.saturating_sub(count),
+            reset: expiry,
+            reset_at: None,
+        };
+        Ok((Decision::from_allowed(allow), output))
+    }
 }
 
 impl SimpleBackend for InMemoryBackend {
@@ -122,19 +612,79 @@ impl SimpleBackend for InMemoryBackend {
         self.map.remove(key);
         Ok(())
     }
-}
 
-impl Drop for InMemoryBackend {
-    fn drop(&mut self) {
-        if let Some(handle) = &self.gc_handle {
-            handle.abort();
+    async fn list(&self, prefix: &str) -> Result<Vec<KeyStatus>, Self::Error> {
+        let now = Instant::now();
+        Ok(self
+            .map
+            .iter()
+            .filter(|entry| entry.key().starts_with(prefix) && entry.ttl > now)
+            .map(|entry| KeyStatus {
+                key: entry.key().to_string(),
+                count: entry.count,
+                ttl: entry.ttl - now,
+            })
+            .collect())
+    }
+
+    async fn clear(&self, prefix: &str) -> Result<(), Self::Error> {
+        self.map.retain(|k, _| !k.starts_with(prefix));
+        Ok(())
+    }
+
+    /// If the windows are aligned, the current window of an existing key is kept, otherwise the
+    /// key resets after `ttl`.
+    ///
+    /// New keys are subject to the [maximum number of keys](Builder::with_max_keys) in the same
+    /// way as requests, so with [EvictionPolicy::RejectNewKeys] the count isn't stored (requests
+    /// for the key are denied regardless).
+    async fn set_count(&self, key: &str, count: u64, ttl: Duration) -> Result<(), Self::Error> {
+        if !self.admit(key) {
+            return Ok(());
         }
+        let now = Instant::now();
+        let current = match &self.timeline {
+            Some(_) => self.map.get(key).map(|v| v.ttl).filter(|ttl| *ttl > now),
+            None => None,
+        };
+        let ttl = current.unwrap_or_else(|| self.window_end(now, ttl.min(MAX_TTL)));
+        let value = Value {
+            ttl,
+            count,
+            credit: 0,
+            retain_until: ttl,
+            last_used: now,
+        };
+        self.map.insert(key.into(), value);
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<KeyStatus>, Self::Error> {
+        let now = Instant::now();
+        Ok(self
+            .map
+            .get(key)
+            .filter(|v| v.ttl > now)
+            .map(|v| KeyStatus {
+                key: key.to_owned(),
+                count: v.count,
+                ttl: v.ttl - now,
+            }))
+    }
+
+    async fn consume(
+        &self,
+        input: SimpleInput,
+        cost: u64,
+    ) -> Result<(Decision, SimpleOutput), Self::Error> {
+        Ok(self.increment(&input, cost.max(1)))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::SerializableRollbackToken;
 
     const MINUTE: Duration = Duration::from_secs(60);
 
@@ -145,7 +695,7 @@ mod tests {
         let input = SimpleInput {
             interval: MINUTE,
             max_requests: 5,
-            key: "KEY1".to_string(),
+            key: "KEY1".into(),
         };
         for _ in 0..5 {
             // First 5 should be allowed
@@ -164,7 +714,7 @@ mod tests {
         let input = SimpleInput {
             interval: MINUTE,
             max_requests: 1,
-            key: "KEY1".to_string(),
+            key: "KEY1".into(),
         };
         // Make first request, should be allowed
         let (decision, _, _) = backend.request(input.clone()).await.unwrap();
@@ -180,17 +730,213 @@ mod tests {
         assert!(decision.is_allowed());
     }
 
+    #[actix_web::test]
+    async fn test_sub_second_interval() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder().with_gc_interval(None).build();
+        let input = SimpleInput {
+            interval: Duration::from_millis(100),
+            max_requests: 1,
+            key: "KEY1".into(),
+        };
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.reset, Instant::now() + Duration::from_millis(100));
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        tokio::time::advance(Duration::from_millis(100)).await;
+        let (decision, _, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+    }
+
+    #[actix_web::test]
+    async fn test_limit_override() {
+        let backend = InMemoryBackend::builder().build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 1,
+            key: "KEY1".into(),
+        };
+        backend.set_limit_override("KEY1", LimitOverride::Multiplier(3.0));
+        assert_eq!(
+            backend.limit_override("KEY1"),
+            Some(LimitOverride::Multiplier(3.0))
+        );
+        for remaining in [2, 1, 0] {
+            let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+            assert!(decision.is_allowed());
+            assert_eq!(output.limit, 3);
+            assert_eq!(output.remaining, remaining);
+        }
+        let (decision, _) = backend.peek(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+
+        // The override survives the key being reset
+        backend.remove_key("KEY1").await.unwrap();
+        backend.set_limit_override("KEY1", LimitOverride::Limit(2));
+        let (_, output, _) = backend.request(input.clone()).await.unwrap();
+        assert_eq!(output.limit, 2);
+
+        // Other keys are unaffected
+        let other = SimpleInput {
+            key: "KEY2".into(),
+            ..input.clone()
+        };
+        let (_, output, _) = backend.request(other).await.unwrap();
+        assert_eq!(output.limit, 1);
+
+        backend.remove_limit_override("KEY1");
+        let (decision, output, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.limit, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_carry_over() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder()
+            .with_gc_interval(None)
+            .with_carry_over(0.5, 0.5)
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 10,
+            key: "KEY1".into(),
+        };
+        for _ in 0..2 {
+            backend.request(input.clone()).await.unwrap();
+        }
+        // Half of the 8 unused requests carry over
+        tokio::time::advance(MINUTE).await;
+        let (_, output) = backend.peek(input.clone()).await.unwrap();
+        assert_eq!(output.limit, 14);
+        for _ in 0..14 {
+            let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+            assert!(decision.is_allowed());
+        }
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.remaining, 0);
+
+        // Nothing was left over
+        tokio::time::advance(MINUTE).await;
+        let (_, output, _) = backend.request(input.clone()).await.unwrap();
+        assert_eq!(output.limit, 10);
+        // Half of the 9 unused requests, rounded down
+        tokio::time::advance(MINUTE).await;
+        let (_, output, _) = backend.request(input.clone()).await.unwrap();
+        assert_eq!(output.limit, 14);
+        // Credit is lost after an idle window
+        tokio::time::advance(MINUTE * 2).await;
+        let (_, output, _) = backend.request(input.clone()).await.unwrap();
+        assert_eq!(output.limit, 10);
+
+        // The credit is capped
+        let backend = InMemoryBackend::builder()
+            .with_gc_interval(None)
+            .with_carry_over(1.0, 0.2)
+            .build();
+        backend.request(input.clone()).await.unwrap();
+        tokio::time::advance(MINUTE).await;
+        let (_, output, _) = backend.request(input).await.unwrap();
+        assert_eq!(output.limit, 12);
+    }
+
+    #[actix_web::test]
+    async fn test_max_keys() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        tokio::time::pause();
+        let input = |key: &str| SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: key.into(),
+        };
+        let reached = Arc::new(AtomicUsize::new(0));
+        let counter = reached.clone();
+        let backend = InMemoryBackend::builder()
+            .with_max_keys(2, EvictionPolicy::RejectNewKeys)
+            .on_max_keys(move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            })
+            .build();
+        for key in ["KEY1", "KEY2"] {
+            let (decision, _, _) = backend.request(input(key)).await.unwrap();
+            assert!(decision.is_allowed());
+        }
+        let (decision, _, _) = backend.request(input("KEY3")).await.unwrap();
+        assert!(decision.is_denied());
+        assert!(!backend.map.contains_key("KEY3"));
+        // Existing keys are unaffected
+        let (decision, _, _) = backend.request(input("KEY1")).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(reached.load(Ordering::Relaxed), 1);
+        // Setting the count is subject to the same limit
+        backend.set_count("KEY3", u64::MAX, MINUTE).await.unwrap();
+        assert!(!backend.map.contains_key("KEY3"));
+        assert_eq!(reached.load(Ordering::Relaxed), 2);
+
+        let backend = InMemoryBackend::builder()
+            .with_max_keys(2, EvictionPolicy::LeastRecentlyUsed)
+            .build();
+        for key in ["KEY1", "KEY2", "KEY1"] {
+            backend.request(input(key)).await.unwrap();
+            tokio::time::advance(Duration::from_secs(1)).await;
+        }
+        let (decision, _, _) = backend.request(input("KEY3")).await.unwrap();
+        assert!(decision.is_allowed());
+        assert!(backend.map.contains_key("KEY1"));
+        assert!(!backend.map.contains_key("KEY2"));
+        assert!(backend.map.contains_key("KEY3"));
+        backend.set_count("KEY4", 1, MINUTE).await.unwrap();
+        assert_eq!(backend.map.len(), 2);
+        assert!(backend.map.contains_key("KEY4"));
+    }
+
+    #[actix_web::test]
+    async fn test_alignment() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder()
+            .with_gc_interval(None)
+            .with_alignment(WindowAlignment::Creation)
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 1,
+            key: "KEY1".into(),
+        };
+        let start = Instant::now();
+        tokio::time::advance(Duration::from_secs(45)).await;
+        // The window started when the backend was created, not at the first request
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.reset, start + MINUTE);
+        tokio::time::advance(Duration::from_secs(15)).await;
+        let (decision, output, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.reset, start + MINUTE * 2);
+        // Setting the count keeps the current window
+        backend.set_count("KEY1", 0, MINUTE * 5).await.unwrap();
+        let status = backend.get("KEY1").await.unwrap().unwrap();
+        assert_eq!(Instant::now() + status.ttl, start + MINUTE * 2);
+        // A new key starts in the current aligned window
+        backend.set_count("KEY2", 1, MINUTE).await.unwrap();
+        let status = backend.get("KEY2").await.unwrap().unwrap();
+        assert_eq!(Instant::now() + status.ttl, start + MINUTE * 2);
+    }
+
     #[actix_web::test]
     async fn test_garbage_collection() {
         tokio::time::pause();
         let backend = InMemoryBackend::builder()
             .with_gc_interval(Some(MINUTE))
+            .with_gc_segments(1)
             .build();
         backend
             .request(SimpleInput {
                 interval: MINUTE,
                 max_requests: 1,
-                key: "KEY1".to_string(),
+                key: "KEY1".into(),
             })
             .await
             .unwrap();
@@ -198,7 +944,7 @@ mod tests {
             .request(SimpleInput {
                 interval: MINUTE * 2,
                 max_requests: 1,
-                key: "KEY2".to_string(),
+                key: "KEY2".into(),
             })
             .await
             .unwrap();
@@ -211,6 +957,79 @@ mod tests {
         assert!(backend.map.contains_key("KEY2"));
     }
 
+    #[actix_web::test]
+    async fn test_garbage_collection_clones() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder()
+            .with_gc_interval(Some(MINUTE))
+            .with_gc_segments(1)
+            .build();
+        let clone = backend.clone();
+        // Dropping one clone mustn't stop the garbage collector for the others
+        drop(backend);
+        clone
+            .request(SimpleInput {
+                interval: MINUTE,
+                max_requests: 1,
+                key: "KEY1".into(),
+            })
+            .await
+            .unwrap();
+        tokio::time::advance(MINUTE).await;
+        assert!(!clone.map.contains_key("KEY1"));
+        // The garbage collector stops once every clone has been dropped
+        let map = Arc::downgrade(&clone.map);
+        drop(clone);
+        tokio::time::advance(MINUTE).await;
+        assert!(map.upgrade().is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_incremental_gc() {
+        use std::sync::Mutex;
+
+        tokio::time::pause();
+        let sweeps = Arc::new(Mutex::new(Vec::new()));
+        let recorded = sweeps.clone();
+        let backend = InMemoryBackend::builder()
+            .with_gc_interval(Some(MINUTE))
+            .with_gc_segments(4)
+            .on_gc(move |stats| recorded.lock().unwrap().push(stats))
+            .build();
+        for i in 0..100 {
+            let input = SimpleInput {
+                interval: Duration::from_secs(1),
+                max_requests: 1,
+                key: format!("KEY{i}").into(),
+            };
+            backend.request(input).await.unwrap();
+        }
+        // Each tick sweeps one segment, letting the paused clock auto-advance between them
+        tokio::time::sleep(MINUTE * 2).await;
+        assert_eq!(backend.map.len(), 0);
+        let sweeps = sweeps.lock().unwrap();
+        assert!(sweeps.iter().all(|stats| stats.scanned < 100));
+        assert_eq!(sweeps.iter().map(|stats| stats.removed).sum::<usize>(), 100);
+    }
+
+    #[actix_web::test]
+    async fn test_gc_now() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder().with_gc_interval(None).build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 1,
+            key: "KEY1".into(),
+        };
+        backend.request(input).await.unwrap();
+        assert_eq!(backend.gc_now().removed, 0);
+        assert!(backend.map.contains_key("KEY1"));
+        tokio::time::advance(MINUTE).await;
+        let stats = backend.gc_now();
+        assert_eq!((stats.scanned, stats.removed), (1, 1));
+        assert!(!backend.map.contains_key("KEY1"));
+    }
+
     #[actix_web::test]
     async fn test_output() {
         tokio::time::pause();
@@ -218,7 +1037,7 @@ mod tests {
         let input = SimpleInput {
             interval: MINUTE,
             max_requests: 2,
-            key: "KEY1".to_string(),
+            key: "KEY1".into(),
         };
         // First of 2 should be allowed.
         let (decision, output, _) = backend.request(input.clone()).await.unwrap();
@@ -247,7 +1066,7 @@ mod tests {
         let input = SimpleInput {
             interval: MINUTE,
             max_requests: 5,
-            key: "KEY1".to_string(),
+            key: "KEY1".into(),
         };
         let (_, output, rollback) = backend.request(input.clone()).await.unwrap();
         assert_eq!(output.remaining, 4);
@@ -257,6 +1076,47 @@ mod tests {
         assert_eq!(output.remaining, 4);
     }
 
+    #[actix_web::test]
+    async fn test_peek() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder().build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 2,
+            key: "KEY1".into(),
+        };
+        let (decision, output) = backend.peek(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 1);
+        backend.request(input.clone()).await.unwrap();
+        backend.request(input.clone()).await.unwrap();
+        // Peeking doesn't count towards the limit
+        let (decision, output) = backend.peek(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.remaining, 0);
+        assert_eq!(output.reset, Instant::now() + MINUTE);
+        let (decision, _) = backend.peek(input).await.unwrap();
+        assert!(decision.is_denied());
+    }
+
+    #[actix_web::test]
+    async fn test_rollback_serialized() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder().build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: "KEY1".into(),
+        };
+        let (_, output, rollback) = backend.request(input.clone()).await.unwrap();
+        assert_eq!(output.remaining, 4);
+        // The token could be sent to another process, and rolled back from there
+        let bytes = rollback.to_bytes();
+        backend.rollback_serialized(&bytes).await.unwrap();
+        let (_, output, _) = backend.request(input).await.unwrap();
+        assert_eq!(output.remaining, 4);
+    }
+
     #[actix_web::test]
     async fn test_remove_key() {
         tokio::time::pause();
@@ -264,7 +1124,7 @@ mod tests {
         let input = SimpleInput {
             interval: MINUTE,
             max_requests: 1,
-            key: "KEY1".to_string(),
+            key: "KEY1".into(),
         };
         let (decision, _, _) = backend.request(input.clone()).await.unwrap();
         assert!(decision.is_allowed());
@@ -275,4 +1135,109 @@ mod tests {
         let (decision, _, _) = backend.request(input).await.unwrap();
         assert!(decision.is_allowed());
     }
+
+    #[actix_web::test]
+    async fn test_consume() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder().with_gc_interval(None).build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 10,
+            key: "KEY1".into(),
+        };
+        // A cost of zero is charged as one request
+        let (_, output) = backend.consume(input.clone(), 0).await.unwrap();
+        assert_eq!(output.remaining, 9);
+        let (decision, output) = backend.consume(input.clone(), 7).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 2);
+        // The cost is charged even though it exceeds the remaining limit
+        let (decision, output) = backend.consume(input.clone(), 3).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.remaining, 0);
+        let (decision, _, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_denied());
+    }
+
+    #[actix_web::test]
+    async fn test_get_and_list() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder().with_gc_interval(None).build();
+        for key in ["user-1", "user-2", "ip-1"] {
+            let input = SimpleInput {
+                interval: MINUTE,
+                max_requests: 5,
+                key: key.into(),
+            };
+            backend.request(input).await.unwrap();
+        }
+        assert!(backend.get("user-3").await.unwrap().is_none());
+        tokio::time::advance(Duration::from_secs(20)).await;
+        let status = backend.get("user-1").await.unwrap().unwrap();
+        assert_eq!(status.count, 1);
+        assert_eq!(status.ttl, Duration::from_secs(40));
+        let mut keys: Vec<_> = backend
+            .list("user-")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["user-1", "user-2"]);
+        // Expired keys are excluded
+        tokio::time::advance(MINUTE).await;
+        assert!(backend.get("user-1").await.unwrap().is_none());
+        assert!(backend.list("").await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_clear() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder().with_gc_interval(None).build();
+        for key in ["user-1", "user-2", "ip-1"] {
+            let input = SimpleInput {
+                interval: MINUTE,
+                max_requests: 5,
+                key: key.into(),
+            };
+            backend.request(input).await.unwrap();
+        }
+        backend.clear("user-").await.unwrap();
+        let keys: Vec<_> = backend
+            .list("")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.key)
+            .collect();
+        assert_eq!(keys, ["ip-1"]);
+        backend.clear("").await.unwrap();
+        assert!(backend.list("").await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_set_count() {
+        tokio::time::pause();
+        let backend = InMemoryBackend::builder().with_gc_interval(None).build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: "KEY1".into(),
+        };
+        for _ in 0..5 {
+            backend.request(input.clone()).await.unwrap();
+        }
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        // Grant two more requests in the current window
+        backend.set_count("KEY1", 3, MINUTE).await.unwrap();
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 1);
+        assert_eq!(backend.get("KEY1").await.unwrap().unwrap().count, 4);
+        // A TTL too large for an Instant is saturated
+        backend.set_count("KEY2", 1, Duration::MAX).await.unwrap();
+        assert_eq!(backend.get("KEY2").await.unwrap().unwrap().ttl, MAX_TTL);
+    }
 }
@@ -1,19 +1,89 @@
-mod input_builder;
+pub mod circuit_breaker;
+#[cfg(feature = "actix")]
+pub(crate) mod client_ip;
+mod consumer;
+mod error;
+#[cfg(feature = "actix")]
+pub(crate) mod input_builder;
+#[cfg(feature = "actix")]
+mod input_handle;
+mod key;
+mod limit_override;
+mod policy;
+#[cfg(feature = "actix")]
+mod policy_set;
+#[cfg(feature = "actix")]
+mod quota;
+mod send;
+#[cfg(feature = "actix")]
+mod tiered_builder;
+mod user_agent;
 
 #[cfg(feature = "dashmap")]
 #[cfg_attr(docsrs, doc(cfg(feature = "dashmap")))]
 pub mod memory;
 
+#[cfg(feature = "governor")]
+#[cfg_attr(docsrs, doc(cfg(feature = "governor")))]
+pub mod governor;
+
 #[cfg(feature = "redis")]
 #[cfg_attr(docsrs, doc(cfg(feature = "redis")))]
 pub mod redis;
 
-pub use input_builder::{SimpleInputFunctionBuilder, SimpleInputFuture};
+pub mod calendar;
+pub mod multi;
+pub mod overage;
+pub mod penalty;
+pub mod per_worker;
+pub mod replicated;
+pub mod retry;
+pub mod sharded;
+pub mod timeout;
+pub mod weighted;
+
+#[cfg(feature = "stats")]
+#[cfg_attr(docsrs, doc(cfg(feature = "stats")))]
+pub mod stats;
+
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+pub mod utilization;
+
+mod window;
+
+pub use consumer::Consumer;
+pub use error::{BackendError, ClassifyError};
+#[cfg(feature = "sha2")]
+#[cfg_attr(docsrs, doc(cfg(feature = "sha2")))]
+pub use key::hash_key;
+pub use key::RateLimitKey;
+pub use limit_override::LimitOverride;
+pub use policy::{ParsePolicyError, RateLimitPolicy};
+pub use send::SendBackend;
 use std::future::Future;
+pub use user_agent::UserAgentClass;
+pub use window::WindowAlignment;
+
+// The input functions are built from an actix-web request.
+#[cfg(feature = "actix")]
+#[cfg_attr(docsrs, doc(cfg(feature = "actix")))]
+pub use self::{
+    input_builder::{
+        ClientCertificate, MissingKey, MultiInputFuture, SimpleInputFunctionBuilder,
+        SimpleInputFuture, UnknownIp,
+    },
+    input_handle::{BoxedInputFuture, InputFunctionHandle},
+    policy_set::{PolicyRule, PolicySet},
+    quota::{CachedQuotaProvider, QuotaProvider},
+    tiered_builder::TieredInputFunctionBuilder,
+};
 
-use crate::HeaderCompatibleOutput;
-use actix_web::rt::time::Instant;
-use std::time::Duration;
+use futures::future::join_all;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio::time::Instant;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Decision {
@@ -76,6 +146,164 @@ pub trait Backend<I: 'static = SimpleInput>: Clone {
     /// * `token`: The token returned from the initial call to [Backend::request()].
     fn rollback(&self, token: Self::RollbackToken)
         -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Check an incoming request without counting it.
+    ///
+    /// Returns the decision and output that [Backend::request()] would return if the request were
+    /// counted now, e.g. `remaining` already accounts for this request.
+    ///
+    /// The default implementation makes the request and then immediately rolls it back; backends
+    /// should override this if they are able to read the current count directly.
+    fn peek(
+        &self,
+        input: I,
+    ) -> impl Future<Output = Result<(Decision, Self::Output), Self::Error>> {
+        async move {
+            let (decision, output, token) = self.request(input).await?;
+            self.rollback(token).await?;
+            Ok((decision, output))
+        }
+    }
+
+    /// Process several inputs at once, e.g. one per policy, returning the results in the same
+    /// order as the inputs.
+    ///
+    /// Either every input is counted or none are: if any request fails, the others are rolled back
+    /// and the first error is returned.
+    ///
+    /// The default implementation makes the requests concurrently; backends with a remote store
+    /// should override this to check all the inputs in a single round trip.
+    #[allow(clippy::type_complexity)]
+    fn request_batch(
+        &self,
+        inputs: Vec<I>,
+    ) -> impl Future<Output = Result<Vec<(Decision, Self::Output, Self::RollbackToken)>, Self::Error>>
+    {
+        async move {
+            let results = join_all(inputs.into_iter().map(|i| self.request(i))).await;
+            let mut succeeded = Vec::with_capacity(results.len());
+            let mut error = None;
+            for result in results {
+                match result {
+                    Ok(result) => succeeded.push(result),
+                    Err(e) => error = error.or(Some(e)),
+                }
+            }
+            match error {
+                None => Ok(succeeded),
+                Some(e) => {
+                    for (_, _, token) in succeeded {
+                        self.rollback(token).await?;
+                    }
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    /// Rollback using a token that was previously serialized with
+    /// [SerializableRollbackToken::to_bytes()].
+    ///
+    /// This allows a rollback to be performed by a different process to the one that made the
+    /// original request, for example a background worker that completes the real work later on.
+    /// This is only meaningful for backends with a shared store, such as Redis.
+    fn rollback_serialized(
+        &self,
+        bytes: &[u8],
+    ) -> impl Future<Output = Result<(), RollbackSerializedError<Self::Error>>>
+    where
+        Self::RollbackToken: SerializableRollbackToken,
+    {
+        let token = Self::RollbackToken::from_bytes(bytes);
+        async move {
+            let token = token.map_err(RollbackSerializedError::InvalidToken)?;
+            self.rollback(token)
+                .await
+                .map_err(RollbackSerializedError::Backend)
+        }
+    }
+
+    /// Checks whether the backend's store is reachable, e.g. for use in a readiness probe, since
+    /// the middleware would otherwise silently allow every request when
+    /// [fail open](crate::RateLimiterBuilder::fail_open) is enabled.
+    ///
+    /// The default implementation always succeeds, which is appropriate for in-memory backends.
+    fn health_check(&self) -> impl Future<Output = Result<(), Self::Error>> {
+        async { Ok(()) }
+    }
+}
+
+/// A [Backend::RollbackToken] that can be converted to and from bytes.
+pub trait SerializableRollbackToken: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, InvalidRollbackToken>;
+}
+
+#[derive(Debug, Error)]
+#[error("Unable to decode rollback token")]
+pub struct InvalidRollbackToken;
+
+#[derive(Debug, Error)]
+pub enum RollbackSerializedError<E> {
+    #[error("{0}")]
+    InvalidToken(#[source] InvalidRollbackToken),
+    #[error("{0}")]
+    Backend(E),
+}
+
+impl SerializableRollbackToken for () {
+    fn to_bytes(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, InvalidRollbackToken> {
+        bytes.is_empty().then_some(()).ok_or(InvalidRollbackToken)
+    }
+}
+
+impl SerializableRollbackToken for String {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, InvalidRollbackToken> {
+        String::from_utf8(bytes.to_vec()).map_err(|_| InvalidRollbackToken)
+    }
+}
+
+impl SerializableRollbackToken for Arc<str> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, InvalidRollbackToken> {
+        String::from_utf8(bytes.to_vec())
+            .map(Into::into)
+            .map_err(|_| InvalidRollbackToken)
+    }
+}
+
+/// None is encoded as no bytes, and Some as a `1` byte followed by the inner token.
+impl<T: SerializableRollbackToken> SerializableRollbackToken for Option<T> {
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            None => Vec::new(),
+            Some(token) => {
+                let mut bytes = vec![1];
+                bytes.extend(token.to_bytes());
+                bytes
+            }
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, InvalidRollbackToken> {
+        match bytes.split_first() {
+            None => Ok(None),
+            Some((1, token)) => T::from_bytes(token).map(Some),
+            Some(_) => Err(InvalidRollbackToken),
+        }
+    }
 }
 
 /// A default [Backend] Input structure.
@@ -88,7 +316,41 @@ pub struct SimpleInput {
     /// The total requests to be allowed within the interval.
     pub max_requests: u64,
     /// The rate limit key to be used for this request.
-    pub key: String,
+    ///
+    /// Keys made up of multiple components can be built using a [RateLimitKey].
+    ///
+    /// This is reference counted so that the key can be cheaply shared, e.g. with
+    /// [SimpleInputFunctionBuilder::intern_keys].
+    pub key: Arc<str>,
+}
+
+impl KeyedInput for SimpleInput {
+    fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl PolicyInput for SimpleInput {
+    fn set_policy(&mut self, policy: &RateLimitPolicy) {
+        self.interval = policy.interval();
+        self.max_requests = policy.allowed_requests();
+    }
+}
+
+/// A [Backend] input that contains a rate limit policy.
+///
+/// This is required to override the policy at runtime using a
+/// [RateLimiterHandle](crate::RateLimiterHandle).
+pub trait PolicyInput {
+    fn set_policy(&mut self, policy: &RateLimitPolicy);
+}
+
+/// A [Backend] input that contains a rate limit key.
+///
+/// This is required to use the key allow/deny lists, e.g.
+/// [RateLimiterBuilder::allowlist_keys](crate::RateLimiterBuilder::allowlist_keys).
+pub trait KeyedInput {
+    fn key(&self) -> &str;
 }
 
 /// A default [Backend::Output] structure.
@@ -102,14 +364,129 @@ pub struct SimpleOutput {
     pub remaining: u64,
     /// Time at which the rate limit resets.
     pub reset: Instant,
+    /// Wall clock time at which the rate limit resets, if known by the backend (e.g. from the
+    /// Redis TTL), since an [Instant] can't be converted to a timestamp.
+    pub reset_at: Option<SystemTime>,
 }
 
+/// The current state of a rate limit key, see [SimpleBackend::get].
+#[derive(Debug, Clone)]
+pub struct KeyStatus {
+    /// The rate limit key (excluding any backend key prefix).
+    pub key: String,
+    /// Number of requests made in the current window.
+    pub count: u64,
+    /// Time until the window resets.
+    pub ttl: Duration,
+}
+
+/// The longest TTL that [SimpleBackend::set_count] sets, so that the expiry can't overflow.
+pub(crate) const MAX_TTL: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
 /// Additional functions for a [Backend] that uses [SimpleInput] and [SimpleOutput].
 pub trait SimpleBackend: Backend<SimpleInput, Output = SimpleOutput> {
     /// Removes the bucket for a given rate limit key.
     ///
     /// Intended to be used to reset a key before changing the interval.
     fn remove_key(&self, key: &str) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Removes the bucket for every rate limit key starting with `prefix`, e.g. to reset all
+    /// state between tests.
+    ///
+    /// Use an empty prefix to remove every key.
+    fn clear(&self, prefix: &str) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Overwrites the count for a given rate limit key, e.g. to grant a customer extra quota in
+    /// the current window by reducing their count, without resetting the window entirely.
+    ///
+    /// The key will reset after `ttl`, unless the backend's windows are aligned to fixed
+    /// boundaries, in which case the current window is kept (and `ttl` is only used as the
+    /// interval if the key doesn't exist). A zero TTL is treated as the shortest TTL the backend
+    /// supports, so the count expires almost immediately, and TTLs longer than 100 years are
+    /// saturated.
+    fn set_count(
+        &self,
+        key: &str,
+        count: u64,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Returns the current count and time until reset for a given rate limit key, without
+    /// counting a request, e.g. for displaying in an admin dashboard.
+    ///
+    /// Returns None if the key doesn't exist (i.e. no requests in the current window).
+    ///
+    /// The limit isn't stored by the backend, use [Backend::peek] if you need a [SimpleOutput].
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<KeyStatus>, Self::Error>>;
+
+    /// Returns the status of every rate limit key starting with `prefix`, in no particular order.
+    ///
+    /// Use an empty prefix to list every key.
+    fn list(&self, prefix: &str) -> impl Future<Output = Result<Vec<KeyStatus>, Self::Error>>;
+
+    /// Charge `cost` requests to the bucket for the input's key, e.g. when a background job is
+    /// enqueued on behalf of a user, so that it counts against the same limit as their HTTP
+    /// requests.
+    ///
+    /// The input should use the same interval and max requests as the middleware input function,
+    /// these are used to create the bucket if it doesn't already exist.
+    ///
+    /// The cost is always charged, even if it exceeds the limit, and it can't be refunded. The
+    /// decision is whether the count is still within the limit after charging. See also
+    /// [Consumer], which produces the input for a given key.
+    ///
+    /// A cost of zero is charged as one request, as it is by
+    /// [WeightedBackend](crate::backend::weighted::WeightedBackend).
+    ///
+    /// The default implementation makes `cost` requests; backends should override this if they
+    /// are able to increment the count atomically.
+    fn consume(
+        &self,
+        input: SimpleInput,
+        cost: u64,
+    ) -> impl Future<Output = Result<(Decision, SimpleOutput), Self::Error>> {
+        async move {
+            let mut result = self.request(input.clone()).await?;
+            for _ in 1..cost.max(1) {
+                result = self.request(input.clone()).await?;
+            }
+            let (decision, output, _) = result;
+            Ok((decision, output))
+        }
+    }
+}
+
+/// A trait that a [Backend::Output] should implement in order to use the
+/// [RateLimiterBuilder::add_headers](crate::RateLimiterBuilder::add_headers) function.
+pub trait HeaderCompatibleOutput {
+    /// Value for the `x-ratelimit-limit` header.
+    fn limit(&self) -> u64;
+
+    /// Value for the `x-ratelimit-remaining` header.
+    fn remaining(&self) -> u64;
+
+    /// Value for the `x-ratelimit-reset` and `retry-at` headers.
+    ///
+    /// This should be the number of seconds from now until the limit resets.\
+    /// If the limit has already reset this should return 0.
+    fn seconds_until_reset(&self) -> u64;
+
+    /// Wall clock time at which the limit resets, used for the `x-ratelimit-reset` header when
+    /// it is a [ResetFormat::UnixTimestamp](crate::ResetFormat::UnixTimestamp).
+    ///
+    /// Defaults to None, in which case it is calculated from
+    /// [HeaderCompatibleOutput::seconds_until_reset].
+    fn reset_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    /// Whether to set the `x-overage: true` header, i.e. the request was allowed but exceeded
+    /// a soft limit.
+    ///
+    /// Defaults to false.
+    fn is_overage(&self) -> bool {
+        false
+    }
 }
 
 impl HeaderCompatibleOutput for SimpleOutput {
@@ -130,11 +507,26 @@ impl HeaderCompatibleOutput for SimpleOutput {
             .as_millis() as f64;
         (millis / 1000f64).ceil() as u64
     }
+
+    fn reset_at(&self) -> Option<SystemTime> {
+        self.reset_at
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_optional_rollback_token() {
+        let token: Option<Arc<str>> = Some("KEY1".into());
+        assert_eq!(Option::from_bytes(&token.to_bytes()).unwrap(), token);
+        let token: Option<Arc<str>> = Some("".into());
+        assert_eq!(Option::from_bytes(&token.to_bytes()).unwrap(), token);
+        assert_eq!(Option::<Arc<str>>::from_bytes(&[]).unwrap(), None);
+        assert!(Option::<Arc<str>>::from_bytes(&[2]).is_err());
+    }
 
     #[actix_web::test]
     async fn test_seconds_until_reset() {
@@ -143,9 +535,48 @@ mod tests {
             limit: 0,
             remaining: 0,
             reset: Instant::now() + Duration::from_secs(60),
+            reset_at: None,
         };
         tokio::time::advance(Duration::from_secs_f64(29.9)).await;
         // Verify rounded upwards from 30.1
         assert_eq!(output.seconds_until_reset(), 31);
     }
+
+    // Counts requests, failing those whose input is true
+    #[derive(Clone, Default)]
+    struct CountingBackend(Arc<AtomicU64>);
+
+    impl Backend<bool> for CountingBackend {
+        type Output = ();
+        type RollbackToken = ();
+        type Error = ();
+
+        async fn request(&self, fail: bool) -> Result<(Decision, (), ()), ()> {
+            if fail {
+                return Err(());
+            }
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok((Decision::Allowed, (), ()))
+        }
+
+        async fn rollback(&self, _: ()) -> Result<(), ()> {
+            self.0.fetch_sub(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_request_batch() {
+        let backend = CountingBackend::default();
+        let results = backend.request_batch(vec![false, false]).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(backend.0.load(Ordering::Relaxed), 2);
+
+        // The successful requests are rolled back if any fail
+        assert!(backend
+            .request_batch(vec![false, true, false])
+            .await
+            .is_err());
+        assert_eq!(backend.0.load(Ordering::Relaxed), 2);
+    }
 }
@@ -0,0 +1,262 @@
+use crate::backend::{Backend, Decision, SimpleInput, SimpleOutput};
+use futures::future::join_all;
+use std::cmp::Reverse;
+use tokio::time::Instant;
+
+/// A [Backend] adapter that enforces multiple policies (e.g. per-second, per-minute and per-day
+/// limits) in a single [RateLimiter](crate::RateLimiter), instead of stacking one middleware per
+/// policy.
+///
+/// The input is a list of [SimpleInput], one per policy, which are requested from the inner
+/// backend together using [Backend::request_batch], e.g. in a single round trip to Redis; each
+/// policy must use a distinct key. See
+/// [SimpleInputFunctionBuilder::build_multi](crate::backend::SimpleInputFunctionBuilder::build_multi).
+///
+/// The policies don't need to share a client, so the same request can also be charged to
+/// several keys, e.g. both the user and their organization, see
+/// [SimpleInputFunctionBuilder::charge_key](crate::backend::SimpleInputFunctionBuilder::charge_key).
+///
+/// The request is denied if any policy is exceeded, in which case the counts of the policies that
+/// were not exceeded are rolled back. The output is that of the most restrictive policy: the one
+/// with the fewest remaining requests if allowed, or the denied policy that resets last if denied.
+///
+/// An empty list of policies is always allowed, with an unlimited output.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::multi::MultiPolicyBackend;
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+/// # use actix_extensible_rate_limit::RateLimiter;
+/// # use std::time::Duration;
+/// # actix_web::rt::System::new().block_on(async {
+/// let backend = MultiPolicyBackend::new(InMemoryBackend::builder().build());
+/// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(1), 5)
+///     .add_policy(Duration::from_secs(60), 100)
+///     .add_policy(Duration::from_secs(60 * 60 * 24), 1000)
+///     .real_ip_key()
+///     .build_multi();
+/// let middleware = RateLimiter::builder(backend, input).add_headers().build();
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct MultiPolicyBackend<B> {
+    backend: B,
+}
+
+impl<B> MultiPolicyBackend<B> {
+    pub fn new(backend: B) -> Self {
+        Self { backend }
+    }
+
+    async fn rollback_all<T>(&self, tokens: impl IntoIterator<Item = T>) -> Result<(), B::Error>
+    where
+        B: Backend<SimpleInput, RollbackToken = T>,
+    {
+        for result in join_all(tokens.into_iter().map(|t| self.backend.rollback(t))).await {
+            result?;
+        }
+        Ok(())
+    }
+}
+
+impl<B> Backend<Vec<SimpleInput>> for MultiPolicyBackend<B>
+where
+    B: Backend<SimpleInput, Output = SimpleOutput>,
+{
+    type Output = SimpleOutput;
+    type RollbackToken = Vec<B::RollbackToken>;
+    type Error = B::Error;
+
+    async fn request(
+        &self,
+        input: Vec<SimpleInput>,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        if input.is_empty() {
+            return Ok((Decision::Allowed, unlimited(), Vec::new()));
+        }
+        let results = self.backend.request_batch(input).await?;
+
+        let mut allowed = Vec::with_capacity(results.len());
+        let mut denied = Vec::new();
+        for (decision, output, token) in results {
+            match decision {
+                Decision::Allowed => allowed.push((output, token)),
+                Decision::Denied => denied.push(output),
+            }
+        }
+
+        if !denied.is_empty() {
+            self.rollback_all(allowed.into_iter().map(|(_, t)| t))
+                .await?;
+            return Ok((Decision::Denied, latest_reset(denied), Vec::new()));
+        }
+
+        let (tokens, outputs): (Vec<_>, Vec<_>) = allowed.into_iter().map(|(o, t)| (t, o)).unzip();
+        Ok((Decision::Allowed, least_remaining(outputs), tokens))
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        self.rollback_all(token).await
+    }
+
+    async fn peek(&self, input: Vec<SimpleInput>) -> Result<(Decision, Self::Output), Self::Error> {
+        if input.is_empty() {
+            return Ok((Decision::Allowed, unlimited()));
+        }
+        let results = join_all(input.into_iter().map(|i| self.backend.peek(i))).await;
+
+        let mut allowed = Vec::with_capacity(results.len());
+        let mut denied = Vec::new();
+        for result in results {
+            match result? {
+                (Decision::Allowed, output) => allowed.push(output),
+                (Decision::Denied, output) => denied.push(output),
+            }
+        }
+        if !denied.is_empty() {
+            return Ok((Decision::Denied, latest_reset(denied)));
+        }
+        Ok((Decision::Allowed, least_remaining(allowed)))
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        self.backend.health_check().await
+    }
+}
+
+/// The output when there are no policies.
+fn unlimited() -> SimpleOutput {
+    SimpleOutput {
+        limit: u64::MAX,
+        remaining: u64::MAX,
+        reset: Instant::now(),
+        reset_at: None,
+    }
+}
+
+/// The denied policy that resets last.
+fn latest_reset(denied: Vec<SimpleOutput>) -> SimpleOutput {
+    denied
+        .into_iter()
+        .max_by_key(|o| o.reset)
+        .expect("At least one denied policy")
+}
+
+/// The allowed policy with the fewest remaining requests.
+fn least_remaining(allowed: Vec<SimpleOutput>) -> SimpleOutput {
+    allowed
+        .into_iter()
+        .min_by_key(|o| (o.remaining, Reverse(o.reset)))
+        .expect("At least one allowed policy")
+}
+
+#[cfg(all(test, feature = "dashmap"))]
+mod tests {
+    use super::*;
+    use crate::backend::memory::InMemoryBackend;
+    use std::time::Duration;
+
+    const SECOND: Duration = Duration::from_secs(1);
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn input() -> Vec<SimpleInput> {
+        vec![
+            SimpleInput {
+                interval: SECOND,
+                max_requests: 2,
+                key: "KEY1-second".into(),
+            },
+            SimpleInput {
+                interval: MINUTE,
+                max_requests: 3,
+                key: "KEY1-minute".into(),
+            },
+        ]
+    }
+
+    #[actix_web::test]
+    async fn test_multi_policy() {
+        tokio::time::pause();
+        let backend = MultiPolicyBackend::new(InMemoryBackend::builder().build());
+        let (decision, output, _) = backend.request(input()).await.unwrap();
+        assert!(decision.is_allowed());
+        // The per second policy has the fewest remaining
+        assert_eq!(output.limit, 2);
+        assert_eq!(output.remaining, 1);
+        let (decision, output, _) = backend.request(input()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.limit, 2);
+        assert_eq!(output.remaining, 0);
+        // Per second policy exceeded
+        let (decision, output, _) = backend.request(input()).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.limit, 2);
+
+        // The denied request should not have counted towards the minute policy
+        tokio::time::advance(SECOND).await;
+        let (decision, output, _) = backend.request(input()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.limit, 3);
+        assert_eq!(output.remaining, 0);
+        let (decision, output, _) = backend.request(input()).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.limit, 3);
+    }
+
+    #[actix_web::test]
+    async fn test_multi_key() {
+        let backend = MultiPolicyBackend::new(InMemoryBackend::builder().build());
+        let input = |user: &str| {
+            vec![
+                SimpleInput {
+                    interval: MINUTE,
+                    max_requests: 2,
+                    key: format!("user-{user}").into(),
+                },
+                SimpleInput {
+                    interval: MINUTE,
+                    max_requests: 3,
+                    key: "org-1".into(),
+                },
+            ]
+        };
+        assert!(backend.request(input("a")).await.unwrap().0.is_allowed());
+        assert!(backend.request(input("a")).await.unwrap().0.is_allowed());
+        // The organization has the fewest remaining
+        let (decision, output, _) = backend.request(input("b")).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.limit, 3);
+        assert_eq!(output.remaining, 0);
+        // The organization limit is exceeded, so the user isn't charged either
+        let (decision, output, _) = backend.request(input("c")).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.limit, 3);
+        let (_, output) = backend.peek(vec![input("c").remove(0)]).await.unwrap();
+        // As if it were the first request
+        assert_eq!(output.remaining, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_multi_policy_rollback() {
+        tokio::time::pause();
+        let backend = MultiPolicyBackend::new(InMemoryBackend::builder().build());
+        let (_, _, token) = backend.request(input()).await.unwrap();
+        backend.rollback(token).await.unwrap();
+        let (_, output, _) = backend.request(input()).await.unwrap();
+        assert_eq!(output.remaining, 1);
+        assert_eq!(output.limit, 2);
+    }
+
+    #[actix_web::test]
+    async fn test_no_policies() {
+        let backend = MultiPolicyBackend::new(InMemoryBackend::builder().build());
+        let (decision, output, token) = backend.request(Vec::new()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, u64::MAX);
+        backend.rollback(token).await.unwrap();
+        let (decision, _) = backend.peek(Vec::new()).await.unwrap();
+        assert!(decision.is_allowed());
+    }
+}
@@ -0,0 +1,263 @@
+use crate::backend::{Backend, Decision, SimpleBackend, SimpleInput, SimpleOutput};
+use crate::HeaderCompatibleOutput;
+use std::time::SystemTime;
+use tokio::time::Instant;
+
+pub const DEFAULT_HARD_LIMIT_MULTIPLIER: f64 = 2f64;
+
+/// A [Backend] decorator for metered billing, where exceeding the limit doesn't deny the request
+/// but marks it as overage so that it can be charged for.
+///
+/// The [SimpleInput::max_requests] is treated as a soft limit. Requests beyond the soft limit are
+/// allowed, with [OverageOutput::overage] set, until the hard limit is reached, after which they
+/// are denied.
+///
+/// If [RateLimiterBuilder::add_headers](crate::RateLimiterBuilder::add_headers) is enabled then
+/// an `x-overage: true` header is added to overage responses.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::overage::OverageBackend;
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+/// # use actix_extensible_rate_limit::RateLimiter;
+/// # use actix_web::HttpResponse;
+/// # use std::time::Duration;
+/// # actix_web::rt::System::new().block_on(async {
+/// // Allow up to 10x the plan's quota, and then require payment
+/// let backend = OverageBackend::builder(InMemoryBackend::builder().build())
+///     .hard_limit_multiplier(10f64)
+///     .build();
+/// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 100)
+///     .real_ip_key()
+///     .build();
+/// let middleware = RateLimiter::builder(backend, input)
+///     .add_headers()
+///     .request_denied_response(|_| HttpResponse::PaymentRequired().finish())
+///     .build();
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct OverageBackend<B> {
+    backend: B,
+    hard_limit_multiplier: f64,
+}
+
+/// The [Backend::Output] of an [OverageBackend].
+#[derive(Debug, Clone)]
+pub struct OverageOutput {
+    /// The soft limit, after which requests are counted as overage.
+    pub limit: u64,
+    /// The hard limit, after which requests are denied.
+    pub hard_limit: u64,
+    /// Number of requests that will be permitted until the soft limit is reached.
+    pub remaining: u64,
+    /// Number of requests made beyond the soft limit within the interval, including this one.
+    ///
+    /// Zero if this request was within the soft limit.
+    pub overage: u64,
+    /// Time at which the rate limit resets.
+    pub reset: Instant,
+    /// Wall clock time at which the rate limit resets, if known by the inner backend.
+    pub reset_at: Option<SystemTime>,
+}
+
+impl OverageOutput {
+    /// Returns true if this request exceeded the soft limit.
+    pub fn is_overage(&self) -> bool {
+        self.overage > 0
+    }
+}
+
+impl HeaderCompatibleOutput for OverageOutput {
+    fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    fn seconds_until_reset(&self) -> u64 {
+        SimpleOutput {
+            limit: self.limit,
+            remaining: self.remaining,
+            reset: self.reset,
+            reset_at: self.reset_at,
+        }
+        .seconds_until_reset()
+    }
+
+    fn reset_at(&self) -> Option<SystemTime> {
+        self.reset_at
+    }
+
+    fn is_overage(&self) -> bool {
+        OverageOutput::is_overage(self)
+    }
+}
+
+impl<B> OverageBackend<B> {
+    pub fn builder(backend: B) -> Builder<B> {
+        Builder {
+            backend,
+            hard_limit_multiplier: DEFAULT_HARD_LIMIT_MULTIPLIER,
+        }
+    }
+
+    fn hard_limit(&self, soft_limit: u64) -> u64 {
+        // Float to int casts saturate, so an infinite multiplier means no hard limit
+        ((soft_limit as f64 * self.hard_limit_multiplier) as u64).max(soft_limit)
+    }
+
+    fn output(&self, soft_limit: u64, decision: Decision, output: SimpleOutput) -> OverageOutput {
+        let hard_limit = self.hard_limit(soft_limit);
+        let count = match decision {
+            // The inner limit may differ from the hard limit, e.g. due to a limit override
+            Decision::Allowed => output.limit.saturating_sub(output.remaining),
+            // The inner backend doesn't report how far beyond the limit a denied request is
+            Decision::Denied => hard_limit.saturating_add(1),
+        };
+        OverageOutput {
+            limit: soft_limit,
+            hard_limit,
+            remaining: soft_limit.saturating_sub(count),
+            overage: count.saturating_sub(soft_limit),
+            reset: output.reset,
+            reset_at: output.reset_at,
+        }
+    }
+}
+
+impl<B: SimpleBackend> OverageBackend<B> {
+    /// Removes the bucket for a given rate limit key.
+    pub async fn remove_key(&self, key: &str) -> Result<(), B::Error> {
+        self.backend.remove_key(key).await
+    }
+}
+
+pub struct Builder<B> {
+    backend: B,
+    hard_limit_multiplier: f64,
+}
+
+impl<B> Builder<B> {
+    /// Override the hard limit, as a multiple of the soft limit.
+    ///
+    /// Set to [f64::INFINITY] to never deny requests.
+    ///
+    /// Defaults to 2.
+    pub fn hard_limit_multiplier(mut self, multiplier: f64) -> Self {
+        assert!(
+            multiplier >= 1f64,
+            "Hard limit multiplier must be at least 1"
+        );
+        self.hard_limit_multiplier = multiplier;
+        self
+    }
+
+    pub fn build(self) -> OverageBackend<B> {
+        OverageBackend {
+            backend: self.backend,
+            hard_limit_multiplier: self.hard_limit_multiplier,
+        }
+    }
+}
+
+impl<B> Backend<SimpleInput> for OverageBackend<B>
+where
+    B: Backend<SimpleInput, Output = SimpleOutput>,
+{
+    type Output = OverageOutput;
+    type RollbackToken = B::RollbackToken;
+    type Error = B::Error;
+
+    async fn request(
+        &self,
+        mut input: SimpleInput,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        let soft_limit = input.max_requests;
+        input.max_requests = self.hard_limit(soft_limit);
+        let (decision, output, token) = self.backend.request(input).await?;
+        Ok((decision, self.output(soft_limit, decision, output), token))
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        self.backend.rollback(token).await
+    }
+
+    async fn peek(&self, mut input: SimpleInput) -> Result<(Decision, Self::Output), Self::Error> {
+        let soft_limit = input.max_requests;
+        input.max_requests = self.hard_limit(soft_limit);
+        let (decision, output) = self.backend.peek(input).await?;
+        Ok((decision, self.output(soft_limit, decision, output)))
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        self.backend.health_check().await
+    }
+}
+
+#[cfg(all(test, feature = "dashmap"))]
+mod tests {
+    use super::*;
+    use crate::backend::memory::InMemoryBackend;
+    use std::time::Duration;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[actix_web::test]
+    async fn test_overage() {
+        tokio::time::pause();
+        let backend = OverageBackend::builder(InMemoryBackend::builder().build())
+            .hard_limit_multiplier(1.5)
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 2,
+            key: "KEY1".into(),
+        };
+        for i in 1..=2 {
+            let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+            assert!(decision.is_allowed());
+            assert!(!output.is_overage());
+            assert_eq!(output.remaining, 2 - i);
+        }
+        // Beyond the soft limit, but within the hard limit of 3
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.overage, 1);
+        assert_eq!(output.remaining, 0);
+        assert_eq!(output.hard_limit, 3);
+        // Beyond the hard limit
+        let (decision, output, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.overage, 2);
+    }
+
+    #[test]
+    fn test_unlimited_overage() {
+        let backend = OverageBackend::builder(())
+            .hard_limit_multiplier(f64::INFINITY)
+            .build();
+        assert_eq!(backend.hard_limit(10), u64::MAX);
+    }
+
+    #[test]
+    fn test_inner_limit_differs() {
+        let backend = OverageBackend::builder(())
+            .hard_limit_multiplier(1.5)
+            .build();
+        // E.g. the inner backend applied a limit override of 100
+        let inner = SimpleOutput {
+            limit: 100,
+            remaining: 97,
+            reset: Instant::now(),
+            reset_at: None,
+        };
+        let output = backend.output(2, Decision::Allowed, inner);
+        assert_eq!(output.remaining, 0);
+        assert_eq!(output.overage, 1);
+    }
+}
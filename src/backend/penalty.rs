@@ -0,0 +1,395 @@
+use crate::backend::{Backend, Decision, KeyStatus, SimpleBackend, SimpleInput, SimpleOutput};
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const DEFAULT_MAX_VIOLATIONS: u64 = 10;
+pub const DEFAULT_VIOLATION_WINDOW_SECONDS: u64 = 60;
+pub const DEFAULT_BAN_DURATION_SECONDS: u64 = 60 * 15;
+pub const DEFAULT_RESET_AFTER_SECONDS: u64 = 60 * 60;
+
+/// A [Backend] decorator that temporarily bans keys that are repeatedly denied.
+///
+/// Each denied request counts as a violation. If a key exceeds the maximum violations within the
+/// violation window then every request for that key is denied for the ban duration, regardless
+/// of its rate limit, so that abusive clients can't keep hammering the rate limiter.
+///
+/// Bans can escalate for keys that keep offending, e.g. 1 minute, then 5 minutes, then 30 minutes,
+/// see [Builder::escalating_ban_durations].
+///
+/// The violations, bans and offences are tracked by the inner backend, using the rate limit key
+/// with a `-violations`, `-ban` and `-offences` suffix respectively, so they are shared between
+/// instances when using a shared store such as Redis. Note that this means every request makes an
+/// additional call to the inner backend, to check whether the key is banned.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::penalty::PenaltyBackend;
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use std::time::Duration;
+/// # actix_web::rt::System::new().block_on(async {
+/// // Ban keys for an hour if they are denied more than 100 times within a minute
+/// let backend = PenaltyBackend::builder(InMemoryBackend::builder().build())
+///     .max_violations(100)
+///     .violation_window(Duration::from_secs(60))
+///     .ban_duration(Duration::from_secs(60 * 60))
+///     .build();
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct PenaltyBackend<B> {
+    backend: B,
+    max_violations: u64,
+    violation_window: Duration,
+    ban_durations: Vec<Duration>,
+    reset_after: Duration,
+}
+
+impl<B> PenaltyBackend<B> {
+    pub fn builder(backend: B) -> Builder<B> {
+        Builder {
+            backend,
+            max_violations: DEFAULT_MAX_VIOLATIONS,
+            violation_window: Duration::from_secs(DEFAULT_VIOLATION_WINDOW_SECONDS),
+            ban_durations: vec![Duration::from_secs(DEFAULT_BAN_DURATION_SECONDS)],
+            reset_after: Duration::from_secs(DEFAULT_RESET_AFTER_SECONDS),
+        }
+    }
+
+    fn violations_input(&self, key: &str) -> SimpleInput {
+        SimpleInput {
+            interval: self.violation_window,
+            max_requests: self.max_violations,
+            key: format!("{key}-violations").into(),
+        }
+    }
+
+    // The ban bucket holds a single request for the ban duration, so it is allowed while the key
+    // isn't banned, and denied (by peeking) while it is.
+    fn ban_input(&self, key: &str, duration: Duration) -> SimpleInput {
+        SimpleInput {
+            interval: duration,
+            max_requests: 1,
+            key: format!("{key}-ban").into(),
+        }
+    }
+
+    // The offences bucket counts the bans issued, and expires once the key has behaved for the
+    // reset period after its latest ban.
+    fn offences_input(&self, key: &str, duration: Duration) -> SimpleInput {
+        SimpleInput {
+            interval: duration + self.reset_after,
+            max_requests: u64::MAX,
+            key: format!("{key}-offences").into(),
+        }
+    }
+}
+
+impl<B: SimpleBackend> PenaltyBackend<B> {
+    /// Ban a key, for the next ban duration if bans are escalating.
+    pub async fn ban(&self, key: &str) -> Result<(), B::Error> {
+        // Peeking the offences bucket returns the number of previous offences (plus this one)
+        let (_, output) = self
+            .backend
+            .peek(self.offences_input(key, Duration::ZERO))
+            .await?;
+        let offences = u64::MAX - output.remaining - 1;
+        let level = (offences as usize).min(self.ban_durations.len() - 1);
+        let duration = self.ban_durations[level];
+
+        // Restart the offences bucket, so that it expires relative to this ban
+        let offences = self.offences_input(key, duration);
+        self.backend.remove_key(&offences.key).await?;
+        for _ in 0..=level {
+            self.backend.request(offences.clone()).await?;
+        }
+
+        let ban = self.ban_input(key, duration);
+        self.backend.remove_key(&ban.key).await?;
+        self.backend.request(ban).await?;
+        Ok(())
+    }
+
+    /// Lift the ban on a key, and reset its violations and offences.
+    pub async fn unban(&self, key: &str) -> Result<(), B::Error> {
+        for input in [
+            self.ban_input(key, Duration::ZERO),
+            self.violations_input(key),
+            self.offences_input(key, Duration::ZERO),
+        ] {
+            self.backend.remove_key(&input.key).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the time at which the ban on a key ends, or None if the key isn't banned.
+    pub async fn banned_until(&self, key: &str) -> Result<Option<Instant>, B::Error> {
+        let input = self.ban_input(key, self.ban_durations[0]);
+        let (decision, output) = self.backend.peek(input).await?;
+        Ok(decision.is_denied().then_some(output.reset))
+    }
+
+    async fn banned_output(&self, input: &SimpleInput) -> Result<Option<SimpleOutput>, B::Error> {
+        Ok(self
+            .banned_until(&input.key)
+            .await?
+            .map(|reset| SimpleOutput {
+                limit: input.max_requests,
+                remaining: 0,
+                reset,
+                reset_at: None,
+            }))
+    }
+
+    /// Records a violation for the key, banning it if there have been too many.
+    async fn violation(&self, key: &str) -> Result<Option<Instant>, B::Error> {
+        let (decision, _, _) = self.backend.request(self.violations_input(key)).await?;
+        if decision.is_allowed() {
+            return Ok(None);
+        }
+        self.ban(key).await?;
+        self.backend
+            .remove_key(&self.violations_input(key).key)
+            .await?;
+        self.banned_until(key).await
+    }
+}
+
+pub struct Builder<B> {
+    backend: B,
+    max_violations: u64,
+    violation_window: Duration,
+    ban_durations: Vec<Duration>,
+    reset_after: Duration,
+}
+
+impl<B> Builder<B> {
+    /// The number of denied requests that are tolerated within the violation window, any more
+    /// will cause the key to be banned.
+    ///
+    /// Defaults to 10.
+    pub fn max_violations(mut self, max_violations: u64) -> Self {
+        self.max_violations = max_violations;
+        self
+    }
+
+    /// The window over which violations are counted.
+    ///
+    /// Defaults to 1 minute.
+    pub fn violation_window(mut self, window: Duration) -> Self {
+        assert!(!window.is_zero(), "Violation window must be non-zero");
+        self.violation_window = window;
+        self
+    }
+
+    /// How long keys are banned for.
+    ///
+    /// Defaults to 15 minutes.
+    pub fn ban_duration(self, duration: Duration) -> Self {
+        self.escalating_ban_durations([duration])
+    }
+
+    /// Ban keys for progressively longer durations each time they are banned, e.g. 1 minute, then
+    /// 5 minutes, then 30 minutes. Once the last duration is reached it is used for all further
+    /// bans.
+    ///
+    /// The escalation resets once a key hasn't been banned for the
+    /// [reset period](Builder::reset_after).
+    pub fn escalating_ban_durations(
+        mut self,
+        durations: impl IntoIterator<Item = Duration>,
+    ) -> Self {
+        self.ban_durations = durations.into_iter().collect();
+        assert!(
+            !self.ban_durations.is_empty(),
+            "At least one ban duration is required"
+        );
+        assert!(
+            self.ban_durations.iter().all(|d| !d.is_zero()),
+            "Ban duration must be non-zero"
+        );
+        self
+    }
+
+    /// How long a key must go without being banned, after its latest ban ends, for the ban
+    /// escalation to reset.
+    ///
+    /// Defaults to 1 hour.
+    pub fn reset_after(mut self, reset_after: Duration) -> Self {
+        self.reset_after = reset_after;
+        self
+    }
+
+    pub fn build(self) -> PenaltyBackend<B> {
+        PenaltyBackend {
+            backend: self.backend,
+            max_violations: self.max_violations,
+            violation_window: self.violation_window,
+            ban_durations: self.ban_durations,
+            reset_after: self.reset_after,
+        }
+    }
+}
+
+impl<B: SimpleBackend> Backend<SimpleInput> for PenaltyBackend<B> {
+    type Output = SimpleOutput;
+    /// None if the request was denied due to a ban.
+    type RollbackToken = Option<B::RollbackToken>;
+    type Error = B::Error;
+
+    async fn request(
+        &self,
+        input: SimpleInput,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        if let Some(output) = self.banned_output(&input).await? {
+            return Ok((Decision::Denied, output, None));
+        }
+        let key = input.key.clone();
+        let (decision, mut output, token) = self.backend.request(input).await?;
+        if decision.is_denied() {
+            if let Some(until) = self.violation(&key).await? {
+                output.reset = output.reset.max(until);
+            }
+        }
+        Ok((decision, output, Some(token)))
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        match token {
+            Some(token) => self.backend.rollback(token).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn peek(&self, input: SimpleInput) -> Result<(Decision, Self::Output), Self::Error> {
+        if let Some(output) = self.banned_output(&input).await? {
+            return Ok((Decision::Denied, output));
+        }
+        self.backend.peek(input).await
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        self.backend.health_check().await
+    }
+}
+
+impl<B: SimpleBackend> SimpleBackend for PenaltyBackend<B> {
+    /// Removes the bucket for a given rate limit key, and lifts any ban.
+    async fn remove_key(&self, key: &str) -> Result<(), Self::Error> {
+        self.unban(key).await?;
+        self.backend.remove_key(key).await
+    }
+
+    /// Removes the buckets, violations and bans for every key starting with `prefix`.
+    async fn clear(&self, prefix: &str) -> Result<(), Self::Error> {
+        self.backend.clear(prefix).await
+    }
+
+    /// Note that this doesn't lift any ban.
+    async fn set_count(&self, key: &str, count: u64, ttl: Duration) -> Result<(), Self::Error> {
+        self.backend.set_count(key, count, ttl).await
+    }
+
+    /// Note that this returns the status of the rate limit bucket, a banned key may still have a
+    /// low count.
+    async fn get(&self, key: &str) -> Result<Option<KeyStatus>, Self::Error> {
+        self.backend.get(key).await
+    }
+
+    /// Note that this includes the `-violations`, `-ban` and `-offences` buckets.
+    async fn list(&self, prefix: &str) -> Result<Vec<KeyStatus>, Self::Error> {
+        self.backend.list(prefix).await
+    }
+}
+
+#[cfg(all(test, feature = "dashmap"))]
+mod tests {
+    use super::*;
+    use crate::backend::memory::InMemoryBackend;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[actix_web::test]
+    async fn test_ban() {
+        tokio::time::pause();
+        let backend = PenaltyBackend::builder(InMemoryBackend::builder().build())
+            .max_violations(2)
+            .violation_window(MINUTE)
+            .ban_duration(MINUTE * 10)
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 1,
+            key: "KEY1".into(),
+        };
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        // Two violations are tolerated
+        for _ in 0..2 {
+            let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+            assert!(decision.is_denied());
+            assert_eq!(output.reset, Instant::now() + MINUTE);
+        }
+        assert!(backend.banned_until("KEY1").await.unwrap().is_none());
+        // The third bans the key
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.reset, Instant::now() + MINUTE * 10);
+
+        // The key remains banned after the rate limit window resets
+        tokio::time::advance(MINUTE * 5).await;
+        let (decision, output, token) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        assert!(token.is_none());
+        assert_eq!(output.reset, Instant::now() + MINUTE * 5);
+        let (decision, _) = backend.peek(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+
+        // Until the ban expires
+        tokio::time::advance(MINUTE * 5).await;
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+    }
+
+    #[actix_web::test]
+    async fn test_escalating_bans() {
+        tokio::time::pause();
+        let backend = PenaltyBackend::builder(InMemoryBackend::builder().build())
+            .escalating_ban_durations([MINUTE, MINUTE * 5, MINUTE * 30])
+            .reset_after(MINUTE * 60)
+            .build();
+        let ban = |expected: Duration| {
+            let backend = backend.clone();
+            async move {
+                backend.ban("KEY1").await.unwrap();
+                let until = backend.banned_until("KEY1").await.unwrap().unwrap();
+                assert_eq!(until, Instant::now() + expected);
+                tokio::time::advance(expected).await;
+                assert!(backend.banned_until("KEY1").await.unwrap().is_none());
+            }
+        };
+        ban(MINUTE).await;
+        ban(MINUTE * 5).await;
+        ban(MINUTE * 30).await;
+        ban(MINUTE * 30).await;
+        // Behaving for the reset period restarts the escalation
+        tokio::time::advance(MINUTE * 60).await;
+        ban(MINUTE).await;
+    }
+
+    #[actix_web::test]
+    async fn test_unban() {
+        let backend = PenaltyBackend::builder(InMemoryBackend::builder().build()).build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 1,
+            key: "KEY1".into(),
+        };
+        backend.ban("KEY1").await.unwrap();
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        backend.unban("KEY1").await.unwrap();
+        let (decision, _, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+    }
+}
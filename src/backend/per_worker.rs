@@ -0,0 +1,227 @@
+use crate::backend::window::{Timeline, WindowAlignment};
+use crate::backend::{Backend, Decision, SimpleInput, SimpleOutput};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const DEFAULT_GC_INTERVAL_SECONDS: u64 = 60 * 10;
+
+/// A Fixed Window rate limiter [Backend] that stores keys in memory local to a single worker
+/// thread, without any locking.
+///
+/// Each worker of a `HttpServer` counts its requests separately, so a client may make up to
+/// `max_requests` requests to every worker. This is intentional, when it is acceptable for the
+/// limit to be approximate, in exchange for the cheapest possible check; for limits shared by all
+/// workers use the [SharedInMemoryBackend](crate::backend::memory::SharedInMemoryBackend).
+///
+/// The backend is not [Send], so it must be created inside the `HttpServer::new` factory closure,
+/// which runs once per worker. The garbage collector is spawned onto the worker using
+/// [tokio::task::spawn_local], and stops once the backend is dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use actix_extensible_rate_limit::backend::per_worker::PerWorkerInMemoryBackend;
+/// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+/// # use actix_extensible_rate_limit::RateLimiter;
+/// # use actix_web::{App, HttpServer};
+/// # use std::time::Duration;
+/// # async fn run() -> std::io::Result<()> {
+/// HttpServer::new(|| {
+///     // Each worker has its own counts
+///     let backend = PerWorkerInMemoryBackend::builder().build();
+///     let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+///         .real_ip_key()
+///         .build();
+///     App::new().wrap(RateLimiter::builder(backend, input).add_headers().build())
+/// })
+/// .bind("127.0.0.1:8080")?
+/// .run()
+/// .await
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct PerWorkerInMemoryBackend {
+    map: Rc<RefCell<HashMap<Arc<str>, Value>>>,
+    timeline: Option<Timeline>,
+}
+
+struct Value {
+    ttl: Instant,
+    count: u64,
+}
+
+impl PerWorkerInMemoryBackend {
+    pub fn builder() -> Builder {
+        Builder {
+            gc_interval: Some(Duration::from_secs(DEFAULT_GC_INTERVAL_SECONDS)),
+            alignment: None,
+        }
+    }
+
+    /// The time at which a window starting at `now` resets.
+    fn window_end(&self, now: Instant, interval: Duration) -> Instant {
+        match &self.timeline {
+            Some(timeline) => timeline.next_reset(now, interval),
+            None => now
+                .checked_add(interval)
+                .expect("Interval unexpectedly large"),
+        }
+    }
+
+    fn garbage_collector(map: Weak<RefCell<HashMap<Arc<str>, Value>>>, interval: Duration) {
+        assert!(
+            interval.as_secs_f64() > 0f64,
+            "GC interval must be non-zero"
+        );
+        tokio::task::spawn_local(async move {
+            loop {
+                let now = Instant::now();
+                match map.upgrade() {
+                    Some(map) => map.borrow_mut().retain(|_k, v| v.ttl > now),
+                    None => break,
+                }
+                tokio::time::sleep_until(now + interval).await;
+            }
+        });
+    }
+}
+
+pub struct Builder {
+    gc_interval: Option<Duration>,
+    alignment: Option<WindowAlignment>,
+}
+
+impl Builder {
+    /// Override the default garbage collector interval.
+    ///
+    /// Set to None to disable garbage collection.
+    ///
+    /// The garbage collector periodically scans the internal map, removing expired buckets.
+    pub fn with_gc_interval(mut self, interval: Option<Duration>) -> Self {
+        self.gc_interval = interval;
+        self
+    }
+
+    /// Align the windows of every key to fixed boundaries, e.g. with [WindowAlignment::utc] a
+    /// daily limit always resets at midnight UTC.
+    ///
+    /// By default each key's window starts at its first request.
+    pub fn with_alignment(mut self, alignment: WindowAlignment) -> Self {
+        self.alignment = Some(alignment);
+        self
+    }
+
+    /// # Panics
+    ///
+    /// If garbage collection is enabled, and this is not called within a
+    /// [LocalSet](tokio::task::LocalSet), e.g. an actix worker.
+    pub fn build(self) -> PerWorkerInMemoryBackend {
+        let map = Rc::new(RefCell::new(HashMap::new()));
+        if let Some(gc_interval) = self.gc_interval {
+            PerWorkerInMemoryBackend::garbage_collector(Rc::downgrade(&map), gc_interval);
+        }
+        PerWorkerInMemoryBackend {
+            map,
+            timeline: self.alignment.map(Timeline::new),
+        }
+    }
+}
+
+impl Backend<SimpleInput> for PerWorkerInMemoryBackend {
+    type Output = SimpleOutput;
+    type RollbackToken = Arc<str>;
+    type Error = Infallible;
+
+    async fn request(
+        &self,
+        input: SimpleInput,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        let now = Instant::now();
+        let window_end = self.window_end(now, input.interval);
+        let mut map = self.map.borrow_mut();
+        let value = map
+            .entry(input.key.clone())
+            .or_insert(Value { ttl: now, count: 0 });
+        if value.ttl <= now {
+            // Expired or new, so start a new window
+            value.ttl = window_end;
+            value.count = 0;
+        }
+        value.count = value.count.saturating_add(1);
+        let output = SimpleOutput {
+            limit: input.max_requests,
+            remaining: input.max_requests.saturating_sub(value.count),
+            reset: value.ttl,
+            reset_at: None,
+        };
+        let allow = value.count <= input.max_requests;
+        Ok((Decision::from_allowed(allow), output, input.key))
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        if let Some(value) = self.map.borrow_mut().get_mut(&token) {
+            value.count = value.count.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    async fn peek(&self, input: SimpleInput) -> Result<(Decision, Self::Output), Self::Error> {
+        let now = Instant::now();
+        let (count, expiry) = match self.map.borrow().get(&input.key) {
+            Some(v) if v.ttl > now => (v.count + 1, v.ttl),
+            _ => (1, self.window_end(now, input.interval)),
+        };
+        let allow = count <= input.max_requests;
+        let output = SimpleOutput {
+            limit: input.max_requests,
+            remaining: input.max_requests.saturating_sub(count),
+            reset: expiry,
+            reset_at: None,
+        };
+        Ok((Decision::from_allowed(allow), output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[actix_web::test]
+    async fn test_per_worker() {
+        tokio::time::pause();
+        let backend = PerWorkerInMemoryBackend::builder()
+            .with_gc_interval(Some(MINUTE))
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 2,
+            key: "KEY1".into(),
+        };
+        let (decision, output, token) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 1);
+        assert_eq!(output.reset, Instant::now() + MINUTE);
+        backend.rollback(token).await.unwrap();
+        backend.request(input.clone()).await.unwrap();
+        backend.request(input.clone()).await.unwrap();
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+
+        // Clones share the same worker's counts
+        let (decision, _) = backend.clone().peek(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+
+        // The window is reset, and the expired key collected
+        tokio::time::advance(MINUTE).await;
+        assert!(backend.map.borrow().is_empty());
+        let (decision, _, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+    }
+}
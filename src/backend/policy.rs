@@ -0,0 +1,174 @@
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// A rate limit policy: the maximum requests allowed within an interval.
+///
+/// A policy can be parsed from a string such as `"100/minute"`, see [RateLimitPolicy::parse].
+///
+/// Policies can be used to override the default policy of a
+/// [SimpleInputFunctionBuilder](crate::backend::SimpleInputFunctionBuilder) for specific routes,
+/// by registering them against a route pattern with
+/// [SimpleInputFunctionBuilder::route_policy](crate::backend::SimpleInputFunctionBuilder::route_policy),
+/// or by attaching them to a scope or resource using `.app_data()`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RateLimitPolicy {
+    interval: Duration,
+    max_requests: u64,
+    cost: u64,
+}
+
+impl RateLimitPolicy {
+    pub fn new(interval: Duration, max_requests: u64) -> Self {
+        Self {
+            interval,
+            max_requests,
+            cost: 1,
+        }
+    }
+
+    /// Count each request as `cost` requests towards the `max_requests`.
+    ///
+    /// Defaults to 1.
+    pub fn with_cost(mut self, cost: u64) -> Self {
+        assert!(cost > 0, "Cost must be non-zero");
+        self.cost = cost;
+        self
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn max_requests(&self) -> u64 {
+        self.max_requests
+    }
+
+    pub fn cost(&self) -> u64 {
+        self.cost
+    }
+
+    /// Parse a policy of the form `<max requests>/<interval>`, e.g. `"10/second"`, `"100/1m"` or
+    /// `"1000/12 hours"`, so that limits can be read from environment variables or config files.
+    ///
+    /// The interval is an optional count followed by a unit: `ms`, `s`, `m`, `h` or `d` (or
+    /// their long forms, e.g. `minute` or `minutes`). The count defaults to 1.
+    pub fn parse(policy: &str) -> Result<Self, ParsePolicyError> {
+        let (max_requests, interval) = policy
+            .split_once('/')
+            .ok_or_else(|| ParsePolicyError::MissingSeparator(policy.to_owned()))?;
+        let max_requests = max_requests
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| ParsePolicyError::InvalidMaxRequests(max_requests.trim().to_owned()))?;
+        let interval = interval.trim();
+        let unit_start = interval
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| ParsePolicyError::MissingUnit(interval.to_owned()))?;
+        let count = match &interval[..unit_start] {
+            "" => 1,
+            count => count
+                .parse::<u32>()
+                .map_err(|_| ParsePolicyError::IntervalTooLarge(interval.to_owned()))?,
+        };
+        let unit = match interval[unit_start..].trim() {
+            "ms" | "millisecond" | "milliseconds" => Duration::from_millis(1),
+            "s" | "sec" | "second" | "seconds" => Duration::from_secs(1),
+            "m" | "min" | "minute" | "minutes" => Duration::from_secs(60),
+            "h" | "hour" | "hours" => Duration::from_secs(60 * 60),
+            "d" | "day" | "days" => Duration::from_secs(60 * 60 * 24),
+            unit => return Err(ParsePolicyError::UnknownUnit(unit.to_owned())),
+        };
+        if count == 0 {
+            return Err(ParsePolicyError::ZeroInterval);
+        }
+        let interval = unit
+            .checked_mul(count)
+            .ok_or_else(|| ParsePolicyError::IntervalTooLarge(interval.to_owned()))?;
+        Ok(Self::new(interval, max_requests))
+    }
+
+    /// The number of requests allowed within the interval, after accounting for the cost.
+    pub(crate) fn allowed_requests(&self) -> u64 {
+        self.max_requests / self.cost
+    }
+}
+
+impl FromStr for RateLimitPolicy {
+    type Err = ParsePolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum ParsePolicyError {
+    #[error(
+        "Invalid rate limit policy `{0}`, expected `<max requests>/<interval>`, e.g. `100/1m`"
+    )]
+    MissingSeparator(String),
+    #[error("Invalid max requests `{0}`, expected a non-negative integer")]
+    InvalidMaxRequests(String),
+    #[error("Missing unit in interval `{0}`, expected one of `ms`, `s`, `m`, `h` or `d`")]
+    MissingUnit(String),
+    #[error("Unknown interval unit `{0}`, expected one of `ms`, `s`, `m`, `h` or `d`")]
+    UnknownUnit(String),
+    #[error("Interval must be non-zero")]
+    ZeroInterval,
+    #[error("Interval `{0}` is too large")]
+    IntervalTooLarge(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_requests() {
+        let policy = RateLimitPolicy::new(Duration::from_secs(60), 10);
+        assert_eq!(policy.allowed_requests(), 10);
+        assert_eq!(policy.with_cost(3).allowed_requests(), 3);
+        assert_eq!(policy.with_cost(20).allowed_requests(), 0);
+    }
+
+    #[test]
+    fn test_parse() {
+        let minute = Duration::from_secs(60);
+        assert_eq!(
+            RateLimitPolicy::parse("100/minute").unwrap(),
+            RateLimitPolicy::new(minute, 100)
+        );
+        assert_eq!(
+            "100/5m".parse::<RateLimitPolicy>().unwrap(),
+            RateLimitPolicy::new(minute * 5, 100)
+        );
+        assert_eq!(
+            RateLimitPolicy::parse(" 10 / 12 hours ").unwrap(),
+            RateLimitPolicy::new(Duration::from_secs(60 * 60 * 12), 10)
+        );
+        assert_eq!(
+            RateLimitPolicy::parse("5/250ms").unwrap(),
+            RateLimitPolicy::new(Duration::from_millis(250), 5)
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        use ParsePolicyError::*;
+        let err = |s| RateLimitPolicy::parse(s).unwrap_err();
+        assert_eq!(err("100"), MissingSeparator("100".to_string()));
+        assert_eq!(err("-1/m"), InvalidMaxRequests("-1".to_string()));
+        assert_eq!(err("10/60"), MissingUnit("60".to_string()));
+        assert_eq!(err("10/fortnight"), UnknownUnit("fortnight".to_string()));
+        assert_eq!(err("10/0s"), ZeroInterval);
+        assert_eq!(
+            err("10/99999999999d"),
+            IntervalTooLarge("99999999999d".to_string())
+        );
+        assert_eq!(
+            err("10/fortnight").to_string(),
+            "Unknown interval unit `fortnight`, expected one of `ms`, `s`, `m`, `h` or `d`"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::backend::RateLimitPolicy;
+use actix_web::dev::ServiceRequest;
+
+/// A set of rate limit policies for different paths and methods, e.g. loaded from a config file.
+///
+/// Requests are matched against the rules in order, the first matching rule's policy is used.
+/// Requests that don't match any rule use the default policy. Use with
+/// [SimpleInputFunctionBuilder::from_policy_set](crate::backend::SimpleInputFunctionBuilder::from_policy_set).
+///
+/// Rule paths are globs, where `*` matches a single path segment and `**` matches any number of
+/// segments. Methods are optional, if empty the rule matches any method.
+///
+/// With the `serde` feature enabled, a policy set can be deserialized from any serde format, where
+/// the policies are written as strings, see [RateLimitPolicy::parse]. For example in TOML:
+///
+/// ```toml
+/// default = "100/1m"
+///
+/// [[rules]]
+/// path = "/login"
+/// methods = ["POST"]
+/// policy = "5/1m"
+///
+/// [[rules]]
+/// path = "/api/**"
+/// policy = "1000/1h"
+/// cost = 10
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct PolicySet {
+    default: RateLimitPolicy,
+    #[cfg_attr(feature = "serde", serde(default))]
+    rules: Vec<PolicyRule>,
+}
+
+/// A rule within a [PolicySet].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct PolicyRule {
+    path: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    methods: Vec<String>,
+    policy: RateLimitPolicy,
+    #[cfg_attr(feature = "serde", serde(default))]
+    cost: Option<u64>,
+}
+
+impl PolicySet {
+    pub fn new(default: RateLimitPolicy) -> Self {
+        Self {
+            default,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Add a rule, after any existing rules.
+    pub fn rule(mut self, rule: PolicyRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn default_policy(&self) -> RateLimitPolicy {
+        self.default
+    }
+
+    pub fn rules(&self) -> &[PolicyRule] {
+        &self.rules
+    }
+
+    /// Returns the first rule matching the request, if any.
+    pub(crate) fn find(&self, req: &ServiceRequest) -> Option<&PolicyRule> {
+        self.rules.iter().find(|rule| rule.matches(req))
+    }
+}
+
+impl PolicyRule {
+    /// # Arguments
+    ///
+    /// * `path`: A path glob, e.g. `/users/*/posts` or `/api/**`.
+    /// * `policy`: The policy to apply to matching requests.
+    pub fn new(path: &str, policy: RateLimitPolicy) -> Self {
+        Self {
+            path: path.to_owned(),
+            methods: Vec::new(),
+            policy,
+            cost: None,
+        }
+    }
+
+    /// Only match requests using one of these methods.
+    pub fn methods<M: Into<String>>(mut self, methods: impl IntoIterator<Item = M>) -> Self {
+        self.methods = methods.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The policy for this rule, including the cost if set.
+    pub fn policy(&self) -> RateLimitPolicy {
+        match self.cost {
+            Some(cost) => self.policy.with_cost(cost),
+            None => self.policy,
+        }
+    }
+
+    /// Identifies this rule within a rate limit key, so that each rule is counted separately.
+    pub(crate) fn name(&self) -> String {
+        if self.methods.is_empty() {
+            self.path.clone()
+        } else {
+            format!("{}:{}", self.methods.join(","), self.path)
+        }
+    }
+
+    fn matches(&self, req: &ServiceRequest) -> bool {
+        let method = req.method().as_str();
+        (self.methods.is_empty() || self.methods.iter().any(|m| m.eq_ignore_ascii_case(method)))
+            && glob_match(&self.path, req.path())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for RateLimitPolicy {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let policy = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        RateLimitPolicy::parse(&policy).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Matches a path against a glob, segment by segment.
+fn glob_match(glob: &str, path: &str) -> bool {
+    fn matches(glob: &[&str], path: &[&str]) -> bool {
+        match glob.split_first() {
+            None => path.is_empty(),
+            Some((&"**", rest)) => (0..=path.len()).any(|skip| matches(rest, &path[skip..])),
+            Some((segment, rest)) => match path.split_first() {
+                Some((first, path)) => (*segment == "*" || segment == first) && matches(rest, path),
+                None => false,
+            },
+        }
+    }
+    let glob: Vec<&str> = glob.trim_matches('/').split('/').collect();
+    let path: Vec<&str> = path.trim_matches('/').split('/').collect();
+    matches(&glob, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("/login", "/login"));
+        assert!(glob_match("/login", "/login/"));
+        assert!(!glob_match("/login", "/logout"));
+        assert!(glob_match("/users/*/posts", "/users/1/posts"));
+        assert!(!glob_match("/users/*/posts", "/users/1/2/posts"));
+        assert!(glob_match("/api/**", "/api"));
+        assert!(glob_match("/api/**", "/api/v1/users"));
+        assert!(glob_match("/**/edit", "/users/1/edit"));
+        assert!(!glob_match("/**/edit", "/users/1"));
+        assert!(glob_match("/**", "/"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize() {
+        use std::time::Duration;
+
+        let set: PolicySet = serde_json::from_str(
+            r#"{
+                "default": "100/1m",
+                "rules": [
+                    { "path": "/login", "methods": ["POST"], "policy": "5/1m" },
+                    { "path": "/api/**", "policy": "1000/1h", "cost": 10 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let minute = Duration::from_secs(60);
+        assert_eq!(set.default_policy(), RateLimitPolicy::new(minute, 100));
+        assert_eq!(set.rules()[0].name(), "POST:/login");
+        assert_eq!(set.rules()[1].policy().cost(), 10);
+
+        let error = serde_json::from_str::<PolicySet>(r#"{ "default": "100/fortnight" }"#)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with("Unknown interval unit `fortnight`"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use crate::backend::RateLimitPolicy;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+type CacheEntries = HashMap<String, (Option<RateLimitPolicy>, Instant)>;
+
+/// Looks up the rate limit policy for a key at request time, e.g. from a database of API keys and
+/// their subscription tiers.
+///
+/// Use with
+/// [SimpleInputFunctionBuilder::build_with_quota](crate::backend::SimpleInputFunctionBuilder::build_with_quota).
+/// Wrap the provider in a [CachedQuotaProvider] to avoid looking up the policy on every request.
+pub trait QuotaProvider {
+    /// Returns the policy for the rate limit key, or None to use the default policy.
+    fn quota(
+        &self,
+        key: &str,
+    ) -> impl Future<Output = Result<Option<RateLimitPolicy>, actix_web::Error>>;
+}
+
+/// A [QuotaProvider] that caches the policies returned by another provider.
+///
+/// Both found and not found policies are cached, errors are not.
+#[derive(Clone)]
+pub struct CachedQuotaProvider<P> {
+    provider: P,
+    ttl: Duration,
+    max_entries: usize,
+    entries: Arc<Mutex<CacheEntries>>,
+}
+
+impl<P> CachedQuotaProvider<P> {
+    /// # Arguments
+    ///
+    /// * `provider`: The provider to cache.
+    /// * `ttl`: How long to cache each policy for, changes to a key's quota may take this long to
+    ///   take effect.
+    pub fn new(provider: P, ttl: Duration) -> Self {
+        Self {
+            provider,
+            ttl,
+            max_entries: 10_000,
+            entries: Default::default(),
+        }
+    }
+
+    /// The maximum number of cached policies.
+    ///
+    /// When full, expired entries are removed, and if that is not enough the cache is cleared.
+    ///
+    /// Defaults to 10,000.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Remove a key from the cache, e.g. after its quota has been changed.
+    pub fn invalidate(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    fn get(&self, key: &str) -> Option<Option<RateLimitPolicy>> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(_, expiry)| *expiry > Instant::now())
+            .map(|(policy, _)| *policy)
+    }
+
+    fn insert(&self, key: &str, policy: Option<RateLimitPolicy>) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(key) {
+            entries.retain(|_, (_, expiry)| *expiry > now);
+            if entries.len() >= self.max_entries {
+                entries.clear();
+            }
+        }
+        entries.insert(key.to_owned(), (policy, now + self.ttl));
+    }
+}
+
+impl<P: QuotaProvider> QuotaProvider for CachedQuotaProvider<P> {
+    async fn quota(&self, key: &str) -> Result<Option<RateLimitPolicy>, actix_web::Error> {
+        if let Some(policy) = self.get(key) {
+            return Ok(policy);
+        }
+        let policy = self.provider.quota(key).await?;
+        self.insert(key, policy);
+        Ok(policy)
+    }
+}
+
+impl<P: QuotaProvider> QuotaProvider for Arc<P> {
+    fn quota(
+        &self,
+        key: &str,
+    ) -> impl Future<Output = Result<Option<RateLimitPolicy>, actix_web::Error>> {
+        self.as_ref().quota(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[derive(Default)]
+    struct CountingProvider {
+        lookups: AtomicU64,
+    }
+
+    impl QuotaProvider for CountingProvider {
+        async fn quota(&self, key: &str) -> Result<Option<RateLimitPolicy>, actix_web::Error> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            Ok((key == "premium").then(|| RateLimitPolicy::new(MINUTE, 1000)))
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_cached_quota() {
+        tokio::time::pause();
+        let provider = Arc::new(CountingProvider::default());
+        let cached = CachedQuotaProvider::new(provider.clone(), MINUTE);
+
+        let premium = Some(RateLimitPolicy::new(MINUTE, 1000));
+        assert_eq!(cached.quota("premium").await.unwrap(), premium);
+        assert_eq!(cached.quota("premium").await.unwrap(), premium);
+        assert_eq!(cached.quota("free").await.unwrap(), None);
+        assert_eq!(cached.quota("free").await.unwrap(), None);
+        assert_eq!(provider.lookups.load(Ordering::Relaxed), 2);
+
+        // Entries expire after the ttl
+        tokio::time::advance(MINUTE).await;
+        assert_eq!(cached.quota("premium").await.unwrap(), premium);
+        assert_eq!(provider.lookups.load(Ordering::Relaxed), 3);
+
+        cached.invalidate("premium");
+        assert_eq!(cached.quota("premium").await.unwrap(), premium);
+        assert_eq!(provider.lookups.load(Ordering::Relaxed), 4);
+    }
+
+    #[actix_web::test]
+    async fn test_max_entries() {
+        let provider = Arc::new(CountingProvider::default());
+        let cached = CachedQuotaProvider::new(provider.clone(), MINUTE).max_entries(2);
+        for key in ["a", "b", "c", "a"] {
+            cached.quota(key).await.unwrap();
+        }
+        // Inserting "c" cleared the full cache
+        assert_eq!(provider.lookups.load(Ordering::Relaxed), 4);
+        assert_eq!(cached.entries.lock().unwrap().len(), 2);
+    }
+}
@@ -1,14 +1,39 @@
-use crate::backend::{Backend, Decision, SimpleBackend, SimpleInput, SimpleOutput};
-use actix_web::rt::time::Instant;
+mod cache;
+mod coalesce;
+mod denied;
+mod overrides;
+
+use crate::backend::window::{Timeline, WindowAlignment};
+pub use crate::backend::KeyStatus;
+use crate::backend::{
+    Backend, BackendError, ClassifyError, Decision, LimitOverride, SimpleBackend, SimpleInput,
+    SimpleOutput, MAX_TTL,
+};
+#[cfg(feature = "actix")]
 use actix_web::{HttpResponse, ResponseError};
-use redis::aio::ConnectionManager;
-use redis::AsyncCommands;
+use cache::ClientSideCache;
+use coalesce::Coalescer;
+use denied::DeniedCache;
+use overrides::OverrideCache;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{AsyncCommands, ErrorKind, Pipeline};
 use std::borrow::Cow;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
+use tokio::time::Instant;
 
 const BITFIELD_ENCODING: &str = "u63";
 const BITFIELD_OFFSET: u8 = 0;
+const BITFIELD_MAX: u64 = (1 << 63) - 1;
+/// Keys within the key prefix that are reserved for the backend's own data.
+const RESERVED_PREFIX: &str = "__";
+/// Prepended to rate limit keys starting with `_`, so that they can't collide with the reserved
+/// keys.
+const ESCAPE_PREFIX: &str = "_~";
+/// The hash storing the limit overrides, within the key prefix.
+const LIMIT_OVERRIDES_KEY: &str = "__overrides";
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -20,8 +45,38 @@ pub enum Error {
     ),
     #[error("Unexpected negative TTL response for the rate limit key")]
     NegativeTtl,
+    /// The error of a [coalesced](Builder::coalesce) increment, shared by every request in the
+    /// batch.
+    #[error("{0}")]
+    Coalesced(Arc<Error>),
 }
 
+impl ClassifyError for Error {
+    fn class(&self) -> BackendError {
+        match self {
+            Error::Redis(e) => match e.kind() {
+                ErrorKind::AuthenticationFailed
+                | ErrorKind::InvalidClientConfig
+                | ErrorKind::MasterNameNotFoundBySentinel
+                | ErrorKind::EmptySentinelList
+                | ErrorKind::RESP3NotSupported => BackendError::PermanentMisconfiguration,
+                ErrorKind::CrossSlot => BackendError::KeyRejected,
+                ErrorKind::ExtensionError if e.code() == Some("WRONGTYPE") => {
+                    BackendError::KeyRejected
+                }
+                ErrorKind::ExtensionError if matches!(e.code(), Some("NOAUTH" | "NOPERM")) => {
+                    BackendError::PermanentMisconfiguration
+                }
+                _ => BackendError::Transient,
+            },
+            // The key exists without an expiry, e.g. it was written by something else
+            Error::NegativeTtl => BackendError::KeyRejected,
+            Error::Coalesced(e) => e.class(),
+        }
+    }
+}
+
+#[cfg(feature = "actix")]
 impl ResponseError for Error {
     fn error_response(&self) -> HttpResponse {
         HttpResponse::InternalServerError().finish()
@@ -29,10 +84,21 @@ impl ResponseError for Error {
 }
 
 /// A Fixed Window rate limiter [Backend] that uses stores data in Redis.
+///
+/// By default each key's window starts at its first request, see [Builder::with_alignment] to
+/// align the windows to the wall clock instead.
+///
+/// Individual keys can be given custom limits with [RedisBackend::set_limit_override], see
+/// [Builder::with_limit_overrides].
 #[derive(Clone)]
 pub struct RedisBackend {
     connection: ConnectionManager,
     key_prefix: Option<String>,
+    cache: Option<Arc<ClientSideCache>>,
+    coalescer: Option<Arc<Coalescer>>,
+    denied: Option<Arc<DeniedCache>>,
+    overrides: Option<Arc<OverrideCache>>,
+    timeline: Option<Timeline>,
 }
 
 impl RedisBackend {
@@ -57,20 +123,310 @@ impl RedisBackend {
         Builder {
             connection,
             key_prefix: None,
+            cache: None,
+            coalescer: None,
+            denied: None,
+            overrides: None,
+            alignment: None,
         }
     }
 
+    /// Create a RedisBackendBuilder that uses
+    /// [client side caching](https://redis.io/docs/latest/develop/reference/client-side-caching/).
+    ///
+    /// Requests for keys that are far from their limit will be counted locally, with the
+    /// increment sent to Redis in the background, avoiding the latency of a round trip. The cached
+    /// counts are invalidated as soon as the key is modified by any other client.
+    ///
+    /// Requires Redis 6 or newer, and the client must be configured to use the RESP3 protocol.
+    ///
+    /// # Arguments
+    ///
+    /// * `client`: A RESP3 Redis client.
+    /// * `max_utilization`: The fraction (0 to 1) of the limit up to which requests may be
+    ///   counted locally. Beyond this point every request is sent to Redis.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use actix_extensible_rate_limit::backend::redis::RedisBackend;
+    /// # async fn example() {
+    /// let client = redis::Client::open("redis://127.0.0.1/?protocol=resp3").unwrap();
+    /// let backend = RedisBackend::builder_with_client_side_caching(client, 0.5)
+    ///     .await
+    ///     .unwrap()
+    ///     .build();
+    /// # };
+    /// ```
+    pub async fn builder_with_client_side_caching(
+        client: redis::Client,
+        max_utilization: f64,
+    ) -> Result<Builder, Error> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let config = ConnectionManagerConfig::new().set_push_sender(sender);
+        let connection = ConnectionManager::new_with_config(client, config).await?;
+        Ok(Builder {
+            connection,
+            key_prefix: None,
+            cache: Some(ClientSideCache::new(max_utilization, receiver)),
+            coalescer: None,
+            denied: None,
+            overrides: None,
+            alignment: None,
+        })
+    }
+
     fn make_key<'t>(&self, key: &'t str) -> Cow<'t, str> {
+        let escape = if key.starts_with('_') {
+            ESCAPE_PREFIX
+        } else {
+            ""
+        };
         match &self.key_prefix {
-            None => Cow::Borrowed(key),
-            Some(prefix) => Cow::Owned(format!("{prefix}{key}")),
+            None if escape.is_empty() => Cow::Borrowed(key),
+            None => Cow::Owned(format!("{escape}{key}")),
+            Some(prefix) => Cow::Owned(format!("{prefix}{escape}{key}")),
         }
     }
+
+    /// The key of the backend's own data, which isn't escaped like a rate limit key.
+    fn reserved_key(&self, key: &str) -> String {
+        let prefix = self.key_prefix.as_deref().unwrap_or_default();
+        format!("{prefix}{key}")
+    }
+
+    /// The time-to-live of a new key, i.e. until the end of the window.
+    fn window_ttl(&self, interval: Duration) -> Duration {
+        match &self.timeline {
+            Some(timeline) => {
+                let now = Instant::now();
+                timeline.next_reset(now, interval) - now
+            }
+            None => interval,
+        }
+    }
+
+    /// Removes the (prefixed) key from the local caches, after it has been modified.
+    fn forget(&self, key: &str) {
+        if let Some(cache) = &self.cache {
+            cache.remove(key);
+        }
+        if let Some(denied) = &self.denied {
+            denied.remove(key);
+        }
+    }
+
+    /// Override the limit of a rate limit key, e.g. to give a customer 10x the usual limit,
+    /// replacing any existing override for the key.
+    ///
+    /// The override is stored in Redis, and applies to every request for the key until it is
+    /// removed, but is only applied by backends built with [Builder::with_limit_overrides].
+    ///
+    /// Note that the key prefix (if set) is automatically included.
+    pub async fn set_limit_override(&self, key: &str, limit: LimitOverride) -> Result<(), Error> {
+        let mut con = self.connection.clone();
+        con.hset::<_, _, _, ()>(
+            self.reserved_key(LIMIT_OVERRIDES_KEY),
+            key,
+            overrides::encode(limit),
+        )
+        .await?;
+        if let Some(overrides) = &self.overrides {
+            overrides.insert(key, limit);
+        }
+        self.forget(&self.make_key(key));
+        Ok(())
+    }
+
+    /// Remove the limit override of a rate limit key, if it has one.
+    ///
+    /// Note that the key prefix (if set) is automatically included.
+    pub async fn remove_limit_override(&self, key: &str) -> Result<(), Error> {
+        let mut con = self.connection.clone();
+        con.hdel::<_, _, ()>(self.reserved_key(LIMIT_OVERRIDES_KEY), key)
+            .await?;
+        if let Some(overrides) = &self.overrides {
+            overrides.remove(key);
+        }
+        self.forget(&self.make_key(key));
+        Ok(())
+    }
+
+    /// Returns the limit override of a rate limit key, if it has one.
+    ///
+    /// Note that the key prefix (if set) is automatically included.
+    pub async fn limit_override(&self, key: &str) -> Result<Option<LimitOverride>, Error> {
+        let mut con = self.connection.clone();
+        let value: Option<String> = con
+            .hget(self.reserved_key(LIMIT_OVERRIDES_KEY), key)
+            .await?;
+        Ok(value.as_deref().and_then(overrides::decode))
+    }
+
+    /// Applies any limit override for the input's key, if enabled, first refreshing the
+    /// overrides if they are stale.
+    async fn apply_override(&self, mut input: SimpleInput) -> Result<SimpleInput, Error> {
+        let Some(overrides) = &self.overrides else {
+            return Ok(input);
+        };
+        if overrides.needs_refresh() {
+            let mut con = self.connection.clone();
+            let key = self.reserved_key(LIMIT_OVERRIDES_KEY);
+            match con.hgetall::<_, HashMap<String, String>>(key).await {
+                Ok(values) => overrides.replace(values),
+                Err(e) => {
+                    overrides.refresh_failed();
+                    return Err(e.into());
+                }
+            }
+        }
+        if let Some(limit) = overrides.get(&input.key) {
+            input.max_requests = limit.apply(input.max_requests);
+        }
+        Ok(input)
+    }
+
+    /// Returns the current count for a rate limit key, without incrementing it.
+    ///
+    /// Returns None if the key doesn't exist (i.e. no requests in the current window).
+    ///
+    /// Note that the key prefix (if set) is automatically included.
+    pub async fn status(&self, key: &str) -> Result<Option<KeyStatus>, Error> {
+        let mut statuses = self.statuses(vec![key.to_string()]).await?;
+        Ok(statuses.pop().flatten())
+    }
+
+    /// Returns the keys with the highest counts, in descending order.
+    ///
+    /// This scans every key matching the key prefix, so should be used sparingly on large
+    /// instances.
+    pub async fn top(&self, limit: usize) -> Result<Vec<KeyStatus>, Error> {
+        let mut statuses = self.list("").await?;
+        statuses.sort_by_key(|s| std::cmp::Reverse(s.count));
+        statuses.truncate(limit);
+        Ok(statuses)
+    }
+
+    /// Returns every rate limit key starting with `prefix`, excluding the key prefix and reserved
+    /// keys.
+    async fn scan(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let key_prefix = self.key_prefix.as_deref().unwrap_or_default();
+        let pattern = format!("{}*", escape_pattern(&self.make_key(prefix)));
+        let mut con = self.connection.clone();
+        let mut keys = Vec::new();
+        let mut cursor = 0u64;
+        loop {
+            let (next, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(1000)
+                .query_async(&mut con)
+                .await?;
+            keys.extend(
+                batch
+                    .into_iter()
+                    .filter_map(|k| k.strip_prefix(key_prefix).map(ToOwned::to_owned))
+                    .filter(|k| !k.starts_with(RESERVED_PREFIX))
+                    .map(|k| match k.strip_prefix(ESCAPE_PREFIX) {
+                        Some(k) => k.to_owned(),
+                        None => k,
+                    }),
+            );
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        Ok(keys)
+    }
+
+    /// Denies all requests for a rate limit key, regardless of the limit, for the given duration.
+    ///
+    /// The ban can be lifted early using [SimpleBackend::remove_key].
+    ///
+    /// Note that the key prefix (if set) is automatically included.
+    pub async fn ban(&self, key: &str, duration: Duration) -> Result<(), Error> {
+        let key = self.make_key(key);
+        let mut con = self.connection.clone();
+        redis::pipe()
+            .atomic()
+            .cmd("BITFIELD")
+            .arg(key.as_ref())
+            .arg("SET")
+            .arg(BITFIELD_ENCODING)
+            .arg(BITFIELD_OFFSET)
+            .arg(BITFIELD_MAX)
+            .ignore()
+            .cmd("PEXPIRE")
+            .arg(key.as_ref())
+            .arg(millis(duration))
+            .ignore()
+            .query_async::<()>(&mut con)
+            .await?;
+        self.forget(&key);
+        Ok(())
+    }
+
+    async fn statuses(&self, keys: Vec<String>) -> Result<Vec<Option<KeyStatus>>, Error> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut con = self.connection.clone();
+        let mut pipe = redis::pipe();
+        for key in &keys {
+            let key = self.make_key(key);
+            pipe.cmd("BITFIELD")
+                .arg(key.as_ref())
+                .arg("GET")
+                .arg(BITFIELD_ENCODING)
+                .arg(BITFIELD_OFFSET)
+                .cmd("PTTL")
+                .arg(key.as_ref());
+        }
+        let results: Vec<(Vec<u64>, i64)> = pipe.query_async(&mut con).await?;
+        Ok(keys
+            .into_iter()
+            .zip(results)
+            .map(|(key, (counts, ttl))| {
+                // A negative TTL means the key doesn't exist (or has already been rolled back)
+                (ttl >= 0).then(|| KeyStatus {
+                    key,
+                    count: counts.first().copied().unwrap_or_default(),
+                    ttl: Duration::from_millis(ttl as u64),
+                })
+            })
+            .collect())
+    }
+}
+
+impl KeyStatus {
+    /// Returns true if the key was banned with [RedisBackend::ban].
+    pub fn is_banned(&self) -> bool {
+        self.count == BITFIELD_MAX
+    }
+}
+
+fn escape_pattern(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
 }
 
 pub struct Builder {
     connection: ConnectionManager,
     key_prefix: Option<String>,
+    cache: Option<Arc<ClientSideCache>>,
+    coalescer: Option<Arc<Coalescer>>,
+    denied: Option<Arc<DeniedCache>>,
+    overrides: Option<Arc<OverrideCache>>,
+    alignment: Option<WindowAlignment>,
 }
 
 impl Builder {
@@ -78,71 +434,316 @@ impl Builder {
     ///
     /// This may be useful when the Redis instance is being used for other purposes; the prefix is
     /// used as a 'namespace' to avoid collision with other caches or keys inside Redis.
+    ///
+    /// The backend's own data (e.g. the limit overrides) is stored under keys starting with `__`
+    /// within the prefix, so rate limit keys starting with `_` are stored with an additional `_~`
+    /// prefix to avoid colliding with them.
     pub fn key_prefix(mut self, key_prefix: Option<&str>) -> Self {
         self.key_prefix = key_prefix.map(ToOwned::to_owned);
         self
     }
 
+    /// Batch concurrent requests for the same key that arrive within the `window` (e.g. 1ms) into
+    /// a single increment, greatly reducing the number of Redis operations when a key receives
+    /// many concurrent requests, e.g. under attack traffic.
+    ///
+    /// Each request is still given the same decision as if it had been sent individually, but
+    /// incurs up to `window` of additional latency. If the requests in a batch have different
+    /// intervals, the interval of the first request is used.
+    ///
+    /// This only applies to [Backend::request], and requests counted locally by
+    /// [client side caching](RedisBackend::builder_with_client_side_caching) are not delayed.
+    pub fn coalesce(mut self, window: Duration) -> Self {
+        self.coalescer = Some(Coalescer::new(window));
+        self
+    }
+
+    /// Remember up to `max_keys` keys that are over their limit, and deny further requests for
+    /// them locally until they reset, avoiding a round trip to Redis for every request from a
+    /// blocked client.
+    ///
+    /// Locally denied requests are not counted in Redis. Keys modified through this backend
+    /// (e.g. [SimpleBackend::remove_key]) are forgotten immediately, but if a key is reset by
+    /// another client (or another process) it will still be denied locally until the original
+    /// reset time.
+    pub fn cache_denied_keys(mut self, max_keys: usize) -> Self {
+        self.denied = Some(Arc::new(DeniedCache::new(max_keys)));
+        self
+    }
+
+    /// Apply the limit overrides set with [RedisBackend::set_limit_override], so that individual
+    /// keys can be given custom limits without a deploy.
+    ///
+    /// The overrides are stored in the `__overrides` hash (within the key prefix), and a
+    /// local copy is used to apply them, which is refreshed every `refresh_interval`. Overrides
+    /// set through this backend apply immediately, but overrides set by other processes can take
+    /// up to `refresh_interval` to apply.
+    pub fn with_limit_overrides(mut self, refresh_interval: Duration) -> Self {
+        self.overrides = Some(Arc::new(OverrideCache::new(refresh_interval)));
+        self
+    }
+
+    /// Align the windows of every key to fixed boundaries, e.g. with [WindowAlignment::utc] a
+    /// daily limit always resets at midnight UTC, as required by "X per day" quotas, instead of
+    /// 24 hours after the key's first request.
+    ///
+    /// The expiry of each key is calculated from the clock of the process that first counts it,
+    /// so every process sharing the Redis instance should use the same [WindowAlignment::Utc]
+    /// alignment, and have a synchronized clock.
+    ///
+    /// By default each key's window starts at its first request.
+    pub fn with_alignment(mut self, alignment: WindowAlignment) -> Self {
+        self.alignment = Some(alignment);
+        self
+    }
+
     pub fn build(self) -> RedisBackend {
         RedisBackend {
             connection: self.connection,
             key_prefix: self.key_prefix,
+            cache: self.cache,
+            coalescer: self.coalescer,
+            denied: self.denied,
+            overrides: self.overrides,
+            timeline: self.alignment.map(Timeline::new),
         }
     }
 }
 
+impl RedisBackend {
+    /// Increment the count of the (prefixed) key by `amount`, returning the new count.
+    async fn increment(
+        &self,
+        key: &str,
+        input: &SimpleInput,
+        amount: u64,
+    ) -> Result<(u64, SimpleOutput), Error> {
+        let (count, ttl) = self.increment_count(key, input.interval, amount).await?;
+        Ok((count, make_output(input, count, ttl)))
+    }
+
+    /// Increment the count of the (prefixed) key by `amount`, returning the new count and the
+    /// time-to-live of the key.
+    async fn increment_count(
+        &self,
+        key: &str,
+        interval: Duration,
+        amount: u64,
+    ) -> Result<(u64, Duration), Error> {
+        let mut con = self.connection.clone();
+        let mut pipe = increment_pipeline(key, self.window_ttl(interval), amount);
+        // Return time-to-live of key
+        pipe.cmd("PTTL").arg(key);
+
+        let (counts, ttl): (Vec<u64>, i64) = pipe.query_async(&mut con).await?;
+        if ttl < 0 {
+            return Err(Error::NegativeTtl);
+        }
+        let count = *counts.first().expect("BITFIELD should return one value");
+        Ok((count, Duration::from_millis(ttl as u64)))
+    }
+
+    /// Enables client-side caching on the connection, if it hasn't been already.
+    async fn enable_tracking(
+        &self,
+        cache: &ClientSideCache,
+        con: &mut ConnectionManager,
+    ) -> Result<(), Error> {
+        if !cache.is_tracking() {
+            redis::cmd("CLIENT")
+                .arg("TRACKING")
+                .arg("ON")
+                .arg("NOLOOP")
+                .query_async::<()>(con)
+                .await?;
+            cache.set_tracking();
+        }
+        Ok(())
+    }
+}
+
+/// The wall clock time of a cached reset [Instant].
+fn system_time(instant: Instant) -> SystemTime {
+    SystemTime::now() + instant.saturating_duration_since(Instant::now())
+}
+
+/// A duration in milliseconds for `PEXPIRE`, which must be at least 1ms since a non-positive
+/// expiry deletes the key.
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis())
+        .unwrap_or(u64::MAX)
+        .max(1)
+}
+
+fn make_output(input: &SimpleInput, count: u64, ttl: Duration) -> SimpleOutput {
+    SimpleOutput {
+        limit: input.max_requests,
+        remaining: input.max_requests.saturating_sub(count),
+        reset: Instant::now() + ttl,
+        reset_at: Some(SystemTime::now() + ttl),
+    }
+}
+
 impl Backend<SimpleInput> for RedisBackend {
     type Output = SimpleOutput;
-    type RollbackToken = String;
+    /// The rate limit key, or None if the request was denied by the
+    /// [denied keys cache](Builder::cache_denied_keys) without being counted in Redis.
+    type RollbackToken = Option<Arc<str>>;
     type Error = Error;
 
     async fn request(
         &self,
         input: SimpleInput,
     ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        let input = self.apply_override(input).await?;
         let key = self.make_key(&input.key);
+        let mut con = self.connection.clone();
 
-        let mut pipe = redis::pipe();
-        pipe.atomic()
-            // Increment the rate limit count
-            .cmd("BITFIELD")
-            .arg(key.as_ref())
-            .arg("OVERFLOW")
-            .arg("SAT")
-            .arg("INCRBY")
-            .arg(BITFIELD_ENCODING)
-            .arg(BITFIELD_OFFSET)
-            .arg(1)
-            .arg("GET")
-            .arg(BITFIELD_ENCODING)
-            .arg(BITFIELD_OFFSET)
-            // Set the key to expire (only if it doesn't already have an expiry)
-            .cmd("EXPIRE")
-            .arg(key.as_ref())
-            .arg(input.interval.as_secs())
-            .arg("NX")
-            .ignore()
-            // Return time-to-live of key
-            .cmd("TTL")
-            .arg(key.as_ref());
+        if let Some(denied) = &self.denied {
+            if let Some(reset) = denied.get(&key, input.max_requests) {
+                let output = SimpleOutput {
+                    limit: input.max_requests,
+                    remaining: 0,
+                    reset,
+                    reset_at: Some(system_time(reset)),
+                };
+                return Ok((Decision::Denied, output, None));
+            }
+        }
 
-        let mut con = self.connection.clone();
-        let (counts, ttl): (Vec<u64>, i64) = pipe.query_async(&mut con).await?;
-        if ttl < 0 {
-            return Err(Error::NegativeTtl);
+        if let Some(cache) = &self.cache {
+            self.enable_tracking(cache, &mut con).await?;
+            if let Some((count, reset)) = cache.try_increment(&key, input.max_requests) {
+                // Send the increment to Redis in the background
+                let pipe = increment_pipeline(&key, self.window_ttl(input.interval), 1);
+                let cache = cache.clone();
+                let key = key.into_owned();
+                tokio::spawn(async move {
+                    if let Err(e) = pipe.query_async::<()>(&mut con).await {
+                        log::warn!("Unable to increment rate limit count for cached key: {e}");
+                        cache.remove(&key);
+                    }
+                });
+                let output = SimpleOutput {
+                    limit: input.max_requests,
+                    remaining: input.max_requests.saturating_sub(count),
+                    reset,
+                    reset_at: Some(system_time(reset)),
+                };
+                return Ok((Decision::Allowed, output, Some(input.key)));
+            }
         }
-        let count = *counts.first().expect("BITFIELD should return one value");
 
+        let (count, output) = match &self.coalescer {
+            Some(coalescer) => {
+                let (count, flush) = coalescer.increment(self, &key, input.interval).await?;
+                if let Some(cache) = &self.cache {
+                    cache.insert(&key, flush.count, Instant::now() + flush.ttl);
+                }
+                (count, make_output(&input, count, flush.ttl))
+            }
+            None => {
+                let (count, output) = self.increment(&key, &input, 1).await?;
+                if let Some(cache) = &self.cache {
+                    cache.insert(&key, count, output.reset);
+                }
+                (count, output)
+            }
+        };
         let allow = count <= input.max_requests;
-        let output = SimpleOutput {
-            limit: input.max_requests,
-            remaining: input.max_requests.saturating_sub(count),
-            reset: Instant::now() + Duration::from_secs(ttl as u64),
+        if let (Some(denied), false) = (&self.denied, allow) {
+            denied.insert(&key, count, output.reset);
+        }
+        Ok((Decision::from_allowed(allow), output, Some(input.key)))
+    }
+
+    /// Increments the counts of every input in a single atomic pipeline, so that e.g. a
+    /// [MultiPolicyBackend](crate::backend::multi::MultiPolicyBackend) only needs one round trip
+    /// to Redis.
+    ///
+    /// Keys in the [denied keys cache](Builder::cache_denied_keys) are still denied locally, but
+    /// the requests are not [coalesced](Builder::coalesce) or counted against the client-side
+    /// cache.
+    async fn request_batch(
+        &self,
+        inputs: Vec<SimpleInput>,
+    ) -> Result<Vec<(Decision, Self::Output, Self::RollbackToken)>, Self::Error> {
+        let mut con = self.connection.clone();
+        if let Some(cache) = &self.cache {
+            self.enable_tracking(cache, &mut con).await?;
+        }
+        let mut overridden = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            overridden.push(self.apply_override(input).await?);
+        }
+        let inputs = overridden;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        let mut pending = 0;
+        let mut denied_resets = Vec::with_capacity(inputs.len());
+        for input in &inputs {
+            let key = self.make_key(&input.key);
+            let reset = self
+                .denied
+                .as_ref()
+                .and_then(|denied| denied.get(&key, input.max_requests));
+            if reset.is_none() {
+                add_increment(&mut pipe, &key, self.window_ttl(input.interval), 1);
+                // Return time-to-live of key
+                pipe.cmd("PTTL").arg(key.as_ref());
+                pending += 1;
+            }
+            denied_resets.push(reset);
+        }
+        let values: Vec<redis::Value> = if pending > 0 {
+            pipe.query_async(&mut con).await?
+        } else {
+            Vec::new()
         };
-        Ok((Decision::from_allowed(allow), output, input.key))
+
+        let mut values = values.chunks_exact(2);
+        let mut results = Vec::with_capacity(inputs.len());
+        for (input, reset) in inputs.into_iter().zip(denied_resets) {
+            if let Some(reset) = reset {
+                let output = SimpleOutput {
+                    limit: input.max_requests,
+                    remaining: 0,
+                    reset,
+                    reset_at: Some(system_time(reset)),
+                };
+                results.push((Decision::Denied, output, None));
+                continue;
+            }
+            let Some([counts, ttl]) = values.next() else {
+                unreachable!("Pipeline should return two values per key")
+            };
+            let counts: Vec<u64> = redis::from_redis_value(counts)?;
+            let ttl: i64 = redis::from_redis_value(ttl)?;
+            if ttl < 0 {
+                return Err(Error::NegativeTtl);
+            }
+            let count = *counts.first().expect("BITFIELD should return one value");
+            let output = make_output(&input, count, Duration::from_millis(ttl as u64));
+
+            let key = self.make_key(&input.key);
+            if let Some(cache) = &self.cache {
+                cache.insert(&key, count, output.reset);
+            }
+            let allow = count <= input.max_requests;
+            if let (Some(denied), false) = (&self.denied, allow) {
+                denied.insert(&key, count, output.reset);
+            }
+            results.push((Decision::from_allowed(allow), output, Some(input.key)));
+        }
+        Ok(results)
     }
 
     async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        // Nothing was counted for locally denied requests
+        let Some(token) = token else {
+            return Ok(());
+        };
         let key = self.make_key(&token);
 
         let mut con = self.connection.clone();
@@ -159,14 +760,43 @@ impl Backend<SimpleInput> for RedisBackend {
             .arg(BITFIELD_OFFSET)
             .arg(-1)
             // Set the key to expire immediately, if it doesn't already have an expiry
-            .cmd("EXPIRE")
+            .cmd("PEXPIRE")
             .arg(key.as_ref())
             .arg(0)
             .arg("NX")
             .ignore();
 
-        pipe.query_async(&mut con).await?;
+        pipe.query_async::<()>(&mut con).await?;
+
+        if let Some(cache) = &self.cache {
+            cache.decrement(&key);
+        }
+        if let Some(denied) = &self.denied {
+            denied.remove(&key);
+        }
+        Ok(())
+    }
+
+    async fn peek(&self, input: SimpleInput) -> Result<(Decision, Self::Output), Self::Error> {
+        let input = self.apply_override(input).await?;
+        let (count, ttl) = match self.status(&input.key).await? {
+            Some(status) => (status.count.saturating_add(1), status.ttl),
+            None => (1, self.window_ttl(input.interval)),
+        };
+        let allow = count <= input.max_requests;
+        let output = SimpleOutput {
+            limit: input.max_requests,
+            remaining: input.max_requests.saturating_sub(count),
+            reset: Instant::now() + ttl,
+            reset_at: Some(SystemTime::now() + ttl),
+        };
+        Ok((Decision::from_allowed(allow), output))
+    }
 
+    /// Sends a `PING` to Redis.
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        let mut con = self.connection.clone();
+        redis::cmd("PING").query_async::<()>(&mut con).await?;
         Ok(())
     }
 }
@@ -177,9 +807,121 @@ impl SimpleBackend for RedisBackend {
     async fn remove_key(&self, key: &str) -> Result<(), Self::Error> {
         let key = self.make_key(key);
         let mut con = self.connection.clone();
-        con.del(key.as_ref()).await?;
+        con.del::<_, ()>(key.as_ref()).await?;
+        self.forget(&key);
         Ok(())
     }
+
+    /// Note that the key prefix (if set) is automatically included, and is excluded from the
+    /// returned keys.
+    ///
+    /// This uses `SCAN`, so should be used sparingly on large instances.
+    async fn list(&self, prefix: &str) -> Result<Vec<KeyStatus>, Self::Error> {
+        let keys = self.scan(prefix).await?;
+        Ok(self.statuses(keys).await?.into_iter().flatten().collect())
+    }
+
+    /// Note that the key prefix (if set) is automatically included, so an empty prefix only
+    /// removes the keys belonging to this backend.
+    ///
+    /// This uses `SCAN`, so keys created while clearing may not be removed.
+    async fn clear(&self, prefix: &str) -> Result<(), Self::Error> {
+        let keys = self.scan(prefix).await?;
+        let mut con = self.connection.clone();
+        for chunk in keys.chunks(1000) {
+            let chunk: Vec<String> = chunk
+                .iter()
+                .map(|k| self.make_key(k).into_owned())
+                .collect();
+            con.del::<_, ()>(&chunk).await?;
+            for key in &chunk {
+                self.forget(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Note that the key prefix (if set) is automatically included, you do not need to prepend
+    /// it yourself.
+    ///
+    /// If the windows are aligned, the current window of an existing key is kept, otherwise the
+    /// key resets after `ttl`.
+    async fn set_count(&self, key: &str, count: u64, ttl: Duration) -> Result<(), Self::Error> {
+        let key = self.make_key(key);
+        let mut con = self.connection.clone();
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .cmd("BITFIELD")
+            .arg(key.as_ref())
+            .arg("SET")
+            .arg(BITFIELD_ENCODING)
+            .arg(BITFIELD_OFFSET)
+            .arg(count.min(BITFIELD_MAX))
+            .ignore()
+            .cmd("PEXPIRE")
+            .arg(key.as_ref())
+            .arg(millis(self.window_ttl(ttl.min(MAX_TTL))));
+        if self.timeline.is_some() {
+            // Keep the current window of an existing key
+            pipe.arg("NX");
+        }
+        pipe.ignore().query_async::<()>(&mut con).await?;
+        self.forget(&key);
+        Ok(())
+    }
+
+    /// Note that the key prefix (if set) is automatically included, you do not need to prepend
+    /// it yourself.
+    async fn get(&self, key: &str) -> Result<Option<KeyStatus>, Self::Error> {
+        self.status(key).await
+    }
+
+    async fn consume(
+        &self,
+        input: SimpleInput,
+        cost: u64,
+    ) -> Result<(Decision, SimpleOutput), Self::Error> {
+        let input = self.apply_override(input).await?;
+        let key = self.make_key(&input.key);
+        let (count, output) = self.increment(&key, &input, cost.max(1)).await?;
+        if let Some(cache) = &self.cache {
+            // Our own writes aren't invalidated (NOLOOP), so drop any stale cached count
+            cache.remove(&key);
+        }
+        Ok((Decision::from_allowed(count <= input.max_requests), output))
+    }
+}
+
+/// Builds a pipeline that increments the rate limit count by `amount`, returning the new count.
+fn increment_pipeline(key: &str, ttl: Duration, amount: u64) -> Pipeline {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    add_increment(&mut pipe, key, ttl, amount);
+    pipe
+}
+
+/// Adds the commands to increment the rate limit count by `amount` to a pipeline, returning the
+/// new count.
+fn add_increment(pipe: &mut Pipeline, key: &str, ttl: Duration, amount: u64) {
+    pipe
+        // Increment the rate limit count
+        .cmd("BITFIELD")
+        .arg(key)
+        .arg("OVERFLOW")
+        .arg("SAT")
+        .arg("INCRBY")
+        .arg(BITFIELD_ENCODING)
+        .arg(BITFIELD_OFFSET)
+        .arg(i64::try_from(amount).unwrap_or(i64::MAX))
+        .arg("GET")
+        .arg(BITFIELD_ENCODING)
+        .arg(BITFIELD_OFFSET)
+        // Set the key to expire (only if it doesn't already have an expiry)
+        .cmd("PEXPIRE")
+        .arg(key)
+        .arg(millis(ttl))
+        .arg("NX")
+        .ignore();
 }
 
 #[cfg(test)]
@@ -187,6 +929,7 @@ mod tests {
     use super::*;
     use crate::HeaderCompatibleOutput;
     use redis::Cmd;
+    use std::time::UNIX_EPOCH;
 
     const MINUTE: Duration = Duration::from_secs(60);
 
@@ -207,7 +950,7 @@ mod tests {
         let input = SimpleInput {
             interval: MINUTE,
             max_requests: 5,
-            key: "test_allow_deny".to_string(),
+            key: "test_allow_deny".into(),
         };
         let mut prev_seconds_until_reset = u64::MAX;
         for i in (0..5).rev() {
@@ -238,7 +981,7 @@ mod tests {
         let input = SimpleInput {
             interval: Duration::from_secs(3),
             max_requests: 1,
-            key: "test_reset".to_string(),
+            key: "test_reset".into(),
         };
         // Make first request, should be allowed
         let (decision, _, _) = backend.request(input.clone()).await.unwrap();
@@ -254,13 +997,56 @@ mod tests {
         assert!(decision.is_allowed());
     }
 
+    #[actix_web::test]
+    async fn test_sub_second_interval() {
+        let backend = make_backend("test_sub_second_interval").await.build();
+        let input = SimpleInput {
+            interval: Duration::from_millis(100),
+            max_requests: 1,
+            key: "test_sub_second_interval".into(),
+        };
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert!(output.reset <= Instant::now() + Duration::from_millis(100));
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let (decision, _, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+    }
+
+    #[actix_web::test]
+    async fn test_alignment() {
+        let backend = make_backend("test_alignment")
+            .await
+            .with_alignment(WindowAlignment::utc())
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 1,
+            key: "test_alignment".into(),
+        };
+        let (_, output, _) = backend.request(input).await.unwrap();
+        // The window should end on the minute
+        let reset_at = output.reset_at.unwrap().duration_since(UNIX_EPOCH).unwrap();
+        let from_minute = reset_at.as_millis() % 60_000;
+        assert!(from_minute.min(60_000 - from_minute) < 1000);
+        // Setting the count keeps the current window
+        backend
+            .set_count("test_alignment", 0, MINUTE * 5)
+            .await
+            .unwrap();
+        let status = backend.get("test_alignment").await.unwrap().unwrap();
+        assert!(status.ttl <= MINUTE);
+    }
+
     #[actix_web::test]
     async fn test_output() {
         let backend = make_backend("test_output").await.build();
         let input = SimpleInput {
             interval: MINUTE,
             max_requests: 2,
-            key: "test_output".to_string(),
+            key: "test_output".into(),
         };
         // First of 2 should be allowed.
         let (decision, output, _) = backend.request(input.clone()).await.unwrap();
@@ -290,7 +1076,7 @@ mod tests {
         let input = SimpleInput {
             interval: MINUTE,
             max_requests: 5,
-            key: "test_rollback".to_string(),
+            key: "test_rollback".into(),
         };
         let (_, output, rollback) = backend.request(input.clone()).await.unwrap();
         assert_eq!(output.remaining, 4);
@@ -308,7 +1094,7 @@ mod tests {
         let backend = make_backend(key).await.build();
         let mut con = backend.connection.clone();
         // The rollback could happen after the key has already expired / gone
-        backend.rollback(key.to_string()).await.unwrap();
+        backend.rollback(Some(key.into())).await.unwrap();
         // In which case the count should remain at 0 (it must not become negative)
         let mut cmd = Cmd::new();
         cmd.arg("BITFIELD")
@@ -326,7 +1112,7 @@ mod tests {
         let input = SimpleInput {
             interval: MINUTE,
             max_requests: 1,
-            key: "test_remove_key".to_string(),
+            key: "test_remove_key".into(),
         };
         let (decision, _, _) = backend.request(input.clone()).await.unwrap();
         assert!(decision.is_allowed());
@@ -338,6 +1124,314 @@ mod tests {
         assert!(decision.is_allowed());
     }
 
+    #[actix_web::test]
+    async fn test_consume() {
+        let backend = make_backend("test_consume").await.build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 10,
+            key: "test_consume".into(),
+        };
+        let (decision, output) = backend.consume(input.clone(), 8).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 2);
+        // The cost is charged even though it exceeds the remaining limit
+        let (decision, output) = backend.consume(input.clone(), 3).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.remaining, 0);
+        let (decision, _, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_denied());
+    }
+
+    #[actix_web::test]
+    async fn test_limit_override() {
+        let builder = make_backend("test_limit_override:KEY1").await;
+        let backend = builder
+            .key_prefix(Some("test_limit_override:"))
+            .with_limit_overrides(MINUTE)
+            .build();
+        backend.remove_limit_override("KEY1").await.unwrap();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 1,
+            key: "KEY1".into(),
+        };
+        backend
+            .set_limit_override("KEY1", LimitOverride::Multiplier(2.0))
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.limit_override("KEY1").await.unwrap(),
+            Some(LimitOverride::Multiplier(2.0))
+        );
+        for remaining in [1, 0] {
+            let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+            assert!(decision.is_allowed());
+            assert_eq!(output.limit, 2);
+            assert_eq!(output.remaining, remaining);
+        }
+        // The overrides hash isn't listed as a rate limit key
+        let keys = backend.list("").await.unwrap();
+        assert_eq!(keys.len(), 1);
+
+        // Overrides set by another process are applied after the refresh interval
+        let other = make_backend("test_limit_override:KEY1")
+            .await
+            .key_prefix(Some("test_limit_override:"))
+            .with_limit_overrides(Duration::ZERO)
+            .build();
+        backend
+            .set_limit_override("KEY1", LimitOverride::Limit(3))
+            .await
+            .unwrap();
+        let (decision, output, _) = other.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.limit, 3);
+
+        backend.remove_limit_override("KEY1").await.unwrap();
+        let (decision, output) = backend.peek(input).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.limit, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_request_batch() {
+        let backend = make_backend("test_request_batch_1")
+            .await
+            .cache_denied_keys(10)
+            .build();
+        backend.remove_key("test_request_batch_2").await.unwrap();
+        let inputs = vec![
+            SimpleInput {
+                interval: MINUTE,
+                max_requests: 5,
+                key: "test_request_batch_1".into(),
+            },
+            SimpleInput {
+                interval: MINUTE,
+                max_requests: 1,
+                key: "test_request_batch_2".into(),
+            },
+        ];
+        let results = backend.request_batch(inputs.clone()).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(decision, _, _)| decision.is_allowed()));
+        assert_eq!(results[0].1.remaining(), 4);
+        assert_eq!(results[1].1.remaining(), 0);
+        assert_eq!(results[1].2.as_deref(), Some("test_request_batch_2"));
+
+        let results = backend.request_batch(inputs.clone()).await.unwrap();
+        assert!(results[0].0.is_allowed());
+        assert!(results[1].0.is_denied());
+        // The denied key is now answered from the local cache, and not incremented
+        let results = backend.request_batch(inputs).await.unwrap();
+        assert!(results[1].0.is_denied());
+        assert_eq!(results[1].2, None);
+        let status = backend.get("test_request_batch_2").await.unwrap().unwrap();
+        assert_eq!(status.count, 2);
+        let status = backend.get("test_request_batch_1").await.unwrap().unwrap();
+        assert_eq!(status.count, 3);
+    }
+
+    #[actix_web::test]
+    async fn test_coalesce() {
+        let backend = make_backend("test_coalesce")
+            .await
+            .coalesce(Duration::from_millis(10))
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: "test_coalesce".into(),
+        };
+        let results =
+            futures::future::join_all((0..8).map(|_| backend.request(input.clone()))).await;
+        let mut remaining: Vec<_> = results
+            .into_iter()
+            .map(|result| {
+                let (decision, output, _) = result.unwrap();
+                (decision.is_allowed(), output.remaining)
+            })
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            [
+                (false, 0),
+                (false, 0),
+                (false, 0),
+                (true, 0),
+                (true, 1),
+                (true, 2),
+                (true, 3),
+                (true, 4)
+            ]
+        );
+        assert_eq!(
+            backend
+                .status("test_coalesce")
+                .await
+                .unwrap()
+                .unwrap()
+                .count,
+            8
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_cache_denied_keys() {
+        let backend = make_backend("test_cache_denied_keys")
+            .await
+            .cache_denied_keys(100)
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 1,
+            key: "test_cache_denied_keys".into(),
+        };
+        assert!(backend.request(input.clone()).await.unwrap().0.is_allowed());
+        assert!(backend.request(input.clone()).await.unwrap().0.is_denied());
+        // Denied locally, so isn't counted
+        let (decision, output, token) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        assert!(output.seconds_until_reset() > 0);
+        // So there is nothing to roll back
+        assert_eq!(token, None);
+        backend.rollback(token).await.unwrap();
+        let status = backend.status("test_cache_denied_keys").await.unwrap();
+        assert_eq!(status.unwrap().count, 2);
+        assert!(backend.request(input.clone()).await.unwrap().0.is_denied());
+        // Resetting the key clears the local cache
+        backend.remove_key("test_cache_denied_keys").await.unwrap();
+        assert!(backend.request(input).await.unwrap().0.is_allowed());
+    }
+
+    #[actix_web::test]
+    async fn test_get_and_list() {
+        let backend = make_backend("test_get_and_list")
+            .await
+            .key_prefix(Some("test_get_and_list:"))
+            .build();
+        for key in ["user-1", "user-2", "ip-1"] {
+            backend.remove_key(key).await.unwrap();
+            let input = SimpleInput {
+                interval: MINUTE,
+                max_requests: 5,
+                key: key.into(),
+            };
+            backend.request(input).await.unwrap();
+        }
+        assert!(backend.get("user-3").await.unwrap().is_none());
+        let status = backend.get("user-1").await.unwrap().unwrap();
+        assert_eq!(status.count, 1);
+        assert!(status.ttl <= MINUTE);
+        let mut keys: Vec<_> = backend
+            .list("user-")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["user-1", "user-2"]);
+    }
+
+    #[actix_web::test]
+    async fn test_reserved_keys() {
+        let backend = make_backend("test_reserved_keys")
+            .await
+            .key_prefix(Some("test_reserved_keys:"))
+            .build();
+        backend
+            .set_limit_override("__overrides", LimitOverride::Limit(100))
+            .await
+            .unwrap();
+        for key in ["__overrides", "_~user"] {
+            backend.remove_key(key).await.unwrap();
+            let input = SimpleInput {
+                interval: MINUTE,
+                max_requests: 5,
+                key: key.into(),
+            };
+            backend.request(input).await.unwrap();
+        }
+        let mut keys: Vec<_> = backend
+            .list("_")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["__overrides", "_~user"]);
+        assert_eq!(
+            backend.limit_override("__overrides").await.unwrap(),
+            Some(LimitOverride::Limit(100))
+        );
+        backend.clear("_").await.unwrap();
+        assert!(backend.get("__overrides").await.unwrap().is_none());
+        assert_eq!(
+            backend.limit_override("__overrides").await.unwrap(),
+            Some(LimitOverride::Limit(100))
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_clear() {
+        let backend = make_backend("test_clear")
+            .await
+            .key_prefix(Some("test_clear:"))
+            .build();
+        for key in ["user-1", "user-2", "ip-1"] {
+            let input = SimpleInput {
+                interval: MINUTE,
+                max_requests: 5,
+                key: key.into(),
+            };
+            backend.request(input).await.unwrap();
+        }
+        backend.clear("user-").await.unwrap();
+        let keys: Vec<_> = backend
+            .list("")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.key)
+            .collect();
+        assert_eq!(keys, ["ip-1"]);
+        backend.clear("").await.unwrap();
+        assert!(backend.list("").await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_set_count() {
+        let backend = make_backend("test_set_count").await.build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: "test_set_count".into(),
+        };
+        for _ in 0..5 {
+            backend.request(input.clone()).await.unwrap();
+        }
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        // Grant two more requests in the current window
+        backend
+            .set_count("test_set_count", 3, MINUTE)
+            .await
+            .unwrap();
+        let (decision, output, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_health_check() {
+        let backend = make_backend("test_health_check").await.build();
+        backend.health_check().await.unwrap();
+    }
+
     #[actix_web::test]
     async fn test_key_prefix() {
         let backend = make_backend("prefix:test_key_prefix")
@@ -348,7 +1442,7 @@ mod tests {
         let input = SimpleInput {
             interval: MINUTE,
             max_requests: 5,
-            key: "test_key_prefix".to_string(),
+            key: "test_key_prefix".into(),
         };
         backend.request(input.clone()).await.unwrap();
         assert!(con
@@ -362,4 +1456,96 @@ mod tests {
             .await
             .unwrap());
     }
+
+    #[actix_web::test]
+    async fn test_introspection() {
+        let backend = make_backend("introspection:test_introspection")
+            .await
+            .key_prefix(Some("introspection:"))
+            .build();
+        let key = "test_introspection";
+        assert!(backend.status(key).await.unwrap().is_none());
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: key.into(),
+        };
+        backend.request(input.clone()).await.unwrap();
+        backend.request(input.clone()).await.unwrap();
+        let status = backend.status(key).await.unwrap().unwrap();
+        assert_eq!(status.count, 2);
+        assert!(status.ttl <= MINUTE);
+        let top = backend.top(10).await.unwrap();
+        assert_eq!(&*top[0].key, key);
+
+        backend.ban(key, MINUTE).await.unwrap();
+        assert!(backend.status(key).await.unwrap().unwrap().is_banned());
+        let (decision, _, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_denied());
+    }
+
+    #[test]
+    fn test_escape_pattern() {
+        assert_eq!(escape_pattern("a*b?[c]"), "a\\*b\\?\\[c\\]");
+    }
+
+    #[actix_web::test]
+    async fn test_client_side_caching() {
+        let key = "test_client_side_caching";
+        let host = option_env!("REDIS_HOST").unwrap_or("127.0.0.1");
+        let port = option_env!("REDIS_PORT").unwrap_or("6379");
+        let client = redis::Client::open(format!("redis://{host}:{port}/?protocol=resp3")).unwrap();
+        let backend = RedisBackend::builder_with_client_side_caching(client, 0.5)
+            .await
+            .unwrap()
+            .build();
+        backend.remove_key(key).await.unwrap();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 4,
+            key: key.into(),
+        };
+        for i in (0..4).rev() {
+            let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+            assert!(decision.is_allowed());
+            assert_eq!(output.remaining, i);
+            // Allow time for any background increments to complete
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+
+        // Modifying the key from another client should invalidate the cache
+        let mut con = make_backend(key).await.build().connection;
+        con.del::<_, ()>(key).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (decision, output, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 3);
+    }
+
+    #[test]
+    fn test_error_class() {
+        let class = |e: redis::RedisError| Error::Redis(e).class();
+        assert_eq!(
+            class((ErrorKind::IoError, "Connection reset").into()),
+            BackendError::Transient
+        );
+        assert_eq!(
+            class((ErrorKind::AuthenticationFailed, "Invalid password").into()),
+            BackendError::PermanentMisconfiguration
+        );
+        assert_eq!(
+            class((ErrorKind::CrossSlot, "Keys in different slots").into()),
+            BackendError::KeyRejected
+        );
+        assert_eq!(Error::NegativeTtl.class(), BackendError::KeyRejected);
+        // The class of a coalesced error is preserved, including the error code
+        let wrong_type = redis::parse_redis_value(b"-WRONGTYPE Wrong kind of value\r\n")
+            .unwrap()
+            .extract_error()
+            .unwrap_err();
+        let coalesced = Error::Coalesced(Arc::new(Error::Redis(wrong_type)));
+        assert_eq!(coalesced.class(), BackendError::KeyRejected);
+    }
 }
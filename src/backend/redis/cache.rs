@@ -0,0 +1,176 @@
+use redis::{PushInfo, PushKind, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::Instant;
+
+/// A local cache of rate limit counts, kept coherent using Redis client side caching
+/// (`CLIENT TRACKING`).
+///
+/// Tracking is enabled with `NOLOOP`, so modifications made by this process do not invalidate
+/// its own cache entries; instead the cached counts are updated locally. Any modification by
+/// another client causes the entry to be invalidated.
+pub(super) struct ClientSideCache {
+    max_utilization: f64,
+    entries: Mutex<HashMap<String, Entry>>,
+    tracking: AtomicBool,
+}
+
+struct Entry {
+    count: u64,
+    reset: Instant,
+}
+
+impl ClientSideCache {
+    pub(super) fn new(max_utilization: f64, pushes: UnboundedReceiver<PushInfo>) -> Arc<Self> {
+        assert!(
+            (0f64..=1f64).contains(&max_utilization),
+            "Maximum utilization must be between 0 and 1"
+        );
+        let cache = Arc::new(Self {
+            max_utilization,
+            entries: Default::default(),
+            tracking: AtomicBool::new(false),
+        });
+        tokio::spawn(Self::listen(Arc::downgrade(&cache), pushes));
+        cache
+    }
+
+    async fn listen(cache: std::sync::Weak<Self>, mut pushes: UnboundedReceiver<PushInfo>) {
+        while let Some(push) = pushes.recv().await {
+            let Some(cache) = cache.upgrade() else {
+                return;
+            };
+            match push.kind {
+                PushKind::Invalidate => cache.invalidate(push.data),
+                // Tracking state is lost when the connection is re-established, and any
+                // invalidation messages in the meantime will have been missed.
+                PushKind::Disconnection => {
+                    cache.tracking.store(false, Ordering::Release);
+                    cache.entries.lock().unwrap().clear();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn invalidate(&self, data: Vec<Value>) {
+        let mut entries = self.entries.lock().unwrap();
+        match data.into_iter().next() {
+            Some(Value::Array(keys)) => {
+                for key in keys {
+                    if let Ok(key) = redis::from_redis_value::<String>(&key) {
+                        entries.remove(&key);
+                    }
+                }
+            }
+            // A null value means that the entire database has been flushed
+            _ => entries.clear(),
+        }
+    }
+
+    /// Whether `CLIENT TRACKING` is known to be enabled on the current connection.
+    pub(super) fn is_tracking(&self) -> bool {
+        self.tracking.load(Ordering::Acquire)
+    }
+
+    pub(super) fn set_tracking(&self) {
+        self.tracking.store(true, Ordering::Release);
+    }
+
+    /// Attempt to count the request locally, returning the new count and reset time if the key
+    /// is cached and far enough from its limit.
+    pub(super) fn try_increment(&self, key: &str, max_requests: u64) -> Option<(u64, Instant)> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(key)?;
+        if entry.reset <= Instant::now() {
+            entries.remove(key);
+            return None;
+        }
+        let count = entry.count + 1;
+        if count as f64 > max_requests as f64 * self.max_utilization {
+            return None;
+        }
+        entry.count = count;
+        Some((count, entry.reset))
+    }
+
+    pub(super) fn insert(&self, key: &str, count: u64, reset: Instant) {
+        if self.is_tracking() {
+            self.entries
+                .lock()
+                .unwrap()
+                .insert(key.to_owned(), Entry { count, reset });
+        }
+    }
+
+    pub(super) fn decrement(&self, key: &str) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(key) {
+            entry.count = entry.count.saturating_sub(1);
+        }
+    }
+
+    pub(super) fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[actix_web::test]
+    async fn test_try_increment() {
+        tokio::time::pause();
+        let (_tx, rx) = unbounded_channel();
+        let cache = ClientSideCache::new(0.5, rx);
+        let reset = Instant::now() + Duration::from_secs(60);
+        // Not cached
+        assert!(cache.try_increment("KEY1", 10).is_none());
+        // Entries are only stored once tracking has been enabled
+        cache.insert("KEY1", 1, reset);
+        assert!(cache.try_increment("KEY1", 10).is_none());
+        cache.set_tracking();
+        cache.insert("KEY1", 1, reset);
+        for count in 2..=5 {
+            assert_eq!(cache.try_increment("KEY1", 10), Some((count, reset)));
+        }
+        // Above 50% utilization requests must go to Redis
+        assert!(cache.try_increment("KEY1", 10).is_none());
+        cache.decrement("KEY1");
+        assert_eq!(cache.try_increment("KEY1", 10), Some((5, reset)));
+        // Entries are not used after they have expired
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(cache.try_increment("KEY1", 10).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_invalidation() {
+        let (tx, rx) = unbounded_channel();
+        let cache = ClientSideCache::new(1.0, rx);
+        cache.set_tracking();
+        let reset = Instant::now() + Duration::from_secs(60);
+        cache.insert("KEY1", 1, reset);
+        cache.insert("KEY2", 1, reset);
+        tx.send(PushInfo {
+            kind: PushKind::Invalidate,
+            data: vec![Value::Array(vec![Value::BulkString(b"KEY1".to_vec())])],
+        })
+        .unwrap();
+        tokio::task::yield_now().await;
+        assert!(cache.try_increment("KEY1", 10).is_none());
+        assert!(cache.try_increment("KEY2", 10).is_some());
+        // Disconnection clears the cache, and requires tracking to be enabled again
+        tx.send(PushInfo {
+            kind: PushKind::Disconnection,
+            data: vec![],
+        })
+        .unwrap();
+        tokio::task::yield_now().await;
+        assert!(!cache.is_tracking());
+        assert!(cache.try_increment("KEY2", 10).is_none());
+    }
+}
@@ -0,0 +1,117 @@
+use super::{Error, RedisBackend};
+use redis::{ErrorKind, RedisError};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Batches concurrent increments of the same key into a single Redis pipeline.
+///
+/// The first request for a key starts a batch, which is flushed after the window using one
+/// `INCRBY` for every request that joined it. Each request is then given the count it would have
+/// seen had it been sent individually, according to the order it joined the batch.
+pub(super) struct Coalescer {
+    window: Duration,
+    batches: Mutex<HashMap<String, Batch>>,
+}
+
+struct Batch {
+    requests: u64,
+    receiver: watch::Receiver<Option<Flushed>>,
+}
+
+type Flushed = Result<Flush, Arc<Error>>;
+
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Flush {
+    /// The count after the whole batch was applied.
+    pub(super) count: u64,
+    pub(super) requests: u64,
+    pub(super) ttl: Duration,
+}
+
+impl Coalescer {
+    pub(super) fn new(window: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            window,
+            batches: Default::default(),
+        })
+    }
+
+    /// Increment the count of the (prefixed) key as part of a batch, returning the count for
+    /// this request, and the result of the whole batch.
+    pub(super) async fn increment(
+        self: &Arc<Self>,
+        backend: &RedisBackend,
+        key: &str,
+        interval: Duration,
+    ) -> Result<(u64, Flush), Error> {
+        let (position, mut receiver) = {
+            let mut batches = self.batches.lock().unwrap();
+            match batches.get_mut(key) {
+                Some(batch) => {
+                    batch.requests += 1;
+                    (batch.requests, batch.receiver.clone())
+                }
+                None => {
+                    let (sender, receiver) = watch::channel(None);
+                    batches.insert(
+                        key.to_owned(),
+                        Batch {
+                            requests: 1,
+                            receiver: receiver.clone(),
+                        },
+                    );
+                    // The flush is spawned so that it still happens if this request is cancelled
+                    tokio::spawn(self.clone().flush(
+                        backend.clone(),
+                        key.to_owned(),
+                        interval,
+                        sender,
+                    ));
+                    (1, receiver)
+                }
+            }
+        };
+        let flushed = receiver.wait_for(Option::is_some).await.map_err(|_| {
+            RedisError::from((ErrorKind::IoError, "Coalesced increment was cancelled"))
+        })?;
+        match flushed.as_ref().expect("Batch should be flushed") {
+            Ok(flush) => {
+                let count = flush
+                    .count
+                    .saturating_sub(flush.requests)
+                    .saturating_add(position);
+                Ok((count, *flush))
+            }
+            // Every request in the batch receives the error, but a RedisError can't be cloned
+            Err(e) => Err(Error::Coalesced(e.clone())),
+        }
+    }
+
+    async fn flush(
+        self: Arc<Self>,
+        backend: RedisBackend,
+        key: String,
+        interval: Duration,
+        sender: watch::Sender<Option<Flushed>>,
+    ) {
+        tokio::time::sleep(self.window).await;
+        let requests = self
+            .batches
+            .lock()
+            .unwrap()
+            .remove(&key)
+            .map_or(1, |batch| batch.requests);
+        let result = backend
+            .increment_count(&key, interval, requests)
+            .await
+            .map(|(count, ttl)| Flush {
+                count,
+                requests,
+                ttl,
+            })
+            .map_err(Arc::new);
+        let _ = sender.send(Some(result));
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::time::Instant;
+
+/// A local cache of keys that are known to be over their limit, so that further requests can be
+/// denied without a round trip to Redis.
+pub(super) struct DeniedCache {
+    max_keys: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+struct Entry {
+    count: u64,
+    reset: Instant,
+}
+
+impl DeniedCache {
+    pub(super) fn new(max_keys: usize) -> Self {
+        Self {
+            max_keys,
+            entries: Default::default(),
+        }
+    }
+
+    /// Returns the reset time if the key is known to be over the limit.
+    pub(super) fn get(&self, key: &str, max_requests: u64) -> Option<Instant> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        if entry.reset <= Instant::now() {
+            entries.remove(key);
+            return None;
+        }
+        // The limit may have been raised since the key was denied
+        (entry.count > max_requests).then_some(entry.reset)
+    }
+
+    pub(super) fn insert(&self, key: &str, count: u64, reset: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_keys && !entries.contains_key(key) {
+            let now = Instant::now();
+            entries.retain(|_, entry| entry.reset > now);
+            if entries.len() >= self.max_keys {
+                return;
+            }
+        }
+        entries.insert(key.to_owned(), Entry { count, reset });
+    }
+
+    pub(super) fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[actix_web::test]
+    async fn test_denied_cache() {
+        tokio::time::pause();
+        let cache = DeniedCache::new(1);
+        let reset = Instant::now() + Duration::from_secs(60);
+        assert!(cache.get("KEY1", 5).is_none());
+        cache.insert("KEY1", 6, reset);
+        assert_eq!(cache.get("KEY1", 5), Some(reset));
+        // Not denied if the limit has been raised
+        assert!(cache.get("KEY1", 6).is_none());
+        // Full
+        cache.insert("KEY2", 6, reset);
+        assert!(cache.get("KEY2", 5).is_none());
+        cache.remove("KEY1");
+        assert!(cache.get("KEY1", 5).is_none());
+        // Entries are not used after they have expired
+        cache.insert("KEY2", 6, reset);
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(cache.get("KEY2", 5).is_none());
+    }
+}
@@ -0,0 +1,139 @@
+use crate::backend::LimitOverride;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A local copy of the limit overrides hash, refreshed periodically so that requests don't need
+/// a round trip to Redis to look up the override for their key.
+pub(super) struct OverrideCache {
+    refresh_interval: Duration,
+    state: RwLock<State>,
+    refreshing: AtomicBool,
+}
+
+#[derive(Default)]
+struct State {
+    fetched: Option<Instant>,
+    overrides: HashMap<String, LimitOverride>,
+}
+
+impl OverrideCache {
+    pub(super) fn new(refresh_interval: Duration) -> Self {
+        Self {
+            refresh_interval,
+            state: Default::default(),
+            refreshing: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns true if the caller should fetch the overrides and call [OverrideCache::replace].
+    ///
+    /// Until the overrides have been fetched once every caller must fetch them, after which only
+    /// one caller at a time refreshes them, while the others use the stale overrides.
+    pub(super) fn needs_refresh(&self) -> bool {
+        match self.state.read().unwrap().fetched {
+            None => true,
+            Some(fetched) => {
+                fetched.elapsed() >= self.refresh_interval
+                    && !self.refreshing.swap(true, Ordering::AcqRel)
+            }
+        }
+    }
+
+    /// Replaces the overrides with the contents of the hash.
+    pub(super) fn replace(&self, values: HashMap<String, String>) {
+        let overrides = values
+            .into_iter()
+            .filter_map(|(key, value)| match decode(&value) {
+                Some(limit) => Some((key, limit)),
+                None => {
+                    log::warn!("Ignoring invalid limit override for key {key}: {value}");
+                    None
+                }
+            })
+            .collect();
+        *self.state.write().unwrap() = State {
+            fetched: Some(Instant::now()),
+            overrides,
+        };
+        self.refreshing.store(false, Ordering::Release);
+    }
+
+    /// Allows the next caller to retry the refresh.
+    pub(super) fn refresh_failed(&self) {
+        self.refreshing.store(false, Ordering::Release);
+    }
+
+    pub(super) fn get(&self, key: &str) -> Option<LimitOverride> {
+        self.state.read().unwrap().overrides.get(key).copied()
+    }
+
+    pub(super) fn insert(&self, key: &str, limit: LimitOverride) {
+        let mut state = self.state.write().unwrap();
+        state.overrides.insert(key.to_owned(), limit);
+    }
+
+    pub(super) fn remove(&self, key: &str) {
+        self.state.write().unwrap().overrides.remove(key);
+    }
+}
+
+/// Encodes an override as a hash value, e.g. `500` for a limit of 500, or `x10` for 10x.
+pub(super) fn encode(limit: LimitOverride) -> String {
+    match limit {
+        LimitOverride::Limit(limit) => limit.to_string(),
+        LimitOverride::Multiplier(multiplier) => format!("x{multiplier}"),
+    }
+}
+
+pub(super) fn decode(value: &str) -> Option<LimitOverride> {
+    match value.strip_prefix('x') {
+        Some(multiplier) => multiplier
+            .parse()
+            .ok()
+            .filter(|m: &f64| m.is_finite())
+            .map(LimitOverride::Multiplier),
+        None => value.parse().ok().map(LimitOverride::Limit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding() {
+        for limit in [
+            LimitOverride::Limit(500),
+            LimitOverride::Multiplier(10.0),
+            LimitOverride::Multiplier(0.25),
+        ] {
+            assert_eq!(decode(&encode(limit)), Some(limit));
+        }
+        assert_eq!(encode(LimitOverride::Multiplier(10.0)), "x10");
+        assert_eq!(decode("x"), None);
+        assert_eq!(decode("xinf"), None);
+        assert_eq!(decode("-1"), None);
+    }
+
+    #[test]
+    fn test_refresh() {
+        let cache = OverrideCache::new(Duration::ZERO);
+        // Every caller must fetch until the first refresh
+        assert!(cache.needs_refresh());
+        assert!(cache.needs_refresh());
+        cache.replace(HashMap::from([
+            ("KEY1".to_owned(), "x2".to_owned()),
+            ("KEY2".to_owned(), "invalid".to_owned()),
+        ]));
+        assert_eq!(cache.get("KEY1"), Some(LimitOverride::Multiplier(2.0)));
+        assert_eq!(cache.get("KEY2"), None);
+        // Then only one caller at a time
+        assert!(cache.needs_refresh());
+        assert!(!cache.needs_refresh());
+        cache.refresh_failed();
+        assert!(cache.needs_refresh());
+    }
+}
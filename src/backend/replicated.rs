@@ -0,0 +1,512 @@
+use crate::backend::window::{Timeline, WindowAlignment};
+use crate::backend::{
+    Backend, Decision, KeyStatus, SimpleBackend, SimpleInput, SimpleOutput, MAX_TTL,
+};
+use futures::future::{select, Either};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::pin::pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, oneshot};
+use tokio::time::Instant;
+
+pub const DEFAULT_SYNC_INTERVAL_MILLIS: u64 = 100;
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
+
+/// A group of [ReplicatedInMemoryBackend]s that share their counts with each other.
+///
+/// This should be created once, outside of the `HttpServer::new` factory closure, and then
+/// [ReplicationGroup::backend()] should be called inside the factory to create a backend for each
+/// worker.
+#[derive(Clone)]
+pub struct ReplicationGroup {
+    timeline: Timeline,
+    sender: broadcast::Sender<Arc<Message>>,
+    sync_interval: Duration,
+    next_id: Arc<AtomicUsize>,
+}
+
+/// A Fixed Window rate limiter [Backend] that keeps counts local to each worker, and
+/// periodically broadcasts them to the other workers in the same [ReplicationGroup].
+///
+/// This gives approximately global limits on a multi-worker `HttpServer` without an external
+/// store; counts from other workers may lag behind by up to the sync interval.
+///
+/// Windows are aligned to epochs counted from the creation of the [ReplicationGroup] (or the wall
+/// clock, see [Builder::with_alignment]), so that all workers agree on when each window starts
+/// and ends.
+#[derive(Clone)]
+pub struct ReplicatedInMemoryBackend {
+    id: usize,
+    timeline: Timeline,
+    state: Arc<Mutex<HashMap<Arc<str>, Window>>>,
+}
+
+struct Window {
+    epoch: u128,
+    interval: Duration,
+    // Requests counted by this worker.
+    local: u64,
+    // Requests counted by other workers.
+    remote: u64,
+    // Change to the local count that has not yet been broadcast.
+    pending: i64,
+}
+
+struct Message {
+    sender_id: usize,
+    deltas: Vec<Delta>,
+}
+
+struct Delta {
+    key: Arc<str>,
+    epoch: u128,
+    interval: Duration,
+    count: i64,
+}
+
+/// The [Backend::RollbackToken] for the [ReplicatedInMemoryBackend].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReplicatedRollbackToken {
+    key: Arc<str>,
+    epoch: u128,
+}
+
+impl ReplicatedInMemoryBackend {
+    pub fn builder() -> Builder {
+        Builder {
+            sync_interval: Duration::from_millis(DEFAULT_SYNC_INTERVAL_MILLIS),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            alignment: WindowAlignment::default(),
+        }
+    }
+
+    fn synchronizer(
+        id: usize,
+        state: Weak<Mutex<HashMap<Arc<str>, Window>>>,
+        timeline: Timeline,
+        sender: broadcast::Sender<Arc<Message>>,
+        interval: Duration,
+    ) {
+        let mut receiver = sender.subscribe();
+        let receive_state = state.clone();
+        // Dropped when the broadcasting task stops, since the channel itself stays open while
+        // other workers are running.
+        let (stopped, mut on_stopped) = oneshot::channel::<()>();
+        // Apply counts broadcast by the other workers.
+        tokio::spawn(async move {
+            loop {
+                let message = match select(pin!(receiver.recv()), &mut on_stopped).await {
+                    Either::Left((message, _)) => message,
+                    Either::Right(_) => return,
+                };
+                let message = match message {
+                    Ok(message) => message,
+                    // Counts that were missed are simply lost, as the limits are approximate.
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                };
+                let Some(state) = receive_state.upgrade() else {
+                    return;
+                };
+                if message.sender_id == id {
+                    continue;
+                }
+                let now = Instant::now();
+                let mut state = state.lock().unwrap();
+                for delta in &message.deltas {
+                    // Ignore counts for windows that have already ended
+                    if timeline.epoch(now, delta.interval) > delta.epoch {
+                        continue;
+                    }
+                    let window = state.entry(delta.key.clone()).or_insert_with(|| Window {
+                        epoch: delta.epoch,
+                        interval: delta.interval,
+                        local: 0,
+                        remote: 0,
+                        pending: 0,
+                    });
+                    if window.epoch < delta.epoch {
+                        window.reset(delta.epoch, delta.interval);
+                    }
+                    if window.epoch == delta.epoch {
+                        window.remote = window.remote.saturating_add_signed(delta.count);
+                    }
+                }
+            }
+        });
+        // Periodically broadcast local counts, and remove windows that have ended.
+        tokio::spawn(async move {
+            let _stopped = stopped;
+            loop {
+                let now = Instant::now();
+                tokio::time::sleep_until(now + interval).await;
+                let Some(state) = state.upgrade() else {
+                    return;
+                };
+                let now = Instant::now();
+                let mut deltas = Vec::new();
+                state.lock().unwrap().retain(|key, window| {
+                    if window.pending != 0 {
+                        deltas.push(Delta {
+                            key: key.clone(),
+                            epoch: window.epoch,
+                            interval: window.interval,
+                            count: std::mem::take(&mut window.pending),
+                        });
+                    }
+                    timeline.epoch(now, window.interval) <= window.epoch
+                });
+                if !deltas.is_empty() {
+                    // An error means there are no other receivers to send to.
+                    let _ = sender.send(Arc::new(Message {
+                        sender_id: id,
+                        deltas,
+                    }));
+                }
+            }
+        });
+    }
+}
+
+impl Window {
+    /// The status of the key, if the window is still current.
+    fn status(&self, key: &str, timeline: &Timeline, now: Instant) -> Option<KeyStatus> {
+        if timeline.epoch(now, self.interval) != self.epoch {
+            return None;
+        }
+        let reset = timeline.window_end(self.epoch, self.interval);
+        Some(KeyStatus {
+            key: key.to_owned(),
+            count: self.local + self.remote,
+            ttl: reset.saturating_duration_since(now),
+        })
+    }
+
+    fn reset(&mut self, epoch: u128, interval: Duration) {
+        self.epoch = epoch;
+        self.interval = interval;
+        self.local = 0;
+        self.remote = 0;
+        self.pending = 0;
+    }
+}
+
+impl ReplicationGroup {
+    /// Create a new backend for the current worker.
+    ///
+    /// This spawns tasks on the current Actix runtime to synchronize the counts, which stop once
+    /// the backend and all of its clones have been dropped.
+    pub fn backend(&self) -> ReplicatedInMemoryBackend {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let state = Arc::new(Mutex::new(HashMap::new()));
+        ReplicatedInMemoryBackend::synchronizer(
+            id,
+            Arc::downgrade(&state),
+            self.timeline,
+            self.sender.clone(),
+            self.sync_interval,
+        );
+        ReplicatedInMemoryBackend {
+            id,
+            timeline: self.timeline,
+            state,
+        }
+    }
+}
+
+pub struct Builder {
+    sync_interval: Duration,
+    channel_capacity: usize,
+    alignment: WindowAlignment,
+}
+
+impl Builder {
+    /// Override how often each worker broadcasts its counts to the other workers.
+    ///
+    /// A shorter interval gives more accurate limits, at the cost of more frequent messages.
+    pub fn with_sync_interval(mut self, interval: Duration) -> Self {
+        assert!(
+            interval.as_secs_f64() > 0f64,
+            "Sync interval must be non-zero"
+        );
+        self.sync_interval = interval;
+        self
+    }
+
+    /// Override the capacity of the broadcast channel.
+    ///
+    /// If a worker falls behind by more than this many messages, the oldest counts are lost.
+    pub fn with_channel_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "Channel capacity must be non-zero");
+        self.channel_capacity = capacity;
+        self
+    }
+
+    /// Override where the window boundaries fall.
+    ///
+    /// Defaults to [WindowAlignment::Creation].
+    pub fn with_alignment(mut self, alignment: WindowAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    pub fn build(self) -> ReplicationGroup {
+        let (sender, _) = broadcast::channel(self.channel_capacity);
+        ReplicationGroup {
+            timeline: Timeline::new(self.alignment),
+            sender,
+            sync_interval: self.sync_interval,
+            next_id: Default::default(),
+        }
+    }
+}
+
+impl Backend<SimpleInput> for ReplicatedInMemoryBackend {
+    type Output = SimpleOutput;
+    type RollbackToken = ReplicatedRollbackToken;
+    type Error = Infallible;
+
+    async fn request(
+        &self,
+        input: SimpleInput,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        let epoch = self.timeline.epoch(Instant::now(), input.interval);
+        let count = {
+            let mut state = self.state.lock().unwrap();
+            let window = state.entry(input.key.clone()).or_insert_with(|| Window {
+                epoch,
+                interval: input.interval,
+                local: 0,
+                remote: 0,
+                pending: 0,
+            });
+            if window.epoch != epoch || window.interval != input.interval {
+                window.reset(epoch, input.interval);
+            }
+            window.local += 1;
+            window.pending += 1;
+            window.local + window.remote
+        };
+        let allow = count <= input.max_requests;
+        let output = SimpleOutput {
+            limit: input.max_requests,
+            remaining: input.max_requests.saturating_sub(count),
+            reset: self.timeline.window_end(epoch, input.interval),
+            reset_at: None,
+        };
+        let token = ReplicatedRollbackToken {
+            key: input.key,
+            epoch,
+        };
+        Ok((Decision::from_allowed(allow), output, token))
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(window) = state.get_mut(&token.key) {
+            if window.epoch == token.epoch && window.local > 0 {
+                window.local -= 1;
+                window.pending -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    async fn peek(&self, input: SimpleInput) -> Result<(Decision, Self::Output), Self::Error> {
+        let epoch = self.timeline.epoch(Instant::now(), input.interval);
+        let count = {
+            let state = self.state.lock().unwrap();
+            match state.get(&input.key) {
+                Some(window) if window.epoch == epoch && window.interval == input.interval => {
+                    window.local + window.remote + 1
+                }
+                _ => 1,
+            }
+        };
+        let allow = count <= input.max_requests;
+        let output = SimpleOutput {
+            limit: input.max_requests,
+            remaining: input.max_requests.saturating_sub(count),
+            reset: self.timeline.window_end(epoch, input.interval),
+            reset_at: None,
+        };
+        Ok((Decision::from_allowed(allow), output))
+    }
+}
+
+impl SimpleBackend for ReplicatedInMemoryBackend {
+    /// Note that this only removes the key from the current worker.
+    async fn remove_key(&self, key: &str) -> Result<(), Self::Error> {
+        self.state.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    /// Note that this only removes the keys from the current worker.
+    async fn clear(&self, prefix: &str) -> Result<(), Self::Error> {
+        self.state
+            .lock()
+            .unwrap()
+            .retain(|k, _| !k.starts_with(prefix));
+        Ok(())
+    }
+
+    /// Sets the count for the current window, by adjusting this worker's count (which is then
+    /// broadcast) to account for requests counted by other workers. The `ttl` is only used as the
+    /// interval if the key doesn't already exist, since windows are aligned to fixed epochs.
+    async fn set_count(&self, key: &str, count: u64, ttl: Duration) -> Result<(), Self::Error> {
+        let ttl = ttl.clamp(Duration::from_nanos(1), MAX_TTL);
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let window = state.entry(key.into()).or_insert_with(|| Window {
+            epoch: self.timeline.epoch(now, ttl),
+            interval: ttl,
+            local: 0,
+            remote: 0,
+            pending: 0,
+        });
+        let epoch = self.timeline.epoch(now, window.interval);
+        if window.epoch != epoch {
+            window.reset(epoch, window.interval);
+        }
+        let local = count.saturating_sub(window.remote).min(i64::MAX as u64);
+        window.pending = window
+            .pending
+            .saturating_add(local as i64 - window.local as i64);
+        window.local = local;
+        Ok(())
+    }
+
+    /// Note that the count includes requests from other workers, up to the last broadcast.
+    async fn get(&self, key: &str) -> Result<Option<KeyStatus>, Self::Error> {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .get(key)
+            .and_then(|window| window.status(key, &self.timeline, now)))
+    }
+
+    /// Note that this only lists the keys known to the current worker.
+    async fn list(&self, prefix: &str) -> Result<Vec<KeyStatus>, Self::Error> {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .filter_map(|(key, window)| window.status(key, &self.timeline, now))
+            .collect())
+    }
+}
+
+impl std::fmt::Debug for ReplicatedInMemoryBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplicatedInMemoryBackend")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+    const SYNC: Duration = Duration::from_secs(1);
+
+    // Sleep for long enough that the workers will have broadcast their counts
+    async fn sync() {
+        tokio::time::sleep(SYNC + Duration::from_millis(1)).await;
+    }
+
+    #[actix_web::test]
+    async fn test_allow_deny() {
+        tokio::time::pause();
+        let backend = ReplicatedInMemoryBackend::builder().build().backend();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: "KEY1".into(),
+        };
+        for _ in 0..5 {
+            // First 5 should be allowed
+            let (allow, _, _) = backend.request(input.clone()).await.unwrap();
+            assert!(allow.is_allowed());
+        }
+        // Sixth should be denied
+        let (allow, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(!allow.is_allowed());
+    }
+
+    #[actix_web::test]
+    async fn test_replication() {
+        tokio::time::pause();
+        let group = ReplicatedInMemoryBackend::builder()
+            .with_sync_interval(SYNC)
+            .build();
+        let worker1 = group.backend();
+        let worker2 = group.backend();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 3,
+            key: "KEY1".into(),
+        };
+        worker1.request(input.clone()).await.unwrap();
+        worker1.request(input.clone()).await.unwrap();
+        // The second worker only sees its own request before synchronizing
+        let (_, output, _) = worker2.request(input.clone()).await.unwrap();
+        assert_eq!(output.remaining, 2);
+        sync().await;
+        // Both workers should now agree on the total count
+        let (decision, output, _) = worker1.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.remaining, 0);
+        let (decision, _, _) = worker2.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        // Once the window ends the counts are reset
+        tokio::time::advance(MINUTE).await;
+        let (decision, output, _) = worker2.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 2);
+    }
+
+    #[actix_web::test]
+    async fn test_rollback_is_replicated() {
+        tokio::time::pause();
+        let group = ReplicatedInMemoryBackend::builder()
+            .with_sync_interval(SYNC)
+            .build();
+        let worker1 = group.backend();
+        let worker2 = group.backend();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: "KEY1".into(),
+        };
+        worker1.request(input.clone()).await.unwrap();
+        sync().await;
+        let (_, _, rollback) = worker1.request(input.clone()).await.unwrap();
+        worker1.rollback(rollback).await.unwrap();
+        sync().await;
+        // Only the first request should have been counted
+        let (_, output, _) = worker2.request(input).await.unwrap();
+        assert_eq!(output.remaining, 3);
+    }
+
+    #[actix_web::test]
+    async fn test_tasks_stop_when_dropped() {
+        tokio::time::pause();
+        let group = ReplicatedInMemoryBackend::builder()
+            .with_sync_interval(SYNC)
+            .build();
+        let worker = group.backend();
+        assert_eq!(group.sender.receiver_count(), 1);
+        drop(worker);
+        sync().await;
+        tokio::task::yield_now().await;
+        // The receiver is dropped even though the group keeps the channel open
+        assert_eq!(group.sender.receiver_count(), 0);
+    }
+}
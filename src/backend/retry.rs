@@ -0,0 +1,435 @@
+use crate::backend::{Backend, ClassifyError, Decision, KeyStatus, SimpleBackend};
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_INITIAL_BACKOFF_MILLIS: u64 = 10;
+pub const DEFAULT_MAX_BACKOFF_MILLIS: u64 = 1000;
+
+/// Decides whether a failed [Backend::request()] should be retried.
+pub trait RetryPolicy<E> {
+    fn should_retry(&self, error: &E) -> bool;
+}
+
+impl<E, F> RetryPolicy<E> for F
+where
+    F: Fn(&E) -> bool,
+{
+    fn should_retry(&self, error: &E) -> bool {
+        self(error)
+    }
+}
+
+/// A [RetryPolicy] that retries every error, regardless of its class.
+///
+/// Note a backend error may occur after the request was counted, e.g. a timeout waiting for the
+/// reply, so retrying non-transient errors can count a request more than once.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlwaysRetry;
+
+impl<E> RetryPolicy<E> for AlwaysRetry {
+    fn should_retry(&self, _error: &E) -> bool {
+        true
+    }
+}
+
+/// The default [RetryPolicy], which only retries
+/// [Transient](crate::backend::BackendError::Transient) errors, since retrying a misconfigured
+/// backend or a rejected key can't succeed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryTransient;
+
+impl<E: ClassifyError> RetryPolicy<E> for RetryTransient {
+    fn should_retry(&self, error: &E) -> bool {
+        error.class().is_transient()
+    }
+}
+
+/// A [Backend] decorator that retries failed requests, with exponential backoff and jitter,
+/// before returning the error to the [RateLimiter](crate::RateLimiter).
+///
+/// Rollbacks are passed straight through to the inner backend without being retried.
+pub struct RetryBackend<B, P = RetryTransient> {
+    backend: B,
+    policy: Arc<P>,
+    config: Arc<Config>,
+}
+
+struct Config {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: f64,
+}
+
+impl<B: Clone, P> Clone for RetryBackend<B, P> {
+    fn clone(&self) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            policy: self.policy.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<B> RetryBackend<B> {
+    /// # Arguments
+    ///
+    /// * `backend`: The backend to retry requests against.
+    pub fn builder(backend: B) -> Builder<B> {
+        Builder {
+            backend,
+            policy: RetryTransient,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: Duration::from_millis(DEFAULT_INITIAL_BACKOFF_MILLIS),
+            max_backoff: Duration::from_millis(DEFAULT_MAX_BACKOFF_MILLIS),
+            multiplier: 2f64,
+            jitter: 0.5,
+        }
+    }
+}
+
+impl<B, P> RetryBackend<B, P> {
+    /// Returns the delay before the given retry attempt (starting from zero).
+    fn backoff(&self, retry: u32) -> Duration {
+        let config = &self.config;
+        // Calculated as a float, since the unclamped delay can overflow a Duration
+        let backoff = (config.initial_backoff.as_secs_f64()
+            * config
+                .multiplier
+                .powi(i32::try_from(retry).unwrap_or(i32::MAX)))
+        .min(config.max_backoff.as_secs_f64());
+        // A pseudo-random number in the range [0, 1), which differs for each call so that
+        // concurrent requests don't retry in lockstep (each RandomState has new keys)
+        let random = RandomState::new().hash_one((retry, Instant::now()));
+        let random = (random >> 11) as f64 / (1u64 << 53) as f64;
+        Duration::try_from_secs_f64(backoff * (1f64 - config.jitter * random))
+            .unwrap_or(config.max_backoff)
+    }
+
+    /// Calls the operation until it succeeds, the error should not be retried, or the maximum
+    /// attempts are reached.
+    async fn with_retries<T, E, Fut>(&self, operation: impl Fn() -> Fut) -> Result<T, E>
+    where
+        Fut: Future<Output = Result<T, E>>,
+        P: RetryPolicy<E>,
+    {
+        let mut retry = 0;
+        loop {
+            match operation().await {
+                Err(e) if retry + 1 < self.config.max_attempts && self.policy.should_retry(&e) => {
+                    let backoff = self.backoff(retry);
+                    log::debug!("Rate limiter backend failed, retrying in {backoff:?}");
+                    tokio::time::sleep(backoff).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+pub struct Builder<B, P = RetryTransient> {
+    backend: B,
+    policy: P,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: f64,
+}
+
+impl<B, P> Builder<B, P> {
+    /// Override the maximum number of attempts, including the initial attempt.
+    ///
+    /// Defaults to 3.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        assert!(max_attempts > 0, "Max attempts must be non-zero");
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Override the delay before the first retry, and the maximum delay between retries.
+    ///
+    /// Defaults to 10ms and 1s respectively.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Override the factor the delay is multiplied by after each retry.
+    ///
+    /// Must be at least 1, defaults to 2.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        assert!(multiplier >= 1f64, "Multiplier must be at least 1");
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Override the fraction (0 to 1) of each delay that may be randomly subtracted, to avoid
+    /// many clients retrying in lockstep.
+    ///
+    /// Defaults to 0.5.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        assert!(
+            (0f64..=1f64).contains(&jitter),
+            "Jitter must be between 0 and 1"
+        );
+        self.jitter = jitter;
+        self
+    }
+
+    /// Only retry errors that match the given predicate, or every error using [AlwaysRetry].
+    ///
+    /// By default only transient errors are retried, see [RetryTransient].
+    pub fn retry_if<F>(self, policy: F) -> Builder<B, F> {
+        Builder {
+            backend: self.backend,
+            policy,
+            max_attempts: self.max_attempts,
+            initial_backoff: self.initial_backoff,
+            max_backoff: self.max_backoff,
+            multiplier: self.multiplier,
+            jitter: self.jitter,
+        }
+    }
+
+    pub fn build(self) -> RetryBackend<B, P> {
+        RetryBackend {
+            backend: self.backend,
+            policy: Arc::new(self.policy),
+            config: Arc::new(Config {
+                max_attempts: self.max_attempts,
+                initial_backoff: self.initial_backoff,
+                max_backoff: self.max_backoff,
+                multiplier: self.multiplier,
+                jitter: self.jitter,
+            }),
+        }
+    }
+}
+
+impl<B, I, P> Backend<I> for RetryBackend<B, P>
+where
+    B: Backend<I>,
+    I: Clone + 'static,
+    P: RetryPolicy<B::Error>,
+{
+    type Output = B::Output;
+    type RollbackToken = B::RollbackToken;
+    type Error = B::Error;
+
+    async fn request(
+        &self,
+        input: I,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        self.with_retries(|| self.backend.request(input.clone()))
+            .await
+    }
+
+    async fn request_batch(
+        &self,
+        inputs: Vec<I>,
+    ) -> Result<Vec<(Decision, Self::Output, Self::RollbackToken)>, Self::Error> {
+        self.with_retries(|| self.backend.request_batch(inputs.clone()))
+            .await
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        self.backend.rollback(token).await
+    }
+
+    async fn peek(&self, input: I) -> Result<(Decision, Self::Output), Self::Error> {
+        self.with_retries(|| self.backend.peek(input.clone())).await
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        self.with_retries(|| self.backend.health_check()).await
+    }
+}
+
+impl<B, P> SimpleBackend for RetryBackend<B, P>
+where
+    B: SimpleBackend,
+    P: RetryPolicy<B::Error>,
+{
+    async fn remove_key(&self, key: &str) -> Result<(), Self::Error> {
+        self.backend.remove_key(key).await
+    }
+
+    async fn clear(&self, prefix: &str) -> Result<(), Self::Error> {
+        self.backend.clear(prefix).await
+    }
+
+    async fn set_count(&self, key: &str, count: u64, ttl: Duration) -> Result<(), Self::Error> {
+        self.backend.set_count(key, count, ttl).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<KeyStatus>, Self::Error> {
+        self.backend.get(key).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<KeyStatus>, Self::Error> {
+        self.backend.list(prefix).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::BackendError;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::time::Instant;
+
+    #[derive(Clone, Default)]
+    struct FlakyBackend {
+        calls: Arc<AtomicU32>,
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum FlakyError {
+        Transient,
+        Permanent,
+    }
+
+    impl ClassifyError for FlakyError {
+        fn class(&self) -> BackendError {
+            match self {
+                FlakyError::Transient => BackendError::Transient,
+                FlakyError::Permanent => BackendError::PermanentMisconfiguration,
+            }
+        }
+    }
+
+    /// Input is the number of transient failures before succeeding, or None for a permanent error.
+    impl Backend<Option<u32>> for FlakyBackend {
+        type Output = ();
+        type RollbackToken = ();
+        type Error = FlakyError;
+
+        async fn request(
+            &self,
+            failures: Option<u32>,
+        ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+            let calls = self.calls.fetch_add(1, Ordering::Relaxed);
+            match failures {
+                None => Err(FlakyError::Permanent),
+                Some(failures) if calls < failures => Err(FlakyError::Transient),
+                Some(_) => Ok((Decision::Allowed, (), ())),
+            }
+        }
+
+        async fn rollback(&self, _: Self::RollbackToken) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_retry() {
+        tokio::time::pause();
+        let inner = FlakyBackend::default();
+        let backend = RetryBackend::builder(inner.clone()).build();
+        // Should succeed on the third attempt
+        let start = Instant::now();
+        assert!(backend.request(Some(2)).await.is_ok());
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 3);
+        // Backoff of up to 10ms and then 20ms
+        assert!(start.elapsed() <= Duration::from_millis(30));
+        assert!(start.elapsed() >= Duration::from_millis(15));
+    }
+
+    #[actix_web::test]
+    async fn test_max_attempts() {
+        tokio::time::pause();
+        let inner = FlakyBackend::default();
+        let backend = RetryBackend::builder(inner.clone()).max_attempts(2).build();
+        assert_eq!(
+            backend.request(Some(5)).await.unwrap_err(),
+            FlakyError::Transient
+        );
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_retry_if() {
+        tokio::time::pause();
+        let inner = FlakyBackend::default();
+        let backend = RetryBackend::builder(inner.clone())
+            .retry_if(|e: &FlakyError| *e == FlakyError::Transient)
+            .build();
+        assert_eq!(
+            backend.request(None).await.unwrap_err(),
+            FlakyError::Permanent
+        );
+        // Permanent errors are not retried
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_retry_transient() {
+        tokio::time::pause();
+        let inner = FlakyBackend::default();
+        let backend = RetryBackend::builder(inner.clone())
+            .retry_if(RetryTransient)
+            .build();
+        assert_eq!(
+            backend.request(None).await.unwrap_err(),
+            FlakyError::Permanent
+        );
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 1);
+        assert!(backend.request(Some(2)).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_default_policy() {
+        tokio::time::pause();
+        let inner = FlakyBackend::default();
+        let backend = RetryBackend::builder(inner.clone()).build();
+        assert_eq!(
+            backend.request(None).await.unwrap_err(),
+            FlakyError::Permanent
+        );
+        // Only transient errors are retried by default
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 1);
+
+        let inner = FlakyBackend::default();
+        let backend = RetryBackend::builder(inner.clone())
+            .retry_if(AlwaysRetry)
+            .build();
+        assert!(backend.request(None).await.is_err());
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let backend = RetryBackend::builder(())
+            .backoff(Duration::from_millis(100), Duration::from_millis(300))
+            .jitter(0f64)
+            .build();
+        assert_eq!(backend.backoff(0), Duration::from_millis(100));
+        assert_eq!(backend.backoff(1), Duration::from_millis(200));
+        assert_eq!(backend.backoff(2), Duration::from_millis(300));
+        // The delay is capped rather than overflowing
+        assert_eq!(backend.backoff(u32::MAX), Duration::from_millis(300));
+        let backend = RetryBackend::builder(())
+            .backoff(Duration::from_millis(100), Duration::MAX)
+            .build();
+        assert!(backend.backoff(100) > Duration::from_secs(60 * 60 * 24 * 365));
+        let backend = RetryBackend::builder(())
+            .backoff(Duration::from_millis(100), Duration::from_millis(300))
+            .build();
+        for retry in 0..5 {
+            let backoff = backend.backoff(retry);
+            assert!(backoff >= Duration::from_millis(50) && backoff <= Duration::from_millis(300));
+        }
+        // The jitter differs between calls for the same retry
+        let backoffs: HashSet<_> = (0..10).map(|_| backend.backoff(0)).collect();
+        assert!(backoffs.len() > 1);
+    }
+}
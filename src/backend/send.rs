@@ -0,0 +1,134 @@
+use crate::backend::replicated::ReplicatedInMemoryBackend;
+use crate::backend::sharded::ShardedInMemoryBackend;
+use crate::backend::{Backend, Decision, SimpleInput};
+use std::future::Future;
+
+/// A [Backend] whose futures are [Send], so that it can be shared with spawned tasks, e.g. a
+/// background job that charges quota outside of a request.
+///
+/// The futures returned by [Backend] can't be assumed to be [Send] in generic code, which is fine
+/// for the [RateLimiter](crate::RateLimiter) since actix runs each worker on a single thread, but
+/// prevents the backend being used with `tokio::spawn` or a multi-threaded runtime.
+///
+/// This is implemented by the built-in stores; the decorators (e.g. timeouts and retries) are
+/// not covered.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::{SendBackend, SimpleInput};
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use std::time::Duration;
+/// fn charge_in_background<B: SendBackend>(backend: B, input: SimpleInput) {
+///     tokio::spawn(async move {
+///         if backend.request_send(input).await.is_err() {
+///             log::warn!("Unable to charge quota");
+///         }
+///     });
+/// }
+/// # actix_web::rt::System::new().block_on(async {
+/// let input = SimpleInput {
+///     interval: Duration::from_secs(60),
+///     max_requests: 100,
+///     key: "job-user-1".into(),
+/// };
+/// charge_in_background(InMemoryBackend::builder().build(), input);
+/// # });
+/// ```
+#[allow(clippy::type_complexity)]
+pub trait SendBackend<I: Send + 'static = SimpleInput>:
+    Backend<I, Output: Send, RollbackToken: Send, Error: Send> + Send + Sync + 'static
+{
+    /// The same as [Backend::request()], returning a [Send] future.
+    fn request_send(
+        &self,
+        input: I,
+    ) -> impl Future<Output = Result<(Decision, Self::Output, Self::RollbackToken), Self::Error>> + Send;
+
+    /// The same as [Backend::rollback()], returning a [Send] future.
+    fn rollback_send(
+        &self,
+        token: Self::RollbackToken,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// The same as [Backend::peek()], returning a [Send] future.
+    fn peek_send(
+        &self,
+        input: I,
+    ) -> impl Future<Output = Result<(Decision, Self::Output), Self::Error>> + Send;
+}
+
+// The concrete futures of these backends are known to be Send, so they can be returned as is.
+macro_rules! impl_send_backend {
+    ($backend:ty) => {
+        impl SendBackend for $backend {
+            fn request_send(
+                &self,
+                input: SimpleInput,
+            ) -> impl Future<
+                Output = Result<(Decision, Self::Output, Self::RollbackToken), Self::Error>,
+            > + Send {
+                self.request(input)
+            }
+
+            fn rollback_send(
+                &self,
+                token: Self::RollbackToken,
+            ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+                self.rollback(token)
+            }
+
+            fn peek_send(
+                &self,
+                input: SimpleInput,
+            ) -> impl Future<Output = Result<(Decision, Self::Output), Self::Error>> + Send {
+                self.peek(input)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "dashmap")]
+impl_send_backend!(crate::backend::memory::InMemoryBackend);
+#[cfg(feature = "governor")]
+impl_send_backend!(crate::backend::governor::GovernorBackend);
+#[cfg(feature = "redis")]
+impl_send_backend!(crate::backend::redis::RedisBackend);
+impl_send_backend!(ShardedInMemoryBackend);
+impl_send_backend!(ReplicatedInMemoryBackend);
+
+#[cfg(all(test, feature = "dashmap"))]
+mod tests {
+    use super::*;
+    use crate::backend::memory::InMemoryBackend;
+    use std::time::Duration;
+
+    async fn spawn_requests<B: SendBackend>(backend: B, input: SimpleInput) -> Vec<Decision> {
+        let mut handles = Vec::new();
+        for _ in 0..3 {
+            let (backend, input) = (backend.clone(), input.clone());
+            handles.push(tokio::spawn(async move {
+                backend.request_send(input).await.ok().unwrap().0
+            }));
+        }
+        let mut decisions = Vec::new();
+        for handle in handles {
+            decisions.push(handle.await.unwrap());
+        }
+        decisions
+    }
+
+    #[actix_web::test]
+    async fn test_spawn() {
+        let backend = InMemoryBackend::builder().build();
+        let input = SimpleInput {
+            interval: Duration::from_secs(60),
+            max_requests: 2,
+            key: "KEY1".into(),
+        };
+        let decisions = spawn_requests(backend.clone(), input.clone()).await;
+        assert_eq!(decisions.iter().filter(|d| d.is_allowed()).count(), 2);
+        let (decision, _) = backend.peek_send(input).await.unwrap();
+        assert!(decision.is_denied());
+    }
+}
@@ -0,0 +1,637 @@
+use crate::backend::window::{Timeline, WindowAlignment};
+use crate::backend::{
+    Backend, Decision, InvalidRollbackToken, KeyStatus, SerializableRollbackToken, SimpleBackend,
+    SimpleInput, SimpleOutput, MAX_TTL,
+};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const DEFAULT_GC_INTERVAL_SECONDS: u64 = 60 * 10;
+
+/// A Fixed Window rate limiter [Backend] designed for very high request rates on machines with
+/// many cores.
+///
+/// Keys are spread across a number of independently locked shards, and each key is backed by a
+/// single [AtomicU64] holding both the window epoch and the request count, so that the hot path
+/// only ever takes a shared read lock and performs a compare-and-swap.
+///
+/// Unlike the [InMemoryBackend](crate::backend::memory::InMemoryBackend), windows are aligned to
+/// epochs counted from the creation of the backend (or the wall clock, see
+/// [Builder::with_alignment]), rather than starting at the first request for a key.
+///
+/// Counts saturate at [u32::MAX].
+#[derive(Clone)]
+pub struct ShardedInMemoryBackend {
+    inner: Arc<Inner>,
+}
+
+type Shard = RwLock<HashMap<String, Arc<Slot>>>;
+
+struct Inner {
+    timeline: Timeline,
+    hasher: RandomState,
+    shards: Box<[Shard]>,
+}
+
+struct Slot {
+    // Window epoch in the upper 32 bits, request count in the lower 32 bits.
+    state: AtomicU64,
+    // Interval (in nanoseconds) of the most recent request, used by the garbage collector.
+    interval: AtomicU64,
+}
+
+/// The [Backend::RollbackToken] for the [ShardedInMemoryBackend].
+///
+/// Rollbacks are only applied if the window they were issued in is still current.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ShardedRollbackToken {
+    key: Arc<str>,
+    epoch: u32,
+}
+
+impl SerializableRollbackToken for ShardedRollbackToken {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.epoch.to_be_bytes().to_vec();
+        bytes.extend_from_slice(self.key.as_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, InvalidRollbackToken> {
+        if bytes.len() < 4 {
+            return Err(InvalidRollbackToken);
+        }
+        let (epoch, key) = bytes.split_at(4);
+        Ok(Self {
+            key: String::from_utf8(key.to_vec())
+                .map_err(|_| InvalidRollbackToken)?
+                .into(),
+            epoch: u32::from_be_bytes(epoch.try_into().unwrap()),
+        })
+    }
+}
+
+fn pack(epoch: u32, count: u32) -> u64 {
+    ((epoch as u64) << 32) | count as u64
+}
+
+fn unpack(state: u64) -> (u32, u32) {
+    ((state >> 32) as u32, state as u32)
+}
+
+impl Inner {
+    fn shard(&self, key: &str) -> &Shard {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    fn get(&self, key: &str) -> Option<Arc<Slot>> {
+        self.shard(key).read().unwrap().get(key).cloned()
+    }
+
+    fn get_or_insert(&self, key: &str) -> Arc<Slot> {
+        if let Some(slot) = self.get(key) {
+            return slot;
+        }
+        self.shard(key)
+            .write()
+            .unwrap()
+            .entry(key.to_owned())
+            .or_insert_with(|| {
+                Arc::new(Slot {
+                    state: AtomicU64::new(0),
+                    interval: AtomicU64::new(0),
+                })
+            })
+            .clone()
+    }
+
+    /// Removes every key whose window has ended.
+    fn collect_garbage(&self, now: Instant) {
+        for shard in self.shards.iter() {
+            shard.write().unwrap().retain(|_k, slot| {
+                let interval = Duration::from_nanos(slot.interval.load(Ordering::Relaxed));
+                let (epoch, _) = unpack(slot.state.load(Ordering::Relaxed));
+                self.timeline.epoch(now, interval) as u32 == epoch
+            });
+        }
+    }
+}
+
+impl ShardedInMemoryBackend {
+    pub fn builder() -> Builder {
+        Builder {
+            gc_interval: Some(Duration::from_secs(DEFAULT_GC_INTERVAL_SECONDS)),
+            shards: std::thread::available_parallelism()
+                .map(|n| n.get() * 4)
+                .unwrap_or(16),
+            alignment: WindowAlignment::default(),
+        }
+    }
+
+    /// Removes every key whose window has ended now, rather than waiting for the garbage
+    /// collector.
+    pub fn gc_now(&self) {
+        self.inner.collect_garbage(Instant::now());
+    }
+
+    // The garbage collector only holds a weak reference to the shards, so that it stops once the
+    // last clone of the backend is dropped.
+    fn garbage_collector(inner: Weak<Inner>, interval: Duration) {
+        assert!(
+            interval.as_secs_f64() > 0f64,
+            "GC interval must be non-zero"
+        );
+        tokio::spawn(async move {
+            loop {
+                let now = Instant::now();
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                inner.collect_garbage(now);
+                drop(inner);
+                tokio::time::sleep_until(now + interval).await;
+            }
+        });
+    }
+
+    /// The status of a key, if it has requests in the current window.
+    fn status(&self, key: &str, slot: &Slot, now: Instant) -> Option<KeyStatus> {
+        let (epoch, count) = unpack(slot.state.load(Ordering::Relaxed));
+        // A new slot has no requests (or interval) until the first request completes
+        if count == 0 {
+            return None;
+        }
+        let interval = Duration::from_nanos(slot.interval.load(Ordering::Relaxed));
+        let current = self.inner.timeline.epoch(now, interval);
+        (current as u32 == epoch).then(|| KeyStatus {
+            key: key.to_owned(),
+            count: count as u64,
+            ttl: self
+                .inner
+                .timeline
+                .window_end(current, interval)
+                .saturating_duration_since(now),
+        })
+    }
+}
+
+pub struct Builder {
+    gc_interval: Option<Duration>,
+    shards: usize,
+    alignment: WindowAlignment,
+}
+
+impl Builder {
+    /// Override the default garbage collector interval.
+    ///
+    /// Set to None to disable garbage collection.
+    ///
+    /// The garbage collector periodically scans each shard, removing keys whose window has ended.
+    pub fn with_gc_interval(mut self, interval: Option<Duration>) -> Self {
+        self.gc_interval = interval;
+        self
+    }
+
+    /// Override the number of shards.
+    ///
+    /// Defaults to four times the available parallelism.
+    pub fn with_shards(mut self, shards: usize) -> Self {
+        assert!(shards > 0, "Number of shards must be non-zero");
+        self.shards = shards;
+        self
+    }
+
+    /// Override where the window boundaries fall.
+    ///
+    /// Defaults to [WindowAlignment::Creation].
+    pub fn with_alignment(mut self, alignment: WindowAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    pub fn build(self) -> ShardedInMemoryBackend {
+        let inner = Arc::new(Inner {
+            timeline: Timeline::new(self.alignment),
+            hasher: RandomState::new(),
+            shards: (0..self.shards)
+                .map(|_| RwLock::new(HashMap::new()))
+                .collect(),
+        });
+        if let Some(gc_interval) = self.gc_interval {
+            ShardedInMemoryBackend::garbage_collector(Arc::downgrade(&inner), gc_interval);
+        }
+        ShardedInMemoryBackend { inner }
+    }
+}
+
+impl Backend<SimpleInput> for ShardedInMemoryBackend {
+    type Output = SimpleOutput;
+    type RollbackToken = ShardedRollbackToken;
+    type Error = Infallible;
+
+    async fn request(
+        &self,
+        input: SimpleInput,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        let now = Instant::now();
+        let epoch = self.inner.timeline.epoch(now, input.interval);
+        let reset = self.inner.timeline.window_end(epoch, input.interval);
+        let epoch = epoch as u32;
+
+        let slot = self.inner.get_or_insert(&input.key);
+        slot.interval
+            .store(input.interval.as_nanos() as u64, Ordering::Relaxed);
+        let mut current = slot.state.load(Ordering::Relaxed);
+        let count = loop {
+            let (current_epoch, current_count) = unpack(current);
+            // If the stored window is no longer current, the count restarts at 1.
+            let count = if current_epoch == epoch {
+                current_count.saturating_add(1)
+            } else {
+                1
+            };
+            match slot.state.compare_exchange_weak(
+                current,
+                pack(epoch, count),
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break count as u64,
+                Err(actual) => current = actual,
+            }
+        };
+
+        let allow = count <= input.max_requests;
+        let output = SimpleOutput {
+            limit: input.max_requests,
+            remaining: input.max_requests.saturating_sub(count),
+            reset,
+            reset_at: None,
+        };
+        let token = ShardedRollbackToken {
+            key: input.key,
+            epoch,
+        };
+        Ok((Decision::from_allowed(allow), output, token))
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        if let Some(slot) = self.inner.get(&token.key) {
+            // Only decrement if the window that was charged is still the current one.
+            let _ = slot
+                .state
+                .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |state| {
+                    let (epoch, count) = unpack(state);
+                    (epoch == token.epoch && count > 0).then(|| pack(epoch, count - 1))
+                });
+        }
+        Ok(())
+    }
+
+    async fn peek(&self, input: SimpleInput) -> Result<(Decision, Self::Output), Self::Error> {
+        let now = Instant::now();
+        let epoch = self.inner.timeline.epoch(now, input.interval);
+        let reset = self.inner.timeline.window_end(epoch, input.interval);
+        let epoch = epoch as u32;
+
+        let count = match self.inner.get(&input.key) {
+            Some(slot) => {
+                let (current_epoch, current_count) = unpack(slot.state.load(Ordering::Relaxed));
+                if current_epoch == epoch {
+                    current_count as u64 + 1
+                } else {
+                    1
+                }
+            }
+            None => 1,
+        };
+
+        let allow = count <= input.max_requests;
+        let output = SimpleOutput {
+            limit: input.max_requests,
+            remaining: input.max_requests.saturating_sub(count),
+            reset,
+            reset_at: None,
+        };
+        Ok((Decision::from_allowed(allow), output))
+    }
+}
+
+impl SimpleBackend for ShardedInMemoryBackend {
+    async fn remove_key(&self, key: &str) -> Result<(), Self::Error> {
+        self.inner.shard(key).write().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn clear(&self, prefix: &str) -> Result<(), Self::Error> {
+        for shard in self.inner.shards.iter() {
+            shard.write().unwrap().retain(|k, _| !k.starts_with(prefix));
+        }
+        Ok(())
+    }
+
+    /// Sets the count for the current window. The `ttl` is only used as the interval if the key
+    /// doesn't already exist, since windows are aligned to fixed epochs.
+    async fn set_count(&self, key: &str, count: u64, ttl: Duration) -> Result<(), Self::Error> {
+        let slot = self.inner.get_or_insert(key);
+        let _ = slot.interval.compare_exchange(
+            0,
+            (ttl.min(MAX_TTL).as_nanos() as u64).max(1),
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        );
+        let interval = Duration::from_nanos(slot.interval.load(Ordering::Relaxed));
+        let epoch = self.inner.timeline.epoch(Instant::now(), interval) as u32;
+        let count = u32::try_from(count).unwrap_or(u32::MAX);
+        slot.state.store(pack(epoch, count), Ordering::Release);
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<KeyStatus>, Self::Error> {
+        Ok(self
+            .inner
+            .get(key)
+            .and_then(|slot| self.status(key, &slot, Instant::now())))
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<KeyStatus>, Self::Error> {
+        let now = Instant::now();
+        let mut statuses = Vec::new();
+        for shard in self.inner.shards.iter() {
+            let shard = shard.read().unwrap();
+            statuses.extend(
+                shard
+                    .iter()
+                    .filter(|(key, _)| key.starts_with(prefix))
+                    .filter_map(|(key, slot)| self.status(key, slot, now)),
+            );
+        }
+        Ok(statuses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn contains_key(backend: &ShardedInMemoryBackend, key: &str) -> bool {
+        backend.inner.get(key).is_some()
+    }
+
+    #[actix_web::test]
+    async fn test_allow_deny() {
+        tokio::time::pause();
+        let backend = ShardedInMemoryBackend::builder().build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: "KEY1".into(),
+        };
+        for _ in 0..5 {
+            // First 5 should be allowed
+            let (allow, _, _) = backend.request(input.clone()).await.unwrap();
+            assert!(allow.is_allowed());
+        }
+        // Sixth should be denied
+        let (allow, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(!allow.is_allowed());
+    }
+
+    #[actix_web::test]
+    async fn test_reset() {
+        tokio::time::pause();
+        let backend = ShardedInMemoryBackend::builder()
+            .with_gc_interval(None)
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 1,
+            key: "KEY1".into(),
+        };
+        // Make first request, should be allowed
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        // Request again, should be denied
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        // Advance time into the next window and try again, should now be allowed
+        tokio::time::advance(MINUTE).await;
+        assert!(contains_key(&backend, "KEY1"));
+        let (decision, _, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+    }
+
+    #[actix_web::test]
+    async fn test_epoch_aligned_windows() {
+        tokio::time::pause();
+        let backend = ShardedInMemoryBackend::builder().build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 2,
+            key: "KEY1".into(),
+        };
+        let start = Instant::now();
+        tokio::time::advance(Duration::from_secs(20)).await;
+        // The window started with the backend, not with the first request
+        let (_, output, _) = backend.request(input).await.unwrap();
+        assert_eq!(output.reset, start + MINUTE);
+        assert_eq!(output.remaining, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_garbage_collection() {
+        tokio::time::pause();
+        let backend = ShardedInMemoryBackend::builder()
+            .with_gc_interval(Some(MINUTE))
+            .build();
+        backend
+            .request(SimpleInput {
+                interval: MINUTE,
+                max_requests: 1,
+                key: "KEY1".into(),
+            })
+            .await
+            .unwrap();
+        backend
+            .request(SimpleInput {
+                interval: MINUTE * 2,
+                max_requests: 1,
+                key: "KEY2".into(),
+            })
+            .await
+            .unwrap();
+        assert!(contains_key(&backend, "KEY1"));
+        assert!(contains_key(&backend, "KEY2"));
+        // Advance time such that the garbage collector runs,
+        // expired KEY1 should be cleaned, but KEY2 should remain.
+        tokio::time::advance(MINUTE).await;
+        assert!(!contains_key(&backend, "KEY1"));
+        assert!(contains_key(&backend, "KEY2"));
+    }
+
+    #[actix_web::test]
+    async fn test_garbage_collection_clones() {
+        tokio::time::pause();
+        let backend = ShardedInMemoryBackend::builder()
+            .with_gc_interval(Some(MINUTE))
+            .build();
+        let clone = backend.clone();
+        // Dropping one clone mustn't stop the garbage collector for the others
+        drop(backend);
+        clone
+            .request(SimpleInput {
+                interval: MINUTE,
+                max_requests: 1,
+                key: "KEY1".into(),
+            })
+            .await
+            .unwrap();
+        tokio::time::advance(MINUTE).await;
+        assert!(!contains_key(&clone, "KEY1"));
+    }
+
+    #[actix_web::test]
+    async fn test_output() {
+        tokio::time::pause();
+        let backend = ShardedInMemoryBackend::builder().build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 2,
+            key: "KEY1".into(),
+        };
+        // First of 2 should be allowed.
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 1);
+        assert_eq!(output.limit, 2);
+        assert_eq!(output.reset, Instant::now() + MINUTE);
+        // Second of 2 should be allowed.
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 0);
+        // Should be denied
+        let (decision, output, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.remaining, 0);
+        assert_eq!(output.limit, 2);
+    }
+
+    #[actix_web::test]
+    async fn test_rollback() {
+        tokio::time::pause();
+        let backend = ShardedInMemoryBackend::builder().build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: "KEY1".into(),
+        };
+        let (_, output, rollback) = backend.request(input.clone()).await.unwrap();
+        assert_eq!(output.remaining, 4);
+        backend.rollback(rollback).await.unwrap();
+        // Remaining requests should still be the same, since the previous call was excluded
+        let (_, output, rollback) = backend.request(input.clone()).await.unwrap();
+        assert_eq!(output.remaining, 4);
+        // A rollback issued in a previous window must not affect the new window
+        tokio::time::advance(MINUTE).await;
+        let (_, output, _) = backend.request(input.clone()).await.unwrap();
+        assert_eq!(output.remaining, 4);
+        backend.rollback(rollback).await.unwrap();
+        let (_, output, _) = backend.request(input).await.unwrap();
+        assert_eq!(output.remaining, 3);
+    }
+
+    #[actix_web::test]
+    async fn test_rollback_serialized() {
+        tokio::time::pause();
+        let backend = ShardedInMemoryBackend::builder().build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: "KEY1".into(),
+        };
+        let (_, _, rollback) = backend.request(input.clone()).await.unwrap();
+        let bytes = rollback.to_bytes();
+        assert_eq!(ShardedRollbackToken::from_bytes(&bytes).unwrap(), rollback);
+        backend.rollback_serialized(&bytes).await.unwrap();
+        let (_, output, _) = backend.request(input).await.unwrap();
+        assert_eq!(output.remaining, 4);
+        // Truncated tokens are rejected
+        assert!(backend.rollback_serialized(&bytes[..2]).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_remove_key() {
+        tokio::time::pause();
+        let backend = ShardedInMemoryBackend::builder()
+            .with_gc_interval(None)
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 1,
+            key: "KEY1".into(),
+        };
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        backend.remove_key("KEY1").await.unwrap();
+        // Counter should have been reset
+        let (decision, _, _) = backend.request(input).await.unwrap();
+        assert!(decision.is_allowed());
+    }
+
+    #[actix_web::test]
+    async fn test_get() {
+        tokio::time::pause();
+        let backend = ShardedInMemoryBackend::builder()
+            .with_gc_interval(None)
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: "KEY1".into(),
+        };
+        assert!(backend.get("KEY1").await.unwrap().is_none());
+        backend.request(input.clone()).await.unwrap();
+        backend.request(input).await.unwrap();
+        tokio::time::advance(Duration::from_secs(20)).await;
+        let status = backend.get("KEY1").await.unwrap().unwrap();
+        assert_eq!(status.count, 2);
+        assert_eq!(status.ttl, Duration::from_secs(40));
+        // The window has ended
+        tokio::time::advance(MINUTE).await;
+        assert!(backend.get("KEY1").await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_set_count() {
+        tokio::time::pause();
+        let backend = ShardedInMemoryBackend::builder()
+            .with_gc_interval(None)
+            .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 5,
+            key: "KEY1".into(),
+        };
+        for _ in 0..5 {
+            backend.request(input.clone()).await.unwrap();
+        }
+        let (decision, _, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_denied());
+        // Grant two more requests in the current window
+        backend.set_count("KEY1", 3, MINUTE).await.unwrap();
+        let (decision, output, _) = backend.request(input.clone()).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 1);
+        assert_eq!(backend.get("KEY1").await.unwrap().unwrap().count, 4);
+    }
+}
@@ -0,0 +1,331 @@
+use crate::backend::{Backend, Decision, KeyStatus, SimpleBackend, SimpleInput, SimpleOutput};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const DEFAULT_WINDOW_SECONDS: u64 = 60 * 5;
+pub const DEFAULT_TOP: usize = 10;
+pub const DEFAULT_MAX_KEYS: usize = 10_000;
+
+// The rolling window is approximated by this many fixed slices.
+const SLICES: u32 = 10;
+
+/// A [Backend] decorator that tracks the keys making the most requests, and the keys being
+/// denied the most, over a rolling window.
+///
+/// This is useful for diagnosing who is actually triggering limits, see
+/// [StatsBackend::stats]. The counts only include requests made through this backend (i.e. on
+/// the current worker), and rollbacks are not subtracted.
+///
+/// To bound the memory usage, at most [Builder::with_max_keys] keys are tracked in each slice of
+/// the window; requests for new keys are ignored once this is reached.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::stats::StatsBackend;
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use std::time::Duration;
+/// # actix_web::rt::System::new().block_on(async {
+/// let backend = StatsBackend::builder(InMemoryBackend::builder().build())
+///     .with_window(Duration::from_secs(60))
+///     .build();
+/// let stats = backend.stats();
+/// for key in stats.top_denied {
+///     println!("{} was denied {} times", key.key, key.denied);
+/// }
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct StatsBackend<B> {
+    backend: B,
+    recorder: Arc<Mutex<Recorder>>,
+    top: usize,
+}
+
+/// A snapshot of the busiest keys, see [StatsBackend::stats].
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitStats {
+    /// The keys with the most requests, in descending order.
+    pub top_requests: Vec<KeyStats>,
+    /// The keys with the most denied requests, in descending order.
+    pub top_denied: Vec<KeyStats>,
+}
+
+/// The number of requests for a key within the rolling window.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct KeyStats {
+    pub key: String,
+    /// Number of requests, including those that were denied.
+    pub requests: u64,
+    /// Number of denied requests.
+    pub denied: u64,
+}
+
+struct Recorder {
+    origin: Instant,
+    slice: Duration,
+    max_keys: usize,
+    // Slice index and the counts within it, oldest first.
+    slices: VecDeque<(u64, HashMap<String, KeyStats>)>,
+}
+
+impl Recorder {
+    fn current_slice(&self, now: Instant) -> u64 {
+        (now.duration_since(self.origin).as_nanos() / self.slice.as_nanos()) as u64
+    }
+
+    fn prune(&mut self, current: u64) {
+        while let Some((index, _)) = self.slices.front() {
+            if *index + u64::from(SLICES) > current {
+                break;
+            }
+            self.slices.pop_front();
+        }
+    }
+
+    fn record(&mut self, key: &str, decision: Decision) {
+        let current = self.current_slice(Instant::now());
+        self.prune(current);
+        if self.slices.back().map(|(index, _)| *index) != Some(current) {
+            self.slices.push_back((current, HashMap::new()));
+        }
+        let (_, counts) = self.slices.back_mut().unwrap();
+        if counts.len() >= self.max_keys && !counts.contains_key(key) {
+            return;
+        }
+        let stats = counts.entry(key.to_owned()).or_insert_with(|| KeyStats {
+            key: key.to_owned(),
+            ..KeyStats::default()
+        });
+        stats.requests += 1;
+        stats.denied += u64::from(decision.is_denied());
+    }
+
+    fn totals(&mut self) -> Vec<KeyStats> {
+        let current = self.current_slice(Instant::now());
+        self.prune(current);
+        let mut totals: HashMap<&str, KeyStats> = HashMap::new();
+        for (_, counts) in &self.slices {
+            for (key, stats) in counts {
+                let total = totals.entry(key).or_insert_with(|| KeyStats {
+                    key: key.clone(),
+                    ..KeyStats::default()
+                });
+                total.requests += stats.requests;
+                total.denied += stats.denied;
+            }
+        }
+        totals.into_values().collect()
+    }
+}
+
+impl<B> StatsBackend<B> {
+    pub fn builder(backend: B) -> Builder<B> {
+        Builder {
+            backend,
+            window: Duration::from_secs(DEFAULT_WINDOW_SECONDS),
+            top: DEFAULT_TOP,
+            max_keys: DEFAULT_MAX_KEYS,
+        }
+    }
+
+    /// Returns the busiest keys within the rolling window.
+    pub fn stats(&self) -> RateLimitStats {
+        let mut totals = self.recorder.lock().unwrap().totals();
+
+        totals.sort_by(|a, b| b.requests.cmp(&a.requests).then_with(|| a.key.cmp(&b.key)));
+        let top_requests = totals.iter().take(self.top).cloned().collect();
+
+        totals.retain(|stats| stats.denied > 0);
+        totals.sort_by(|a, b| b.denied.cmp(&a.denied).then_with(|| a.key.cmp(&b.key)));
+        totals.truncate(self.top);
+
+        RateLimitStats {
+            top_requests,
+            top_denied: totals,
+        }
+    }
+}
+
+pub struct Builder<B> {
+    backend: B,
+    window: Duration,
+    top: usize,
+    max_keys: usize,
+}
+
+impl<B> Builder<B> {
+    /// Override the duration of the rolling window.
+    ///
+    /// Defaults to 5 minutes.
+    pub fn with_window(mut self, window: Duration) -> Self {
+        assert!(!window.is_zero(), "Window must be non-zero");
+        self.window = window;
+        self
+    }
+
+    /// Override how many keys are returned in each list of [RateLimitStats].
+    ///
+    /// Defaults to 10.
+    pub fn with_top(mut self, top: usize) -> Self {
+        self.top = top;
+        self
+    }
+
+    /// Override the maximum number of keys tracked in each slice of the window.
+    ///
+    /// Defaults to 10,000.
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    pub fn build(self) -> StatsBackend<B> {
+        let recorder = Recorder {
+            origin: Instant::now(),
+            slice: (self.window / SLICES).max(Duration::from_nanos(1)),
+            max_keys: self.max_keys,
+            slices: VecDeque::new(),
+        };
+        StatsBackend {
+            backend: self.backend,
+            recorder: Arc::new(Mutex::new(recorder)),
+            top: self.top,
+        }
+    }
+}
+
+impl<B> Backend<SimpleInput> for StatsBackend<B>
+where
+    B: Backend<SimpleInput, Output = SimpleOutput>,
+{
+    type Output = SimpleOutput;
+    type RollbackToken = B::RollbackToken;
+    type Error = B::Error;
+
+    async fn request(
+        &self,
+        input: SimpleInput,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        let key = input.key.clone();
+        let (decision, output, token) = self.backend.request(input).await?;
+        self.recorder.lock().unwrap().record(&key, decision);
+        Ok((decision, output, token))
+    }
+
+    async fn request_batch(
+        &self,
+        inputs: Vec<SimpleInput>,
+    ) -> Result<Vec<(Decision, Self::Output, Self::RollbackToken)>, Self::Error> {
+        let keys: Vec<_> = inputs.iter().map(|i| i.key.clone()).collect();
+        let results = self.backend.request_batch(inputs).await?;
+        let mut recorder = self.recorder.lock().unwrap();
+        for (key, (decision, _, _)) in keys.iter().zip(&results) {
+            recorder.record(key, *decision);
+        }
+        Ok(results)
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        self.backend.rollback(token).await
+    }
+
+    async fn peek(&self, input: SimpleInput) -> Result<(Decision, Self::Output), Self::Error> {
+        self.backend.peek(input).await
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        self.backend.health_check().await
+    }
+}
+
+impl<B> SimpleBackend for StatsBackend<B>
+where
+    B: SimpleBackend,
+{
+    async fn remove_key(&self, key: &str) -> Result<(), Self::Error> {
+        self.backend.remove_key(key).await
+    }
+
+    async fn clear(&self, prefix: &str) -> Result<(), Self::Error> {
+        self.backend.clear(prefix).await
+    }
+
+    async fn set_count(&self, key: &str, count: u64, ttl: Duration) -> Result<(), Self::Error> {
+        self.backend.set_count(key, count, ttl).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<KeyStatus>, Self::Error> {
+        self.backend.get(key).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<KeyStatus>, Self::Error> {
+        self.backend.list(prefix).await
+    }
+}
+
+#[cfg(all(test, feature = "dashmap"))]
+mod tests {
+    use super::*;
+    use crate::backend::memory::InMemoryBackend;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[actix_web::test]
+    async fn test_stats() {
+        tokio::time::pause();
+        let backend = StatsBackend::builder(InMemoryBackend::builder().build())
+            .with_window(MINUTE)
+            .with_top(2)
+            .build();
+        for (key, requests) in [("KEY1", 3), ("KEY2", 5), ("KEY3", 1)] {
+            let input = SimpleInput {
+                interval: MINUTE,
+                max_requests: 2,
+                key: key.into(),
+            };
+            for _ in 0..requests {
+                backend.request(input.clone()).await.unwrap();
+            }
+        }
+        let stats = backend.stats();
+        let requests: Vec<_> = stats
+            .top_requests
+            .iter()
+            .map(|s| (s.key.as_str(), s.requests))
+            .collect();
+        assert_eq!(requests, [("KEY2", 5), ("KEY1", 3)]);
+        let denied: Vec<_> = stats
+            .top_denied
+            .iter()
+            .map(|s| (s.key.as_str(), s.denied))
+            .collect();
+        assert_eq!(denied, [("KEY2", 3), ("KEY1", 1)]);
+
+        // Requests age out of the rolling window
+        tokio::time::advance(MINUTE).await;
+        let stats = backend.stats();
+        assert!(stats.top_requests.is_empty());
+        assert!(stats.top_denied.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_max_keys() {
+        let backend = StatsBackend::builder(InMemoryBackend::builder().build())
+            .with_max_keys(1)
+            .build();
+        for key in ["KEY1", "KEY2", "KEY1"] {
+            let input = SimpleInput {
+                interval: MINUTE,
+                max_requests: 5,
+                key: key.into(),
+            };
+            backend.request(input).await.unwrap();
+        }
+        let stats = backend.stats();
+        assert_eq!(stats.top_requests.len(), 1);
+        assert_eq!(stats.top_requests[0].requests, 2);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::backend::input_builder::{unlimited_input, Error};
+use crate::backend::{
+    RateLimitPolicy, SimpleInput, SimpleInputFunctionBuilder, SimpleInputFuture, UnknownIp,
+};
+use actix_web::dev::ServiceRequest;
+use std::collections::HashMap;
+use std::future::ready;
+use std::time::Duration;
+
+type TierFn = Box<dyn Fn(&ServiceRequest) -> Result<String, actix_web::Error> + Send + Sync>;
+
+/// Utility to create an input function that applies a different policy depending on the tier of
+/// the client, e.g. `free`, `pro` or `enterprise`.
+///
+/// The tier name is appended to the rate limiting key, so that a client changing tier starts with
+/// a fresh count. If the tier function returns a tier that hasn't been registered the request
+/// fails with an internal server error.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::{RateLimitPolicy, TieredInputFunctionBuilder};
+/// # use std::time::Duration;
+/// const HOUR: Duration = Duration::from_secs(60 * 60);
+/// let input = TieredInputFunctionBuilder::new(|req| {
+///     let plan = req.headers().get("x-plan").and_then(|plan| plan.to_str().ok());
+///     Ok(plan.unwrap_or("free").to_owned())
+/// })
+/// .tier("free", RateLimitPolicy::new(HOUR, 100))
+/// .tier("pro", RateLimitPolicy::new(HOUR, 10_000))
+/// .tier("enterprise", RateLimitPolicy::new(HOUR, 1_000_000))
+/// .real_ip_key()
+/// .build();
+/// ```
+pub struct TieredInputFunctionBuilder {
+    tier_fn: TierFn,
+    tiers: HashMap<String, RateLimitPolicy>,
+    keys: SimpleInputFunctionBuilder,
+}
+
+impl TieredInputFunctionBuilder {
+    /// # Arguments
+    ///
+    /// * `tier_fn`: Returns the name of the tier for a request.
+    pub fn new<F>(tier_fn: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> Result<String, actix_web::Error> + Send + Sync + 'static,
+    {
+        Self {
+            tier_fn: Box::new(tier_fn),
+            tiers: HashMap::new(),
+            keys: SimpleInputFunctionBuilder::new(Duration::ZERO, 0),
+        }
+    }
+
+    /// Register the policy for a tier.
+    pub fn tier(mut self, name: &str, policy: RateLimitPolicy) -> Self {
+        self.tiers.insert(name.to_owned(), policy);
+        self
+    }
+
+    /// Adds the client's real IP to the rate limiting key, see
+    /// [SimpleInputFunctionBuilder::real_ip_key].
+    pub fn real_ip_key(mut self) -> Self {
+        self.keys = self.keys.real_ip_key();
+        self
+    }
+
+    /// What to do when the client IP is unknown or can't be parsed, see
+    /// [SimpleInputFunctionBuilder::unknown_ip].
+    pub fn unknown_ip(mut self, unknown_ip: UnknownIp) -> Self {
+        self.keys = self.keys.unknown_ip(unknown_ip);
+        self
+    }
+
+    /// Adds the connection peer IP to the rate limiting key, see
+    /// [SimpleInputFunctionBuilder::peer_ip_key].
+    pub fn peer_ip_key(mut self) -> Self {
+        self.keys = self.keys.peer_ip_key();
+        self
+    }
+
+    /// Add the request path to the rate limiting key
+    pub fn path_key(mut self) -> Self {
+        self.keys = self.keys.path_key();
+        self
+    }
+
+    /// Add a custom component to the rate limiting key
+    pub fn custom_key(mut self, key: &str) -> Self {
+        self.keys = self.keys.custom_key(key);
+        self
+    }
+
+    /// Dynamically add a custom component to the rate limiting key, e.g. the API key or user
+    pub fn custom_fn<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> Result<String, actix_web::Error> + Send + Sync + 'static,
+    {
+        self.keys = self.keys.custom_fn(f);
+        self
+    }
+
+    pub fn build(self) -> impl Fn(&ServiceRequest) -> SimpleInputFuture + Send + Sync + 'static {
+        move |req| ready(self.input(req))
+    }
+
+    fn input(&self, req: &ServiceRequest) -> Result<SimpleInput, actix_web::Error> {
+        let tier = (self.tier_fn)(req)?;
+        let policy = self
+            .tiers
+            .get(&tier)
+            .ok_or_else(|| Error::UnknownTier(tier.clone()))?;
+        let Some(key) = self.keys.key(req)? else {
+            return Ok(unlimited_input(policy.interval()));
+        };
+        Ok(SimpleInput {
+            interval: policy.interval(),
+            max_requests: policy.allowed_requests(),
+            key: key.with(tier).into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[actix_web::test]
+    async fn test_tiers() {
+        const MINUTE: Duration = Duration::from_secs(60);
+        let input_fn = TieredInputFunctionBuilder::new(|req| {
+            Ok(req
+                .headers()
+                .get("x-plan")
+                .and_then(|plan| plan.to_str().ok())
+                .unwrap_or("free")
+                .to_owned())
+        })
+        .tier("free", RateLimitPolicy::new(MINUTE, 10))
+        .tier("pro", RateLimitPolicy::new(MINUTE, 1000).with_cost(2))
+        .custom_key("client")
+        .build();
+
+        let input = input_fn(&TestRequest::default().to_srv_request())
+            .await
+            .unwrap();
+        assert_eq!(input.max_requests, 10);
+        assert_eq!(&*input.key, "client-free");
+
+        let req = TestRequest::default()
+            .insert_header(("x-plan", "pro"))
+            .to_srv_request();
+        let input = input_fn(&req).await.unwrap();
+        assert_eq!(input.max_requests, 500);
+        assert_eq!(&*input.key, "client-pro");
+
+        let req = TestRequest::default()
+            .insert_header(("x-plan", "platinum"))
+            .to_srv_request();
+        let err = input_fn(&req).await.unwrap_err();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
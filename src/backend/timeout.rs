@@ -0,0 +1,230 @@
+use crate::backend::{Backend, BackendError, ClassifyError, Decision, KeyStatus, SimpleBackend};
+#[cfg(feature = "actix")]
+use actix_web::http::StatusCode;
+#[cfg(feature = "actix")]
+use actix_web::{HttpResponse, ResponseError};
+use std::time::Duration;
+use thiserror::Error;
+
+pub const DEFAULT_TIMEOUT_MILLIS: u64 = 500;
+
+#[derive(Debug, Error)]
+pub enum Error<E> {
+    #[error("Rate limiter backend timed out")]
+    Timeout,
+    #[error("{0}")]
+    Backend(E),
+}
+
+impl<E: ClassifyError> ClassifyError for Error<E> {
+    fn class(&self) -> BackendError {
+        match self {
+            Error::Timeout => BackendError::Transient,
+            Error::Backend(e) => e.class(),
+        }
+    }
+}
+
+#[cfg(feature = "actix")]
+impl<E: ResponseError> ResponseError for Error<E> {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Timeout => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Backend(e) => e.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Error::Timeout => HttpResponse::ServiceUnavailable().finish(),
+            Error::Backend(e) => e.error_response(),
+        }
+    }
+}
+
+/// A [Backend] decorator that bounds how long each call to the inner backend may take, so that a
+/// slow store doesn't add unbounded latency to every request.
+///
+/// Calls that take longer than the timeout are cancelled and fail with [Error::Timeout]. Whether
+/// those requests are then allowed or denied is controlled by
+/// [RateLimiterBuilder::fail_open](crate::RateLimiterBuilder::fail_open).
+#[derive(Clone)]
+pub struct TimeoutBackend<B> {
+    backend: B,
+    timeout: Duration,
+}
+
+impl<B> TimeoutBackend<B> {
+    pub fn builder(backend: B) -> Builder<B> {
+        Builder {
+            backend,
+            timeout: Duration::from_millis(DEFAULT_TIMEOUT_MILLIS),
+        }
+    }
+}
+
+pub struct Builder<B> {
+    backend: B,
+    timeout: Duration,
+}
+
+impl<B> Builder<B> {
+    /// Override the maximum time a call to the inner backend may take.
+    ///
+    /// Defaults to 500ms.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn build(self) -> TimeoutBackend<B> {
+        TimeoutBackend {
+            backend: self.backend,
+            timeout: self.timeout,
+        }
+    }
+}
+
+impl<B, I> Backend<I> for TimeoutBackend<B>
+where
+    B: Backend<I>,
+    I: 'static,
+{
+    type Output = B::Output;
+    type RollbackToken = B::RollbackToken;
+    type Error = Error<B::Error>;
+
+    async fn request(
+        &self,
+        input: I,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        match tokio::time::timeout(self.timeout, self.backend.request(input)).await {
+            Ok(result) => result.map_err(Error::Backend),
+            Err(_) => {
+                log::warn!("Rate limiter backend request timed out");
+                Err(Error::Timeout)
+            }
+        }
+    }
+
+    async fn request_batch(
+        &self,
+        inputs: Vec<I>,
+    ) -> Result<Vec<(Decision, Self::Output, Self::RollbackToken)>, Self::Error> {
+        match tokio::time::timeout(self.timeout, self.backend.request_batch(inputs)).await {
+            Ok(result) => result.map_err(Error::Backend),
+            Err(_) => {
+                log::warn!("Rate limiter backend request timed out");
+                Err(Error::Timeout)
+            }
+        }
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        match tokio::time::timeout(self.timeout, self.backend.rollback(token)).await {
+            Ok(result) => result.map_err(Error::Backend),
+            Err(_) => {
+                log::warn!("Rate limiter backend rollback timed out");
+                Err(Error::Timeout)
+            }
+        }
+    }
+
+    async fn peek(&self, input: I) -> Result<(Decision, Self::Output), Self::Error> {
+        match tokio::time::timeout(self.timeout, self.backend.peek(input)).await {
+            Ok(result) => result.map_err(Error::Backend),
+            Err(_) => {
+                log::warn!("Rate limiter backend peek timed out");
+                Err(Error::Timeout)
+            }
+        }
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        match tokio::time::timeout(self.timeout, self.backend.health_check()).await {
+            Ok(result) => result.map_err(Error::Backend),
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+}
+
+impl<B> SimpleBackend for TimeoutBackend<B>
+where
+    B: SimpleBackend,
+{
+    async fn remove_key(&self, key: &str) -> Result<(), Self::Error> {
+        match tokio::time::timeout(self.timeout, self.backend.remove_key(key)).await {
+            Ok(result) => result.map_err(Error::Backend),
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+
+    async fn clear(&self, prefix: &str) -> Result<(), Self::Error> {
+        match tokio::time::timeout(self.timeout, self.backend.clear(prefix)).await {
+            Ok(result) => result.map_err(Error::Backend),
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+
+    async fn set_count(&self, key: &str, count: u64, ttl: Duration) -> Result<(), Self::Error> {
+        let future = self.backend.set_count(key, count, ttl);
+        match tokio::time::timeout(self.timeout, future).await {
+            Ok(result) => result.map_err(Error::Backend),
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<KeyStatus>, Self::Error> {
+        match tokio::time::timeout(self.timeout, self.backend.get(key)).await {
+            Ok(result) => result.map_err(Error::Backend),
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<KeyStatus>, Self::Error> {
+        match tokio::time::timeout(self.timeout, self.backend.list(prefix)).await {
+            Ok(result) => result.map_err(Error::Backend),
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone)]
+    struct SlowBackend;
+
+    /// Input is how long the request should take.
+    impl Backend<Duration> for SlowBackend {
+        type Output = ();
+        type RollbackToken = ();
+        type Error = ();
+
+        async fn request(
+            &self,
+            delay: Duration,
+        ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+            tokio::time::sleep(delay).await;
+            Ok((Decision::Allowed, (), ()))
+        }
+
+        async fn rollback(&self, _: Self::RollbackToken) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_timeout() {
+        tokio::time::pause();
+        let backend = TimeoutBackend::builder(SlowBackend)
+            .timeout(Duration::from_millis(100))
+            .build();
+        assert!(backend.request(Duration::from_millis(50)).await.is_ok());
+        assert!(matches!(
+            backend.request(Duration::from_millis(150)).await,
+            Err(Error::Timeout)
+        ));
+    }
+}
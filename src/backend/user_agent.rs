@@ -0,0 +1,94 @@
+/// A coarse classification of the client's `User-Agent`, for use with
+/// [SimpleInputFunctionBuilder::user_agent_class_key](crate::backend::SimpleInputFunctionBuilder::user_agent_class_key).
+///
+/// # Security
+///
+/// Clients can send any user agent, so this is only useful to separate well-behaved automated
+/// clients from browsers, it can't be relied upon to identify malicious clients.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum UserAgentClass {
+    /// Crawlers, scripts and HTTP libraries, e.g. `Googlebot`, `curl` or `python-requests`.
+    Bot,
+    /// Mobile browsers and apps.
+    Mobile,
+    /// Desktop browsers.
+    Browser,
+    /// The `User-Agent` header is missing or unrecognized.
+    Unknown,
+}
+
+const BOT_TOKENS: &[&str] = &[
+    "bot",
+    "crawl",
+    "spider",
+    "slurp",
+    "curl",
+    "wget",
+    "python",
+    "java/",
+    "go-http",
+    "okhttp",
+    "axios",
+    "node-fetch",
+    "libwww",
+    "httpclient",
+    "postman",
+    "headless",
+];
+
+const MOBILE_TOKENS: &[&str] = &["mobile", "android", "iphone", "ipad", "ipod"];
+
+impl UserAgentClass {
+    /// Classifies a `User-Agent` header value using simple substring matching.
+    pub fn classify(user_agent: Option<&str>) -> Self {
+        let Some(user_agent) = user_agent.filter(|ua| !ua.trim().is_empty()) else {
+            return Self::Unknown;
+        };
+        let user_agent = user_agent.to_ascii_lowercase();
+        let contains = |tokens: &[&str]| tokens.iter().any(|token| user_agent.contains(token));
+        if contains(BOT_TOKENS) {
+            Self::Bot
+        } else if contains(MOBILE_TOKENS) {
+            Self::Mobile
+        } else if user_agent.starts_with("mozilla/") || user_agent.starts_with("opera/") {
+            Self::Browser
+        } else {
+            Self::Unknown
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bot => "bot",
+            Self::Mobile => "mobile",
+            Self::Browser => "browser",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let classify = |ua| UserAgentClass::classify(Some(ua));
+        assert_eq!(
+            classify("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"),
+            UserAgentClass::Bot
+        );
+        assert_eq!(classify("curl/8.4.0"), UserAgentClass::Bot);
+        assert_eq!(classify("python-requests/2.31.0"), UserAgentClass::Bot);
+        assert_eq!(
+            classify("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) Mobile/15E148"),
+            UserAgentClass::Mobile
+        );
+        assert_eq!(
+            classify("Mozilla/5.0 (Windows NT 10.0; Win64; x64) Chrome/120.0 Safari/537.36"),
+            UserAgentClass::Browser
+        );
+        assert_eq!(classify("MyApp/1.0"), UserAgentClass::Unknown);
+        assert_eq!(UserAgentClass::classify(None), UserAgentClass::Unknown);
+    }
+}
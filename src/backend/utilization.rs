@@ -0,0 +1,263 @@
+use crate::backend::{Backend, Decision, KeyStatus, SimpleBackend, SimpleInput, SimpleOutput};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const DEFAULT_FLUSH_INTERVAL_SECONDS: u64 = 60;
+
+/// The name of the histogram that window utilization is recorded to.
+pub const UTILIZATION_HISTOGRAM: &str = "rate_limit_window_utilization";
+
+/// A [Backend] decorator that records a histogram of how much of the limit each window used, via
+/// the [metrics] crate.
+///
+/// When a window ends the utilization (the number of requests made in the window divided by the
+/// limit) is recorded to the [UTILIZATION_HISTOGRAM] histogram with a `policy` label. Values above
+/// 1 mean that requests were denied.
+///
+/// This allows capacity planning by seeing how close real traffic runs to the configured limits,
+/// without scraping individual keys.
+///
+/// Windows are flushed either when the next request for the same key arrives, or periodically by
+/// a background task.
+#[derive(Clone)]
+pub struct UtilizationMetricsBackend<B> {
+    backend: B,
+    policy: Arc<str>,
+    windows: Arc<Mutex<HashMap<String, Window>>>,
+}
+
+struct Window {
+    reset: Instant,
+    limit: u64,
+    allowed: u64,
+    denied: u64,
+}
+
+impl Window {
+    fn utilization(&self) -> f64 {
+        (self.allowed + self.denied) as f64 / self.limit.max(1) as f64
+    }
+}
+
+impl<B> UtilizationMetricsBackend<B> {
+    /// # Arguments
+    ///
+    /// * `backend`: The backend to record utilization for.
+    /// * `policy`: The name of the policy, used as the `policy` label of the histogram.
+    pub fn builder(backend: B, policy: impl Into<String>) -> Builder<B> {
+        Builder {
+            backend,
+            policy: policy.into(),
+            flush_interval: Some(Duration::from_secs(DEFAULT_FLUSH_INTERVAL_SECONDS)),
+        }
+    }
+
+    fn flusher(
+        windows: Weak<Mutex<HashMap<String, Window>>>,
+        policy: Arc<str>,
+        interval: Duration,
+    ) {
+        assert!(
+            interval.as_secs_f64() > 0f64,
+            "Flush interval must be non-zero"
+        );
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(windows) = windows.upgrade() else {
+                    return;
+                };
+                let now = Instant::now();
+                windows.lock().unwrap().retain(|_, window| {
+                    if window.reset <= now {
+                        record(&policy, window);
+                        false
+                    } else {
+                        true
+                    }
+                });
+            }
+        });
+    }
+
+    fn update(&self, key: &str, decision: Decision, output: &SimpleOutput) {
+        let mut windows = self.windows.lock().unwrap();
+        if let Some(window) = windows.get_mut(key) {
+            if window.reset > Instant::now() {
+                match decision {
+                    Decision::Allowed => {
+                        window.allowed = window
+                            .allowed
+                            .max(output.limit.saturating_sub(output.remaining))
+                    }
+                    Decision::Denied => window.denied += 1,
+                }
+                return;
+            }
+            record(&self.policy, window);
+        }
+        windows.insert(
+            key.to_string(),
+            Window {
+                reset: output.reset,
+                limit: output.limit,
+                allowed: output.limit.saturating_sub(output.remaining),
+                denied: u64::from(decision.is_denied()),
+            },
+        );
+    }
+}
+
+fn record(policy: &Arc<str>, window: &Window) {
+    metrics::histogram!(UTILIZATION_HISTOGRAM, "policy" => policy.to_string())
+        .record(window.utilization());
+}
+
+pub struct Builder<B> {
+    backend: B,
+    policy: String,
+    flush_interval: Option<Duration>,
+}
+
+impl<B> Builder<B> {
+    /// Override how often ended windows are flushed to the histogram.
+    ///
+    /// Set to None to only flush a window when the next request for the same key arrives.
+    pub fn with_flush_interval(mut self, interval: Option<Duration>) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    pub fn build(self) -> UtilizationMetricsBackend<B> {
+        let windows = Arc::new(Mutex::new(HashMap::new()));
+        let policy: Arc<str> = self.policy.into();
+        if let Some(interval) = self.flush_interval {
+            UtilizationMetricsBackend::<B>::flusher(
+                Arc::downgrade(&windows),
+                policy.clone(),
+                interval,
+            );
+        }
+        UtilizationMetricsBackend {
+            backend: self.backend,
+            policy,
+            windows,
+        }
+    }
+}
+
+impl<B> Backend<SimpleInput> for UtilizationMetricsBackend<B>
+where
+    B: Backend<SimpleInput, Output = SimpleOutput>,
+{
+    type Output = SimpleOutput;
+    type RollbackToken = B::RollbackToken;
+    type Error = B::Error;
+
+    async fn request(
+        &self,
+        input: SimpleInput,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        let key = input.key.clone();
+        let (decision, output, token) = self.backend.request(input).await?;
+        self.update(&key, decision, &output);
+        Ok((decision, output, token))
+    }
+
+    async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+        self.backend.rollback(token).await
+    }
+
+    async fn peek(&self, input: SimpleInput) -> Result<(Decision, Self::Output), Self::Error> {
+        // Nothing is counted, so there is no change in utilization to record
+        self.backend.peek(input).await
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        self.backend.health_check().await
+    }
+}
+
+impl<B> SimpleBackend for UtilizationMetricsBackend<B>
+where
+    B: SimpleBackend,
+{
+    async fn remove_key(&self, key: &str) -> Result<(), Self::Error> {
+        self.backend.remove_key(key).await
+    }
+
+    async fn clear(&self, prefix: &str) -> Result<(), Self::Error> {
+        self.backend.clear(prefix).await
+    }
+
+    async fn set_count(&self, key: &str, count: u64, ttl: Duration) -> Result<(), Self::Error> {
+        self.backend.set_count(key, count, ttl).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<KeyStatus>, Self::Error> {
+        self.backend.get(key).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<KeyStatus>, Self::Error> {
+        self.backend.list(prefix).await
+    }
+}
+
+#[cfg(all(test, feature = "dashmap"))]
+mod tests {
+    use super::*;
+    use crate::backend::memory::InMemoryBackend;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[actix_web::test]
+    async fn test_utilization() {
+        tokio::time::pause();
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        // The test runtime is single threaded, so this also applies to the background task
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let backend =
+            UtilizationMetricsBackend::builder(InMemoryBackend::builder().build(), "test_policy")
+                .with_flush_interval(Some(MINUTE))
+                .build();
+        let input = SimpleInput {
+            interval: MINUTE,
+            max_requests: 4,
+            key: "KEY1".into(),
+        };
+        // First window is 50% utilized, and flushed by the next request
+        for _ in 0..2 {
+            backend.request(input.clone()).await.unwrap();
+        }
+        tokio::time::advance(MINUTE).await;
+        // Second window has one denied request, and is flushed by the background task
+        for _ in 0..5 {
+            backend.request(input.clone()).await.unwrap();
+        }
+        tokio::time::sleep(MINUTE * 2).await;
+
+        // The InMemoryBackend also records its garbage collection metrics
+        let snapshot: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter(|(key, _, _, _)| key.key().name() == UTILIZATION_HISTOGRAM)
+            .collect();
+        assert_eq!(snapshot.len(), 1);
+        let (key, _, _, value) = &snapshot[0];
+        assert!(key
+            .key()
+            .labels()
+            .any(|l| l.key() == "policy" && l.value() == "test_policy"));
+        let DebugValue::Histogram(values) = value else {
+            panic!("Expected a histogram");
+        };
+        let values: Vec<f64> = values.iter().map(|v| v.into_inner()).collect();
+        assert_eq!(values, vec![0.5, 1.25]);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::backend::{
+    Backend, Decision, KeyedInput, PolicyInput, RateLimitPolicy, SimpleBackend, SimpleInput,
+    SimpleOutput,
+};
+
+/// A [SimpleInput] along with the cost of the request, see [WeightedBackend].
+#[derive(Debug, Clone)]
+pub struct WeightedInput {
+    pub input: SimpleInput,
+    /// The number of requests to charge, e.g. proportional to the size of an upload.
+    pub cost: u64,
+}
+
+impl KeyedInput for WeightedInput {
+    fn key(&self) -> &str {
+        &self.input.key
+    }
+}
+
+impl PolicyInput for WeightedInput {
+    fn set_policy(&mut self, policy: &RateLimitPolicy) {
+        self.input.set_policy(policy);
+    }
+}
+
+/// A [Backend] adapter that charges each request its own cost, using
+/// [SimpleBackend::consume], so that e.g. a 100MB upload consumes more of the limit than a 1KB
+/// POST.
+///
+/// The cost is produced by the input function, see
+/// [SimpleInputFunctionBuilder::build_weighted](crate::backend::SimpleInputFunctionBuilder::build_weighted).
+/// A request is allowed if the count is still within the limit after charging its cost; like
+/// other requests, the cost of denied requests is still charged.
+///
+/// Rollbacks are not supported, since a cost can't be refunded, so they have no effect. A cost
+/// of zero is charged as one request.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_extensible_rate_limit::backend::weighted::{content_length_cost, WeightedBackend};
+/// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+/// # use actix_extensible_rate_limit::RateLimiter;
+/// # use std::time::Duration;
+/// # actix_web::rt::System::new().block_on(async {
+/// // Each client can upload 1GB per hour, counted in 1MB units
+/// let backend = WeightedBackend::new(InMemoryBackend::builder().build());
+/// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60 * 60), 1024)
+///     .real_ip_key()
+///     .build_weighted(content_length_cost(1024 * 1024, 1024));
+/// let middleware = RateLimiter::builder(backend, input).add_headers().build();
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct WeightedBackend<B> {
+    backend: B,
+}
+
+impl<B> WeightedBackend<B> {
+    pub fn new(backend: B) -> Self {
+        Self { backend }
+    }
+}
+
+impl<B: SimpleBackend> Backend<WeightedInput> for WeightedBackend<B> {
+    type Output = SimpleOutput;
+    type RollbackToken = ();
+    type Error = B::Error;
+
+    async fn request(
+        &self,
+        input: WeightedInput,
+    ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+        let (decision, output) = self.backend.consume(input.input, input.cost.max(1)).await?;
+        Ok((decision, output, ()))
+    }
+
+    async fn rollback(&self, _: Self::RollbackToken) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn peek(&self, input: WeightedInput) -> Result<(Decision, Self::Output), Self::Error> {
+        // The output is as if a single request were made, so the remaining cost must fit
+        let (decision, mut output) = self.backend.peek(input.input).await?;
+        let additional = input.cost.max(1) - 1;
+        let allowed = decision.is_allowed() && output.remaining >= additional;
+        output.remaining = output.remaining.saturating_sub(additional);
+        Ok((Decision::from_allowed(allowed), output))
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        self.backend.health_check().await
+    }
+}
+
+/// A cost function for [SimpleInputFunctionBuilder::build_weighted](crate::backend::SimpleInputFunctionBuilder::build_weighted),
+/// that charges one request per `unit` bytes (rounded up) of the request's `Content-Length`.
+///
+/// Requests without a `Content-Length` (e.g. chunked uploads) are charged `missing`. The body
+/// itself isn't buffered, since the input function can't take the request payload, so a server
+/// accepting chunked uploads should also limit the payload size, e.g. using
+/// [PayloadConfig](actix_web::web::PayloadConfig).
+#[cfg(feature = "actix")]
+#[cfg_attr(docsrs, doc(cfg(feature = "actix")))]
+pub fn content_length_cost(
+    unit: u64,
+    missing: u64,
+) -> impl Fn(&actix_web::dev::ServiceRequest) -> std::future::Ready<Result<u64, actix_web::Error>>
+       + Send
+       + Sync
+       + 'static {
+    use actix_web::http::header::CONTENT_LENGTH;
+    assert!(unit > 0, "Unit must be non-zero");
+    move |req| {
+        let length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        let cost = match length {
+            Some(length) => length.div_ceil(unit),
+            None => missing,
+        };
+        std::future::ready(Ok(cost))
+    }
+}
+
+#[cfg(all(test, feature = "dashmap"))]
+mod tests {
+    use super::*;
+    use crate::backend::memory::InMemoryBackend;
+    use std::time::Duration;
+
+    fn input(cost: u64) -> WeightedInput {
+        WeightedInput {
+            input: SimpleInput {
+                interval: Duration::from_secs(60),
+                max_requests: 10,
+                key: "KEY1".into(),
+            },
+            cost,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_weighted() {
+        let backend = WeightedBackend::new(InMemoryBackend::builder().build());
+        let (decision, output, _) = backend.request(input(6)).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 4);
+        // Zero is charged as one
+        let (_, output, _) = backend.request(input(0)).await.unwrap();
+        assert_eq!(output.remaining, 3);
+
+        let (decision, output) = backend.peek(input(3)).await.unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(output.remaining, 0);
+        let (decision, _) = backend.peek(input(4)).await.unwrap();
+        assert!(decision.is_denied());
+
+        let (decision, output, _) = backend.request(input(4)).await.unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(output.remaining, 0);
+    }
+}
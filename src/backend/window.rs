@@ -0,0 +1,113 @@
+//! Helpers for fixed windows that are aligned to epochs counted from a common origin.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// Controls where the boundaries of epoch aligned fixed windows fall.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum WindowAlignment {
+    /// Windows are aligned to the time the backend was created.
+    #[default]
+    Creation,
+    /// Windows are aligned to the wall clock in UTC, shifted by an offset in seconds east of UTC.
+    ///
+    /// For example with an offset of 0 an hourly window always starts on the hour in UTC, and a
+    /// daily window at midnight UTC. With an offset of `-5 * 3600` a daily window starts at
+    /// midnight in UTC-5.
+    ///
+    /// Deployments in different regions using the same alignment will agree on the window
+    /// boundaries, and so report consistent reset times to clients (assuming their clocks are
+    /// synchronized).
+    Utc { offset_seconds: i32 },
+}
+
+impl WindowAlignment {
+    /// Windows aligned to the wall clock in UTC.
+    pub fn utc() -> Self {
+        Self::Utc { offset_seconds: 0 }
+    }
+}
+
+/// Maps instants onto window epochs.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timeline {
+    origin: Instant,
+    // Nanoseconds between the alignment point and the origin.
+    phase: u128,
+}
+
+impl Timeline {
+    pub(crate) fn new(alignment: WindowAlignment) -> Self {
+        let origin = Instant::now();
+        let phase = match alignment {
+            WindowAlignment::Creation => 0,
+            WindowAlignment::Utc { offset_seconds } => {
+                let since_epoch = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("System clock is before the Unix epoch")
+                    .as_nanos() as i128;
+                let offset = offset_seconds as i128 * 1_000_000_000;
+                (since_epoch + offset).max(0) as u128
+            }
+        };
+        Self { origin, phase }
+    }
+
+    /// Returns the epoch number of the window containing `now`.
+    pub(crate) fn epoch(&self, now: Instant, interval: Duration) -> u128 {
+        let interval = interval.as_nanos().max(1);
+        (now.saturating_duration_since(self.origin).as_nanos() + self.phase) / interval
+    }
+
+    /// Returns the time at which the window containing `now` ends.
+    pub(crate) fn next_reset(&self, now: Instant, interval: Duration) -> Instant {
+        self.window_end(self.epoch(now, interval), interval)
+    }
+
+    /// Returns the time at which the window with the given epoch number ends.
+    pub(crate) fn window_end(&self, epoch: u128, interval: Duration) -> Instant {
+        let nanos = (epoch + 1) * interval.as_nanos().max(1) - self.phase;
+        let nanos = u64::try_from(nanos).expect("Interval unexpectedly large");
+        self.origin
+            .checked_add(Duration::from_nanos(nanos))
+            .expect("Interval unexpectedly large")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[actix_web::test]
+    async fn test_utc_alignment() {
+        let timeline = Timeline::new(WindowAlignment::utc());
+        let now = Instant::now();
+        let epoch = timeline.epoch(now, HOUR);
+        let unix = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        assert_eq!(epoch, (unix.as_secs() / 3600) as u128);
+        // The window should end on the hour
+        let until_end = timeline.window_end(epoch, HOUR) - now;
+        let expected = HOUR.as_secs() - unix.as_secs() % 3600;
+        assert!(until_end.as_secs().abs_diff(expected) <= 1);
+    }
+
+    #[actix_web::test]
+    async fn test_offset_alignment() {
+        let utc = Timeline::new(WindowAlignment::utc());
+        let offset = Timeline::new(WindowAlignment::Utc {
+            offset_seconds: 30 * 60,
+        });
+        let now = Instant::now();
+        // A half hour offset moves the hourly window boundary by half an hour
+        let utc_end = utc.window_end(utc.epoch(now, HOUR), HOUR);
+        let offset_end = offset.window_end(offset.epoch(now, HOUR), HOUR);
+        let difference = if utc_end > offset_end {
+            utc_end - offset_end
+        } else {
+            offset_end - utc_end
+        };
+        assert!(difference.as_secs().abs_diff(30 * 60) <= 1);
+    }
+}
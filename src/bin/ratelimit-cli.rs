@@ -0,0 +1,113 @@
+//! Command line tool for inspecting and managing rate limits stored by the
+//! [RedisBackend](actix_extensible_rate_limit::backend::redis::RedisBackend).
+
+use actix_extensible_rate_limit::backend::redis::{KeyStatus, RedisBackend};
+use actix_extensible_rate_limit::backend::{hash_key, SimpleBackend};
+use clap::{Parser, Subcommand};
+use redis::aio::ConnectionManager;
+use std::process::ExitCode;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(name = "ratelimit-cli", version, about)]
+struct Args {
+    /// Redis connection URL.
+    #[arg(long, env = "REDIS_URL", default_value = "redis://127.0.0.1/")]
+    url: String,
+    /// The key prefix configured on the server's RedisBackend.
+    #[arg(long, env = "RATELIMIT_KEY_PREFIX")]
+    key_prefix: Option<String>,
+    /// Hash the keys with SHA-256, for servers that use `hash_key`.
+    #[arg(long)]
+    hash_keys: bool,
+    /// The salt of servers that use `hash_key_salted`, implies --hash-keys.
+    #[arg(long, env = "RATELIMIT_KEY_SALT")]
+    salt: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show the current count and reset time of a key.
+    Status { key: String },
+    /// Reset the count of a key (also lifts a ban).
+    Reset { key: String },
+    /// List the keys with the highest counts.
+    Top {
+        /// Maximum number of keys to show.
+        #[arg(long, short = 'n', default_value_t = 10)]
+        limit: usize,
+    },
+    /// Deny all requests for a key.
+    Ban {
+        key: String,
+        /// Duration of the ban in seconds.
+        #[arg(long, default_value_t = 3600)]
+        seconds: u64,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+impl Args {
+    /// Converts a key to the form stored by the server.
+    fn key(&self, key: &str) -> String {
+        match (&self.salt, self.hash_keys) {
+            (Some(salt), _) => hash_key(key, salt.as_bytes()),
+            (None, true) => hash_key(key, b""),
+            (None, false) => key.to_owned(),
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let client = redis::Client::open(args.url.as_str())?;
+    let manager = ConnectionManager::new(client).await?;
+    let backend = RedisBackend::builder(manager)
+        .key_prefix(args.key_prefix.as_deref())
+        .build();
+    match &args.command {
+        Command::Status { key } => match backend.status(&args.key(key)).await? {
+            Some(status) => print_statuses(&[status]),
+            None => println!("{key}: no requests in the current window"),
+        },
+        Command::Reset { key } => {
+            backend.remove_key(&args.key(key)).await?;
+            println!("{key}: reset");
+        }
+        Command::Top { limit } => print_statuses(&backend.top(*limit).await?),
+        Command::Ban { key, seconds } => {
+            backend
+                .ban(&args.key(key), Duration::from_secs(*seconds))
+                .await?;
+            println!("{key}: banned for {seconds}s");
+        }
+    }
+    Ok(())
+}
+
+fn print_statuses(statuses: &[KeyStatus]) {
+    for status in statuses {
+        let count = if status.is_banned() {
+            "banned".to_string()
+        } else {
+            status.count.to_string()
+        };
+        println!(
+            "{}\tcount={count}\treset={}s",
+            status.key,
+            status.ttl.as_secs()
+        );
+    }
+}
@@ -0,0 +1,45 @@
+//! Runtime support for the [rate_limit](macro@crate::rate_limit) attribute macro.
+
+use crate::backend::memory::InMemoryBackend;
+use crate::backend::{SimpleInputFunctionBuilder, SimpleInputFuture, SimpleOutput};
+use crate::RateLimiter;
+use actix_web::dev::ServiceRequest;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+pub enum Key {
+    RealIp,
+    PeerIp,
+    Global,
+}
+
+/// Builds the middleware for a handler annotated with [rate_limit](macro@crate::rate_limit).
+///
+/// All handlers share a single [InMemoryBackend], the handler path is included in the key so that
+/// each handler is counted separately.
+pub fn handler_limiter(
+    handler: &'static str,
+    interval_millis: u64,
+    max_requests: u64,
+    key: Key,
+) -> RateLimiter<
+    InMemoryBackend,
+    SimpleOutput,
+    impl Fn(&ServiceRequest) -> SimpleInputFuture + Send + Sync + 'static,
+> {
+    static BACKEND: OnceLock<InMemoryBackend> = OnceLock::new();
+    let backend = BACKEND
+        .get_or_init(|| InMemoryBackend::builder().build())
+        .clone();
+    let input =
+        SimpleInputFunctionBuilder::new(Duration::from_millis(interval_millis), max_requests)
+            .custom_key(handler);
+    let input = match key {
+        Key::RealIp => input.real_ip_key(),
+        Key::PeerIp => input.peer_ip_key(),
+        Key::Global => input,
+    };
+    RateLimiter::builder(backend, input.build())
+        .add_headers()
+        .build()
+}
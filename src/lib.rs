@@ -25,11 +25,90 @@
 //!     .await
 //! }
 //! ```
+//!
+//! # Without actix-web
+//!
+//! The backends don't depend on actix-web, so they can also be used from other contexts, such as a
+//! background worker or a CLI tool, by disabling the default `actix` feature:
+//!
+//! ```toml
+//! actix-extensible-rate-limit = { version = "0.4", default-features = false, features = ["redis"] }
+//! ```
+//!
+//! This leaves the [backend] module (except for the input function builders), and the
+//! [tower] adapter if enabled.
 
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+#[cfg(feature = "admin")]
+#[cfg_attr(docsrs, doc(cfg(feature = "admin")))]
+pub mod admin;
 pub mod backend;
+#[cfg(feature = "macros")]
+mod handler_limiter;
+#[cfg(feature = "actix")]
 mod middleware;
+#[cfg(feature = "tower")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tower")))]
+pub mod tower;
+
+pub use ipnet;
+
+pub use backend::HeaderCompatibleOutput;
+#[cfg(all(feature = "actix", feature = "dashmap"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "actix", feature = "dashmap"))))]
+pub use middleware::extractor::{PolicyProvider, RateLimit};
+#[cfg(feature = "actix")]
+#[cfg_attr(docsrs, doc(cfg(feature = "actix")))]
+pub use middleware::{
+    audit::{AuditSink, DenialRecord},
+    builder::{RateLimiterBuilder, ResetFormat},
+    event::RateLimitEvent,
+    guard::RateLimitGuard,
+    handle::RateLimiterHandle,
+    hook::{HookDecision, ResponseContext},
+    localized::LocalizedMessages,
+    merge::HeaderMergeStrategy,
+    notify::LimitViolation,
+    rate_limit,
+    recommended::RecommendedBackend,
+    status::RateLimitStatus,
+    RateLimiter, RateLimiterMiddleware,
+};
+
+/// Rate limit a single handler, without having to wrap it in a [RateLimiter] yourself.
+///
+/// Takes a policy string of the form `<max requests>/<interval>`, e.g. `"10/minute"` or
+/// `"100/5m"`, and optionally the `key` to limit by: `"peer_ip"` (the default), `"real_ip"` or
+/// `"global"`. Invalid policies are reported at compile time.
+///
+/// The attribute must be placed above the actix-web route macro. Each handler is counted
+/// separately, using an [InMemoryBackend](backend::memory::InMemoryBackend) that is shared by
+/// every annotated handler, and the rate limit headers are added to the responses.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::rate_limit;
+/// # use actix_web::{get, post, HttpResponse, Responder};
+/// #[rate_limit("5/minute", key = "real_ip")]
+/// #[post("/login")]
+/// async fn login() -> impl Responder {
+///     HttpResponse::Ok()
+/// }
+///
+/// #[rate_limit("100/1m")]
+/// #[get("/search")]
+/// async fn search() -> impl Responder {
+///     HttpResponse::Ok()
+/// }
+/// ```
+#[cfg(feature = "macros")]
+#[cfg_attr(docsrs, doc(cfg(feature = "macros")))]
+pub use actix_extensible_rate_limit_macros::rate_limit;
 
-pub use middleware::builder::{HeaderCompatibleOutput, RateLimiterBuilder};
-pub use middleware::RateLimiter;
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::handler_limiter::{handler_limiter, Key};
+}
@@ -0,0 +1,96 @@
+use crate::backend::client_ip::parse_ip;
+use actix_web::dev::ServiceRequest;
+use actix_web::HttpResponse;
+use ipnet::IpNet;
+use std::any::Any;
+use std::collections::HashSet;
+
+/// Extracts the rate limit key from the (type erased) backend input.
+pub(crate) type KeyFn = for<'a> fn(&'a dyn Any) -> Option<&'a str>;
+
+type DenylistResponse = dyn Fn(&ServiceRequest) -> HttpResponse + Send + Sync;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Access {
+    /// Always allowed, and never counted.
+    Allow,
+    /// Always denied.
+    Deny,
+}
+
+/// IP address and key allow/deny lists. Denylists take precedence over allowlists.
+pub(crate) struct AccessList {
+    pub(crate) allow_ips: Vec<IpNet>,
+    pub(crate) deny_ips: Vec<IpNet>,
+    pub(crate) real_ip: bool,
+    pub(crate) allow_keys: HashSet<String>,
+    pub(crate) deny_keys: HashSet<String>,
+    pub(crate) key_fn: Option<KeyFn>,
+    pub(crate) denied_response: Box<DenylistResponse>,
+}
+
+impl Default for AccessList {
+    fn default() -> Self {
+        Self {
+            allow_ips: Vec::new(),
+            deny_ips: Vec::new(),
+            real_ip: false,
+            allow_keys: HashSet::new(),
+            deny_keys: HashSet::new(),
+            key_fn: None,
+            denied_response: Box::new(|_| HttpResponse::Forbidden().finish()),
+        }
+    }
+}
+
+impl AccessList {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.allow_ips.is_empty()
+            && self.deny_ips.is_empty()
+            && self.allow_keys.is_empty()
+            && self.deny_keys.is_empty()
+    }
+
+    /// Checks the client IP address against the IP lists.
+    pub(crate) fn check_request(&self, req: &ServiceRequest) -> Option<Access> {
+        if self.allow_ips.is_empty() && self.deny_ips.is_empty() {
+            return None;
+        }
+        let ip = if self.real_ip {
+            req.connection_info()
+                .realip_remote_addr()
+                .and_then(parse_ip)
+        } else {
+            req.peer_addr().map(|addr| addr.ip())
+        }?;
+        check(
+            |nets: &Vec<IpNet>| nets.iter().any(|net| net.contains(&ip)),
+            &self.allow_ips,
+            &self.deny_ips,
+        )
+    }
+
+    /// Checks the rate limit key of the backend input against the key lists.
+    pub(crate) fn check_input(&self, input: &dyn Any) -> Option<Access> {
+        let key = self.key_fn.and_then(|key_fn| key_fn(input))?;
+        check(
+            |keys: &HashSet<String>| keys.contains(key),
+            &self.allow_keys,
+            &self.deny_keys,
+        )
+    }
+
+    pub(crate) fn denied_response(&self, req: &ServiceRequest) -> HttpResponse {
+        (self.denied_response)(req)
+    }
+}
+
+fn check<T>(contains: impl Fn(&T) -> bool, allow: &T, deny: &T) -> Option<Access> {
+    if contains(deny) {
+        Some(Access::Deny)
+    } else if contains(allow) {
+        Some(Access::Allow)
+    } else {
+        None
+    }
+}
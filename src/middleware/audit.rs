@@ -0,0 +1,84 @@
+use crate::backend::client_ip::parse_ip;
+use crate::middleware::access::KeyFn;
+use actix_web::dev::ServiceRequest;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+/// A structured record of a denied request, see
+/// [RateLimiterBuilder::audit_denials](crate::RateLimiterBuilder::audit_denials).
+///
+/// With the `serde` feature enabled this implements `Serialize`, with the timestamp as
+/// milliseconds since the Unix epoch.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DenialRecord {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "unix_millis"))]
+    pub timestamp: SystemTime,
+    /// The rate limit key that was denied.
+    pub key: String,
+    pub method: String,
+    /// The matched route pattern, e.g. `/users/{id}`, if any.
+    pub route: Option<String>,
+    pub path: String,
+    /// The maximum number of requests.
+    pub limit: u64,
+    /// The IP address of the connection peer.
+    pub peer_ip: Option<IpAddr>,
+    /// The client IP address, taken from the `Forwarded` or `X-Forwarded-For` headers if
+    /// present, otherwise the peer address.
+    ///
+    /// The headers can be spoofed by the client unless the server is behind a proxy that
+    /// overwrites them.
+    pub client_ip: Option<IpAddr>,
+}
+
+/// A destination for [DenialRecord]s, e.g. a log file or a SIEM.
+///
+/// This is implemented for any `Fn(DenialRecord)`.
+pub trait AuditSink: Send + Sync + 'static {
+    /// Called synchronously on the request path, so this should be quick, e.g. sending the
+    /// record to a channel rather than writing it to the network.
+    fn record(&self, record: DenialRecord);
+}
+
+impl<F> AuditSink for F
+where
+    F: Fn(DenialRecord) + Send + Sync + 'static,
+{
+    fn record(&self, record: DenialRecord) {
+        self(record)
+    }
+}
+
+/// See [RateLimiterBuilder::audit_denials](crate::RateLimiterBuilder::audit_denials).
+pub(crate) struct Audit<BO> {
+    pub(crate) sink: Box<dyn AuditSink>,
+    pub(crate) key_fn: KeyFn,
+    pub(crate) limit: fn(&BO) -> u64,
+}
+
+impl<BO> Audit<BO> {
+    pub(crate) fn record(&self, req: &ServiceRequest, key: &str, output: &BO) {
+        self.sink.record(DenialRecord {
+            timestamp: SystemTime::now(),
+            key: key.to_owned(),
+            method: req.method().to_string(),
+            route: req.match_pattern(),
+            path: req.path().to_owned(),
+            limit: (self.limit)(output),
+            peer_ip: req.peer_addr().map(|addr| addr.ip()),
+            client_ip: req
+                .connection_info()
+                .realip_remote_addr()
+                .and_then(parse_ip),
+        });
+    }
+}
+
+#[cfg(feature = "serde")]
+fn unix_millis<S: serde::Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    let millis = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+    serializer.serialize_u64(millis)
+}
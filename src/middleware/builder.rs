@@ -1,11 +1,30 @@
-use crate::backend::Backend;
-use crate::middleware::{AllowedTransformation, DeniedResponse, RateLimiter, RollbackCondition};
+use crate::backend::{Backend, Decision, HeaderCompatibleOutput, KeyedInput, PolicyInput};
+use crate::middleware::access::AccessList;
+use crate::middleware::audit::{Audit, AuditSink};
+use crate::middleware::event::{EventHook, RateLimitEvent};
+use crate::middleware::global::{self, GlobalLimit, GlobalLimitResponse};
+use crate::middleware::handle::RateLimiterHandle;
+use crate::middleware::hook::{HookDecision, ResponseContext};
+use crate::middleware::localized::LocalizedMessages;
+use crate::middleware::merge::{HeaderMerge, HeaderMergeStrategy};
+use crate::middleware::notify::{LimitViolation, Notifier};
+#[cfg(feature = "opentelemetry")]
+use crate::middleware::otel::Telemetry;
+#[cfg(feature = "tracing")]
+use crate::middleware::trace::Tracing;
+use crate::middleware::{
+    AllowedTransformation, Challenge, Config, CountOnResponse, DecisionHook, DeniedResponse,
+    FailOpenCondition, PostHook, PreHook, RateLimiter, RollbackCondition, SkipCondition, SoftLimit,
+};
 use actix_web::dev::ServiceRequest;
 use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
-use actix_web::http::StatusCode;
+use actix_web::http::{Method, StatusCode};
 use actix_web::HttpResponse;
-use std::future::Future;
-use std::rc::Rc;
+use futures::future::LocalBoxFuture;
+use ipnet::IpNet;
+use std::future::{ready, Future};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[allow(clippy::declare_interior_mutable_const)]
 pub const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
@@ -13,14 +32,81 @@ pub const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-l
 pub const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
 #[allow(clippy::declare_interior_mutable_const)]
 pub const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+#[allow(clippy::declare_interior_mutable_const)]
+pub const X_OVERAGE: HeaderName = HeaderName::from_static("x-overage");
+#[allow(clippy::declare_interior_mutable_const)]
+pub const X_RATELIMIT_WARNING: HeaderName = HeaderName::from_static("x-ratelimit-warning");
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// The format of the `x-ratelimit-reset` header, see
+/// [RateLimiterBuilder::add_headers_with_reset_format].
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum ResetFormat {
+    /// The number of seconds until the limit resets.
+    #[default]
+    DeltaSeconds,
+    /// The Unix timestamp (in seconds, rounded upwards) at which the limit resets, as used by
+    /// e.g. the GitHub API.
+    ///
+    /// This uses [HeaderCompatibleOutput::reset_at] if the backend provides it, otherwise it is
+    /// calculated from the current system time.
+    UnixTimestamp,
+}
+
+impl ResetFormat {
+    fn value<BO: HeaderCompatibleOutput>(self, status: &BO) -> u64 {
+        match self {
+            ResetFormat::DeltaSeconds => status.seconds_until_reset(),
+            ResetFormat::UnixTimestamp => {
+                let reset_at = status.reset_at().unwrap_or_else(|| {
+                    SystemTime::now() + Duration::from_secs(status.seconds_until_reset())
+                });
+                let since_epoch = reset_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+                since_epoch.as_secs() + u64::from(since_epoch.subsec_nanos() > 0)
+            }
+        }
+    }
+}
 
 pub struct RateLimiterBuilder<BE, BO, F> {
     backend: BE,
     input_fn: F,
     fail_open: bool,
-    allowed_transformation: Option<Rc<AllowedTransformation<BO>>>,
-    denied_response: Rc<DeniedResponse<BO>>,
-    rollback_condition: Option<Rc<RollbackCondition>>,
+    fail_open_transient: bool,
+    allowed_transformation: Option<Arc<AllowedTransformation<BO>>>,
+    denied_response: Arc<DeniedResponse<BO>>,
+    rollback_condition: Option<Arc<RollbackCondition>>,
+    header_merge: Option<Arc<HeaderMerge<BO>>>,
+    skip_condition: Option<Arc<SkipCondition>>,
+    access_list: AccessList,
+    rollback_on_cancel: bool,
+    count_on_response: Option<Arc<CountOnResponse>>,
+    only_methods: Option<Vec<Method>>,
+    challenge: Option<Arc<Challenge<BO>>>,
+    fail_open_condition: Option<Arc<FailOpenCondition>>,
+    soft_limit: Option<SoftLimit<BO>>,
+    peek_only: bool,
+    limit_connections: bool,
+    on_event: Option<Arc<EventHook<BO>>>,
+    notifier: Option<Arc<Notifier>>,
+    audit: Option<Arc<Audit<BO>>>,
+    pre_hook: Option<Arc<PreHook>>,
+    decision_hook: Option<Arc<DecisionHook<BO>>>,
+    post_hook: Option<Arc<PostHook<BO>>>,
+    global_limit: Option<(Duration, u64)>,
+    global_limit_response: Box<GlobalLimitResponse>,
+    #[cfg(feature = "tracing")]
+    tracing: Option<Arc<Tracing<BO>>>,
+    #[cfg(feature = "opentelemetry")]
+    otel: Option<Arc<Telemetry<BO>>>,
+}
+
+fn sync_denied_response<BO, R>(denied_response: R) -> Arc<DeniedResponse<BO>>
+where
+    R: Fn(&BO) -> HttpResponse + Send + Sync + 'static,
+{
+    Arc::new(move |_, output| Box::pin(ready(denied_response(output))))
 }
 
 impl<BE, BI, BO, F, O> RateLimiterBuilder<BE, BO, F>
@@ -35,9 +121,33 @@ where
             backend,
             input_fn,
             fail_open: false,
+            fail_open_transient: false,
             allowed_transformation: None,
-            denied_response: Rc::new(|_| HttpResponse::TooManyRequests().finish()),
+            denied_response: sync_denied_response(|_| HttpResponse::TooManyRequests().finish()),
             rollback_condition: None,
+            header_merge: None,
+            skip_condition: None,
+            access_list: AccessList::default(),
+            rollback_on_cancel: false,
+            count_on_response: None,
+            only_methods: None,
+            challenge: None,
+            fail_open_condition: None,
+            soft_limit: None,
+            peek_only: false,
+            limit_connections: false,
+            on_event: None,
+            notifier: None,
+            audit: None,
+            pre_hook: None,
+            decision_hook: None,
+            post_hook: None,
+            global_limit: None,
+            global_limit_response: Box::new(global::default_response),
+            #[cfg(feature = "tracing")]
+            tracing: None,
+            #[cfg(feature = "opentelemetry")]
+            otel: None,
         }
     }
 
@@ -49,6 +159,36 @@ where
         self
     }
 
+    /// Choose whether to allow a request if the backend returns a
+    /// [Transient](crate::backend::BackendError::Transient) failure, but fail closed on other
+    /// classes of [BackendError](crate::backend::BackendError), since a misconfigured backend
+    /// would otherwise silently disable rate limiting, and a rejected key would let that client
+    /// bypass it.
+    ///
+    /// This has no effect if [RateLimiterBuilder::fail_open] is enabled.
+    ///
+    /// Default is false.
+    pub fn fail_open_transient(mut self, fail_open_transient: bool) -> Self {
+        self.fail_open_transient = fail_open_transient;
+        self
+    }
+
+    /// Allow a request if the backend returns a failure matching the condition, e.g. fail open on
+    /// connection timeouts, but fail closed on errors that indicate a misconfiguration.
+    ///
+    /// This is checked in addition to [RateLimiterBuilder::fail_open_transient], so can be used
+    /// to fail open on other classes of [BackendError](crate::backend::BackendError) too.
+    pub fn fail_open_if<C>(mut self, condition: C) -> Self
+    where
+        BE::Error: 'static,
+        C: Fn(&BE::Error) -> bool + Send + Sync + 'static,
+    {
+        self.fail_open_condition = Some(Arc::new(move |e| {
+            e.downcast_ref::<BE::Error>().is_some_and(&condition)
+        }));
+        self
+    }
+
     /// Sets the [RateLimiterBuilder::request_allowed_transformation] and
     /// [RateLimiterBuilder::request_denied_response] functions, such that the following headers
     /// are set in both the allowed and denied responses:
@@ -57,13 +197,25 @@ where
     /// - `x-ratelimit-remaining`\
     /// - `x-ratelimit-reset` (seconds until the reset)
     /// - `retry-after` (denied only, seconds until the reset)
+    /// - `x-overage: true` (allowed only, if [HeaderCompatibleOutput::is_overage] is true)
     ///
     /// This function requires the Backend Output to implement [HeaderCompatibleOutput]
-    pub fn add_headers(mut self) -> Self
+    pub fn add_headers(self) -> Self
+    where
+        BO: HeaderCompatibleOutput,
+    {
+        self.add_headers_with_reset_format(ResetFormat::DeltaSeconds)
+    }
+
+    /// Like [RateLimiterBuilder::add_headers], but with the given format for the
+    /// `x-ratelimit-reset` header, e.g. a Unix timestamp for clients that expect one.
+    ///
+    /// The `retry-after` header is always in seconds until the reset.
+    pub fn add_headers_with_reset_format(mut self, format: ResetFormat) -> Self
     where
         BO: HeaderCompatibleOutput,
     {
-        self.allowed_transformation = Some(Rc::new(|map, output, rolled_back| {
+        self.allowed_transformation = Some(Arc::new(move |map, output, rolled_back| {
             if let Some(status) = output {
                 map.insert(X_RATELIMIT_LIMIT, HeaderValue::from(status.limit()));
                 let remaining = if rolled_back {
@@ -72,25 +224,173 @@ where
                     status.remaining()
                 };
                 map.insert(X_RATELIMIT_REMAINING, HeaderValue::from(remaining));
-                map.insert(
-                    X_RATELIMIT_RESET,
-                    HeaderValue::from(status.seconds_until_reset()),
-                );
+                map.insert(X_RATELIMIT_RESET, HeaderValue::from(format.value(status)));
+                if status.is_overage() && !rolled_back {
+                    map.insert(X_OVERAGE, HeaderValue::from_static("true"));
+                }
             }
         }));
-        self.denied_response = Rc::new(|status| {
+        self.denied_response = sync_denied_response(move |status: &BO| {
             let mut response = HttpResponse::TooManyRequests().finish();
             let map = response.headers_mut();
             map.insert(X_RATELIMIT_LIMIT, HeaderValue::from(status.limit()));
             map.insert(X_RATELIMIT_REMAINING, HeaderValue::from(status.remaining()));
+            map.insert(X_RATELIMIT_RESET, HeaderValue::from(format.value(status)));
+            map.insert(RETRY_AFTER, HeaderValue::from(status.seconds_until_reset()));
+            response
+        });
+        self.header_merge = None;
+        self
+    }
+
+    /// Like [RateLimiterBuilder::add_headers], but for when multiple [RateLimiter]s wrap the same
+    /// service, where otherwise the outermost limiter would overwrite the headers of the others.
+    ///
+    /// Each limiter records the status of its policy in the response extensions, and the headers
+    /// are rendered from all the recorded policies according to the `strategy`. All the limiters
+    /// should use the same strategy.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy`: A name for the policy enforced by this limiter, e.g. `minute`. This is used
+    ///   as the header prefix for [HeaderMergeStrategy::Prefixed].
+    /// * `strategy`: How to combine the headers.
+    pub fn add_merged_headers(mut self, policy: &str, strategy: HeaderMergeStrategy) -> Self
+    where
+        BO: HeaderCompatibleOutput,
+    {
+        self.allowed_transformation = None;
+        self.denied_response = sync_denied_response(|status: &BO| {
+            let mut response = HttpResponse::TooManyRequests().finish();
             let seconds = status.seconds_until_reset();
-            map.insert(X_RATELIMIT_RESET, HeaderValue::from(seconds));
-            map.insert(RETRY_AFTER, HeaderValue::from(seconds));
             response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+            response
+        });
+        self.header_merge = Some(Arc::new(HeaderMerge::new(policy, strategy)));
+        self
+    }
+
+    /// Sets the [RateLimiterBuilder::request_denied_response] to an
+    /// [RFC 9457](https://www.rfc-editor.org/rfc/rfc9457) `application/problem+json` body, with
+    /// the `retry-after` header, for example:
+    ///
+    /// ```json
+    /// {
+    ///   "type": "about:blank",
+    ///   "title": "Too Many Requests",
+    ///   "status": 429,
+    ///   "detail": "Rate limit exceeded, retry after 30 seconds",
+    ///   "limit": 100,
+    ///   "remaining": 0,
+    ///   "retry_after": 30
+    /// }
+    /// ```
+    ///
+    /// Note this replaces the denied response set by [RateLimiterBuilder::add_headers], so should
+    /// be called afterwards if both are used.
+    pub fn deny_with_problem_json(mut self) -> Self
+    where
+        BO: HeaderCompatibleOutput,
+    {
+        self.denied_response = sync_denied_response(|status: &BO| {
+            let seconds = status.seconds_until_reset();
+            let body = format!(
+                concat!(
+                    r#"{{"type":"about:blank","title":"Too Many Requests","status":429,"#,
+                    r#""detail":"Rate limit exceeded, retry after {} seconds","#,
+                    r#""limit":{},"remaining":{},"retry_after":{}}}"#
+                ),
+                seconds,
+                status.limit(),
+                status.remaining(),
+                seconds
+            );
+            HttpResponse::TooManyRequests()
+                .content_type(PROBLEM_JSON)
+                .insert_header((RETRY_AFTER, seconds))
+                .body(body)
         });
         self
     }
 
+    /// Sets the [RateLimiterBuilder::request_denied_response] to a JSON body, serialized from the
+    /// value returned by `body`, with the `application/json` content type.
+    ///
+    /// Note this replaces the denied response set by [RateLimiterBuilder::add_headers], so should
+    /// be called afterwards if both are used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::RateLimiter;
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::{SimpleInputFunctionBuilder, SimpleOutput};
+    /// # use actix_extensible_rate_limit::HeaderCompatibleOutput;
+    /// # use std::time::Duration;
+    /// # actix_web::rt::System::new().block_on(async {
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .deny_json(|output: &SimpleOutput| {
+    ///         serde_json::json!({
+    ///             "error": "rate_limited",
+    ///             "retry_after": output.seconds_until_reset(),
+    ///         })
+    ///     })
+    ///     .build();
+    /// # });
+    /// ```
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub fn deny_json<J, T>(mut self, body: J) -> Self
+    where
+        J: Fn(&BO) -> T + Send + Sync + 'static,
+        T: serde::Serialize,
+    {
+        self.denied_response = sync_denied_response(move |output: &BO| {
+            HttpResponse::TooManyRequests().json(body(output))
+        });
+        self
+    }
+
+    /// Add an `x-ratelimit-warning: true` header to allowed responses once the fraction of the
+    /// limit remaining falls below the threshold, e.g. `0.1` to warn once fewer than 10% of the
+    /// requests remain. This lets clients back off before they are denied.
+    pub fn soft_limit_warning(mut self, threshold: f64) -> Self
+    where
+        BO: HeaderCompatibleOutput,
+    {
+        assert!(
+            (0f64..=1f64).contains(&threshold),
+            "Soft limit threshold must be between 0 and 1"
+        );
+        self.soft_limit
+            .get_or_insert_with(SoftLimit::new)
+            .header_threshold = Some(threshold);
+        self
+    }
+
+    /// Call a function for each allowed request once the fraction of the limit remaining falls
+    /// below the threshold, e.g. to log clients that are approaching their limit.
+    ///
+    /// The function is called before the request is passed to the inner service.
+    pub fn on_soft_limit<C>(mut self, threshold: f64, callback: C) -> Self
+    where
+        BO: HeaderCompatibleOutput,
+        C: Fn(&ServiceRequest, &BO) + Send + Sync + 'static,
+    {
+        assert!(
+            (0f64..=1f64).contains(&threshold),
+            "Soft limit threshold must be between 0 and 1"
+        );
+        self.soft_limit.get_or_insert_with(SoftLimit::new).callback =
+            Some((threshold, Box::new(callback)));
+        self
+    }
+
     /// In the event that the request is allowed:
     ///
     /// You can optionally mutate the response headers to include the rate limit status.
@@ -104,9 +404,54 @@ where
     /// request count can be adjusted).
     pub fn request_allowed_transformation<M>(mut self, mutation: Option<M>) -> Self
     where
-        M: Fn(&mut HeaderMap, Option<&BO>, bool) + 'static,
+        M: Fn(&mut HeaderMap, Option<&BO>, bool) + Send + Sync + 'static,
+    {
+        self.allowed_transformation =
+            mutation.map(|m| Arc::new(m) as Arc<AllowedTransformation<BO>>);
+        self
+    }
+
+    /// Sets the [RateLimiterBuilder::request_denied_response] to a plain text message in the
+    /// language that best matches the client's `Accept-Language` header, with the
+    /// `content-language` and `retry-after` headers.
+    ///
+    /// See [LocalizedMessages] for the placeholders available in the templates.
+    ///
+    /// Note this replaces the denied response set by [RateLimiterBuilder::add_headers], so should
+    /// be called afterwards if both are used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::{LocalizedMessages, RateLimiter};
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use std::time::Duration;
+    /// # actix_web::rt::System::new().block_on(async {
+    /// let messages = LocalizedMessages::new("en", "Too many requests, retry after {retry_after}s")
+    ///     .add("es", "Demasiadas solicitudes, reintente en {retry_after} s");
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .add_headers()
+    ///     .deny_with_localized_message(messages)
+    ///     .build();
+    /// # });
+    /// ```
+    pub fn deny_with_localized_message(mut self, messages: LocalizedMessages) -> Self
+    where
+        BO: HeaderCompatibleOutput,
     {
-        self.allowed_transformation = mutation.map(|m| Rc::new(m) as Rc<AllowedTransformation<BO>>);
+        self.denied_response = Arc::new(move |req, status| {
+            let response = messages.response(
+                req,
+                status.limit(),
+                status.remaining(),
+                status.seconds_until_reset(),
+            );
+            Box::pin(ready(response))
+        });
         self
     }
 
@@ -115,21 +460,67 @@ where
     /// Defaults to an empty body with status 429.
     pub fn request_denied_response<R>(mut self, denied_response: R) -> Self
     where
-        R: Fn(&BO) -> HttpResponse + 'static,
+        R: Fn(&BO) -> HttpResponse + Send + Sync + 'static,
     {
-        self.denied_response = Rc::new(denied_response);
+        self.denied_response = sync_denied_response(denied_response);
+        self
+    }
+
+    /// Like [RateLimiterBuilder::request_denied_response], but the [HttpResponse] is created
+    /// asynchronously, e.g. to render a custom page from a template on disk.
+    ///
+    /// The body can also be streamed using
+    /// [HttpResponseBuilder::streaming](actix_web::HttpResponseBuilder::streaming).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::RateLimiter;
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::{SimpleInputFunctionBuilder, SimpleOutput};
+    /// # use actix_web::web::Bytes;
+    /// # use actix_web::HttpResponse;
+    /// # use std::time::Duration;
+    /// # async fn render_template(name: &str, remaining: u64) -> String { String::new() }
+    /// # actix_web::rt::System::new().block_on(async {
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .request_denied_response_async(|output: &SimpleOutput| {
+    ///         let limit = output.limit;
+    ///         async move {
+    ///             let page = render_template("429.html", limit).await;
+    ///             let body = futures::stream::once(async move {
+    ///                 Ok::<_, actix_web::Error>(Bytes::from(page))
+    ///             });
+    ///             HttpResponse::TooManyRequests()
+    ///                 .content_type("text/html")
+    ///                 .streaming(body)
+    ///         }
+    ///     })
+    ///     .build();
+    /// # });
+    /// ```
+    pub fn request_denied_response_async<R, RO>(mut self, denied_response: R) -> Self
+    where
+        R: Fn(&BO) -> RO + Send + Sync + 'static,
+        RO: Future<Output = HttpResponse> + 'static,
+    {
+        self.denied_response = Arc::new(move |_, output| Box::pin(denied_response(output)));
         self
     }
 
     /// After processing a request, attempt to rollback the request count based on the status
     /// of the service response.
     ///
-    /// By default the rate limit is never rolled back.
+    /// By default the rate limit is never rolled back. To only count some responses, consider
+    /// [RateLimiterBuilder::count_when] instead.
     pub fn rollback_condition<C>(mut self, condition: Option<C>) -> Self
     where
-        C: Fn(StatusCode) -> bool + 'static,
+        C: Fn(StatusCode) -> bool + Send + Sync + 'static,
     {
-        self.rollback_condition = condition.map(|m| Rc::new(m) as Rc<RollbackCondition>);
+        self.rollback_condition = condition.map(|m| Arc::new(m) as Arc<RollbackCondition>);
         self
     }
 
@@ -139,30 +530,673 @@ where
         self.rollback_condition(Some(|status: StatusCode| status.is_server_error()))
     }
 
+    /// Rollback the rate limit count if the request is cancelled before the inner service
+    /// completes, e.g. because the client disconnected.
+    ///
+    /// The rollback is performed in a background task.
+    ///
+    /// Default is false.
+    pub fn rollback_on_cancel(mut self, rollback_on_cancel: bool) -> Self {
+        self.rollback_on_cancel = rollback_on_cancel;
+        self
+    }
+
+    /// In the event that the request is denied, optionally return an alternative [HttpResponse]
+    /// based on the rate limit key, e.g. to serve a CAPTCHA challenge or redirect suspicious
+    /// clients to a verification page instead of a bare 429.
+    ///
+    /// If the function returns None then the [RateLimiterBuilder::request_denied_response] is
+    /// used. An alternative response is returned as is, without any rate limit headers.
+    pub fn challenge_response<R>(mut self, response: R) -> Self
+    where
+        BI: KeyedInput,
+        R: Fn(&ServiceRequest, &str, &BO) -> Option<HttpResponse> + Send + Sync + 'static,
+    {
+        self.challenge = Some(Arc::new(Challenge {
+            response: Box::new(response),
+            key_fn: |input| input.downcast_ref::<BI>().map(BI::key),
+        }));
+        self
+    }
+
+    /// Override the decision of the backend, e.g. to let premium clients exceed the limit, or to
+    /// deny requests that are allowed by the limit but are otherwise suspicious.
+    ///
+    /// The hook is called with the decision and output of the backend, before any other handling
+    /// of the decision (e.g. [RateLimiterBuilder::on_event] and
+    /// [RateLimiterBuilder::challenge_response] see the overridden decision). A
+    /// [HookDecision::Deny] response is returned as is, without any rate limit headers.
+    ///
+    /// The request has already been counted by the backend when the hook is called, overriding
+    /// the decision doesn't change the count.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::{HookDecision, RateLimiter};
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use std::time::Duration;
+    /// # #[actix_web::main]
+    /// # async fn main() {
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .decision_hook(|req, _, _| {
+    ///         if req.headers().contains_key("x-premium") {
+    ///             HookDecision::Allow
+    ///         } else {
+    ///             HookDecision::Continue
+    ///         }
+    ///     })
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn decision_hook<H>(mut self, hook: H) -> Self
+    where
+        H: Fn(&ServiceRequest, Decision, &BO) -> HookDecision + Send + Sync + 'static,
+    {
+        self.decision_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Run a hook once the response to a request checked by the backend is ready, after the
+    /// headers have been added, e.g. to feed per-tenant usage into a billing pipeline.
+    ///
+    /// The hook receives the [ResponseContext], including the decision, the output of the backend
+    /// and the final status code. It is called for allowed and denied requests, but not for
+    /// requests that bypassed the backend (e.g. [skipped](RateLimiterBuilder::skip_if)), that
+    /// failed when not failing open, or when [peeking](RateLimiterBuilder::peek_only).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::RateLimiter;
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use std::time::Duration;
+    /// # #[actix_web::main]
+    /// # async fn main() {
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .post_hook(|context| {
+    ///         log::info!(
+    ///             "{} {:?} {} in {:?}",
+    ///             context.request.path(),
+    ///             context.decision,
+    ///             context.status,
+    ///             context.elapsed
+    ///         )
+    ///     })
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn post_hook<H>(mut self, hook: H) -> Self
+    where
+        H: Fn(&ResponseContext<'_, BO>) + Send + Sync + 'static,
+    {
+        self.post_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Check requests using [Backend::peek()] without counting or denying them, e.g. for an
+    /// endpoint that shows the client how much of their quota they have used.
+    ///
+    /// The status is available to handlers using the [RateLimitStatus](crate::RateLimitStatus)
+    /// extractor, it is the same as if the request had been counted now. The
+    /// decision may be denied, but the request is still passed to the inner service, and no
+    /// headers are added to the response.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::{RateLimiter, RateLimitStatus};
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use actix_web::{web, App};
+    /// # use std::time::Duration;
+    /// # #[actix_web::main]
+    /// # async fn main() {
+    /// let backend = InMemoryBackend::builder().build();
+    /// let input = || SimpleInputFunctionBuilder::new(Duration::from_secs(60), 100)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let app = App::new()
+    ///     .service(
+    ///         web::resource("/quota")
+    ///             .route(web::get().to(|status: RateLimitStatus| async move {
+    ///                 format!("{:?} requests remaining", status.remaining())
+    ///             }))
+    ///             .wrap(RateLimiter::builder(backend.clone(), input()).peek_only().build()),
+    ///     )
+    ///     .service(
+    ///         web::scope("/api").wrap(RateLimiter::builder(backend, input()).build()),
+    ///     );
+    /// # }
+    /// ```
+    pub fn peek_only(mut self) -> Self {
+        self.peek_only = true;
+        self
+    }
+
+    /// Limit long-lived connections, i.e. WebSocket upgrades and server-sent events (identified
+    /// by a `101 Switching Protocols` status or a `text/event-stream` content type), as concurrent
+    /// sessions per key, rather than as a single request.
+    ///
+    /// The count of a long-lived connection is rolled back once its response body is dropped,
+    /// which is when the connection closes, so `max_requests` is the number of connections each
+    /// key may have open at once. Rollbacks are performed in a background task. Denied requests
+    /// are also rolled back, so that they don't hold a session. Other requests are counted as
+    /// usual, so this is best used on a scope containing only the long-lived routes.
+    ///
+    /// The interval of the policy should be longer than any connection, since the count of a
+    /// connection that outlives the window is no longer held. This is not supported in
+    /// combination with [RateLimiterBuilder::count_when].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::RateLimiter;
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use actix_web::{web, App};
+    /// # use std::time::Duration;
+    /// # actix_web::rt::System::new().block_on(async {
+    /// // At most 3 open connections per client
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60 * 60 * 24), 3)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .limit_connections()
+    ///     .build();
+    /// let app = App::new().service(web::scope("/events").wrap(limiter));
+    /// # });
+    /// ```
+    pub fn limit_connections(mut self) -> Self {
+        self.limit_connections = true;
+        self
+    }
+
+    /// Call a function whenever a request is allowed, denied or rolled back, or the backend
+    /// fails, e.g. to integrate with a metrics or alerting system.
+    ///
+    /// The function is called synchronously on the request path, so it should be quick. When
+    /// [RateLimiterBuilder::peek_only] is enabled, only backend errors are reported.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::{RateLimiter, RateLimitEvent};
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use std::time::Duration;
+    /// # #[actix_web::main]
+    /// # async fn main() {
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .on_event(|event| {
+    ///         if let RateLimitEvent::Denied { key, .. } = &event {
+    ///             log::info!("Denied request for {key}");
+    ///         }
+    ///         if let Some(latency) = event.latency().filter(|l| *l > Duration::from_millis(5)) {
+    ///             log::warn!("Rate limiter backend took {latency:?}");
+    ///         }
+    ///     })
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn on_event<E>(mut self, callback: E) -> Self
+    where
+        BI: KeyedInput,
+        E: Fn(RateLimitEvent<'_, BO>) + Send + Sync + 'static,
+    {
+        self.on_event = Some(Arc::new(EventHook {
+            callback: Box::new(callback),
+            key_fn: |input| input.downcast_ref::<BI>().map(BI::key),
+        }));
+        self
+    }
+
+    /// Call an async function when a key is denied `threshold` times within a `window`, e.g. to
+    /// post an alert to Slack or PagerDuty about sustained limit violations.
+    ///
+    /// Notifications are deduplicated, so the function is called at most once per key in each
+    /// window (which starts at the first denied request). The future is spawned on the current
+    /// worker, so it doesn't delay the response. The denied requests are counted in memory, and
+    /// are shared by every clone of the [RateLimiter].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::RateLimiter;
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use std::time::Duration;
+    /// # #[actix_web::main]
+    /// # async fn main() {
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .notify_violations(100, Duration::from_secs(600), |violation| async move {
+    ///         log::warn!("{} was denied {} times", violation.key, violation.denied);
+    ///     })
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn notify_violations<N, NO>(mut self, threshold: u64, window: Duration, callback: N) -> Self
+    where
+        BI: KeyedInput,
+        N: Fn(LimitViolation) -> NO + Send + Sync + 'static,
+        NO: Future<Output = ()> + 'static,
+    {
+        assert!(threshold > 0, "Threshold must be non-zero");
+        assert!(!window.is_zero(), "Window must be non-zero");
+        self.notifier = Some(Arc::new(Notifier::new(
+            |input| input.downcast_ref::<BI>().map(BI::key),
+            threshold,
+            window,
+            Box::new(move |violation| Box::pin(callback(violation))),
+        )));
+        self
+    }
+
+    /// Send a structured [DenialRecord](crate::DenialRecord) to the sink for
+    /// every denied request, including the key, route, limit and client IP, e.g. to feed
+    /// throttling events into a SIEM.
+    ///
+    /// Enable the `serde` feature to serialize the records.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::RateLimiter;
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use std::time::Duration;
+    /// # #[actix_web::main]
+    /// # async fn main() {
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .audit_denials(|record| log::warn!(target: "audit", "{record:?}"))
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn audit_denials<S>(mut self, sink: S) -> Self
+    where
+        BI: KeyedInput,
+        BO: HeaderCompatibleOutput,
+        S: AuditSink,
+    {
+        self.audit = Some(Arc::new(Audit {
+            sink: Box::new(sink),
+            key_fn: |input| input.downcast_ref::<BI>().map(BI::key),
+            limit: |output| output.limit(),
+        }));
+        self
+    }
+
+    /// Instrument each request with a `rate_limit` [tracing] span around the backend call, so
+    /// that denials can be correlated with the surrounding request span in distributed traces.
+    ///
+    /// The span has the following fields:
+    /// - `key_hash`: A hash of the rate limit key, so that client identifiers aren't exported.
+    /// - `decision`: `allowed`, `denied` or `error`.
+    /// - `remaining`: The number of requests remaining.
+    /// - `latency_ms`: The duration of the backend call.
+    ///
+    /// An event is also emitted within the span when a request is denied, or the backend fails.
+    /// Requests checked with [RateLimiterBuilder::peek_only] are not instrumented.
+    #[cfg(feature = "tracing")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "tracing")))]
+    pub fn tracing(mut self) -> Self
+    where
+        BI: KeyedInput,
+        BO: HeaderCompatibleOutput,
+    {
+        self.tracing = Some(Arc::new(Tracing {
+            key_fn: |input| input.downcast_ref::<BI>().map(BI::key),
+            remaining: |output| output.remaining(),
+        }));
+        self
+    }
+
+    /// Record the rate limit decision on the active [OpenTelemetry](opentelemetry) span, which is
+    /// usually the HTTP server span, so that throttling can be charted per endpoint.
+    ///
+    /// The following span attributes are set:
+    /// - `rate_limiting.decision`: `allowed`, `denied` or `error`.
+    /// - `rate_limiting.limit`: The maximum number of requests.
+    /// - `rate_limiting.remaining`: The number of requests remaining.
+    /// - `rate_limiting.reset_after`: The number of seconds until the limit resets.
+    ///
+    /// A `rate_limiting.denied` event is also added when a request is denied, or a
+    /// `rate_limiting.error` event when the backend fails.
+    /// Requests checked with [RateLimiterBuilder::peek_only] are not recorded.
+    #[cfg(feature = "opentelemetry")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "opentelemetry")))]
+    pub fn opentelemetry(mut self) -> Self
+    where
+        BO: HeaderCompatibleOutput,
+    {
+        self.otel = Some(Arc::new(Telemetry {
+            limit: |output| output.limit(),
+            remaining: |output| output.remaining(),
+            seconds_until_reset: |output| output.seconds_until_reset(),
+        }));
+        self
+    }
+
+    /// Only count a request against the rate limit if the condition matches the response status
+    /// code, e.g. to only count successful (2xx) responses, or only failed login attempts (401).
+    ///
+    /// The request is counted after the inner service has responded. Requests are still denied
+    /// beforehand if the limit has been reached, using [Backend::peek()], so the backend is
+    /// queried once for requests that are not counted and twice for those that are. This is
+    /// cheaper than a [RateLimiterBuilder::rollback_condition] (which counts every request, and
+    /// then rolls back those that shouldn't have been) when most requests are not counted, but
+    /// concurrent requests may all be allowed before any of them are counted.
+    ///
+    /// In this mode there is nothing to rollback, so [RateLimiterBuilder::rollback_condition]
+    /// and [RateLimiterBuilder::rollback_on_cancel] have no effect.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::RateLimiter;
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use actix_web::http::StatusCode;
+    /// # use std::time::Duration;
+    /// # actix_web::rt::System::new().block_on(async {
+    /// // Allow 5 failed login attempts per hour
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60 * 60), 5)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .count_when(|status| status == StatusCode::UNAUTHORIZED)
+    ///     .build();
+    /// # });
+    /// ```
+    pub fn count_when<C>(mut self, condition: C) -> Self
+    where
+        BI: Clone,
+        C: Fn(StatusCode) -> bool + Send + Sync + 'static,
+    {
+        self.count_on_response = Some(Arc::new(CountOnResponse::new::<BI>(Box::new(condition))));
+        self
+    }
+
+    /// Bypass the rate limiter entirely for requests matching the condition, e.g. health checks,
+    /// CORS preflight requests, or authenticated admin traffic.
+    ///
+    /// Skipped requests are not counted, and no headers are added to their responses.
+    pub fn skip_if<C>(mut self, condition: C) -> Self
+    where
+        C: Fn(&ServiceRequest) -> bool + Send + Sync + 'static,
+    {
+        self.skip_condition = Some(Arc::new(move |req| Box::pin(ready(condition(req)))));
+        self
+    }
+
+    /// Asynchronous version of [RateLimiterBuilder::skip_if].
+    pub fn skip_if_async<C, CO>(mut self, condition: C) -> Self
+    where
+        C: Fn(&ServiceRequest) -> CO + Send + Sync + 'static,
+        CO: Future<Output = bool> + 'static,
+    {
+        self.skip_condition = Some(Arc::new(move |req| Box::pin(condition(req))));
+        self
+    }
+
+    /// Run a hook before the backend is called, which may allow the request without rate limiting
+    /// it, deny it with a response, or continue as usual. This generalizes
+    /// [RateLimiterBuilder::skip_if], e.g. for maintenance mode or authentication based bypasses.
+    ///
+    /// The hook runs after any [skip condition](RateLimiterBuilder::skip_if), and before the
+    /// allowlists and denylists. Requests that are allowed or denied by the hook are not counted,
+    /// and no headers are added to their responses.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::{HookDecision, RateLimiter};
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use actix_web::HttpResponse;
+    /// # use std::sync::atomic::{AtomicBool, Ordering};
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// # #[actix_web::main]
+    /// # async fn main() {
+    /// let maintenance = Arc::new(AtomicBool::new(false));
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .pre_hook(move |_| {
+    ///         let maintenance = maintenance.load(Ordering::Relaxed);
+    ///         async move {
+    ///             if maintenance {
+    ///                 HookDecision::Deny(HttpResponse::ServiceUnavailable().finish())
+    ///             } else {
+    ///                 HookDecision::Continue
+    ///             }
+    ///         }
+    ///     })
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn pre_hook<H, HO>(mut self, hook: H) -> Self
+    where
+        H: Fn(&ServiceRequest) -> HO + Send + Sync + 'static,
+        HO: Future<Output = HookDecision> + 'static,
+    {
+        self.pre_hook = Some(Arc::new(move |req| Box::pin(hook(req))));
+        self
+    }
+
+    /// Only rate limit requests using one of these methods, e.g. `POST`, `PUT` and `DELETE`.
+    ///
+    /// Requests using other methods bypass the rate limiter, in the same way as
+    /// [RateLimiterBuilder::skip_if], which this can be combined with.
+    pub fn only_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.only_methods = Some(methods.into_iter().collect());
+        self
+    }
+
+    /// Requests from these IP networks are always allowed, and are never counted.
+    ///
+    /// By default the IP lists are matched against the connection peer address, see
+    /// [RateLimiterBuilder::ip_lists_use_real_ip].
+    pub fn allowlist_ips(mut self, networks: impl IntoIterator<Item = IpNet>) -> Self {
+        self.access_list.allow_ips.extend(networks);
+        self
+    }
+
+    /// Requests from these IP networks are always denied, with the
+    /// [RateLimiterBuilder::denylist_response].
+    ///
+    /// The denylist takes precedence over the allowlist.
+    pub fn denylist_ips(mut self, networks: impl IntoIterator<Item = IpNet>) -> Self {
+        self.access_list.deny_ips.extend(networks);
+        self
+    }
+
+    /// Match the IP lists against
+    /// [ConnectionInfo::realip_remote_addr()](actix_web::dev::ConnectionInfo::realip_remote_addr)
+    /// instead of the connection peer address.
+    ///
+    /// # Security
+    ///
+    /// This is only suitable for Actix applications deployed behind a proxy that you control.
+    pub fn ip_lists_use_real_ip(mut self, real_ip: bool) -> Self {
+        self.access_list.real_ip = real_ip;
+        self
+    }
+
+    /// Requests whose rate limit key (produced by the input function) matches one of these keys
+    /// are always allowed, and are never counted.
+    pub fn allowlist_keys<K>(mut self, keys: impl IntoIterator<Item = K>) -> Self
+    where
+        K: Into<String>,
+        BI: KeyedInput,
+    {
+        self.access_list
+            .allow_keys
+            .extend(keys.into_iter().map(Into::into));
+        self.access_list.key_fn = Some(|input| input.downcast_ref::<BI>().map(BI::key));
+        self
+    }
+
+    /// Requests whose rate limit key (produced by the input function) matches one of these keys
+    /// are always denied, with the [RateLimiterBuilder::denylist_response].
+    ///
+    /// The denylist takes precedence over the allowlist.
+    pub fn denylist_keys<K>(mut self, keys: impl IntoIterator<Item = K>) -> Self
+    where
+        K: Into<String>,
+        BI: KeyedInput,
+    {
+        self.access_list
+            .deny_keys
+            .extend(keys.into_iter().map(Into::into));
+        self.access_list.key_fn = Some(|input| input.downcast_ref::<BI>().map(BI::key));
+        self
+    }
+
+    /// Configure the [HttpResponse] returned for requests matching a denylist.
+    ///
+    /// Defaults to an empty body with status 403.
+    pub fn denylist_response<R>(mut self, response: R) -> Self
+    where
+        R: Fn(&ServiceRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.access_list.denied_response = Box::new(response);
+        self
+    }
+
+    /// Also enforce a limit shared by all requests, e.g. to protect a database from traffic
+    /// spikes, in addition to the limit per client enforced by the backend.
+    ///
+    /// The global limit is counted in memory using a fixed window, so it is cheap to check, but
+    /// is only shared by the workers of this server (the middleware and its clones), not across
+    /// multiple instances. It is checked before the backend, and requests that the backend then
+    /// denies don't count towards it. Requests over the global limit aren't passed to the backend
+    /// at all, and receive the [RateLimiterBuilder::global_limit_response].
+    ///
+    /// Requests that bypass the backend (e.g. [skipped](RateLimiterBuilder::skip_if) or
+    /// [allowlisted](RateLimiterBuilder::allowlist_ips) requests) aren't counted either.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::RateLimiter;
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use std::time::Duration;
+    /// # actix_web::rt::System::new().block_on(async {
+    /// // 10 requests per second per client, and 500 per second in total
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(1), 10)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let middleware = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+    ///     .global_limit(Duration::from_secs(1), 500)
+    ///     .add_headers()
+    ///     .build();
+    /// # });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If the interval is zero.
+    pub fn global_limit(mut self, interval: Duration, max_requests: u64) -> Self {
+        assert!(!interval.is_zero(), "Interval must be non-zero");
+        self.global_limit = Some((interval, max_requests));
+        self
+    }
+
+    /// Configure the [HttpResponse] returned for requests over the
+    /// [RateLimiterBuilder::global_limit], which is passed the time until the limit resets.
+    ///
+    /// Defaults to an empty body with status 503, and a `Retry-After` header.
+    pub fn global_limit_response<R>(mut self, response: R) -> Self
+    where
+        R: Fn(&ServiceRequest, Duration) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.global_limit_response = Box::new(response);
+        self
+    }
+
     pub fn build(self) -> RateLimiter<BE, BO, F> {
-        RateLimiter {
+        self.build_config(None)
+    }
+
+    fn build_config(self, handle: Option<RateLimiterHandle>) -> RateLimiter<BE, BO, F> {
+        let skip_condition = match (self.only_methods, self.skip_condition) {
+            (Some(methods), skip_condition) => Some(Arc::new(move |req: &ServiceRequest| {
+                if !methods.contains(req.method()) {
+                    return Box::pin(ready(true)) as LocalBoxFuture<'static, bool>;
+                }
+                match &skip_condition {
+                    Some(skip_condition) => skip_condition(req),
+                    None => Box::pin(ready(false)),
+                }
+            }) as Arc<SkipCondition>),
+            (None, skip_condition) => skip_condition,
+        };
+        let config = Config {
             backend: self.backend,
-            input_fn: Rc::new(self.input_fn),
+            input_fn: self.input_fn,
             fail_open: self.fail_open,
-            allowed_mutation: self.allowed_transformation,
+            fail_open_transient: self.fail_open_transient,
+            allowed_transformation: self.allowed_transformation,
             denied_response: self.denied_response,
             rollback_condition: self.rollback_condition,
+            header_merge: self.header_merge,
+            skip_condition,
+            access_list: (!self.access_list.is_empty()).then(|| Arc::new(self.access_list)),
+            rollback_on_cancel: self.rollback_on_cancel,
+            count_on_response: self.count_on_response,
+            handle,
+            challenge: self.challenge,
+            fail_open_condition: self.fail_open_condition,
+            soft_limit: self.soft_limit.map(Arc::new),
+            peek_only: self.peek_only,
+            limit_connections: self.limit_connections,
+            on_event: self.on_event,
+            notifier: self.notifier,
+            audit: self.audit,
+            pre_hook: self.pre_hook,
+            decision_hook: self.decision_hook,
+            post_hook: self.post_hook,
+            global_limit: self.global_limit.map(|(interval, max_requests)| {
+                GlobalLimit::new(interval, max_requests, self.global_limit_response)
+            }),
+            #[cfg(feature = "tracing")]
+            tracing: self.tracing,
+            #[cfg(feature = "opentelemetry")]
+            otel: self.otel,
+        };
+        RateLimiter {
+            config: Arc::new(config),
         }
     }
-}
-
-/// A trait that a [Backend::Output] should implement in order to use the
-/// [RateLimiterBuilder::add_headers] function.
-pub trait HeaderCompatibleOutput {
-    /// Value for the `x-ratelimit-limit` header.
-    fn limit(&self) -> u64;
-
-    /// Value for the `x-ratelimit-remaining` header.
-    fn remaining(&self) -> u64;
 
-    /// Value for the `x-ratelimit-reset` and `retry-at` headers.
-    ///
-    /// This should be the number of seconds from now until the limit resets.\
-    /// If the limit has already reset this should return 0.
-    fn seconds_until_reset(&self) -> u64;
+    /// Build the middleware, along with a [RateLimiterHandle] that can be used to change the
+    /// policy and [RateLimiterBuilder::fail_open] setting at runtime.
+    pub fn build_with_handle(self) -> (RateLimiter<BE, BO, F>, RateLimiterHandle)
+    where
+        BI: PolicyInput,
+    {
+        let handle = RateLimiterHandle::new::<BI>(self.fail_open);
+        (self.build_config(Some(handle.clone())), handle)
+    }
 }
@@ -0,0 +1,50 @@
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use std::error::Error as StdError;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Whether the response is for a long-lived connection, i.e. a WebSocket upgrade or a stream of
+/// server-sent events.
+pub(crate) fn is_long_lived<B>(response: &ServiceResponse<B>) -> bool {
+    response.status() == StatusCode::SWITCHING_PROTOCOLS
+        || response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"))
+}
+
+/// A response body that holds a guard until it is dropped, which is once the body has been sent
+/// or the connection has closed.
+pub(crate) struct ConnectionBody<G> {
+    body: BoxBody,
+    _guard: Box<G>,
+}
+
+impl<G> ConnectionBody<G> {
+    pub(crate) fn new<B: MessageBody + 'static>(body: B, guard: G) -> Self {
+        Self {
+            body: BoxBody::new(body),
+            _guard: Box::new(guard),
+        }
+    }
+}
+
+impl<G> MessageBody for ConnectionBody<G> {
+    type Error = Box<dyn StdError>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Pin::new(&mut self.get_mut().body).poll_next(cx)
+    }
+}
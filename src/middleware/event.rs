@@ -0,0 +1,68 @@
+use crate::backend::BackendError;
+use crate::middleware::access::KeyFn;
+use std::fmt::Display;
+use std::time::Duration;
+
+/// An event emitted by the [RateLimiter](crate::RateLimiter), see
+/// [RateLimiterBuilder::on_event](crate::RateLimiterBuilder::on_event).
+///
+/// The `latency` of an event is how long the backend took to respond, so that operators can
+/// alert when the rate limiter is adding too much latency to requests.
+pub enum RateLimitEvent<'a, BO> {
+    /// The request was allowed by the backend.
+    Allowed {
+        key: &'a str,
+        output: &'a BO,
+        latency: Duration,
+    },
+    /// The request was denied by the backend.
+    Denied {
+        key: &'a str,
+        output: &'a BO,
+        latency: Duration,
+    },
+    /// The backend failed, the request may still be allowed if failing open.
+    BackendError {
+        key: &'a str,
+        error: &'a dyn Display,
+        class: BackendError,
+        latency: Duration,
+    },
+    /// The count was rolled back because of the
+    /// [rollback condition](crate::RateLimiterBuilder::rollback_condition).
+    RolledBack {
+        key: &'a str,
+        /// The output of the backend before rolling back.
+        output: Option<&'a BO>,
+    },
+}
+
+impl<BO> RateLimitEvent<'_, BO> {
+    /// The rate limit key of the request.
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Allowed { key, .. }
+            | Self::Denied { key, .. }
+            | Self::BackendError { key, .. }
+            | Self::RolledBack { key, .. } => key,
+        }
+    }
+
+    /// How long the backend took to respond, if the event was the result of a backend call.
+    pub fn latency(&self) -> Option<Duration> {
+        match self {
+            Self::Allowed { latency, .. }
+            | Self::Denied { latency, .. }
+            | Self::BackendError { latency, .. } => Some(*latency),
+            Self::RolledBack { .. } => None,
+        }
+    }
+}
+
+type EventCallback<BO> = dyn Fn(RateLimitEvent<'_, BO>) + Send + Sync;
+
+/// See [RateLimiterBuilder::on_event](crate::RateLimiterBuilder::on_event).
+pub(crate) struct EventHook<BO> {
+    pub(crate) callback: Box<EventCallback<BO>>,
+    pub(crate) key_fn: KeyFn,
+}
@@ -0,0 +1,175 @@
+use crate::backend::input_builder::ip_key;
+use crate::backend::memory::InMemoryBackend;
+use crate::backend::{Backend, PolicyInput, RateLimitPolicy, SimpleInput, SimpleOutput};
+use crate::middleware::builder::{X_RATELIMIT_LIMIT, X_RATELIMIT_REMAINING, X_RATELIMIT_RESET};
+use crate::HeaderCompatibleOutput;
+use actix_web::dev::Payload;
+use actix_web::error::{ErrorBadRequest, InternalError};
+use actix_web::http::header::{HeaderValue, RETRY_AFTER};
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+use futures::future::LocalBoxFuture;
+use std::marker::PhantomData;
+use std::sync::OnceLock;
+
+/// The rate limit policy of a [RateLimit] extractor.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::RateLimitPolicy;
+/// # use actix_extensible_rate_limit::PolicyProvider;
+/// # use actix_web::HttpRequest;
+/// # use std::time::Duration;
+/// struct ApiKeyPolicy;
+///
+/// impl PolicyProvider for ApiKeyPolicy {
+///     fn policy() -> RateLimitPolicy {
+///         RateLimitPolicy::new(Duration::from_secs(60), 100)
+///     }
+///
+///     // Limit by API key instead of the client IP
+///     fn key(req: &HttpRequest) -> Result<String, actix_web::Error> {
+///         req.headers()
+///             .get("x-api-key")
+///             .and_then(|key| key.to_str().ok())
+///             .map(str::to_owned)
+///             .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing API key"))
+///     }
+/// }
+/// ```
+pub trait PolicyProvider: 'static {
+    /// The policy that each key is limited to.
+    fn policy() -> RateLimitPolicy;
+
+    /// The rate limit key of the request.
+    ///
+    /// Defaults to the connection peer IP, see
+    /// [SimpleInputFunctionBuilder::peer_ip_key](crate::backend::SimpleInputFunctionBuilder::peer_ip_key).
+    fn key(req: &HttpRequest) -> Result<String, actix_web::Error> {
+        let addr = req
+            .connection_info()
+            .peer_addr()
+            .map(str::to_owned)
+            .ok_or_else(|| ErrorBadRequest("Unable to determine the client IP address"))?;
+        Ok(ip_key(&addr)?)
+    }
+
+    /// A name to prefix the keys with, so that each provider is counted separately.
+    ///
+    /// Defaults to the type name of the provider.
+    fn name() -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// An extractor that counts the request against the [PolicyProvider] `P`, failing with a
+/// `429 Too Many Requests` response once the limit is exhausted, so that a handful of handlers
+/// can be rate limited without wrapping them in a [RateLimiter](crate::RateLimiter).
+///
+/// The requests are counted by the [InMemoryBackend] in the app data, as a
+/// `web::Data<InMemoryBackend>`, or otherwise by a backend shared by every [RateLimit]
+/// extractor. Handlers using the same provider share their limit.
+///
+/// The denied responses include the same headers as
+/// [RateLimiterBuilder::add_headers](crate::RateLimiterBuilder::add_headers). If a handler is
+/// rejected by another extractor after the [RateLimit] has been extracted, the request is still
+/// counted.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::RateLimitPolicy;
+/// # use actix_extensible_rate_limit::{PolicyProvider, RateLimit};
+/// # use actix_web::post;
+/// # use std::time::Duration;
+/// struct LoginPolicy;
+///
+/// impl PolicyProvider for LoginPolicy {
+///     fn policy() -> RateLimitPolicy {
+///         RateLimitPolicy::new(Duration::from_secs(60), 5)
+///     }
+/// }
+///
+/// #[post("/login")]
+/// async fn login(limit: RateLimit<LoginPolicy>) -> String {
+///     format!("{} login attempts remaining", limit.remaining())
+/// }
+/// ```
+pub struct RateLimit<P> {
+    output: SimpleOutput,
+    _policy: PhantomData<P>,
+}
+
+impl<P> RateLimit<P> {
+    /// The [Backend::Output] of the request.
+    pub fn output(&self) -> &SimpleOutput {
+        &self.output
+    }
+
+    /// Total number of requests that are permitted within the rate limit interval.
+    pub fn limit(&self) -> u64 {
+        self.output.limit()
+    }
+
+    /// Number of requests that will be permitted until the limit resets.
+    pub fn remaining(&self) -> u64 {
+        self.output.remaining()
+    }
+
+    /// Number of seconds until the limit resets.
+    pub fn seconds_until_reset(&self) -> u64 {
+        self.output.seconds_until_reset()
+    }
+}
+
+impl<P: PolicyProvider> FromRequest for RateLimit<P> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let backend = match req.app_data::<web::Data<InMemoryBackend>>() {
+            Some(backend) => backend.get_ref().clone(),
+            None => shared_backend(),
+        };
+        let key = P::key(req);
+        Box::pin(async move {
+            let policy = P::policy();
+            let mut input = SimpleInput {
+                interval: policy.interval(),
+                max_requests: policy.max_requests(),
+                key: format!("{}-{}", P::name(), key?).into(),
+            };
+            input.set_policy(&policy);
+            let Ok((decision, output, _)) = backend.request(input).await;
+            if decision.is_denied() {
+                return Err(
+                    InternalError::from_response("Too many requests", denied(&output)).into(),
+                );
+            }
+            Ok(Self {
+                output,
+                _policy: PhantomData,
+            })
+        })
+    }
+}
+
+fn shared_backend() -> InMemoryBackend {
+    static BACKEND: OnceLock<InMemoryBackend> = OnceLock::new();
+    BACKEND
+        .get_or_init(|| InMemoryBackend::builder().build())
+        .clone()
+}
+
+fn denied(output: &SimpleOutput) -> HttpResponse {
+    let mut response = HttpResponse::TooManyRequests().finish();
+    let map = response.headers_mut();
+    map.insert(X_RATELIMIT_LIMIT, HeaderValue::from(output.limit()));
+    map.insert(X_RATELIMIT_REMAINING, HeaderValue::from(output.remaining()));
+    map.insert(
+        X_RATELIMIT_RESET,
+        HeaderValue::from(output.seconds_until_reset()),
+    );
+    map.insert(RETRY_AFTER, HeaderValue::from(output.seconds_until_reset()));
+    response
+}
@@ -0,0 +1,104 @@
+use crate::backend::{Backend, ClassifyError};
+use crate::middleware::{RateLimiter, RateLimiterMiddleware};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use futures::future::LocalBoxFuture;
+use std::cell::RefCell;
+use std::future::Future;
+use std::rc::Rc;
+
+/// Create a rate limiting middleware function for [from_fn](actix_web::middleware::from_fn), as
+/// an alternative to wrapping with a [RateLimiter], for apps that prefer function style
+/// middleware.
+///
+/// This is equivalent to `RateLimiter::builder(backend, input_fn).build().into_fn()`, use
+/// [RateLimiter::into_fn] to customize the rate limiter, e.g. to add headers.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+/// # use actix_extensible_rate_limit::rate_limit;
+/// # use actix_web::middleware::from_fn;
+/// # use actix_web::App;
+/// # use std::time::Duration;
+/// # actix_web::rt::System::new().block_on(async {
+/// let backend = InMemoryBackend::builder().build();
+/// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+///     .real_ip_key()
+///     .build();
+/// let app = App::new().wrap(from_fn(rate_limit(backend, input)));
+/// # });
+/// ```
+#[allow(clippy::type_complexity)]
+pub fn rate_limit<B, BA, BI, BO, BE, F, O>(
+    backend: BA,
+    input_fn: F,
+) -> impl Fn(
+    ServiceRequest,
+    Next<B>,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>
+       + 'static
+where
+    B: MessageBody + 'static,
+    BA: Backend<BI, Output = BO, Error = BE> + 'static,
+    BI: 'static,
+    BO: 'static,
+    BE: Into<actix_web::Error> + ClassifyError + std::fmt::Display + 'static,
+    F: Fn(&ServiceRequest) -> O + 'static,
+    O: Future<Output = Result<BI, actix_web::Error>>,
+{
+    RateLimiter::builder(backend, input_fn).build().into_fn()
+}
+
+impl<BA, BO, F> RateLimiter<BA, BO, F> {
+    /// Convert the rate limiter into a middleware function for
+    /// [from_fn](actix_web::middleware::from_fn), see [rate_limit].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::backend::SimpleInputFunctionBuilder;
+    /// # use actix_extensible_rate_limit::RateLimiter;
+    /// # use actix_web::middleware::from_fn;
+    /// # use actix_web::App;
+    /// # use std::time::Duration;
+    /// # actix_web::rt::System::new().block_on(async {
+    /// let backend = InMemoryBackend::builder().build();
+    /// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+    ///     .real_ip_key()
+    ///     .build();
+    /// let limiter = RateLimiter::builder(backend, input).add_headers().build();
+    /// let app = App::new().wrap(from_fn(limiter.into_fn()));
+    /// # });
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn into_fn<B, BI, BE, O>(
+        self,
+    ) -> impl Fn(
+        ServiceRequest,
+        Next<B>,
+    )
+        -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>
+           + 'static
+    where
+        B: MessageBody + 'static,
+        BA: Backend<BI, Output = BO, Error = BE> + 'static,
+        BI: 'static,
+        BO: 'static,
+        BE: Into<actix_web::Error> + ClassifyError + std::fmt::Display + 'static,
+        F: Fn(&ServiceRequest) -> O + 'static,
+        O: Future<Output = Result<BI, actix_web::Error>>,
+    {
+        move |req, next| {
+            let middleware = RateLimiterMiddleware {
+                service: Rc::new(RefCell::new(next)),
+                config: self.config.clone(),
+            };
+            middleware.call(req)
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::HttpResponse;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub(crate) type GlobalLimitResponse =
+    dyn Fn(&ServiceRequest, Duration) -> HttpResponse + Send + Sync;
+
+pub(crate) fn default_response(_: &ServiceRequest, retry_after: Duration) -> HttpResponse {
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    HttpResponse::ServiceUnavailable()
+        .insert_header((RETRY_AFTER, seconds))
+        .finish()
+}
+
+/// A fixed window limit shared by all requests to the same [RateLimiter](crate::RateLimiter),
+/// counted in memory using atomics.
+///
+/// The window and count are updated separately, so requests racing with the start of a new
+/// window may be counted in either window.
+pub(crate) struct GlobalLimit {
+    interval: Duration,
+    max_requests: u64,
+    started: Instant,
+    window: AtomicU64,
+    count: AtomicU64,
+    pub(crate) denied_response: Box<GlobalLimitResponse>,
+}
+
+/// A request counted towards the [GlobalLimit].
+pub(crate) struct GlobalPermit {
+    window: u64,
+}
+
+impl GlobalLimit {
+    pub(crate) fn new(
+        interval: Duration,
+        max_requests: u64,
+        denied_response: Box<GlobalLimitResponse>,
+    ) -> Self {
+        Self {
+            interval,
+            max_requests,
+            started: Instant::now(),
+            window: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+            denied_response,
+        }
+    }
+
+    /// Counts a request, or returns the time until the next window if the limit is exceeded.
+    pub(crate) fn acquire(&self) -> Result<GlobalPermit, Duration> {
+        let elapsed = self.started.elapsed();
+        let window = (elapsed.as_nanos() / self.interval.as_nanos()) as u64;
+        let current = self.window.load(Ordering::Acquire);
+        if window > current
+            && self
+                .window
+                .compare_exchange(current, window, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            self.count.store(0, Ordering::Release);
+        }
+        let counted = self
+            .count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |c| {
+                (c < self.max_requests).then_some(c + 1)
+            });
+        match counted {
+            Ok(_) => Ok(GlobalPermit { window }),
+            Err(_) => {
+                let next_window = self.interval.as_nanos() * (u128::from(window) + 1);
+                Err(Duration::from_nanos(
+                    (next_window - elapsed.as_nanos()) as u64,
+                ))
+            }
+        }
+    }
+
+    /// Releases a request that was counted, but was then denied by the backend, as long as the
+    /// window hasn't changed.
+    pub(crate) fn release(&self, permit: GlobalPermit) {
+        if self.window.load(Ordering::Acquire) == permit.window {
+            let _ = self
+                .count
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |c| c.checked_sub(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_global_limit() {
+        tokio::time::pause();
+        let limit = GlobalLimit::new(Duration::from_secs(1), 2, Box::new(default_response));
+        let first = limit.acquire().unwrap();
+        limit.acquire().unwrap();
+        tokio::time::advance(Duration::from_millis(200)).await;
+        assert_eq!(limit.acquire().err(), Some(Duration::from_millis(800)));
+        // Released requests can be used by others
+        limit.release(first);
+        let first = limit.acquire().unwrap();
+        assert!(limit.acquire().is_err());
+
+        // A permit from a previous window doesn't affect the new window
+        tokio::time::advance(Duration::from_secs(1)).await;
+        limit.acquire().unwrap();
+        limit.release(first);
+        limit.acquire().unwrap();
+        assert!(limit.acquire().is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use crate::backend::Backend;
+use actix_web::guard::{Guard, GuardContext};
+use futures::FutureExt;
+use std::fmt::Display;
+
+/// A [Guard] that only matches while the client is within its rate limit, according to a
+/// [Backend::peek], so that routes can be hidden from clients that are over a limit instead of
+/// denying their requests, e.g. to serve expensive search traffic from a cheaper handler.
+///
+/// The guard never counts requests itself, they should be counted by a [RateLimiter] wrapping the
+/// guarded resource with the same backend and input, which only sees the requests the guard
+/// matched. Use [guard::Not](actix_web::guard::Not) to match clients that are over the limit
+/// instead.
+///
+/// Guards are synchronous, so the input is produced from the [GuardContext] rather than by an
+/// input function, and the peek must complete without waiting, as it does for the
+/// [InMemoryBackend](crate::backend::memory::InMemoryBackend) and
+/// [ShardedInMemoryBackend](crate::backend::sharded::ShardedInMemoryBackend). If the peek would
+/// have to wait, e.g. for Redis, or the backend fails, the guard matches as if the client were
+/// within its limit. Requests for which the input function returns None also match.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_extensible_rate_limit::backend::SimpleInput;
+/// # use actix_extensible_rate_limit::{RateLimitGuard, RateLimiter};
+/// # use actix_web::dev::{RequestHead, ServiceRequest};
+/// # use actix_web::error::ErrorBadRequest;
+/// # use actix_web::{web, App, HttpResponse};
+/// # use std::future::ready;
+/// # use std::time::Duration;
+/// // Each API key gets 100 full searches per minute
+/// fn search_input(head: &RequestHead) -> Option<SimpleInput> {
+///     let key = head.headers().get("x-api-key")?.to_str().ok()?;
+///     Some(SimpleInput {
+///         interval: Duration::from_secs(60),
+///         max_requests: 100,
+///         key: format!("search-{key}").into(),
+///     })
+/// }
+///
+/// # actix_web::rt::System::new().block_on(async {
+/// let backend = InMemoryBackend::builder().build();
+/// let guard = RateLimitGuard::new(backend.clone(), |ctx| search_input(ctx.head()));
+/// let input = |req: &ServiceRequest| {
+///     ready(search_input(req.head()).ok_or_else(|| ErrorBadRequest("Missing API key")))
+/// };
+/// let app = App::new()
+///     .service(
+///         web::resource("/search")
+///             .guard(guard)
+///             .wrap(RateLimiter::builder(backend, input).build())
+///             .to(|| async { HttpResponse::Ok().body("Full search") }),
+///     )
+///     // Clients that are over the limit get the cached results instead
+///     .service(web::resource("/search").to(|| async { HttpResponse::Ok().body("Cached") }));
+/// # });
+/// ```
+///
+/// [RateLimiter]: crate::RateLimiter
+pub struct RateLimitGuard<BA, F> {
+    backend: BA,
+    input_fn: F,
+}
+
+impl<BA, F> RateLimitGuard<BA, F> {
+    /// Create a guard for the backend, where `input_fn` produces the input of each request.
+    pub fn new<BI>(backend: BA, input_fn: F) -> Self
+    where
+        F: Fn(&GuardContext<'_>) -> Option<BI>,
+    {
+        Self { backend, input_fn }
+    }
+}
+
+impl<BA, BI, F> Guard for RateLimitGuard<BA, F>
+where
+    BA: Backend<BI>,
+    BA::Error: Display,
+    BI: 'static,
+    F: Fn(&GuardContext<'_>) -> Option<BI>,
+{
+    fn check(&self, ctx: &GuardContext<'_>) -> bool {
+        let Some(input) = (self.input_fn)(ctx) else {
+            return true;
+        };
+        match self.backend.peek(input).now_or_never() {
+            Some(Ok((decision, _))) => decision.is_allowed(),
+            Some(Err(e)) => {
+                log::warn!("Rate limit guard failed: {e}, matching the request anyway");
+                true
+            }
+            None => {
+                log::warn!("Rate limit guard backend didn't complete immediately, matching the request anyway");
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::backend::{PolicyInput, RateLimitPolicy};
+use arc_swap::ArcSwapOption;
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A handle to change the settings of a [RateLimiter](crate::RateLimiter) at runtime, without
+/// restarting the server, e.g. to tighten the limits during an attack.
+///
+/// Created using [RateLimiterBuilder::build_with_handle](crate::RateLimiterBuilder::build_with_handle).
+/// The handle can be cloned, e.g. into the app data of an admin endpoint, and changes are picked
+/// up by the middleware on the next request.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_extensible_rate_limit::backend::{RateLimitPolicy, SimpleInputFunctionBuilder};
+/// # use actix_extensible_rate_limit::RateLimiter;
+/// # use std::time::Duration;
+/// # actix_web::rt::System::new().block_on(async {
+/// let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 100)
+///     .real_ip_key()
+///     .build();
+/// let (middleware, handle) = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+///     .build_with_handle();
+///
+/// // Later on, e.g. from an admin endpoint:
+/// handle.set_policy(Some(RateLimitPolicy::new(Duration::from_secs(60), 10)));
+/// handle.set_fail_open(false);
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct RateLimiterHandle {
+    state: Arc<State>,
+}
+
+struct State {
+    policy: ArcSwapOption<RateLimitPolicy>,
+    fail_open: AtomicBool,
+    // Type erased PolicyInput::set_policy for the input type
+    set_policy: fn(&mut dyn Any, &RateLimitPolicy),
+}
+
+impl RateLimiterHandle {
+    pub(crate) fn new<BI: PolicyInput + 'static>(fail_open: bool) -> Self {
+        Self {
+            state: Arc::new(State {
+                policy: ArcSwapOption::empty(),
+                fail_open: AtomicBool::new(fail_open),
+                set_policy: |input, policy| {
+                    if let Some(input) = input.downcast_mut::<BI>() {
+                        input.set_policy(policy);
+                    }
+                },
+            }),
+        }
+    }
+
+    /// Override the policy produced by the input function for every request.
+    ///
+    /// Set to None to restore the policies produced by the input function.
+    pub fn set_policy(&self, policy: Option<RateLimitPolicy>) {
+        self.state.policy.store(policy.map(Arc::new));
+    }
+
+    /// The current policy override, if any.
+    pub fn policy(&self) -> Option<RateLimitPolicy> {
+        self.state.policy.load().as_deref().copied()
+    }
+
+    /// Choose whether to allow a request if the backend returns a failure.
+    pub fn set_fail_open(&self, fail_open: bool) {
+        self.state.fail_open.store(fail_open, Ordering::Relaxed);
+    }
+
+    /// Whether requests are currently allowed if the backend returns a failure.
+    pub fn fail_open(&self) -> bool {
+        self.state.fail_open.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn apply<BI: 'static>(&self, input: &mut BI) {
+        if let Some(policy) = self.state.policy.load().as_deref() {
+            (self.state.set_policy)(input, policy);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use crate::backend::Decision;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use std::time::Duration;
+
+/// The result of a hook that can override the usual rate limiting flow, see
+/// [RateLimiterBuilder::pre_hook](crate::RateLimiterBuilder::pre_hook) and
+/// [RateLimiterBuilder::decision_hook](crate::RateLimiterBuilder::decision_hook).
+#[derive(Debug)]
+pub enum HookDecision {
+    /// Allow the request, regardless of the rate limit.
+    Allow,
+    /// Continue with the usual decision.
+    Continue,
+    /// Deny the request with the given response, which is returned as is.
+    Deny(HttpResponse),
+}
+
+/// The outcome of a request that was checked by the [RateLimiter](crate::RateLimiter), see
+/// [RateLimiterBuilder::post_hook](crate::RateLimiterBuilder::post_hook).
+pub struct ResponseContext<'a, BO> {
+    pub request: &'a HttpRequest,
+    /// The final decision, including any override by a
+    /// [decision hook](crate::RateLimiterBuilder::decision_hook).
+    pub decision: Decision,
+    /// The output of the backend, which is [None] if the backend failed and the request was
+    /// allowed anyway.
+    pub output: Option<&'a BO>,
+    /// The status code of the final response.
+    pub status: StatusCode,
+    /// Time since the middleware received the request.
+    pub elapsed: Duration,
+    /// Whether the count was rolled back (or not counted) after the response.
+    pub rolled_back: bool,
+}
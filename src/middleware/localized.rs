@@ -0,0 +1,132 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{
+    AcceptLanguage, Header, Preference, Quality, CONTENT_LANGUAGE, RETRY_AFTER, VARY,
+};
+use actix_web::HttpResponse;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Denied response messages in multiple languages, see
+/// [RateLimiterBuilder::deny_with_localized_message](crate::RateLimiterBuilder::deny_with_localized_message).
+///
+/// Each message is a template, in which the `{retry_after}`, `{limit}` and `{remaining}`
+/// placeholders are replaced with the rate limit status.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::LocalizedMessages;
+/// let messages = LocalizedMessages::new("en", "Too many requests, retry after {retry_after}s")
+///     .add("fr", "Trop de requêtes, réessayez dans {retry_after} s")
+///     .add("de", "Zu viele Anfragen, versuchen Sie es in {retry_after} s erneut");
+/// ```
+#[derive(Debug, Clone)]
+pub struct LocalizedMessages {
+    default_locale: String,
+    // Keyed by the lowercase locale
+    templates: HashMap<String, (String, String)>,
+}
+
+impl LocalizedMessages {
+    /// Create the messages with the template used when none of the client's languages are
+    /// available.
+    pub fn new(default_locale: &str, template: &str) -> Self {
+        Self {
+            default_locale: default_locale.to_owned(),
+            templates: HashMap::new(),
+        }
+        .add(default_locale, template)
+    }
+
+    /// Add the template for a locale, e.g. `fr` or `pt-BR`.
+    ///
+    /// A client requesting a more specific locale, e.g. `fr-CH`, falls back to its language, so
+    /// the template for `fr` is used if there is no template for `fr-CH`.
+    pub fn add(mut self, locale: &str, template: &str) -> Self {
+        self.templates.insert(
+            locale.to_ascii_lowercase(),
+            (locale.to_owned(), template.to_owned()),
+        );
+        self
+    }
+
+    /// Returns the locale and template best matching the client's `Accept-Language` header.
+    pub(crate) fn negotiate(&self, req: &ServiceRequest) -> (&str, &str) {
+        let mut accepted = AcceptLanguage::parse(req).map(|h| h.0).unwrap_or_default();
+        // Unlike AcceptLanguage::ranked(), languages with a quality of zero are not acceptable
+        accepted.retain(|language| language.quality > Quality::ZERO);
+        // Stable, so that languages with the same quality keep their order
+        accepted.sort_by_key(|language| Reverse(language.quality));
+        accepted
+            .into_iter()
+            .find_map(|language| match language.item {
+                Preference::Any => Some(self.default()),
+                Preference::Specific(tag) => self
+                    .get(tag.as_str())
+                    .or_else(|| self.get(tag.primary_language())),
+            })
+            .unwrap_or_else(|| self.default())
+    }
+
+    fn get(&self, locale: &str) -> Option<(&str, &str)> {
+        self.templates
+            .get(&locale.to_ascii_lowercase())
+            .map(|(locale, template)| (locale.as_str(), template.as_str()))
+    }
+
+    fn default(&self) -> (&str, &str) {
+        self.get(&self.default_locale)
+            .expect("The default locale always has a template")
+    }
+
+    pub(crate) fn response(
+        &self,
+        req: &ServiceRequest,
+        limit: u64,
+        remaining: u64,
+        retry_after: u64,
+    ) -> HttpResponse {
+        let (locale, template) = self.negotiate(req);
+        let body = template
+            .replace("{retry_after}", &retry_after.to_string())
+            .replace("{limit}", &limit.to_string())
+            .replace("{remaining}", &remaining.to_string());
+        HttpResponse::TooManyRequests()
+            .content_type("text/plain; charset=utf-8")
+            .insert_header((CONTENT_LANGUAGE, locale))
+            .insert_header((VARY, "accept-language"))
+            .insert_header((RETRY_AFTER, retry_after))
+            .body(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_negotiate() {
+        let messages = LocalizedMessages::new("en", "en")
+            .add("fr", "fr")
+            .add("pt-BR", "pt-BR");
+        let negotiate = |accept_language: Option<&str>| {
+            let mut req = TestRequest::default();
+            if let Some(value) = accept_language {
+                req = req.insert_header(("accept-language", value));
+            }
+            messages.negotiate(&req.to_srv_request()).0.to_owned()
+        };
+        assert_eq!(negotiate(None), "en");
+        assert_eq!(negotiate(Some("fr")), "fr");
+        // Falls back to the language
+        assert_eq!(negotiate(Some("fr-CH, de;q=0.9")), "fr");
+        assert_eq!(negotiate(Some("pt-br")), "pt-BR");
+        // Ordered by quality
+        assert_eq!(negotiate(Some("de, en;q=0.5, fr;q=0.8")), "fr");
+        // Not acceptable
+        assert_eq!(negotiate(Some("fr;q=0, pt-BR;q=0.1")), "pt-BR");
+        assert_eq!(negotiate(Some("de, *;q=0.5")), "en");
+        assert_eq!(negotiate(Some("invalid;;")), "en");
+    }
+}
@@ -0,0 +1,115 @@
+use crate::middleware::builder::{X_RATELIMIT_LIMIT, X_RATELIMIT_REMAINING, X_RATELIMIT_RESET};
+use crate::HeaderCompatibleOutput;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::HttpResponse;
+use std::cmp::Reverse;
+
+/// How the rate limit headers of multiple [RateLimiter](crate::RateLimiter)s wrapping the same
+/// service are combined, see [RateLimiterBuilder::add_merged_headers](crate::RateLimiterBuilder::add_merged_headers).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HeaderMergeStrategy {
+    /// Set the `x-ratelimit-*` headers from the policy with the fewest remaining requests
+    /// (ties are broken by the latest reset).
+    MostRestrictive,
+    /// Set a separate group of headers for each policy, prefixed by the policy name, e.g.
+    /// `x-ratelimit-minute-remaining`.
+    Prefixed,
+}
+
+type StatusFn<BO> = dyn Fn(&BO) -> PolicyStatus + Send + Sync;
+
+/// Records the status of a policy in the response extensions, and renders the headers from all
+/// the policies recorded so far.
+///
+/// Since the outermost middleware is the last to render, the final headers reflect every policy.
+pub(crate) struct HeaderMerge<BO> {
+    policy: String,
+    strategy: HeaderMergeStrategy,
+    status_fn: Box<StatusFn<BO>>,
+}
+
+#[derive(Debug, Clone)]
+struct PolicyStatus {
+    policy: String,
+    limit: u64,
+    remaining: u64,
+    reset: u64,
+}
+
+/// Response extension accumulating the status of each policy.
+#[derive(Default)]
+struct PolicyStatuses(Vec<PolicyStatus>);
+
+impl<BO> HeaderMerge<BO> {
+    pub(crate) fn new(policy: &str, strategy: HeaderMergeStrategy) -> Self
+    where
+        BO: HeaderCompatibleOutput,
+    {
+        let policy = policy.to_ascii_lowercase();
+        assert!(
+            HeaderName::try_from(prefixed_name(&policy, "limit")).is_ok(),
+            "Policy name must be valid within a header name"
+        );
+        let name = policy.clone();
+        Self {
+            policy,
+            strategy,
+            status_fn: Box::new(move |output: &BO| PolicyStatus {
+                policy: name.clone(),
+                limit: output.limit(),
+                remaining: output.remaining(),
+                reset: output.seconds_until_reset(),
+            }),
+        }
+    }
+
+    pub(crate) fn apply<B>(&self, response: &mut HttpResponse<B>, output: &BO, rolled_back: bool) {
+        let mut status = (self.status_fn)(output);
+        if rolled_back {
+            status.remaining += 1;
+        }
+        let statuses = {
+            let mut extensions = response.extensions_mut();
+            if !extensions.contains::<PolicyStatuses>() {
+                extensions.insert(PolicyStatuses::default());
+            }
+            let statuses = extensions.get_mut::<PolicyStatuses>().unwrap();
+            statuses.0.retain(|s| s.policy != self.policy);
+            statuses.0.push(status);
+            statuses.0.clone()
+        };
+        let map = response.headers_mut();
+        match self.strategy {
+            HeaderMergeStrategy::MostRestrictive => {
+                let status = most_restrictive(&statuses);
+                map.insert(X_RATELIMIT_LIMIT, HeaderValue::from(status.limit));
+                map.insert(X_RATELIMIT_REMAINING, HeaderValue::from(status.remaining));
+                map.insert(X_RATELIMIT_RESET, HeaderValue::from(status.reset));
+            }
+            HeaderMergeStrategy::Prefixed => {
+                for status in &statuses {
+                    for (suffix, value) in [
+                        ("limit", status.limit),
+                        ("remaining", status.remaining),
+                        ("reset", status.reset),
+                    ] {
+                        let name = HeaderName::try_from(prefixed_name(&status.policy, suffix))
+                            .expect("Validated policy name");
+                        map.insert(name, HeaderValue::from(value));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn prefixed_name(policy: &str, suffix: &str) -> String {
+    format!("x-ratelimit-{policy}-{suffix}")
+}
+
+fn most_restrictive(statuses: &[PolicyStatus]) -> &PolicyStatus {
+    statuses
+        .iter()
+        .min_by_key(|s| (s.remaining, Reverse(s.reset)))
+        .expect("At least one status")
+}
@@ -1,47 +1,194 @@
+mod access;
+pub mod audit;
 pub mod builder;
+mod connection;
+pub mod event;
+#[cfg(feature = "dashmap")]
+pub mod extractor;
+mod from_fn;
+mod global;
+pub mod guard;
+pub mod handle;
+pub mod hook;
+pub mod localized;
+pub mod merge;
+pub mod notify;
+#[cfg(feature = "opentelemetry")]
+mod otel;
+pub mod recommended;
+pub mod status;
 #[cfg(test)]
 mod tests;
+#[cfg(feature = "tracing")]
+mod trace;
 
-use crate::backend::Backend;
-use actix_web::body::EitherBody;
+use crate::backend::HeaderCompatibleOutput;
+use crate::backend::{Backend, ClassifyError, Decision};
+use access::{Access, AccessList, KeyFn};
+use actix_web::body::{EitherBody, MessageBody};
 use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
-use actix_web::http::header::HeaderMap;
+use actix_web::http::header::{HeaderMap, HeaderValue};
 use actix_web::http::StatusCode;
+use actix_web::HttpMessage;
 use actix_web::HttpResponse;
-use builder::RateLimiterBuilder;
+use audit::Audit;
+use builder::{RateLimiterBuilder, X_RATELIMIT_WARNING};
+use connection::ConnectionBody;
+use event::{EventHook, RateLimitEvent};
+pub use from_fn::rate_limit;
 use futures::future::{ok, LocalBoxFuture, Ready};
+use global::GlobalLimit;
+use handle::RateLimiterHandle;
+use hook::{HookDecision, ResponseContext};
+use merge::HeaderMerge;
+use notify::Notifier;
+#[cfg(feature = "opentelemetry")]
+use otel::Telemetry;
+use status::RateLimitStatus;
+use std::any::Any;
 use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::sync::Arc;
 use std::{future::Future, rc::Rc};
+#[cfg(feature = "tracing")]
+use trace::Tracing;
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
 
-type AllowedTransformation<BO> = dyn Fn(&mut HeaderMap, Option<&BO>, bool);
-type DeniedResponse<BO> = dyn Fn(&BO) -> HttpResponse;
-type RollbackCondition = dyn Fn(StatusCode) -> bool;
+type AllowedTransformation<BO> = dyn Fn(&mut HeaderMap, Option<&BO>, bool) + Send + Sync;
+type DeniedResponse<BO> =
+    dyn Fn(&ServiceRequest, &BO) -> LocalBoxFuture<'static, HttpResponse> + Send + Sync;
+type RollbackCondition = dyn Fn(StatusCode) -> bool + Send + Sync;
+type SkipCondition = dyn Fn(&ServiceRequest) -> LocalBoxFuture<'static, bool> + Send + Sync;
+type CountCondition = dyn Fn(StatusCode) -> bool + Send + Sync;
+// Takes the (type erased) backend error
+type FailOpenCondition = dyn Fn(&dyn Any) -> bool + Send + Sync;
+type ChallengeResponse<BO> =
+    dyn Fn(&ServiceRequest, &str, &BO) -> Option<HttpResponse> + Send + Sync;
+
+type PreHook = dyn Fn(&ServiceRequest) -> LocalBoxFuture<'static, HookDecision> + Send + Sync;
+type DecisionHook<BO> = dyn Fn(&ServiceRequest, Decision, &BO) -> HookDecision + Send + Sync;
+type PostHook<BO> = dyn Fn(&ResponseContext<'_, BO>) + Send + Sync;
+
+type SoftLimitCallback<BO> = dyn Fn(&ServiceRequest, &BO) + Send + Sync;
+
+/// See [RateLimiterBuilder::soft_limit_warning] and [RateLimiterBuilder::on_soft_limit].
+struct SoftLimit<BO> {
+    // The fraction of the limit remaining
+    remaining: fn(&BO) -> f64,
+    header_threshold: Option<f64>,
+    callback: Option<(f64, Box<SoftLimitCallback<BO>>)>,
+}
+
+impl<BO: HeaderCompatibleOutput> SoftLimit<BO> {
+    fn new() -> Self {
+        Self {
+            remaining: |output| match output.limit() {
+                0 => 0f64,
+                limit => output.remaining() as f64 / limit as f64,
+            },
+            header_threshold: None,
+            callback: None,
+        }
+    }
+}
+
+/// See [RateLimiterBuilder::challenge_response].
+struct Challenge<BO> {
+    response: Box<ChallengeResponse<BO>>,
+    key_fn: KeyFn,
+}
+
+/// See [RateLimiterBuilder::count_when].
+struct CountOnResponse {
+    condition: Box<CountCondition>,
+    // The input is needed both to check the request beforehand and to count it afterwards, so
+    // this clones the (type erased) input.
+    clone_input: fn(&dyn Any) -> Box<dyn Any>,
+}
+
+impl CountOnResponse {
+    fn new<BI: Clone + 'static>(condition: Box<CountCondition>) -> Self {
+        Self {
+            condition,
+            clone_input: |input| {
+                Box::new(
+                    input
+                        .downcast_ref::<BI>()
+                        .expect("Input type should match")
+                        .clone(),
+                )
+            },
+        }
+    }
+
+    fn clone_input<BI: 'static>(&self, input: &BI) -> BI {
+        *(self.clone_input)(input)
+            .downcast()
+            .expect("Input type should match")
+    }
+}
 
 /// Rate limit middleware.
+///
+/// The middleware is [Send] and [Sync] (provided the backend and input function are), so it can
+/// be constructed once and moved into the `HttpServer::new` factory closure.
 pub struct RateLimiter<BA, BO, F> {
+    config: Arc<Config<BA, BO, F>>,
+}
+
+/// The configuration of a [RateLimiter], shared by every worker so that each request only
+/// needs to clone a single [Arc].
+struct Config<BA, BO, F> {
     backend: BA,
-    input_fn: Rc<F>,
+    input_fn: F,
     fail_open: bool,
-    allowed_mutation: Option<Rc<AllowedTransformation<BO>>>,
-    denied_response: Rc<DeniedResponse<BO>>,
-    rollback_condition: Option<Rc<RollbackCondition>>,
+    fail_open_transient: bool,
+    allowed_transformation: Option<Arc<AllowedTransformation<BO>>>,
+    denied_response: Arc<DeniedResponse<BO>>,
+    rollback_condition: Option<Arc<RollbackCondition>>,
+    header_merge: Option<Arc<HeaderMerge<BO>>>,
+    skip_condition: Option<Arc<SkipCondition>>,
+    access_list: Option<Arc<AccessList>>,
+    rollback_on_cancel: bool,
+    count_on_response: Option<Arc<CountOnResponse>>,
+    handle: Option<RateLimiterHandle>,
+    challenge: Option<Arc<Challenge<BO>>>,
+    fail_open_condition: Option<Arc<FailOpenCondition>>,
+    soft_limit: Option<Arc<SoftLimit<BO>>>,
+    peek_only: bool,
+    limit_connections: bool,
+    on_event: Option<Arc<EventHook<BO>>>,
+    notifier: Option<Arc<Notifier>>,
+    audit: Option<Arc<Audit<BO>>>,
+    pre_hook: Option<Arc<PreHook>>,
+    decision_hook: Option<Arc<DecisionHook<BO>>>,
+    post_hook: Option<Arc<PostHook<BO>>>,
+    global_limit: Option<GlobalLimit>,
+    #[cfg(feature = "tracing")]
+    tracing: Option<Arc<Tracing<BO>>>,
+    #[cfg(feature = "opentelemetry")]
+    otel: Option<Arc<Telemetry<BO>>>,
 }
 
-impl<BA, BI, BO, F, O> Clone for RateLimiter<BA, BO, F>
-where
-    BA: Backend<BI> + 'static,
-    BI: 'static,
-    F: Fn(&ServiceRequest) -> O + 'static,
-    O: Future<Output = Result<BI, actix_web::Error>>,
-{
+impl<BA, BO, F> Config<BA, BO, F> {
+    /// Extracts the rate limit key used by the hooks, which all extract the same key.
+    fn hook_key<'a>(&self, input: &'a dyn Any) -> Option<&'a str> {
+        let key_fn = self
+            .on_event
+            .as_ref()
+            .map(|hook| hook.key_fn)
+            .or_else(|| self.notifier.as_ref().map(|notifier| notifier.key_fn))
+            .or_else(|| self.audit.as_ref().map(|audit| audit.key_fn))
+            .or_else(|| self.challenge.as_ref().map(|challenge| challenge.key_fn))?;
+        key_fn(input)
+    }
+}
+
+impl<BA, BO, F> Clone for RateLimiter<BA, BO, F> {
     fn clone(&self) -> Self {
         Self {
-            backend: self.backend.clone(),
-            input_fn: self.input_fn.clone(),
-            fail_open: self.fail_open,
-            allowed_mutation: self.allowed_mutation.clone(),
-            denied_response: self.denied_response.clone(),
-            rollback_condition: self.rollback_condition.clone(),
+            config: self.config.clone(),
         }
     }
 }
@@ -66,11 +213,11 @@ impl<S, B, BA, BI, BO, BE, F, O> Transform<S, ServiceRequest> for RateLimiter<BA
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
     S::Future: 'static,
-    B: 'static,
+    B: MessageBody + 'static,
     BA: Backend<BI, Output = BO, Error = BE> + 'static,
     BI: 'static,
     BO: 'static,
-    BE: Into<actix_web::Error> + std::fmt::Display + 'static,
+    BE: Into<actix_web::Error> + ClassifyError + std::fmt::Display + 'static,
     F: Fn(&ServiceRequest) -> O + 'static,
     O: Future<Output = Result<BI, actix_web::Error>>,
 {
@@ -83,35 +230,26 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ok(RateLimiterMiddleware {
             service: Rc::new(RefCell::new(service)),
-            backend: self.backend.clone(),
-            input_fn: Rc::clone(&self.input_fn),
-            fail_open: self.fail_open,
-            allowed_transformation: self.allowed_mutation.clone(),
-            denied_response: self.denied_response.clone(),
-            rollback_condition: self.rollback_condition.clone(),
+            config: self.config.clone(),
         })
     }
 }
 
+/// The service created by a [RateLimiter] for each worker, see [Transform].
 pub struct RateLimiterMiddleware<S, BE, BO, F> {
     service: Rc<RefCell<S>>,
-    backend: BE,
-    input_fn: Rc<F>,
-    fail_open: bool,
-    allowed_transformation: Option<Rc<AllowedTransformation<BO>>>,
-    denied_response: Rc<DeniedResponse<BO>>,
-    rollback_condition: Option<Rc<RollbackCondition>>,
+    config: Arc<Config<BE, BO, F>>,
 }
 
 impl<S, B, BA, BI, BO, BE, F, O> Service<ServiceRequest> for RateLimiterMiddleware<S, BA, BO, F>
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
     S::Future: 'static,
-    B: 'static,
+    B: MessageBody + 'static,
     BA: Backend<BI, Output = BO, Error = BE> + 'static,
     BI: 'static,
     BO: 'static,
-    BE: Into<actix_web::Error> + std::fmt::Display + 'static,
+    BE: Into<actix_web::Error> + ClassifyError + std::fmt::Display + 'static,
     F: Fn(&ServiceRequest) -> O + 'static,
     O: Future<Output = Result<BI, actix_web::Error>>,
 {
@@ -123,38 +261,307 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let service = self.service.clone();
-        let backend = self.backend.clone();
-        let input_fn = self.input_fn.clone();
-        let fail_open = self.fail_open;
-        let allowed_transformation = self.allowed_transformation.clone();
-        let denied_response = self.denied_response.clone();
-        let rollback_condition = self.rollback_condition.clone();
+        let config = self.config.clone();
 
         Box::pin(async move {
-            let input = match input_fn(&req).await {
+            let started = actix_web::rt::time::Instant::now();
+            let Config {
+                backend,
+                input_fn,
+                allowed_transformation,
+                denied_response,
+                rollback_condition,
+                header_merge,
+                skip_condition,
+                access_list,
+                rollback_on_cancel,
+                count_on_response,
+                handle,
+                challenge,
+                fail_open_transient,
+                fail_open_condition,
+                soft_limit,
+                peek_only,
+                limit_connections,
+                on_event,
+                notifier,
+                audit,
+                pre_hook,
+                decision_hook,
+                post_hook,
+                global_limit,
+                #[cfg(feature = "tracing")]
+                tracing,
+                #[cfg(feature = "opentelemetry")]
+                otel,
+                ..
+            } = &*config;
+            let fail_open = handle
+                .as_ref()
+                .map_or(config.fail_open, RateLimiterHandle::fail_open);
+            if let Some(skip_condition) = skip_condition {
+                if skip_condition(&req).await {
+                    let service_response = service.call(req).await?;
+                    return Ok(service_response.map_into_left_body());
+                }
+            }
+
+            if let Some(pre_hook) = pre_hook {
+                match pre_hook(&req).await {
+                    HookDecision::Allow => {
+                        let service_response = service.call(req).await?;
+                        return Ok(service_response.map_into_left_body());
+                    }
+                    HookDecision::Deny(response) => {
+                        return Ok(req.into_response(response).map_into_right_body());
+                    }
+                    HookDecision::Continue => {}
+                }
+            }
+
+            if let Some(access_list) = &access_list {
+                match access_list.check_request(&req) {
+                    Some(Access::Allow) => {
+                        let service_response = service.call(req).await?;
+                        return Ok(service_response.map_into_left_body());
+                    }
+                    Some(Access::Deny) => {
+                        let response = access_list.denied_response(&req);
+                        return Ok(req.into_response(response).map_into_right_body());
+                    }
+                    None => {}
+                }
+            }
+
+            let mut input = match input_fn(&req).await {
                 Ok(input) => input,
                 Err(e) => {
                     log::error!("Rate limiter input function failed: {e}");
                     return Ok(req.into_response(e.error_response()).map_into_right_body());
                 }
             };
+            if let Some(handle) = &handle {
+                handle.apply(&mut input);
+            }
 
-            let (output, rollback) = match backend.request(input).await {
+            if let Some(access_list) = &access_list {
+                match access_list.check_input(&input) {
+                    Some(Access::Allow) => {
+                        let service_response = service.call(req).await?;
+                        return Ok(service_response.map_into_left_body());
+                    }
+                    Some(Access::Deny) => {
+                        let response = access_list.denied_response(&req);
+                        return Ok(req.into_response(response).map_into_right_body());
+                    }
+                    None => {}
+                }
+            }
+
+            // The key is only copied once, and only if a hook needs it
+            let hook_key = config.hook_key(&input).map(ToOwned::to_owned);
+            let emit = |event: RateLimitEvent<'_, BO>| {
+                if let Some(hook) = &on_event {
+                    (hook.callback)(event);
+                }
+            };
+
+            if *peek_only {
+                let backend_started = actix_web::rt::time::Instant::now();
+                let status = match backend.peek(input).await {
+                    Ok((decision, output)) => RateLimitStatus::new(decision, Some(Rc::new(output))),
+                    Err(e) => {
+                        let class = e.class();
+                        if let Some(key) = &hook_key {
+                            emit(RateLimitEvent::BackendError {
+                                key,
+                                error: &e,
+                                class,
+                                latency: backend_started.elapsed(),
+                            });
+                        }
+                        if fail_open
+                            || (*fail_open_transient && class.is_transient())
+                            || fail_open_condition
+                                .as_ref()
+                                .is_some_and(|condition| condition(&e))
+                        {
+                            log::warn!("Rate limiter failed: {}, allowing the request anyway", e);
+                            RateLimitStatus::new(Decision::Allowed, None)
+                        } else {
+                            log::error!("Rate limiter failed ({}): {}", class, e);
+                            return Ok(req
+                                .into_response(e.into().error_response())
+                                .map_into_right_body());
+                        }
+                    }
+                };
+                req.extensions_mut().insert(status);
+                let service_response = service.call(req).await?;
+                return Ok(service_response.map_into_left_body());
+            }
+
+            // Checked before the backend, so that requests over the global limit are rejected
+            // without a round trip, and released again if the backend denies them.
+            let global_permit = match global_limit {
+                Some(global_limit) => match global_limit.acquire() {
+                    Ok(permit) => Some(permit),
+                    Err(retry_after) => {
+                        let response = (global_limit.denied_response)(&req, retry_after);
+                        return Ok(req.into_response(response).map_into_right_body());
+                    }
+                },
+                None => None,
+            };
+            let release_global = |permit: Option<_>| {
+                if let (Some(global_limit), Some(permit)) = (global_limit, permit) {
+                    global_limit.release(permit);
+                }
+            };
+
+            #[cfg(feature = "tracing")]
+            let span = tracing.as_ref().map(|tracing| tracing.span(&input));
+
+            // When counting on response the request is only checked now, and counted once the
+            // inner service has completed.
+            let backend_call = async {
+                match &count_on_response {
+                    Some(count_on_response) => {
+                        let deferred_input = count_on_response.clone_input(&input);
+                        let result = backend.peek(input).await;
+                        (Some(deferred_input), result.map(|(d, o)| (d, o, None)))
+                    }
+                    None => {
+                        let result = backend.request(input).await;
+                        (None, result.map(|(d, o, t)| (d, o, Some(t))))
+                    }
+                }
+            };
+            let backend_started = actix_web::rt::time::Instant::now();
+            #[cfg(feature = "tracing")]
+            let (deferred_input, result) = match &span {
+                Some(span) => backend_call.instrument(span.clone()).await,
+                None => backend_call.await,
+            };
+            #[cfg(not(feature = "tracing"))]
+            let (deferred_input, result) = backend_call.await;
+            let latency = backend_started.elapsed();
+
+            #[cfg(feature = "tracing")]
+            if let (Some(tracing), Some(span)) = (&tracing, &span) {
+                match &result {
+                    Ok((decision, output, _)) => {
+                        tracing.record_output(span, *decision, output, started.elapsed())
+                    }
+                    Err(e) => tracing.record_error(span, e, e.class(), started.elapsed()),
+                }
+            }
+
+            #[cfg(feature = "opentelemetry")]
+            if let Some(otel) = &otel {
+                match &result {
+                    Ok((decision, output, _)) => otel.record_output(*decision, output),
+                    Err(e) => otel.record_error(e, e.class()),
+                }
+            }
+
+            let (mut output, rollback) = match result {
                 // Able to successfully query rate limiter backend
                 Ok((decision, output, rollback)) => {
+                    let mut hook_response = None;
+                    let decision = match decision_hook
+                        .as_ref()
+                        .map(|hook| hook(&req, decision, &output))
+                    {
+                        None | Some(HookDecision::Continue) => decision,
+                        Some(HookDecision::Allow) => Decision::Allowed,
+                        Some(HookDecision::Deny(response)) => {
+                            hook_response = Some(response);
+                            Decision::Denied
+                        }
+                    };
+                    if let Some(key) = &hook_key {
+                        emit(match decision {
+                            Decision::Allowed => RateLimitEvent::Allowed {
+                                key,
+                                output: &output,
+                                latency,
+                            },
+                            Decision::Denied => RateLimitEvent::Denied {
+                                key,
+                                output: &output,
+                                latency,
+                            },
+                        });
+                    }
                     if decision.is_denied() {
-                        let response: HttpResponse = denied_response(&output);
-                        return Ok(req.into_response(response).map_into_right_body());
+                        release_global(global_permit);
+                        // Denied connection attempts don't hold a session
+                        if let (true, Some(token)) = (*limit_connections, rollback) {
+                            if let Err(e) = backend.rollback(token).await {
+                                log::error!("Unable to rollback rate-limit count for denied connection: {e}");
+                            }
+                        }
+                        if let (Some(notifier), Some(key)) = (notifier, &hook_key) {
+                            notifier.denied(key);
+                        }
+                        if let (Some(audit), Some(key)) = (audit, &hook_key) {
+                            audit.record(&req, key, &output);
+                        }
+                        let challenge_response = match (challenge, &hook_key) {
+                            (Some(challenge), Some(key)) if hook_response.is_none() => {
+                                (challenge.response)(&req, key, &output)
+                            }
+                            _ => None,
+                        };
+                        let response = match hook_response.or(challenge_response) {
+                            Some(response) => response,
+                            None => {
+                                let mut response: HttpResponse =
+                                    denied_response(&req, &output).await;
+                                if let Some(header_merge) = header_merge {
+                                    header_merge.apply(&mut response, &output, false);
+                                }
+                                response
+                            }
+                        };
+                        let service_response = req.into_response(response);
+                        if let Some(post_hook) = post_hook {
+                            post_hook(&ResponseContext {
+                                request: service_response.request(),
+                                decision,
+                                output: Some(&output),
+                                status: service_response.status(),
+                                elapsed: started.elapsed(),
+                                rolled_back: false,
+                            });
+                        }
+                        return Ok(service_response.map_into_right_body());
                     }
-                    (Some(output), Some(rollback))
+                    (Some(Rc::new(output)), rollback)
                 }
                 // Unable to query rate limiter backend
                 Err(e) => {
-                    if fail_open {
+                    let class = e.class();
+                    if let Some(key) = &hook_key {
+                        emit(RateLimitEvent::BackendError {
+                            key,
+                            error: &e,
+                            class,
+                            latency,
+                        });
+                    }
+                    if fail_open
+                        || (*fail_open_transient && class.is_transient())
+                        || fail_open_condition
+                            .as_ref()
+                            .is_some_and(|condition| condition(&e))
+                    {
                         log::warn!("Rate limiter failed: {}, allowing the request anyway", e);
                         (None, None)
                     } else {
-                        log::error!("Rate limiter failed: {}", e);
+                        log::error!("Rate limiter failed ({}): {}", class, e);
+                        release_global(global_permit);
                         return Ok(req
                             .into_response(e.into().error_response())
                             .map_into_right_body());
@@ -162,7 +569,42 @@ where
                 }
             };
 
-            let mut service_response = service.call(req).await?;
+            req.extensions_mut()
+                .insert(RateLimitStatus::new(Decision::Allowed, output.clone()));
+
+            let mut soft_limit_warning = false;
+            if let (Some(soft_limit), Some(output)) = (&soft_limit, &output) {
+                let remaining = (soft_limit.remaining)(output);
+                if let Some((threshold, callback)) = &soft_limit.callback {
+                    if remaining < *threshold {
+                        callback(&req, output);
+                    }
+                }
+                soft_limit_warning = soft_limit
+                    .header_threshold
+                    .is_some_and(|threshold| remaining < threshold);
+            }
+
+            // Rollback if this future is dropped (e.g. the client disconnected) before the inner
+            // service completes.
+            let mut guard = RollbackGuard {
+                backend: rollback_on_cancel.then(|| backend.clone()),
+                token: rollback,
+                input: PhantomData,
+            };
+            let result = service.call(req).await;
+            let mut rollback = guard.token.take();
+            let mut service_response = result?;
+
+            // Long-lived connections keep their count until the response body is dropped, i.e.
+            // the connection has closed, so that they are limited as concurrent sessions.
+            let connection_guard = rollback
+                .take_if(|_| *limit_connections && connection::is_long_lived(&service_response))
+                .map(|token| RollbackGuard {
+                    backend: Some(backend.clone()),
+                    token: Some(token),
+                    input: PhantomData,
+                });
 
             let mut rolled_back = false;
             if let Some(token) = rollback {
@@ -173,16 +615,104 @@ where
                             log::error!("Unable to rollback rate-limit count for response: {:?}, error: {e}", status);
                         } else {
                             rolled_back = true;
+                            if let Some(key) = &hook_key {
+                                emit(RateLimitEvent::RolledBack {
+                                    key,
+                                    output: output.as_deref(),
+                                });
+                            }
                         };
                     }
                 }
             }
 
+            if let (Some(count_on_response), Some(input)) = (count_on_response, deferred_input) {
+                let status = service_response.status();
+                // If the response isn't counted, the output is the same as if it had been rolled
+                // back.
+                rolled_back = true;
+                if (count_on_response.condition)(status) {
+                    match backend.request(input).await {
+                        Ok((_, counted, _)) => {
+                            output = Some(Rc::new(counted));
+                            rolled_back = false;
+                        }
+                        Err(e) => {
+                            log::error!(
+                                "Unable to count rate-limit for response: {:?}, error: {e}",
+                                status
+                            );
+                        }
+                    }
+                }
+            }
+
             if let Some(transformation) = allowed_transformation {
-                transformation(service_response.headers_mut(), output.as_ref(), rolled_back);
+                transformation(
+                    service_response.headers_mut(),
+                    output.as_deref(),
+                    rolled_back,
+                );
             }
 
-            Ok(service_response.map_into_left_body())
+            if let (Some(header_merge), Some(output)) = (header_merge, &output) {
+                header_merge.apply(service_response.response_mut(), output, rolled_back);
+            }
+
+            if soft_limit_warning && !rolled_back {
+                service_response
+                    .headers_mut()
+                    .insert(X_RATELIMIT_WARNING, HeaderValue::from_static("true"));
+            }
+
+            if let Some(post_hook) = post_hook {
+                post_hook(&ResponseContext {
+                    request: service_response.request(),
+                    decision: Decision::Allowed,
+                    output: output.as_deref(),
+                    status: service_response.status(),
+                    elapsed: started.elapsed(),
+                    rolled_back,
+                });
+            }
+
+            match connection_guard {
+                Some(guard) => Ok(service_response
+                    .map_body(|_, body| ConnectionBody::new(body, guard))
+                    .map_into_boxed_body()
+                    .map_into_right_body()),
+                None => Ok(service_response.map_into_left_body()),
+            }
         })
     }
 }
+
+/// Rolls back the rate limit count when dropped, unless the token has been taken.
+struct RollbackGuard<BA, BI>
+where
+    BA: Backend<BI> + 'static,
+    BA::Error: std::fmt::Display,
+    BI: 'static,
+{
+    /// Only cloned when rolling back on cancellation is enabled.
+    backend: Option<BA>,
+    token: Option<BA::RollbackToken>,
+    input: PhantomData<BI>,
+}
+
+impl<BA, BI> Drop for RollbackGuard<BA, BI>
+where
+    BA: Backend<BI> + 'static,
+    BA::Error: std::fmt::Display,
+    BI: 'static,
+{
+    fn drop(&mut self) {
+        if let (Some(token), Some(backend)) = (self.token.take(), self.backend.take()) {
+            actix_web::rt::spawn(async move {
+                if let Err(e) = backend.rollback(token).await {
+                    log::error!("Unable to rollback rate-limit count for cancelled request: {e}");
+                }
+            });
+        }
+    }
+}
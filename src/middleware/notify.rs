@@ -0,0 +1,88 @@
+use crate::middleware::access::KeyFn;
+use actix_web::rt::time::Instant;
+use futures::future::LocalBoxFuture;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A key that was denied too many times, see
+/// [RateLimiterBuilder::notify_violations](crate::RateLimiterBuilder::notify_violations).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LimitViolation {
+    /// The rate limit key that was denied.
+    pub key: String,
+    /// The number of denied requests within the window, i.e. the threshold.
+    pub denied: u64,
+    /// The window the denied requests were counted over.
+    pub window: Duration,
+}
+
+type NotifyCallback = dyn Fn(LimitViolation) -> LocalBoxFuture<'static, ()> + Send + Sync;
+
+/// See [RateLimiterBuilder::notify_violations](crate::RateLimiterBuilder::notify_violations).
+pub(crate) struct Notifier {
+    pub(crate) key_fn: KeyFn,
+    threshold: u64,
+    window: Duration,
+    callback: Box<NotifyCallback>,
+    state: Mutex<State>,
+}
+
+struct State {
+    last_prune: Instant,
+    // The start of the window, and the number of denied requests within it.
+    keys: HashMap<String, (Instant, u64)>,
+}
+
+impl Notifier {
+    pub(crate) fn new(
+        key_fn: KeyFn,
+        threshold: u64,
+        window: Duration,
+        callback: Box<NotifyCallback>,
+    ) -> Self {
+        Self {
+            key_fn,
+            threshold,
+            window,
+            callback,
+            state: Mutex::new(State {
+                last_prune: Instant::now(),
+                keys: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Records a denied request, and spawns the callback if the key has just reached the
+    /// threshold within its window.
+    pub(crate) fn denied(&self, key: &str) {
+        if let Some(violation) = self.record(key) {
+            actix_web::rt::spawn((self.callback)(violation));
+        }
+    }
+
+    fn record(&self, key: &str) -> Option<LimitViolation> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if now.duration_since(state.last_prune) >= self.window {
+            let window = self.window;
+            state
+                .keys
+                .retain(|_, (start, _)| now.duration_since(*start) < window);
+            state.last_prune = now;
+        }
+        let (start, denied) = state.keys.entry(key.to_owned()).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *denied = 0;
+        }
+        *denied += 1;
+        // Only notifying when the threshold is first reached deduplicates the notifications
+        // within each window.
+        (*denied == self.threshold).then(|| LimitViolation {
+            key: key.to_owned(),
+            denied: *denied,
+            window: self.window,
+        })
+    }
+}
@@ -0,0 +1,60 @@
+use crate::backend::{BackendError, Decision};
+use opentelemetry::trace::get_active_span;
+use opentelemetry::KeyValue;
+use std::fmt::Display;
+
+pub(crate) const DECISION: &str = "rate_limiting.decision";
+pub(crate) const LIMIT: &str = "rate_limiting.limit";
+pub(crate) const REMAINING: &str = "rate_limiting.remaining";
+pub(crate) const RESET_AFTER: &str = "rate_limiting.reset_after";
+pub(crate) const DENIED_EVENT: &str = "rate_limiting.denied";
+pub(crate) const ERROR_EVENT: &str = "rate_limiting.error";
+
+/// See [RateLimiterBuilder::opentelemetry](crate::RateLimiterBuilder::opentelemetry).
+pub(crate) struct Telemetry<BO> {
+    pub(crate) limit: fn(&BO) -> u64,
+    pub(crate) remaining: fn(&BO) -> u64,
+    pub(crate) seconds_until_reset: fn(&BO) -> u64,
+}
+
+impl<BO> Telemetry<BO> {
+    /// Records the decision on the currently active span (i.e. the HTTP server span).
+    pub(crate) fn record_output(&self, decision: Decision, output: &BO) {
+        get_active_span(|span| {
+            span.set_attribute(KeyValue::new(
+                DECISION,
+                match decision {
+                    Decision::Allowed => "allowed",
+                    Decision::Denied => "denied",
+                },
+            ));
+            span.set_attribute(KeyValue::new(LIMIT, to_i64((self.limit)(output))));
+            span.set_attribute(KeyValue::new(REMAINING, to_i64((self.remaining)(output))));
+            span.set_attribute(KeyValue::new(
+                RESET_AFTER,
+                to_i64((self.seconds_until_reset)(output)),
+            ));
+            if decision.is_denied() {
+                span.add_event(DENIED_EVENT, vec![]);
+            }
+        });
+    }
+
+    pub(crate) fn record_error(&self, error: &dyn Display, class: BackendError) {
+        get_active_span(|span| {
+            span.set_attribute(KeyValue::new(DECISION, "error"));
+            span.add_event(
+                ERROR_EVENT,
+                vec![
+                    KeyValue::new("exception.message", error.to_string()),
+                    KeyValue::new("error.type", class.as_str()),
+                ],
+            );
+        });
+    }
+}
+
+// OpenTelemetry only supports signed integer attributes.
+fn to_i64(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
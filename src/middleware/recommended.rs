@@ -0,0 +1,117 @@
+use crate::backend::input_builder::ip_key;
+use crate::backend::timeout::TimeoutBackend;
+use crate::backend::{
+    Backend, SimpleInput, SimpleInputFunctionBuilder, SimpleInputFuture, SimpleOutput,
+};
+use crate::{RateLimiter, RateLimiterBuilder};
+use actix_web::dev::ServiceRequest;
+use std::net::IpAddr;
+use std::time::Duration;
+
+pub const RECOMMENDED_INTERVAL_SECONDS: u64 = 60;
+pub const RECOMMENDED_MAX_REQUESTS: u64 = 100;
+pub const RECOMMENDED_TIMEOUT_MILLIS: u64 = 250;
+
+/// The backend type used by [RateLimiter::recommended].
+#[cfg(not(feature = "metrics"))]
+pub type RecommendedBackend<B> = TimeoutBackend<B>;
+
+/// The backend type used by [RateLimiter::recommended].
+#[cfg(feature = "metrics")]
+pub type RecommendedBackend<B> =
+    TimeoutBackend<crate::backend::utilization::UtilizationMetricsBackend<B>>;
+
+impl<BA> RateLimiter<BA, SimpleOutput, ()> {
+    /// Creates a [RateLimiterBuilder] with sensible production defaults, that can then be
+    /// further customised:
+    ///
+    /// - 100 requests per minute per client IP address (IPv6 addresses are grouped per /64).
+    /// - The client IP address is taken from the proxy headers only if the connection peer is a
+    ///   loopback or private network address (i.e. a reverse proxy in the same network),
+    ///   otherwise the peer address is used, so that clients can't spoof their address.
+    /// - The standard headers are added, see [RateLimiterBuilder::add_headers].
+    /// - Backend calls time out after 250ms, in which case the request is allowed
+    ///   (fail open), so a slow store adds bounded latency.
+    /// - If the `metrics` feature is enabled then window utilization is recorded under the
+    ///   `default` policy, see
+    ///   [UtilizationMetricsBackend](crate::backend::utilization::UtilizationMetricsBackend).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+    /// # use actix_extensible_rate_limit::RateLimiter;
+    /// # actix_web::rt::System::new().block_on(async {
+    /// let backend = InMemoryBackend::builder().build();
+    /// let middleware = RateLimiter::recommended(backend).build();
+    /// # });
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn recommended(
+        backend: BA,
+    ) -> RateLimiterBuilder<
+        RecommendedBackend<BA>,
+        SimpleOutput,
+        impl Fn(&ServiceRequest) -> SimpleInputFuture + Send + Sync + 'static,
+    >
+    where
+        BA: Backend<SimpleInput, Output = SimpleOutput> + 'static,
+    {
+        #[cfg(feature = "metrics")]
+        let backend =
+            crate::backend::utilization::UtilizationMetricsBackend::builder(backend, "default")
+                .build();
+        let backend = TimeoutBackend::builder(backend)
+            .timeout(Duration::from_millis(RECOMMENDED_TIMEOUT_MILLIS))
+            .build();
+        let input = SimpleInputFunctionBuilder::new(
+            Duration::from_secs(RECOMMENDED_INTERVAL_SECONDS),
+            RECOMMENDED_MAX_REQUESTS,
+        )
+        .custom_fn(client_ip_key)
+        .build();
+        RateLimiter::builder(backend, input)
+            .add_headers()
+            .fail_open(true)
+    }
+}
+
+/// Uses the real IP address only if the request came via a proxy in a private network.
+fn client_ip_key(req: &ServiceRequest) -> Result<String, actix_web::Error> {
+    let peer = req.peer_addr().map(|addr| addr.ip());
+    let trusted = peer.is_some_and(|ip| match ip {
+        IpAddr::V4(v4) => v4.is_loopback() || v4.is_private(),
+        IpAddr::V6(v6) => v6.is_loopback() || (v6.segments()[0] & 0xfe00) == 0xfc00,
+    });
+    let addr = match peer {
+        Some(peer) if !trusted => peer.to_string(),
+        _ => req
+            .connection_info()
+            .realip_remote_addr()
+            .unwrap_or_default()
+            .to_owned(),
+    };
+    Ok(ip_key(&addr)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_client_ip_key() {
+        // Direct connections can't spoof their address
+        let req = TestRequest::default()
+            .peer_addr("203.0.113.1:1234".parse().unwrap())
+            .insert_header(("x-forwarded-for", "198.51.100.1"))
+            .to_srv_request();
+        assert_eq!(client_ip_key(&req).unwrap(), "203.0.113.1");
+        // Proxies in a private network are trusted
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .insert_header(("x-forwarded-for", "198.51.100.1"))
+            .to_srv_request();
+        assert_eq!(client_ip_key(&req).unwrap(), "198.51.100.1");
+    }
+}
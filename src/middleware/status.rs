@@ -0,0 +1,95 @@
+use crate::backend::{Decision, SimpleOutput};
+use crate::HeaderCompatibleOutput;
+use actix_web::dev::Payload;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use futures::future::{ready, Ready};
+use std::rc::Rc;
+
+/// The result of the rate limit check for the current request, inserted into the request
+/// extensions by the [RateLimiter](crate::RateLimiter) before calling the inner service.
+///
+/// This can be used as an extractor, so that handlers can include the rate limit status in their
+/// own responses. Extraction fails with a 500 error if the request didn't pass through a
+/// [RateLimiter](crate::RateLimiter) with the same output type; use `Option<RateLimitStatus>` if
+/// this is expected.
+///
+/// If multiple limiters are applied, this contains the status of the innermost one.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::RateLimitStatus;
+/// # use actix_web::get;
+/// #[get("/")]
+/// async fn index(status: RateLimitStatus) -> String {
+///     match status.remaining() {
+///         Some(remaining) => format!("You have {remaining} calls left"),
+///         None => "Rate limit status unavailable".to_string(),
+///     }
+/// }
+/// ```
+pub struct RateLimitStatus<BO = SimpleOutput> {
+    decision: Decision,
+    output: Option<Rc<BO>>,
+}
+
+impl<BO> Clone for RateLimitStatus<BO> {
+    fn clone(&self) -> Self {
+        Self {
+            decision: self.decision,
+            output: self.output.clone(),
+        }
+    }
+}
+
+impl<BO> RateLimitStatus<BO> {
+    pub(crate) fn new(decision: Decision, output: Option<Rc<BO>>) -> Self {
+        Self { decision, output }
+    }
+
+    /// The decision made by the backend.
+    pub fn decision(&self) -> Decision {
+        self.decision
+    }
+
+    /// The [Backend::Output](crate::backend::Backend::Output).
+    ///
+    /// This will be [None] if the backend failed and
+    /// [RateLimiterBuilder::fail_open](crate::RateLimiterBuilder::fail_open) is enabled.
+    pub fn output(&self) -> Option<&BO> {
+        self.output.as_deref()
+    }
+}
+
+impl<BO: HeaderCompatibleOutput> RateLimitStatus<BO> {
+    /// Total number of requests that are permitted within the rate limit interval.
+    pub fn limit(&self) -> Option<u64> {
+        self.output().map(HeaderCompatibleOutput::limit)
+    }
+
+    /// Number of requests that will be permitted until the limit resets.
+    pub fn remaining(&self) -> Option<u64> {
+        self.output().map(HeaderCompatibleOutput::remaining)
+    }
+
+    /// Number of seconds until the limit resets.
+    pub fn seconds_until_reset(&self) -> Option<u64> {
+        self.output()
+            .map(HeaderCompatibleOutput::seconds_until_reset)
+    }
+}
+
+impl<BO: 'static> FromRequest for RateLimitStatus<BO> {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<RateLimitStatus<BO>>()
+                .cloned()
+                .ok_or_else(|| ErrorInternalServerError("Rate limit status is not available")),
+        )
+    }
+}
@@ -1,11 +1,15 @@
-use crate::backend::Decision;
+use crate::backend::{
+    BackendError, ClassifyError, Decision, PolicyInput, RateLimitPolicy, SimpleOutput,
+};
 use crate::middleware::*;
+use crate::{HeaderCompatibleOutput, HeaderMergeStrategy, LocalizedMessages, ResetFormat};
 use actix_web::http::header::{HeaderName, HeaderValue};
 use actix_web::http::StatusCode;
 use actix_web::test::{read_body, TestRequest};
 use actix_web::{get, test, App, HttpResponse, Responder, ResponseError};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 use thiserror::Error;
 
 #[get("/200")]
@@ -26,6 +30,7 @@ struct MockBackendInner {
     counter: AtomicU64,
 }
 
+#[derive(Clone)]
 struct MockBackendInput<T> {
     max: u64,
     output: T,
@@ -54,11 +59,18 @@ impl<T: 'static> Backend<MockBackendInput<T>> for MockBackend {
     }
 }
 
+impl<T> PolicyInput for MockBackendInput<T> {
+    fn set_policy(&mut self, policy: &RateLimitPolicy) {
+        self.max = policy.max_requests();
+    }
+}
+
 #[derive(Debug, Clone, Error)]
 #[error("MockError: {message}")]
 struct MockError {
     code: StatusCode,
     message: String,
+    class: BackendError,
 }
 
 impl Default for MockError {
@@ -66,10 +78,17 @@ impl Default for MockError {
         MockError {
             code: StatusCode::INTERNAL_SERVER_ERROR,
             message: "Mock Error".to_string(),
+            class: BackendError::Transient,
         }
     }
 }
 
+impl ClassifyError for MockError {
+    fn class(&self) -> BackendError {
+        self.class
+    }
+}
+
 impl ResponseError for MockError {
     fn status_code(&self) -> StatusCode {
         self.code
@@ -102,6 +121,142 @@ async fn test_allow_deny() {
     );
 }
 
+#[actix_web::test]
+async fn test_from_fn() {
+    use actix_web::middleware::from_fn;
+
+    let input = |_req: &ServiceRequest| async {
+        Ok(MockBackendInput {
+            max: 1,
+            output: (),
+            backend_error: None,
+        })
+    };
+    let app = test::init_service(
+        App::new()
+            .service(route_200)
+            .wrap(from_fn(rate_limit(MockBackend::default(), input))),
+    )
+    .await;
+    let request = || TestRequest::get().uri("/200").to_request();
+    assert!(test::call_service(&app, request())
+        .await
+        .status()
+        .is_success());
+    assert_eq!(
+        test::call_service(&app, request()).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // A customized rate limiter
+    let limiter = RateLimiter::builder(MockBackend::default(), input)
+        .request_denied_response(|_| HttpResponse::ServiceUnavailable().finish())
+        .build();
+    let app = test::init_service(
+        App::new()
+            .service(route_200)
+            .wrap(from_fn(limiter.into_fn())),
+    )
+    .await;
+    test::call_service(&app, request()).await;
+    assert_eq!(
+        test::call_service(&app, request()).await.status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+}
+
+#[cfg(feature = "dashmap")]
+#[actix_web::test]
+async fn test_guard() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInput;
+    use actix_web::guard::GuardContext;
+    use actix_web::web;
+
+    fn input() -> SimpleInput {
+        SimpleInput {
+            interval: Duration::from_secs(60),
+            max_requests: 2,
+            key: "KEY1".into(),
+        }
+    }
+    let backend = InMemoryBackend::builder().build();
+    let guard = guard::RateLimitGuard::new(backend.clone(), |_: &GuardContext<'_>| Some(input()));
+    let limiter = RateLimiter::builder(backend, |_: &ServiceRequest| {
+        std::future::ready(Ok(input()))
+    })
+    .build();
+    let app = test::init_service(
+        App::new()
+            .service(
+                web::resource("/search")
+                    .guard(guard)
+                    .wrap(limiter)
+                    .to(|| async { "full" }),
+            )
+            .service(web::resource("/search").to(|| async { "cached" })),
+    )
+    .await;
+    let request = || TestRequest::get().uri("/search").to_request();
+    for expected in ["full", "full", "cached", "cached"] {
+        let body = read_body(test::call_service(&app, request()).await).await;
+        assert_eq!(body, expected);
+    }
+}
+
+#[cfg(feature = "dashmap")]
+#[actix_web::test]
+async fn test_rate_limit_extractor() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleBackend;
+    use crate::middleware::builder::X_RATELIMIT_REMAINING;
+    use crate::{PolicyProvider, RateLimit};
+    use actix_web::http::header::RETRY_AFTER;
+    use actix_web::{web, HttpRequest};
+
+    struct TestPolicy;
+
+    impl PolicyProvider for TestPolicy {
+        fn policy() -> RateLimitPolicy {
+            RateLimitPolicy::new(Duration::from_secs(60), 2)
+        }
+
+        fn key(_: &HttpRequest) -> Result<String, actix_web::Error> {
+            Ok("KEY1".to_owned())
+        }
+
+        fn name() -> &'static str {
+            "test"
+        }
+    }
+
+    async fn handler(limit: RateLimit<TestPolicy>) -> String {
+        limit.remaining().to_string()
+    }
+
+    let backend = InMemoryBackend::builder().build();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(backend.clone()))
+            .route("/", web::get().to(handler)),
+    )
+    .await;
+    let request = || TestRequest::get().uri("/").to_request();
+    let body = read_body(test::call_service(&app, request()).await).await;
+    assert_eq!(body, "1");
+    let body = read_body(test::call_service(&app, request()).await).await;
+    assert_eq!(body, "0");
+    let response = test::call_service(&app, request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(RETRY_AFTER));
+    assert_eq!(
+        response.headers().get(X_RATELIMIT_REMAINING).unwrap(),
+        HeaderValue::from(0)
+    );
+    // Counted by the backend in the app data
+    assert_eq!(backend.get("test-KEY1").await.unwrap().unwrap().count, 3);
+}
+
 #[actix_web::test]
 async fn test_custom_deny_response() {
     let backend = MockBackend::default();
@@ -121,6 +276,36 @@ async fn test_custom_deny_response() {
     assert_eq!(body, "Custom denied response");
 }
 
+#[actix_web::test]
+async fn test_custom_deny_response_async() {
+    use actix_web::web::Bytes;
+    use futures::stream;
+
+    let backend = MockBackend::default();
+    let limiter = RateLimiter::builder(backend, |_req| async {
+        Ok(MockBackendInput {
+            max: 0,
+            output: StatusCode::IM_A_TEAPOT,
+            backend_error: None,
+        })
+    })
+    .request_denied_response_async(|output| {
+        let status = *output;
+        async move {
+            actix_web::rt::task::yield_now().await;
+            let chunks = ["Streamed ", "denied ", "response"]
+                .map(|chunk| Ok::<_, actix_web::Error>(Bytes::from(chunk)));
+            HttpResponse::build(status).streaming(stream::iter(chunks))
+        }
+    })
+    .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert_eq!(body, "Streamed denied response");
+}
+
 #[actix_web::test]
 async fn test_header_transformation() {
     let backend = MockBackend::default();
@@ -168,7 +353,7 @@ async fn test_fail_open() {
         Ok(MockBackendInput {
             max: u64::MAX,
             output: (),
-            backend_error: Some(MockError::default().into()),
+            backend_error: Some(MockError::default()),
         })
     })
     .build();
@@ -181,7 +366,7 @@ async fn test_fail_open() {
         Ok(MockBackendInput {
             max: u64::MAX,
             output: (),
-            backend_error: Some(MockError::default().into()),
+            backend_error: Some(MockError::default()),
         })
     })
     .request_allowed_transformation(Some(
@@ -202,6 +387,88 @@ async fn test_fail_open() {
     assert!(response.headers().contains_key("custom-header"))
 }
 
+#[actix_web::test]
+async fn test_fail_open_transient() {
+    for transient_only in [false, true] {
+        let limiter = RateLimiter::builder(MockBackend::default(), |req: &ServiceRequest| {
+            let class = match req.path() {
+                "/transient" => BackendError::Transient,
+                "/misconfigured" => BackendError::PermanentMisconfiguration,
+                _ => BackendError::KeyRejected,
+            };
+            async move {
+                Ok(MockBackendInput {
+                    max: u64::MAX,
+                    output: (),
+                    backend_error: Some(MockError {
+                        class,
+                        ..Default::default()
+                    }),
+                })
+            }
+        })
+        .fail_open(!transient_only)
+        .fail_open_transient(transient_only)
+        .build();
+        let app = test::init_service(
+            App::new()
+                .default_service(actix_web::web::get().to(HttpResponse::Ok))
+                .wrap(limiter),
+        )
+        .await;
+        let status = |uri: &'static str| {
+            let app = &app;
+            async move {
+                test::call_service(app, TestRequest::get().uri(uri).to_request())
+                    .await
+                    .status()
+            }
+        };
+        // Fail open allows every class of error, otherwise only transient errors are allowed
+        let other = match transient_only {
+            true => StatusCode::INTERNAL_SERVER_ERROR,
+            false => StatusCode::OK,
+        };
+        assert_eq!(status("/transient").await, StatusCode::OK);
+        assert_eq!(status("/misconfigured").await, other);
+        assert_eq!(status("/rejected").await, other);
+    }
+}
+
+#[actix_web::test]
+async fn test_fail_open_if() {
+    let limiter = RateLimiter::builder(MockBackend::default(), |req: &ServiceRequest| {
+        let code = match req.path() {
+            "/timeout" => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        async move {
+            Ok(MockBackendInput {
+                max: u64::MAX,
+                output: (),
+                backend_error: Some(MockError {
+                    code,
+                    ..Default::default()
+                }),
+            })
+        }
+    })
+    .fail_open_if(|e: &MockError| e.code == StatusCode::GATEWAY_TIMEOUT)
+    .build();
+    let app = test::init_service(
+        App::new()
+            .route("/timeout", actix_web::web::get().to(HttpResponse::Ok))
+            .route("/misconfigured", actix_web::web::get().to(HttpResponse::Ok))
+            .wrap(limiter),
+    )
+    .await;
+    let response = test::call_service(&app, TestRequest::get().uri("/timeout").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response =
+        test::call_service(&app, TestRequest::get().uri("/misconfigured").to_request()).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
 #[actix_web::test]
 async fn test_rollback() {
     let backend = MockBackend::default();
@@ -232,3 +499,1272 @@ async fn test_rollback() {
     assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
     assert_eq!(backend.0.counter.load(Ordering::Relaxed), 1);
 }
+
+#[actix_web::test]
+async fn test_count_when() {
+    let backend = MockBackend::default();
+    let limiter = RateLimiter::builder(backend.clone(), |_req| async {
+        Ok(MockBackendInput {
+            max: 1,
+            output: (),
+            backend_error: None,
+        })
+    })
+    .count_when(|status| status.is_server_error())
+    .build();
+    let app = test::init_service(
+        App::new()
+            .service(route_200)
+            .service(route_500)
+            .wrap(limiter),
+    )
+    .await;
+
+    // Successful responses aren't counted
+    for _ in 0..3 {
+        let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    assert_eq!(backend.0.counter.load(Ordering::Relaxed), 0);
+
+    let response = test::call_service(&app, TestRequest::get().uri("/500").to_request()).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(backend.0.counter.load(Ordering::Relaxed), 1);
+
+    // The limit has now been reached
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(backend.0.counter.load(Ordering::Relaxed), 1);
+}
+
+#[derive(Clone)]
+struct MockOverageOutput(bool);
+
+impl HeaderCompatibleOutput for MockOverageOutput {
+    fn limit(&self) -> u64 {
+        1
+    }
+
+    fn remaining(&self) -> u64 {
+        0
+    }
+
+    fn seconds_until_reset(&self) -> u64 {
+        0
+    }
+
+    fn is_overage(&self) -> bool {
+        self.0
+    }
+}
+
+#[actix_web::test]
+async fn test_overage_header() {
+    let backend = MockBackend::default();
+    let limiter = RateLimiter::builder(backend, |req: &ServiceRequest| {
+        let overage = req.headers().contains_key("overage");
+        async move {
+            Ok(MockBackendInput {
+                max: u64::MAX,
+                output: MockOverageOutput(overage),
+                backend_error: None,
+            })
+        }
+    })
+    .add_headers()
+    .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    assert!(!response.headers().contains_key("x-overage"));
+    let request = TestRequest::get()
+        .uri("/200")
+        .insert_header(("overage", "1"))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.headers().get("x-overage").unwrap(), "true");
+}
+
+#[cfg(feature = "dashmap")]
+#[actix_web::test]
+async fn test_soft_limit_warning() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+
+    let warnings = Arc::new(AtomicU64::new(0));
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 10).build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .soft_limit_warning(0.2)
+        .on_soft_limit(0.5, {
+            let warnings = warnings.clone();
+            move |_, _| {
+                warnings.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    for remaining in (0..10).rev() {
+        let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // Below 20% of the limit remaining
+        assert_eq!(
+            response.headers().contains_key("x-ratelimit-warning"),
+            remaining < 2
+        );
+    }
+    // Called for each request with fewer than 5 remaining
+    assert_eq!(warnings.load(Ordering::Relaxed), 5);
+}
+
+#[cfg(feature = "dashmap")]
+#[actix_web::test]
+async fn test_send_sync() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use std::time::Duration;
+
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
+        .real_ip_key()
+        .build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .add_headers()
+        .rollback_server_errors()
+        .build();
+    // Can be built once and moved into the HttpServer::new() factory
+    assert_send_sync(&limiter);
+}
+
+#[derive(Clone)]
+struct MockHeaderOutput {
+    limit: u64,
+    remaining: u64,
+}
+
+impl HeaderCompatibleOutput for MockHeaderOutput {
+    fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    fn seconds_until_reset(&self) -> u64 {
+        self.limit
+    }
+}
+
+#[actix_web::test]
+async fn test_add_headers_unix_timestamp() {
+    let limiter = RateLimiter::builder(MockBackend::default(), |_req| async {
+        Ok(MockBackendInput {
+            max: 1,
+            output: SimpleOutput {
+                limit: 1,
+                remaining: 0,
+                reset: tokio::time::Instant::now() + Duration::from_secs(30),
+                reset_at: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_500)),
+            },
+            backend_error: None,
+        })
+    })
+    .add_headers_with_reset_format(ResetFormat::UnixTimestamp)
+    .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("x-ratelimit-reset").unwrap(),
+        "1700000001"
+    );
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        response.headers().get("x-ratelimit-reset").unwrap(),
+        "1700000001"
+    );
+    assert_eq!(response.headers().get("retry-after").unwrap(), "30");
+}
+
+type MockInputFuture<T> = futures::future::Ready<Result<MockBackendInput<T>, actix_web::Error>>;
+
+fn merged_limiter(
+    policy: &str,
+    limit: u64,
+    remaining: u64,
+    strategy: HeaderMergeStrategy,
+) -> RateLimiter<
+    MockBackend,
+    MockHeaderOutput,
+    impl Fn(&ServiceRequest) -> MockInputFuture<MockHeaderOutput>,
+> {
+    RateLimiter::builder(MockBackend::default(), move |_req: &ServiceRequest| {
+        futures::future::ok(MockBackendInput {
+            max: u64::MAX,
+            output: MockHeaderOutput { limit, remaining },
+            backend_error: None,
+        })
+    })
+    .add_merged_headers(policy, strategy)
+    .build()
+}
+
+#[actix_web::test]
+async fn test_merged_headers_most_restrictive() {
+    let strategy = HeaderMergeStrategy::MostRestrictive;
+    let app = test::init_service(
+        App::new()
+            .service(route_200)
+            .wrap(merged_limiter("second", 10, 9, strategy))
+            .wrap(merged_limiter("minute", 100, 2, strategy))
+            .wrap(merged_limiter("day", 1000, 500, strategy)),
+    )
+    .await;
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    let headers = response.headers();
+    assert_eq!(headers.get("x-ratelimit-limit").unwrap(), "100");
+    assert_eq!(headers.get("x-ratelimit-remaining").unwrap(), "2");
+    assert_eq!(headers.get("x-ratelimit-reset").unwrap(), "100");
+}
+
+#[actix_web::test]
+async fn test_merged_headers_prefixed() {
+    let strategy = HeaderMergeStrategy::Prefixed;
+    let app = test::init_service(
+        App::new()
+            .service(route_200)
+            .wrap(merged_limiter("second", 10, 9, strategy))
+            .wrap(merged_limiter("minute", 100, 2, strategy)),
+    )
+    .await;
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    let headers = response.headers();
+    assert_eq!(headers.get("x-ratelimit-second-remaining").unwrap(), "9");
+    assert_eq!(headers.get("x-ratelimit-minute-remaining").unwrap(), "2");
+    assert!(!headers.contains_key("x-ratelimit-remaining"));
+}
+
+#[actix_web::test]
+async fn test_skip_if() {
+    let backend = MockBackend::default();
+    let limiter = RateLimiter::builder(backend.clone(), |_req| async {
+        Ok(MockBackendInput {
+            max: 1,
+            output: (),
+            backend_error: None,
+        })
+    })
+    .skip_if(|req| req.method() == actix_web::http::Method::OPTIONS)
+    .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    for _ in 0..3 {
+        let request = TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri("/200")
+            .to_request();
+        test::call_service(&app, request).await;
+    }
+    // Skipped requests are not counted
+    assert_eq!(backend.0.counter.load(Ordering::Relaxed), 0);
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    assert!(response.status().is_success());
+}
+
+#[actix_web::test]
+async fn test_skip_if_async() {
+    let backend = MockBackend::default();
+    let limiter = RateLimiter::builder(backend.clone(), |_req| async {
+        Ok(MockBackendInput {
+            max: 0,
+            output: (),
+            backend_error: None,
+        })
+    })
+    .skip_if_async(|req| {
+        let admin = req.headers().contains_key("admin-token");
+        async move { admin }
+    })
+    .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let request = TestRequest::get()
+        .uri("/200")
+        .insert_header(("admin-token", "secret"))
+        .to_request();
+    assert!(test::call_service(&app, request)
+        .await
+        .status()
+        .is_success());
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn test_only_methods() {
+    use actix_web::http::Method;
+    use actix_web::web;
+
+    let limiter = RateLimiter::builder(MockBackend::default(), |_req| async {
+        Ok(MockBackendInput {
+            max: 0,
+            output: (),
+            backend_error: None,
+        })
+    })
+    .only_methods([Method::POST, Method::DELETE])
+    .skip_if(|req| req.headers().contains_key("admin-token"))
+    .build();
+    let app = test::init_service(
+        App::new()
+            .route("/", web::get().to(HttpResponse::Ok))
+            .route("/", web::post().to(HttpResponse::Ok))
+            .wrap(limiter),
+    )
+    .await;
+    let response = test::call_service(&app, TestRequest::get().uri("/").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = test::call_service(&app, TestRequest::post().uri("/").to_request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let request = TestRequest::post()
+        .uri("/")
+        .insert_header(("admin-token", "secret"))
+        .to_request();
+    let response = test::call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[cfg(feature = "dashmap")]
+#[actix_web::test]
+async fn test_access_lists() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use std::time::Duration;
+
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+        .custom_fn(|req| {
+            Ok(req
+                .headers()
+                .get("api-key")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_owned())
+        })
+        .build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .allowlist_ips(["10.0.0.0/8".parse().unwrap()])
+        .denylist_ips(["10.1.0.0/16".parse().unwrap()])
+        .allowlist_keys(["trusted"])
+        .denylist_keys(["revoked"])
+        .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let request = |ip: &str, key: &str| {
+        TestRequest::get()
+            .uri("/200")
+            .peer_addr(format!("{ip}:1234").parse().unwrap())
+            .insert_header(("api-key", key))
+            .to_request()
+    };
+
+    // Allowlisted IPs and keys are never counted
+    for _ in 0..3 {
+        let response = test::call_service(&app, request("10.0.0.1", "a")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, request("192.168.0.1", "trusted")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    // The denylist takes precedence
+    let response = test::call_service(&app, request("10.1.0.1", "a")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = test::call_service(&app, request("192.168.0.1", "revoked")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    // Otherwise limited as normal
+    let response = test::call_service(&app, request("192.168.0.1", "b")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = test::call_service(&app, request("192.168.0.1", "b")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[cfg(feature = "dashmap")]
+#[actix_web::test]
+async fn test_pre_hook() {
+    let backend = MockBackend::default();
+    let limiter = RateLimiter::builder(backend.clone(), |_| {
+        std::future::ready(Ok(MockBackendInput::<()> {
+            max: 1,
+            output: (),
+            backend_error: None,
+        }))
+    })
+    .pre_hook(|req| {
+        let decision = match req.headers().get("hook").map(|v| v.as_bytes()) {
+            Some(b"allow") => HookDecision::Allow,
+            Some(b"deny") => HookDecision::Deny(HttpResponse::ServiceUnavailable().finish()),
+            _ => HookDecision::Continue,
+        };
+        async move { decision }
+    })
+    .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let request = |hook: &str| {
+        TestRequest::get()
+            .uri("/200")
+            .insert_header(("hook", hook))
+            .to_request()
+    };
+
+    let response = test::call_service(&app, request("deny")).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let response = test::call_service(&app, request("allow")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // Neither were counted
+    assert_eq!(backend.0.counter.load(Ordering::Relaxed), 0);
+    let response = test::call_service(&app, request("continue")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = test::call_service(&app, request("continue")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn test_decision_hook() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+        .custom_fn(|req| Ok(req.path().to_owned()))
+        .build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .decision_hook(|req, decision, _| match req.headers().get("plan") {
+            Some(plan) if plan == "premium" => HookDecision::Allow,
+            Some(plan) if plan == "banned" => {
+                HookDecision::Deny(HttpResponse::Forbidden().finish())
+            }
+            _ => {
+                assert!(decision.is_allowed() || req.headers().contains_key("expect-denied"));
+                HookDecision::Continue
+            }
+        })
+        .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let request = |headers: &[(&str, &str)]| {
+        let mut request = TestRequest::get().uri("/200");
+        for header in headers {
+            request = request.insert_header(*header);
+        }
+        request.to_request()
+    };
+
+    let response = test::call_service(&app, request(&[("plan", "banned")])).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = test::call_service(&app, request(&[("expect-denied", "")])).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    // Allowed despite exceeding the limit
+    let response = test::call_service(&app, request(&[("plan", "premium")])).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_post_hook() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use std::sync::Mutex;
+
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 2)
+        .custom_fn(|_| Ok("KEY1".to_owned()))
+        .build();
+    let recorder = records.clone();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .add_headers()
+        .rollback_server_errors()
+        .post_hook(move |context| {
+            recorder.lock().unwrap().push((
+                context.request.path().to_owned(),
+                context.decision,
+                context.output.map(|output| output.remaining),
+                context.status,
+                context.rolled_back,
+            ));
+        })
+        .build();
+    let app = test::init_service(
+        App::new()
+            .service(route_200)
+            .service(route_500)
+            .wrap(limiter),
+    )
+    .await;
+
+    for uri in ["/200", "/500", "/200", "/200"] {
+        test::call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+    }
+    assert_eq!(
+        *records.lock().unwrap(),
+        vec![
+            (
+                "/200".into(),
+                Decision::Allowed,
+                Some(1),
+                StatusCode::OK,
+                false
+            ),
+            (
+                "/500".into(),
+                Decision::Allowed,
+                Some(0),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                true
+            ),
+            (
+                "/200".into(),
+                Decision::Allowed,
+                Some(0),
+                StatusCode::OK,
+                false
+            ),
+            (
+                "/200".into(),
+                Decision::Denied,
+                Some(0),
+                StatusCode::TOO_MANY_REQUESTS,
+                false
+            ),
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn test_global_limit() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use actix_web::http::header::RETRY_AFTER;
+
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+        .custom_fn(|req| {
+            Ok(req
+                .headers()
+                .get("client")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .into())
+        })
+        .build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .global_limit(Duration::from_secs(60), 2)
+        .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let request = |client: &str| {
+        TestRequest::get()
+            .uri("/200")
+            .insert_header(("client", client))
+            .to_request()
+    };
+
+    let response = test::call_service(&app, request("a")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // Denied by the backend, so not counted towards the global limit
+    let response = test::call_service(&app, request("a")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = test::call_service(&app, request("b")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = test::call_service(&app, request("c")).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.headers().contains_key(RETRY_AFTER));
+}
+
+#[actix_web::test]
+async fn test_limit_connections() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use actix_web::web::{self, Bytes};
+    use futures::{stream, StreamExt};
+
+    async fn events() -> HttpResponse {
+        let events = stream::iter([Ok::<_, actix_web::Error>(Bytes::from("data: 1\n\n"))]);
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .streaming(events.chain(stream::pending()))
+    }
+
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+        .custom_fn(|_| Ok("KEY1".to_owned()))
+        .build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .limit_connections()
+        .build();
+    let app = test::init_service(
+        App::new()
+            .route("/events", web::get().to(events))
+            .wrap(limiter),
+    )
+    .await;
+    let request = || TestRequest::get().uri("/events").to_request();
+
+    let connection = test::call_service(&app, request()).await;
+    assert_eq!(connection.status(), StatusCode::OK);
+    // Only one connection may be open at once
+    let response = test::call_service(&app, request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    // Closing the connection releases the count
+    drop(connection);
+    actix_web::rt::task::yield_now().await;
+    let response = test::call_service(&app, request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_challenge_response() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use actix_web::http::header::LOCATION;
+
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+        .custom_fn(|req| {
+            Ok(req
+                .headers()
+                .get("api-key")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_owned())
+        })
+        .build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .challenge_response(|req, key, _| {
+            key.starts_with("suspicious").then(|| {
+                HttpResponse::SeeOther()
+                    .insert_header((LOCATION, format!("/verify?next={}", req.path())))
+                    .finish()
+            })
+        })
+        .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let request = |key: &str| {
+        TestRequest::get()
+            .uri("/200")
+            .insert_header(("api-key", key))
+            .to_request()
+    };
+
+    for key in ["normal", "suspicious-1"] {
+        let response = test::call_service(&app, request(key)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = test::call_service(&app, request("normal")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = test::call_service(&app, request("suspicious-1")).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        response.headers().get(LOCATION).unwrap(),
+        "/verify?next=/200"
+    );
+}
+
+#[actix_web::test]
+async fn test_status_extractor() {
+    use crate::RateLimitStatus;
+
+    async fn handler(status: RateLimitStatus<MockHeaderOutput>) -> String {
+        format!("{} calls left", status.remaining().unwrap())
+    }
+
+    async fn optional_handler(status: Option<RateLimitStatus<MockHeaderOutput>>) -> String {
+        status.is_some().to_string()
+    }
+
+    let backend = MockBackend::default();
+    let limiter = RateLimiter::builder(backend, |_req| async {
+        Ok(MockBackendInput {
+            max: u64::MAX,
+            output: MockHeaderOutput {
+                limit: 10,
+                remaining: 7,
+            },
+            backend_error: None,
+        })
+    })
+    .build();
+    let app = test::init_service(
+        App::new()
+            .service(
+                actix_web::web::scope("/limited")
+                    .wrap(limiter)
+                    .route("", actix_web::web::get().to(handler)),
+            )
+            .route("/unlimited", actix_web::web::get().to(optional_handler))
+            .route("/error", actix_web::web::get().to(handler)),
+    )
+    .await;
+    let response = test::call_service(&app, TestRequest::get().uri("/limited").to_request()).await;
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert_eq!(body, "7 calls left");
+    let response =
+        test::call_service(&app, TestRequest::get().uri("/unlimited").to_request()).await;
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert_eq!(body, "false");
+    let response = test::call_service(&app, TestRequest::get().uri("/error").to_request()).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[cfg(feature = "dashmap")]
+#[actix_web::test]
+async fn test_peek_only() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use crate::RateLimitStatus;
+
+    async fn quota(status: RateLimitStatus) -> String {
+        format!("{:?} {}", status.decision(), status.remaining().unwrap())
+    }
+
+    let backend = InMemoryBackend::builder().build();
+    let input = || SimpleInputFunctionBuilder::new(Duration::from_secs(60), 2).build();
+    let app = test::init_service(
+        App::new()
+            .service(
+                actix_web::web::resource("/quota")
+                    .route(actix_web::web::get().to(quota))
+                    .wrap(
+                        RateLimiter::builder(backend.clone(), input())
+                            .peek_only()
+                            .build(),
+                    ),
+            )
+            .service(
+                actix_web::web::scope("")
+                    .wrap(RateLimiter::builder(backend, input()).build())
+                    .service(route_200),
+            ),
+    )
+    .await;
+    let quota = || async {
+        let response =
+            test::call_service(&app, TestRequest::get().uri("/quota").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        String::from_utf8(read_body(response).await.to_vec()).unwrap()
+    };
+    // Checking the quota doesn't count
+    assert_eq!(quota().await, "Allowed 1");
+    assert_eq!(quota().await, "Allowed 1");
+    for _ in 0..2 {
+        let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    // Nor is it denied
+    assert_eq!(quota().await, "Denied 0");
+}
+
+#[cfg(feature = "dashmap")]
+#[actix_web::test]
+async fn test_event_latency() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::{SimpleInput, SimpleInputFunctionBuilder};
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+
+    #[derive(Clone)]
+    struct SlowBackend(InMemoryBackend);
+
+    impl Backend<SimpleInput> for SlowBackend {
+        type Output = SimpleOutput;
+        type RollbackToken = Arc<str>;
+        type Error = Infallible;
+
+        async fn request(
+            &self,
+            input: SimpleInput,
+        ) -> Result<(Decision, Self::Output, Self::RollbackToken), Self::Error> {
+            actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+            self.0.request(input).await
+        }
+
+        async fn rollback(&self, token: Self::RollbackToken) -> Result<(), Self::Error> {
+            self.0.rollback(token).await
+        }
+    }
+
+    tokio::time::pause();
+    let latencies = Arc::new(Mutex::new(Vec::new()));
+    let recorded = latencies.clone();
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+        .custom_key("key")
+        .build();
+    let backend = SlowBackend(InMemoryBackend::builder().build());
+    let limiter = RateLimiter::builder(backend, input)
+        .on_event(move |event| {
+            recorded.lock().unwrap().push(event.latency());
+        })
+        .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    for _ in 0..2 {
+        test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    }
+    let latencies = latencies.lock().unwrap();
+    assert_eq!(latencies.len(), 2);
+    for latency in latencies.iter() {
+        assert!(latency.unwrap() >= Duration::from_millis(50));
+    }
+}
+
+#[cfg(feature = "dashmap")]
+#[actix_web::test]
+async fn test_on_event() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use crate::RateLimitEvent;
+    use std::sync::Mutex;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 2)
+        .custom_key("key")
+        .build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .rollback_server_errors()
+        .on_event(move |event| {
+            let name = match &event {
+                RateLimitEvent::Allowed { output, .. } => format!("allowed {}", output.remaining),
+                RateLimitEvent::Denied { output, .. } => format!("denied {}", output.remaining),
+                RateLimitEvent::BackendError { .. } => "error".to_string(),
+                RateLimitEvent::RolledBack { .. } => "rolled back".to_string(),
+            };
+            recorded
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", event.key(), name));
+        })
+        .build();
+    let app = test::init_service(
+        App::new()
+            .wrap(limiter)
+            .service(route_200)
+            .service(route_500),
+    )
+    .await;
+    for uri in ["/500", "/200", "/200", "/200"] {
+        test::call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+    }
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            "key allowed 1",
+            "key rolled back",
+            "key allowed 1",
+            "key allowed 0",
+            "key denied 0",
+        ]
+    );
+}
+
+#[cfg(feature = "dashmap")]
+#[actix_web::test]
+async fn test_notify_violations() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use crate::LimitViolation;
+    use std::sync::Mutex;
+
+    tokio::time::pause();
+    let violations = Arc::new(Mutex::new(Vec::new()));
+    let recorded = violations.clone();
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(3600), 1)
+        .custom_key("key")
+        .build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .notify_violations(2, Duration::from_secs(60), move |violation| {
+            let recorded = recorded.clone();
+            async move { recorded.lock().unwrap().push(violation) }
+        })
+        .build();
+    let app = test::init_service(App::new().wrap(limiter).service(route_200)).await;
+    let send = || async {
+        test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+        actix_web::rt::task::yield_now().await;
+    };
+
+    // One allowed request, followed by three denied requests, should only notify once
+    for _ in 0..4 {
+        send().await;
+    }
+    assert_eq!(
+        *violations.lock().unwrap(),
+        [LimitViolation {
+            key: "key".to_string(),
+            denied: 2,
+            window: Duration::from_secs(60),
+        }]
+    );
+
+    // Notifies again in the next window
+    tokio::time::advance(Duration::from_secs(60)).await;
+    send().await;
+    assert_eq!(violations.lock().unwrap().len(), 1);
+    send().await;
+    assert_eq!(violations.lock().unwrap().len(), 2);
+}
+
+#[cfg(feature = "dashmap")]
+#[actix_web::test]
+async fn test_audit_denials() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use crate::DenialRecord;
+    use std::sync::Mutex;
+
+    let records = Arc::new(Mutex::new(Vec::<DenialRecord>::new()));
+    let recorded = records.clone();
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+        .custom_key("key")
+        .build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .audit_denials(move |record| recorded.lock().unwrap().push(record))
+        .build();
+    let app = test::init_service(App::new().wrap(limiter).service(route_200)).await;
+    for _ in 0..2 {
+        let req = TestRequest::get()
+            .uri("/200")
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .insert_header(("x-forwarded-for", "1.2.3.4"))
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+    let records = records.lock().unwrap();
+    assert_eq!(records.len(), 1);
+    let record = &records[0];
+    assert_eq!(&*record.key, "key");
+    assert_eq!(record.method, "GET");
+    assert_eq!(record.route.as_deref(), Some("/200"));
+    assert_eq!(record.path, "/200");
+    assert_eq!(record.limit, 1);
+    assert_eq!(record.peer_ip, Some("10.0.0.1".parse().unwrap()));
+    assert_eq!(record.client_ip, Some("1.2.3.4".parse().unwrap()));
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_value(record).unwrap();
+        assert!(json["timestamp"].is_u64());
+        assert_eq!(json["client_ip"], "1.2.3.4");
+    }
+}
+
+#[cfg(feature = "serde")]
+#[actix_web::test]
+async fn test_deny_json() {
+    let backend = MockBackend::default();
+    let limiter = RateLimiter::builder(backend, |_req| async {
+        Ok(MockBackendInput {
+            max: 0,
+            output: MockHeaderOutput {
+                limit: 30,
+                remaining: 0,
+            },
+            backend_error: None,
+        })
+    })
+    .deny_json(|output: &MockHeaderOutput| {
+        serde_json::json!({
+            "error": "rate_limited",
+            "limit": output.limit(),
+        })
+    })
+    .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        response.headers().get("content-type").unwrap(),
+        "application/json"
+    );
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert_eq!(body, r#"{"error":"rate_limited","limit":30}"#);
+}
+
+#[actix_web::test]
+async fn test_deny_with_problem_json() {
+    let backend = MockBackend::default();
+    let limiter = RateLimiter::builder(backend, |_req| async {
+        Ok(MockBackendInput {
+            max: 0,
+            output: MockHeaderOutput {
+                limit: 30,
+                remaining: 0,
+            },
+            backend_error: None,
+        })
+    })
+    .add_headers()
+    .deny_with_problem_json()
+    .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let headers = response.headers();
+    assert_eq!(
+        headers.get("content-type").unwrap(),
+        "application/problem+json"
+    );
+    assert_eq!(headers.get("retry-after").unwrap(), "30");
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert_eq!(
+        body,
+        r#"{"type":"about:blank","title":"Too Many Requests","status":429,"detail":"Rate limit exceeded, retry after 30 seconds","limit":30,"remaining":0,"retry_after":30}"#
+    );
+}
+
+#[actix_web::test]
+async fn test_deny_with_localized_message() {
+    let backend = MockBackend::default();
+    let messages = LocalizedMessages::new("en", "Retry after {retry_after}s")
+        .add("fr", "Réessayez dans {retry_after} s ({limit} requêtes)");
+    let limiter = RateLimiter::builder(backend, |_req| async {
+        Ok(MockBackendInput {
+            max: 0,
+            output: MockHeaderOutput {
+                limit: 30,
+                remaining: 0,
+            },
+            backend_error: None,
+        })
+    })
+    .add_headers()
+    .deny_with_localized_message(messages)
+    .build();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let request = TestRequest::get()
+        .uri("/200")
+        .insert_header(("accept-language", "fr-CA, en;q=0.8"));
+    let response = test::call_service(&app, request.to_request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let headers = response.headers();
+    assert_eq!(headers.get("content-language").unwrap(), "fr");
+    assert_eq!(headers.get("retry-after").unwrap(), "30");
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert_eq!(body, "Réessayez dans 30 s (30 requêtes)");
+
+    let response = test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    assert_eq!(response.headers().get("content-language").unwrap(), "en");
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert_eq!(body, "Retry after 30s");
+}
+
+#[actix_web::test]
+async fn test_rollback_on_cancel() {
+    use actix_web::dev::Service;
+
+    async fn pending() -> HttpResponse {
+        std::future::pending().await
+    }
+
+    let backend = MockBackend::default();
+    let limiter = RateLimiter::builder(backend.clone(), |_req| async {
+        Ok(MockBackendInput {
+            max: u64::MAX,
+            output: (),
+            backend_error: None,
+        })
+    })
+    .rollback_on_cancel(true)
+    .build();
+    let app = test::init_service(
+        App::new()
+            .route("/pending", actix_web::web::get().to(pending))
+            .wrap(limiter),
+    )
+    .await;
+    let mut future = Box::pin(app.call(TestRequest::get().uri("/pending").to_request()));
+    assert!(futures::poll!(&mut future).is_pending());
+    assert_eq!(backend.0.counter.load(Ordering::Relaxed), 1);
+    // Simulate the client disconnecting
+    drop(future);
+    tokio::task::yield_now().await;
+    assert_eq!(backend.0.counter.load(Ordering::Relaxed), 0);
+}
+
+#[actix_web::test]
+async fn test_handle() {
+    let backend = MockBackend::default();
+    let (limiter, handle) = RateLimiter::builder(backend.clone(), |req: &ServiceRequest| {
+        let backend_error = (req.path() == "/error").then(MockError::default);
+        async move {
+            Ok(MockBackendInput {
+                max: 0,
+                output: (),
+                backend_error,
+            })
+        }
+    })
+    .build_with_handle();
+    let app = test::init_service(App::new().service(route_200).wrap(limiter)).await;
+    let status = |uri: &'static str| {
+        let app = &app;
+        async move {
+            test::call_service(app, TestRequest::get().uri(uri).to_request())
+                .await
+                .status()
+        }
+    };
+    assert_eq!(status("/200").await, StatusCode::TOO_MANY_REQUESTS);
+
+    handle.set_policy(Some(RateLimitPolicy::new(Duration::from_secs(60), 10)));
+    assert_eq!(status("/200").await, StatusCode::OK);
+    handle.set_policy(None);
+    assert_eq!(status("/200").await, StatusCode::TOO_MANY_REQUESTS);
+
+    assert_eq!(status("/error").await, StatusCode::INTERNAL_SERVER_ERROR);
+    handle.set_fail_open(true);
+    assert_eq!(status("/error").await, StatusCode::NOT_FOUND);
+}
+
+#[cfg(all(feature = "tracing", feature = "dashmap"))]
+#[actix_web::test]
+async fn test_tracing() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use std::sync::Mutex;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    // Records the fields of every span, as `name=value`
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Visit for &Recorder {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for &'static Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            values.record(&mut &**self);
+        }
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    let recorder: &'static Recorder = Box::leak(Box::default());
+    let _guard = tracing::subscriber::set_default(recorder);
+
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+        .custom_key("key")
+        .build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .tracing()
+        .build();
+    let app = test::init_service(App::new().wrap(limiter).service(route_200)).await;
+    for _ in 0..2 {
+        test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    }
+    let fields = recorder.0.lock().unwrap().clone();
+    assert_eq!(
+        fields.iter().filter(|f| f.starts_with("key_hash=")).count(),
+        2
+    );
+    let fields: Vec<_> = fields
+        .iter()
+        .filter(|f| !f.starts_with("latency_ms=") && !f.starts_with("key_hash="))
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            "decision=\"allowed\"",
+            "remaining=0",
+            "decision=\"denied\"",
+            "remaining=0",
+        ]
+    );
+}
+
+#[cfg(all(feature = "opentelemetry", feature = "dashmap"))]
+#[actix_web::test]
+async fn test_opentelemetry() {
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::SimpleInputFunctionBuilder;
+    use opentelemetry::trace::{Span, SpanContext, Status, TraceContextExt};
+    use opentelemetry::{Context, KeyValue};
+    use std::borrow::Cow;
+    use std::sync::Mutex;
+    use std::time::SystemTime;
+
+    // Records the attributes and events of the span, as `name=value`
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Span for Recorder {
+        fn add_event_with_timestamp<T>(&mut self, name: T, _: SystemTime, _: Vec<KeyValue>)
+        where
+            T: Into<Cow<'static, str>>,
+        {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("event={}", name.into()));
+        }
+        fn span_context(&self) -> &SpanContext {
+            const EMPTY: &SpanContext = &SpanContext::NONE;
+            EMPTY
+        }
+        fn is_recording(&self) -> bool {
+            true
+        }
+        fn set_attribute(&mut self, attribute: KeyValue) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{}={}", attribute.key, attribute.value));
+        }
+        fn set_status(&mut self, _: Status) {}
+        fn update_name<T>(&mut self, _: T)
+        where
+            T: Into<Cow<'static, str>>,
+        {
+        }
+        fn add_link(&mut self, _: SpanContext, _: Vec<KeyValue>) {}
+        fn end_with_timestamp(&mut self, _: SystemTime) {}
+    }
+
+    let recorder = Recorder::default();
+    let _guard = Context::current_with_span(recorder.clone()).attach();
+
+    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 1)
+        .custom_key("key")
+        .build();
+    let limiter = RateLimiter::builder(InMemoryBackend::builder().build(), input)
+        .opentelemetry()
+        .build();
+    let app = test::init_service(App::new().wrap(limiter).service(route_200)).await;
+    for _ in 0..2 {
+        test::call_service(&app, TestRequest::get().uri("/200").to_request()).await;
+    }
+    let recorded: Vec<_> = recorder
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|r| !r.starts_with("rate_limiting.reset_after="))
+        .cloned()
+        .collect();
+    assert_eq!(
+        recorded,
+        [
+            "rate_limiting.decision=allowed",
+            "rate_limiting.limit=1",
+            "rate_limiting.remaining=0",
+            "rate_limiting.decision=denied",
+            "rate_limiting.limit=1",
+            "rate_limiting.remaining=0",
+            "event=rate_limiting.denied",
+        ]
+    );
+}
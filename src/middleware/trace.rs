@@ -0,0 +1,77 @@
+use crate::backend::{BackendError, Decision};
+use crate::middleware::access::KeyFn;
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+use tracing::field::Empty;
+use tracing::Span;
+
+/// See [RateLimiterBuilder::tracing](crate::RateLimiterBuilder::tracing).
+pub(crate) struct Tracing<BO> {
+    pub(crate) key_fn: KeyFn,
+    pub(crate) remaining: fn(&BO) -> u64,
+}
+
+impl<BO> Tracing<BO> {
+    /// Creates the span for a backend call, as a child of the current span.
+    pub(crate) fn span(&self, input: &dyn Any) -> Span {
+        let span = tracing::info_span!(
+            "rate_limit",
+            key_hash = Empty,
+            decision = Empty,
+            remaining = Empty,
+            latency_ms = Empty,
+        );
+        if let Some(key) = (self.key_fn)(input) {
+            span.record("key_hash", key_hash(key));
+        }
+        span
+    }
+
+    pub(crate) fn record_output(
+        &self,
+        span: &Span,
+        decision: Decision,
+        output: &BO,
+        latency: Duration,
+    ) {
+        span.record(
+            "decision",
+            match decision {
+                Decision::Allowed => "allowed",
+                Decision::Denied => "denied",
+            },
+        );
+        span.record("remaining", (self.remaining)(output));
+        span.record("latency_ms", latency.as_secs_f64() * 1000f64);
+        if decision.is_denied() {
+            tracing::info!(parent: span, "Request denied by rate limiter");
+        }
+    }
+
+    pub(crate) fn record_error(
+        &self,
+        span: &Span,
+        error: &dyn Display,
+        class: BackendError,
+        latency: Duration,
+    ) {
+        span.record("decision", "error");
+        span.record("latency_ms", latency.as_secs_f64() * 1000f64);
+        tracing::error!(
+            parent: span,
+            error = %error,
+            error_class = class.as_str(),
+            "Rate limiter backend failed"
+        );
+    }
+}
+
+/// The key is hashed so that client identifiers (e.g. IP addresses) aren't exported to traces.
+fn key_hash(key: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
@@ -0,0 +1,254 @@
+//! A [tower](https://docs.rs/tower) adapter, so that the same backends and policies can protect
+//! other stacks, e.g. a tonic gRPC server running alongside actix-web.
+
+use crate::backend::{ClassifyError, Decision, SendBackend};
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use thiserror::Error;
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// A [Layer] that rate limits requests to the inner service using a [SendBackend].
+///
+/// The input function returns the backend input for a request, or [None] to skip rate limiting
+/// it. Denied requests are answered using the denied response function, which receives the output
+/// of the backend, without calling the inner service.
+///
+/// # Examples
+///
+/// ```
+/// # use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+/// # use actix_extensible_rate_limit::backend::{SimpleInput, SimpleOutput};
+/// # use actix_extensible_rate_limit::tower::RateLimitLayer;
+/// # use std::time::Duration;
+/// struct Request {
+///     client_id: String,
+/// }
+///
+/// # actix_web::rt::System::new().block_on(async {
+/// let backend = InMemoryBackend::builder().build();
+/// let layer = RateLimitLayer::builder(
+///     backend,
+///     |req: &Request| {
+///         Some(SimpleInput {
+///             interval: Duration::from_secs(60),
+///             max_requests: 100,
+///             key: req.client_id.as_str().into(),
+///         })
+///     },
+///     |_: &SimpleOutput| String::from("Too many requests"),
+/// )
+/// .fail_open(true)
+/// .build();
+/// # });
+/// ```
+pub struct RateLimitLayer<B, F, D> {
+    config: Arc<Config<B, F, D>>,
+}
+
+struct Config<B, F, D> {
+    backend: B,
+    input_fn: F,
+    denied_response: D,
+    fail_open: bool,
+    fail_open_transient: bool,
+}
+
+impl<B, F, D> Clone for RateLimitLayer<B, F, D> {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<B, F, D> RateLimitLayer<B, F, D> {
+    pub fn builder(backend: B, input_fn: F, denied_response: D) -> Builder<B, F, D> {
+        Builder {
+            backend,
+            input_fn,
+            denied_response,
+            fail_open: false,
+            fail_open_transient: false,
+        }
+    }
+}
+
+pub struct Builder<B, F, D> {
+    backend: B,
+    input_fn: F,
+    denied_response: D,
+    fail_open: bool,
+    fail_open_transient: bool,
+}
+
+impl<B, F, D> Builder<B, F, D> {
+    /// Choose whether to allow a request if the backend returns a failure.
+    ///
+    /// Default is false, in which case [Error::Backend] is returned.
+    pub fn fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// Choose whether to allow a request if the backend returns a
+    /// [Transient](crate::backend::BackendError::Transient) failure, but return [Error::Backend]
+    /// for other classes of failure.
+    ///
+    /// Default is false.
+    pub fn fail_open_transient(mut self, fail_open_transient: bool) -> Self {
+        self.fail_open_transient = fail_open_transient;
+        self
+    }
+
+    pub fn build(self) -> RateLimitLayer<B, F, D> {
+        RateLimitLayer {
+            config: Arc::new(Config {
+                backend: self.backend,
+                input_fn: self.input_fn,
+                denied_response: self.denied_response,
+                fail_open: self.fail_open,
+                fail_open_transient: self.fail_open_transient,
+            }),
+        }
+    }
+}
+
+impl<S, B, F, D> Layer<S> for RateLimitLayer<B, F, D> {
+    type Service = RateLimitService<S, B, F, D>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// The [Service] created by a [RateLimitLayer].
+pub struct RateLimitService<S, B, F, D> {
+    inner: S,
+    config: Arc<Config<B, F, D>>,
+}
+
+impl<S: Clone, B, F, D> Clone for RateLimitService<S, B, F, D> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// The error returned by a [RateLimitService].
+#[derive(Debug, Error)]
+pub enum Error<E, BE> {
+    /// The inner service failed.
+    #[error(transparent)]
+    Inner(E),
+    /// The backend failed, and the layer is not failing open.
+    #[error("Rate limiter backend failed: {0}")]
+    Backend(BE),
+}
+
+impl<S, B, F, D, I, Req> Service<Req> for RateLimitService<S, B, F, D>
+where
+    S: Service<Req> + Clone + Send + 'static,
+    S::Future: Send,
+    B: SendBackend<I>,
+    B::Error: ClassifyError + std::fmt::Display,
+    F: Fn(&Req) -> Option<I> + Send + Sync + 'static,
+    D: Fn(&B::Output) -> S::Response + Send + Sync + 'static,
+    I: Send + 'static,
+    Req: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = Error<S::Error, B::Error>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Error::Inner)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        // The clone may not be ready, so keep the instance that was polled
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+        let input = (config.input_fn)(&req);
+        Box::pin(async move {
+            if let Some(input) = input {
+                match config.backend.request_send(input).await {
+                    Ok((Decision::Denied, output, _)) => {
+                        return Ok((config.denied_response)(&output));
+                    }
+                    Ok((Decision::Allowed, _, _)) => {}
+                    Err(e) => {
+                        let transient = config.fail_open_transient && e.class().is_transient();
+                        if !config.fail_open && !transient {
+                            return Err(Error::Backend(e));
+                        }
+                        log::warn!("Rate limiter failed: {e}, allowing the request anyway");
+                    }
+                }
+            }
+            inner.call(req).await.map_err(Error::Inner)
+        })
+    }
+}
+
+#[cfg(all(test, feature = "dashmap"))]
+mod tests {
+    use super::*;
+    use crate::backend::memory::InMemoryBackend;
+    use crate::backend::{SimpleInput, SimpleOutput};
+    use futures::future::{poll_fn, ready, Ready};
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<String> for Echo {
+        type Response = String;
+        type Error = Infallible;
+        type Future = Ready<Result<String, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: String) -> Self::Future {
+            ready(Ok(req))
+        }
+    }
+
+    async fn call<S: Service<String>>(service: &mut S, req: &str) -> Result<S::Response, S::Error> {
+        poll_fn(|cx| service.poll_ready(cx)).await?;
+        service.call(req.to_owned()).await
+    }
+
+    #[actix_web::test]
+    async fn test_layer() {
+        let layer = RateLimitLayer::builder(
+            InMemoryBackend::builder().build(),
+            |req: &String| {
+                (req != "skip").then(|| SimpleInput {
+                    interval: Duration::from_secs(60),
+                    max_requests: 1,
+                    key: "KEY1".into(),
+                })
+            },
+            |output: &SimpleOutput| format!("Denied, {} remaining", output.remaining),
+        )
+        .build();
+        let mut service = layer.layer(Echo);
+        assert_eq!(call(&mut service, "hello").await.unwrap(), "hello");
+        assert_eq!(
+            call(&mut service, "hello").await.unwrap(),
+            "Denied, 0 remaining"
+        );
+        assert_eq!(call(&mut service, "skip").await.unwrap(), "skip");
+    }
+}
@@ -0,0 +1,142 @@
+//! Drives each of the examples in the `examples/` directory.
+
+use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+use actix_web::http::StatusCode;
+use actix_web::test::{call_service, init_service, TestRequest};
+use actix_web::{web, App};
+
+#[allow(dead_code)]
+#[path = "../examples/behind_proxy.rs"]
+mod behind_proxy;
+
+#[allow(dead_code)]
+#[path = "../examples/login_protection.rs"]
+mod login_protection;
+
+#[allow(dead_code)]
+#[path = "../examples/tiered_api_keys.rs"]
+mod tiered_api_keys;
+
+#[cfg(feature = "admin")]
+#[allow(dead_code)]
+#[path = "../examples/admin_endpoints.rs"]
+mod admin_endpoints;
+
+const PEER: &str = "127.0.0.1:12345";
+
+#[actix_web::test]
+async fn test_behind_proxy() {
+    let backend = InMemoryBackend::builder().build();
+    let app = init_service(
+        App::new()
+            .wrap(behind_proxy::rate_limiter(backend))
+            .configure(behind_proxy::routes),
+    )
+    .await;
+    let request = |ip: &str| {
+        TestRequest::get()
+            .uri("/")
+            .peer_addr(PEER.parse().unwrap())
+            .insert_header(("x-forwarded-for", ip))
+            .to_request()
+    };
+    for _ in 0..5 {
+        let response = call_service(&app, request("1.1.1.1")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = call_service(&app, request("1.1.1.1")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    // A different client behind the same proxy has their own limit
+    let response = call_service(&app, request("2.2.2.2")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_login_protection() {
+    let backend = InMemoryBackend::builder().build();
+    let app = init_service(
+        App::new().service(
+            web::scope("")
+                .wrap(login_protection::rate_limiter(backend))
+                .configure(login_protection::routes),
+        ),
+    )
+    .await;
+    let login = |password: &'static str| {
+        TestRequest::post()
+            .uri("/login")
+            .peer_addr(PEER.parse().unwrap())
+            .set_payload(password)
+            .to_request()
+    };
+    // Successful logins are not counted
+    for _ in 0..10 {
+        let response = call_service(&app, login("hunter2")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    for _ in 0..login_protection::MAX_FAILED_ATTEMPTS {
+        let response = call_service(&app, login("wrong")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    // Even the correct password is now rejected
+    let response = call_service(&app, login("hunter2")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn test_tiered_api_keys() {
+    let backend = InMemoryBackend::builder().build();
+    let app = init_service(
+        App::new()
+            .wrap(tiered_api_keys::rate_limiter(backend))
+            .configure(tiered_api_keys::routes),
+    )
+    .await;
+    let request = |key: &str| {
+        TestRequest::get()
+            .uri("/")
+            .insert_header(("x-api-key", key))
+            .to_request()
+    };
+    let response = call_service(&app, request("free_abc")).await;
+    assert_eq!(
+        response.headers().get("x-ratelimit-limit").unwrap(),
+        &tiered_api_keys::FREE_LIMIT.to_string()
+    );
+    let response = call_service(&app, request("pro_abc")).await;
+    assert_eq!(
+        response.headers().get("x-ratelimit-limit").unwrap(),
+        &tiered_api_keys::PRO_LIMIT.to_string()
+    );
+    // Requests without an API key are rejected
+    let response = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[cfg(feature = "admin")]
+#[actix_web::test]
+async fn test_admin_endpoints() {
+    let backend = InMemoryBackend::builder().build();
+    let app = init_service(App::new().configure(admin_endpoints::routes(backend))).await;
+    let api = || {
+        TestRequest::get()
+            .uri("/api")
+            .peer_addr(PEER.parse().unwrap())
+            .to_request()
+    };
+    for _ in 0..5 {
+        assert_eq!(call_service(&app, api()).await.status(), StatusCode::OK);
+    }
+    assert_eq!(
+        call_service(&app, api()).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    let reset = TestRequest::delete()
+        .uri("/admin/rate-limit/keys/127.0.0.1")
+        .to_request();
+    assert_eq!(
+        call_service(&app, reset).await.status(),
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(call_service(&app, api()).await.status(), StatusCode::OK);
+}